@@ -23,16 +23,13 @@ fn main() {
         let mut app = App::new(opts, None);
         let compressor: u32 = CompressOptions::SharedCompressor.into();
         let decompressor: u32 = CompressOptions::SharedDecompressor.into();
-        let route_settings = WsRouteSettings {
-            compression: Some(compressor | decompressor),
-            max_payload_length: Some(1024),
-            idle_timeout: Some(800),
-            max_backpressure: Some(10),
-            close_on_backpressure_limit: Some(false),
-            reset_idle_timeout_on_send: Some(true),
-            send_pings_automatically: Some(true),
-            max_lifetime: Some(111),
-        };
+        let route_settings = WsRouteSettings::new()
+            .compression(compressor | decompressor)
+            .max_payload(1024)
+            .idle_timeout(800)
+            .max_backpressure(16 * 1024)
+            .reset_idle_timeout_on_send(true)
+            .max_lifetime(111);
 
         app.ws(
             "/",
@@ -40,9 +37,11 @@ fn main() {
             handler_ws,
             HttpConnection::default_upgrade,
         )
+        .unwrap()
         .listen(9001, None::<fn(ListenSocket)>)
         .run();
         println!("Server exiting");
+    });
 }
 
 async fn handler_ws(mut ws: Websocket<false>) {
@@ -1,6 +1,5 @@
 use std::time::Duration;
 
-use tokio::sync::{broadcast, oneshot};
 use tokio::time::sleep;
 
 use async_uws::app::App;
@@ -29,15 +28,8 @@ fn main() {
             data: "String containing data".to_string(),
         };
 
-        let (sink, stream) = oneshot::channel::<()>();
-        let (b_sink, mut b_stream) = broadcast::channel::<()>(1);
-        tokio_uring::spawn(async move {
-            let _ = b_stream.recv().await;
-            sink.send(()).unwrap();
-        });
-        let mut app = App::new(opts, Some(stream));
+        let mut app = App::new(opts, None);
         app.data(shared_data);
-        app.data(b_sink);
 
         app.get("/get", get_handler)
             .post("/post", post_handler)
@@ -1,8 +1,5 @@
 use std::time::Duration;
 
-use tokio::sync::{broadcast, oneshot};
-use tokio::sync::broadcast::Sender;
-
 use async_uws::app::App;
 use async_uws::data_storage::DataStorage;
 use async_uws::http_request::HttpRequest;
@@ -13,6 +10,7 @@ use async_uws::uwebsockets_rs::UsSocketContextOptions;
 use async_uws::websocket::Websocket;
 use async_uws::ws_behavior::WsRouteSettings;
 use async_uws::ws_message::WsMessage;
+use bytes::Bytes;
 
 #[derive(Clone)]
 struct SharedData {
@@ -35,68 +33,63 @@ fn main() {
             data: "String containing data".to_string(),
         };
 
-        let (sink, stream) = oneshot::channel::<()>();
-        let (b_sink, mut b_stream) = broadcast::channel::<()>(1);
-        tokio_uring::spawn(async move {
-            let _ = b_stream.recv().await;
-            sink.send(()).unwrap();
-        });
-
-        let mut app = App::new(opts, Some(stream));
+        let mut app = App::new(opts, None);
         let compressor: u32 = CompressOptions::SharedCompressor.into();
         let decompressor: u32 = CompressOptions::SharedDecompressor.into();
-        let route_settings = WsRouteSettings {
-            compression: Some(compressor | decompressor),
-            max_payload_length: Some(1024),
-            idle_timeout: Some(800),
-            max_backpressure: Some(10),
-            close_on_backpressure_limit: Some(false),
-            reset_idle_timeout_on_send: Some(true),
-            send_pings_automatically: Some(true),
-            max_lifetime: Some(111),
-        };
+        let route_settings = WsRouteSettings::new()
+            .compression(compressor | decompressor)
+            .max_payload(1024)
+            .idle_timeout(800)
+            .max_backpressure(16 * 1024)
+            .reset_idle_timeout_on_send(true)
+            .max_lifetime(111);
         app.data(shared_data);
-        app.data(b_sink);
+        let shutdown = app.shutdown_handle();
 
         app.ws(
             "/shutdown",
             route_settings.clone(),
-            |mut ws| async move {
-                let b_sink = ws.data::<Sender<()>>().unwrap().clone();
-                let status = ws.send("hello".into()).await;
-                println!("Send status: {status:#?}");
-
-                while let Some(msg) = ws.stream.recv().await {
-                    println!("{msg:#?}");
-                    if let WsMessage::Message(data, _) = msg {
-                        println!("{data:#?}");
-                        b_sink.send(()).unwrap();
-                    };
-                    let status = ws
-                        .send(WsMessage::Message(
-                            "asdfasdf".as_bytes().to_vec(),
-                            Opcode::Text,
-                        ))
-                        .await;
-                    println!("{status:#?}");
+            move |mut ws| {
+                let shutdown = shutdown.clone();
+                async move {
+                    let status = ws.send("hello".into()).await;
+                    println!("Send status: {status:#?}");
+
+                    while let Some(msg) = ws.stream.recv().await {
+                        println!("{msg:#?}");
+                        if let WsMessage::Message(data, _) = msg {
+                            println!("{data:#?}");
+                            shutdown.shutdown();
+                        };
+                        let status = ws
+                            .send(WsMessage::Message(
+                                Bytes::from_static(b"asdfasdf"),
+                                Opcode::Text,
+                            ))
+                            .await;
+                        println!("{status:#?}");
+                    }
                 }
             },
             |req, res| {
                 custom_upgrade(req, res);
             },
         )
+        .unwrap()
         .ws(
             "/ws-test",
             route_settings.clone(),
             handler_ws,
             custom_upgrade,
         )
+        .unwrap()
         .ws(
             "/split",
             route_settings,
             ws_split,
             HttpConnection::default_upgrade,
         )
+        .unwrap()
         .listen(
             3001,
             Some(|listen_socket| {
@@ -160,7 +153,7 @@ async fn handler_ws(mut ws: Websocket<false>) {
         match msg {
             WsMessage::Message(bin, opcode) => {
                 if opcode == Opcode::Text {
-                    let msg = String::from_utf8(bin).unwrap();
+                    let msg = String::from_utf8(bin.to_vec()).unwrap();
                     println!("{msg}");
 
                     if msg.contains("close") {
@@ -180,9 +173,15 @@ async fn handler_ws(mut ws: Websocket<false>) {
                 println!("Got close: {code}, {reason:#?}");
                 break;
             }
+            WsMessage::Violation(violation) => {
+                println!("Got violation: {violation:#?}");
+            }
+            WsMessage::Drain(buffered) => {
+                println!("Drained, {buffered} bytes still buffered");
+            }
         }
         ws.send(WsMessage::Message(
-            Vec::from("response to your message".as_bytes()),
+            Bytes::from_static(b"response to your message"),
             Opcode::Text,
         ))
         .await
@@ -195,7 +194,7 @@ async fn ws_split(ws: Websocket<false>) {
     let (sink, mut stream) = ws.split();
     tokio_uring::spawn(async move {
         loop {
-            if let Err(e) = sink.send(("Hello! I'm timer".into(), false, true)) {
+            if let Err(e) = sink.send(("Hello! I'm timer".into(), false, true)).await {
                 println!("Error send to socket:{e:#?}");
                 break;
             }
@@ -0,0 +1,104 @@
+use std::ffi::c_int;
+use std::sync::{Arc, Mutex};
+
+use libuwebsockets_sys::{
+    us_listen_socket_close, us_listen_socket_t, us_poll_change, us_poll_t, us_socket_context,
+    us_socket_context_loop, us_socket_t,
+};
+use tokio::sync::watch;
+use uwebsockets_rs::uws_loop::UwsLoop;
+
+use crate::diagnostics::loop_defer;
+
+// LIBUS_SOCKET_READABLE, EPOLLIN and the kqueue read filter are both 1
+const SOCKET_READABLE: c_int = 1;
+
+// Listen sockets opened by App::listen(), pointers are stored as usize and only used on the loop thread
+#[derive(Default)]
+pub(crate) struct Listeners {
+    sockets: Vec<usize>,
+    is_paused: bool,
+    is_closed: bool,
+}
+
+impl Listeners {
+    // Sockets added while accepting is paused start out paused
+    pub(crate) fn add(&mut self, listen_socket: *mut us_listen_socket_t, ssl: bool) {
+        if self.is_paused {
+            set_accepting(listen_socket, ssl, false);
+        }
+        self.sockets.push(listen_socket as usize);
+    }
+
+    // Closes every listen socket for good, used on shutdown
+    pub(crate) fn close_all(&mut self, ssl: bool) {
+        for listen_socket in self.sockets.drain(..) {
+            unsafe {
+                us_listen_socket_close(ssl as c_int, listen_socket as *mut us_listen_socket_t)
+            };
+        }
+        self.is_closed = true;
+    }
+
+    fn set_accepting(&mut self, ssl: bool, is_accepting: bool) {
+        if self.is_closed {
+            return;
+        }
+        self.is_paused = !is_accepting;
+        for listen_socket in self.sockets.iter() {
+            set_accepting(*listen_socket as *mut us_listen_socket_t, ssl, is_accepting);
+        }
+    }
+}
+
+/***
+ * Stops and resumes accepting new connections, open HTTP / WS traffic is not affected.
+ * Listen sockets stay bound while paused, the loop just stops polling them, so new clients
+ * wait in the backlog until resume() (or time out there). The same goes for relayed listeners
+ * (listen_activated(), listen_rustls()).
+ ***/
+#[derive(Clone)]
+pub struct AcceptControl {
+    pub(crate) listeners: Arc<Mutex<Listeners>>,
+    pub(crate) paused: watch::Sender<bool>,
+    pub(crate) uws_loop: UwsLoop,
+    pub(crate) ssl: bool,
+}
+
+impl AcceptControl {
+    pub fn pause(&self) {
+        if self.paused.send_replace(true) {
+            return;
+        }
+        self.set_accepting(false);
+    }
+
+    pub fn resume(&self) {
+        if !self.paused.send_replace(false) {
+            return;
+        }
+        self.set_accepting(true);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    fn set_accepting(&self, is_accepting: bool) {
+        let listeners = self.listeners.clone();
+        let ssl = self.ssl;
+        loop_defer(self.uws_loop, move || {
+            listeners.lock().unwrap().set_accepting(ssl, is_accepting);
+        });
+    }
+}
+
+// On the loop thread, a listen socket is only read to accept
+fn set_accepting(listen_socket: *mut us_listen_socket_t, ssl: bool, is_accepting: bool) {
+    let events = if is_accepting { SOCKET_READABLE } else { 0 };
+    unsafe {
+        let context = us_socket_context(ssl as c_int, listen_socket as *mut us_socket_t);
+        let uws_loop = us_socket_context_loop(ssl as c_int, context);
+        us_poll_change(listen_socket as *mut us_poll_t, uws_loop, events);
+    }
+}
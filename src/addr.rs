@@ -0,0 +1,119 @@
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use crate::ws_message::WsMessage;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/***
+ * Handle to a connection's task, other parts of the app send it typed commands instead of
+ * writing to its sink directly. The connection's handler owns the Mailbox and decides what a
+ * command does:
+ *
+ *   let (addr, mut mailbox) = mailbox::<Cmd>();
+ *   registry.insert(user_id, addr);
+ *   while let Some(event) = mailbox.next(&mut ws.stream).await {
+ *       match event {
+ *           ConnectionEvent::Message(message) => ..,
+ *           ConnectionEvent::Command(Cmd::Kick) => break,
+ *       }
+ *   }
+ *
+ * Clones share the id, which makes Addr usable as a map key.
+ ***/
+#[derive(Debug)]
+pub struct Addr<C> {
+    id: u64,
+    sink: UnboundedSender<C>,
+}
+
+#[derive(Debug)]
+pub struct Mailbox<C> {
+    stream: UnboundedReceiver<C>,
+    is_closed: bool,
+}
+
+#[derive(Debug)]
+pub enum ConnectionEvent<C> {
+    Message(WsMessage),
+    Command(C),
+}
+
+pub fn mailbox<C>() -> (Addr<C>, Mailbox<C>) {
+    let (sink, stream) = unbounded_channel();
+    let addr = Addr {
+        id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        sink,
+    };
+    let mailbox = Mailbox {
+        stream,
+        is_closed: false,
+    };
+    (addr, mailbox)
+}
+
+impl<C> Addr<C> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // Fails once the connection's mailbox is dropped
+    pub fn do_send(&self, command: C) -> Result<(), String> {
+        self.sink
+            .send(command)
+            .map_err(|_| format!("Connection {} is gone", self.id))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.sink.is_closed()
+    }
+}
+
+impl<C> Clone for Addr<C> {
+    fn clone(&self) -> Self {
+        Addr {
+            id: self.id,
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+impl<C> PartialEq for Addr<C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<C> Eq for Addr<C> {}
+
+impl<C> Hash for Addr<C> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<C> Mailbox<C> {
+    // Next command alone, None once every Addr is dropped
+    pub async fn recv(&mut self) -> Option<C> {
+        self.stream.recv().await
+    }
+
+    // Next message from the client or command from an Addr, None once the client stream ends.
+    // Dropping every Addr doesn't end it, the connection keeps getting its messages
+    pub async fn next(
+        &mut self,
+        stream: &mut UnboundedReceiver<WsMessage>,
+    ) -> Option<ConnectionEvent<C>> {
+        loop {
+            tokio::select! {
+                message = stream.recv() => return message.map(ConnectionEvent::Message),
+                command = self.stream.recv(), if !self.is_closed => match command {
+                    Some(command) => return Some(ConnectionEvent::Command(command)),
+                    None => self.is_closed = true,
+                },
+            }
+        }
+    }
+}
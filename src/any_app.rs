@@ -0,0 +1,962 @@
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{Receiver, UnboundedReceiver};
+use tokio::sync::oneshot::Receiver as ShutdownReceiver;
+use uwebsockets_rs::listen_socket::ListenSocket;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::accept_control::AcceptControl;
+use crate::app::{App, AppSSL, AppStruct, BoxedHandlerFuture};
+use crate::app_config::AppConfig;
+use crate::app_handle::AppHandle;
+use crate::backplane::Backplane;
+use crate::body_reader::{BodyChunk, BodyStream};
+use crate::broadcast::{Broadcast, BroadcastBridge};
+use crate::cache_control::CacheControl;
+use crate::cancellation::CancellationToken;
+use crate::coalesce::Coalescer;
+use crate::compression::{Compression, Decompression};
+use crate::connection_registry::{ConnectionId, ConnectionRegistry};
+use crate::cookie::Cookie;
+#[cfg(feature = "signed-cookies")]
+use crate::cookie::CookieKey;
+use crate::cors::Cors;
+use crate::data_storage::SharedDataStorage;
+use crate::diagnostics::Diagnostics;
+use crate::embedded_assets::EmbeddedAssets;
+use crate::error::HttpError;
+use crate::file_transfer::FileTransfer;
+use crate::guard::Guard;
+use crate::health::Health;
+use crate::http_connection::{
+    FallbackResponse, HttpConnection, ResponseState, ResponseStateHandle,
+};
+use crate::http_request::HttpRequest;
+#[cfg(feature = "jwt")]
+use crate::jwt::{JwtAuth, JwtClaims};
+use crate::live_settings::{LiveSettings, SettingsHandle};
+use crate::middleware::Next;
+use crate::multipart::Multipart;
+use crate::presence::Presence;
+use crate::progress::Progress;
+use crate::rate_limit::RateLimiter;
+use crate::response::{IntoResponse, Response};
+use crate::response_cache::{CachedResponse, ResponseCache};
+use crate::rooms::Rooms;
+use crate::session::{Session, Sessions};
+use crate::shutdown::{ShutdownHandle, WsShutdown};
+use crate::static_files::ServeDir;
+use crate::tcp_options::TcpOptions;
+use crate::tls_options::TlsConfig;
+use crate::trusted_proxies::TrustedProxies;
+use crate::validate::Validation;
+use crate::websocket::{SendStatus, Websocket, WsSink, WsStream};
+use crate::ws_actor::WsActor;
+use crate::ws_behavior::WsRouteSettings;
+use crate::ws_message::WsMessage;
+use crate::ws_stats::WsStats;
+
+// Runs the same expression for both variants, `$inner` is bound to the wrapped value
+macro_rules! dispatch {
+    ($value:expr, $any:ident, $inner:ident => $body:expr) => {
+        match $value {
+            $any::Plain($inner) => $body,
+            $any::Ssl($inner) => $body,
+        }
+    };
+}
+
+/***
+ * App with the `const SSL: bool` parameter erased, so TLS can be chosen at startup
+ * and handlers are written once against AnyHttpConnection / AnyWebsocket.
+ ***/
+pub enum AnyApp {
+    Plain(App),
+    Ssl(AppSSL),
+}
+
+pub enum AnyHttpConnection {
+    Plain(HttpConnection<false>),
+    Ssl(HttpConnection<true>),
+}
+
+pub enum AnyNext {
+    Plain(Next<false>),
+    Ssl(Next<true>),
+}
+
+pub enum AnyWebsocket {
+    Plain(Websocket<false>),
+    Ssl(Websocket<true>),
+}
+
+macro_rules! any_http_route {
+    ($($method:ident),*) => {
+        $(
+            pub fn $method<T, W>(&mut self, pattern: &str, handler: T) -> &mut Self
+            where
+                T: (Fn(AnyHttpConnection, HttpRequest) -> W) + 'static + Send + Sync,
+                W: Future<Output = ()> + 'static + Send,
+            {
+                match self {
+                    AnyApp::Plain(app) => {
+                        app.$method(pattern, move |res, req| handler(AnyHttpConnection::Plain(res), req));
+                    }
+                    AnyApp::Ssl(app) => {
+                        app.$method(pattern, move |res, req| handler(AnyHttpConnection::Ssl(res), req));
+                    }
+                }
+                self
+            }
+        )*
+    };
+}
+
+impl AnyApp {
+    // SSL app when `tls` is passed, plain otherwise
+    pub fn new(
+        tls: Option<TlsConfig>,
+        shutdown_stream: Option<ShutdownReceiver<()>>,
+    ) -> Result<Self, String> {
+        match tls {
+            Some(tls) => Ok(AnyApp::Ssl(AppStruct::new(tls.build()?, shutdown_stream))),
+            None => Ok(AnyApp::Plain(AppStruct::new(
+                TlsConfig::new().build()?,
+                shutdown_stream,
+            ))),
+        }
+    }
+
+    // SSL app when the config has a `tls` section
+    pub fn from_config(
+        config: AppConfig,
+        shutdown_stream: Option<ShutdownReceiver<()>>,
+    ) -> Result<Self, String> {
+        if config.tls.is_some() {
+            Ok(AnyApp::Ssl(AppStruct::from_config(
+                config,
+                shutdown_stream,
+            )?))
+        } else {
+            Ok(AnyApp::Plain(AppStruct::from_config(
+                config,
+                shutdown_stream,
+            )?))
+        }
+    }
+
+    pub fn is_ssl(&self) -> bool {
+        matches!(self, AnyApp::Ssl(_))
+    }
+
+    pub fn data<T>(&mut self, data: T) -> &mut Self
+    where
+        T: Sync + Send + Clone + 'static,
+    {
+        dispatch!(self, AnyApp, app => { app.data(data); });
+        self
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    pub fn cookie_key(&mut self, key: CookieKey) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.cookie_key(key); });
+        self
+    }
+
+    pub fn tcp_options(&mut self, tcp_options: TcpOptions) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.tcp_options(tcp_options); });
+        self
+    }
+
+    pub fn fallback_response(&mut self, fallback_response: Option<FallbackResponse>) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.fallback_response(fallback_response); });
+        self
+    }
+
+    pub fn panic_response(&mut self, panic_response: FallbackResponse) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.panic_response(panic_response); });
+        self
+    }
+
+    pub fn error_handler<F>(&mut self, error_handler: F) -> &mut Self
+    where
+        F: Fn(&HttpError) -> Response + Send + Sync + 'static,
+    {
+        dispatch!(self, AnyApp, app => { app.error_handler(error_handler); });
+        self
+    }
+
+    pub fn request_deadline(&mut self, deadline: Duration) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.request_deadline(deadline); });
+        self
+    }
+
+    pub fn route_deadline(&mut self, pattern: &str, deadline: Duration) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_deadline(pattern, deadline); });
+        self
+    }
+
+    pub fn route_validation(&mut self, pattern: &str, validation: Validation) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_validation(pattern, validation); });
+        self
+    }
+
+    pub fn route_guard(&mut self, pattern: &str, guard: Guard) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_guard(pattern, guard); });
+        self
+    }
+
+    pub fn middleware<T, W>(&mut self, middleware: T) -> &mut Self
+    where
+        T: (Fn(AnyHttpConnection, HttpRequest, AnyNext) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        match self {
+            AnyApp::Plain(app) => {
+                app.middleware(move |res, req, next| {
+                    middleware(AnyHttpConnection::Plain(res), req, AnyNext::Plain(next))
+                });
+            }
+            AnyApp::Ssl(app) => {
+                app.middleware(move |res, req, next| {
+                    middleware(AnyHttpConnection::Ssl(res), req, AnyNext::Ssl(next))
+                });
+            }
+        }
+        self
+    }
+
+    pub fn route_middleware<T, W>(&mut self, pattern: &str, middleware: T) -> &mut Self
+    where
+        T: (Fn(AnyHttpConnection, HttpRequest, AnyNext) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        match self {
+            AnyApp::Plain(app) => {
+                app.route_middleware(pattern, move |res, req, next| {
+                    middleware(AnyHttpConnection::Plain(res), req, AnyNext::Plain(next))
+                });
+            }
+            AnyApp::Ssl(app) => {
+                app.route_middleware(pattern, move |res, req, next| {
+                    middleware(AnyHttpConnection::Ssl(res), req, AnyNext::Ssl(next))
+                });
+            }
+        }
+        self
+    }
+
+    pub fn cors(&mut self, cors: Cors) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.cors(cors); });
+        self
+    }
+
+    pub fn sessions(&mut self, sessions: Sessions) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.sessions(sessions); });
+        self
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn jwt_auth(&mut self, auth: JwtAuth) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.jwt_auth(auth); });
+        self
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn route_jwt_auth(&mut self, pattern: &str, auth: JwtAuth) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_jwt_auth(pattern, auth); });
+        self
+    }
+
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.compression(compression); });
+        self
+    }
+
+    pub fn route_compression(&mut self, pattern: &str, compression: Compression) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_compression(pattern, compression); });
+        self
+    }
+
+    pub fn decompression(&mut self, decompression: Decompression) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.decompression(decompression); });
+        self
+    }
+
+    pub fn route_decompression(
+        &mut self,
+        pattern: &str,
+        decompression: Decompression,
+    ) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_decompression(pattern, decompression); });
+        self
+    }
+
+    pub fn rate_limit(&mut self, limiter: RateLimiter) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.rate_limit(limiter); });
+        self
+    }
+
+    pub fn route_rate_limit(&mut self, pattern: &str, limiter: RateLimiter) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_rate_limit(pattern, limiter); });
+        self
+    }
+
+    pub fn trusted_proxies(&mut self, proxies: TrustedProxies) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.trusted_proxies(proxies); });
+        self
+    }
+
+    pub fn max_body_size(&mut self, bytes: u64) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.max_body_size(bytes); });
+        self
+    }
+
+    pub fn route_max_body_size(&mut self, pattern: &str, bytes: u64) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_max_body_size(pattern, bytes); });
+        self
+    }
+
+    pub fn update_settings<F>(&self, update: F) -> &Self
+    where
+        F: FnOnce(&mut LiveSettings) + Send + 'static,
+    {
+        dispatch!(self, AnyApp, app => { app.update_settings(update); });
+        self
+    }
+
+    pub fn settings_handle(&self) -> SettingsHandle {
+        dispatch!(self, AnyApp, app => app.settings_handle())
+    }
+
+    pub fn first_byte_timeout(&mut self, timeout: Duration) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.first_byte_timeout(timeout); });
+        self
+    }
+
+    pub fn route_first_byte_timeout(&mut self, pattern: &str, timeout: Duration) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.route_first_byte_timeout(pattern, timeout); });
+        self
+    }
+
+    any_http_route!(get, post, patch, delete, options, put, trace, connect, any);
+
+    pub fn fallback<T, W>(&mut self, handler: T) -> &mut Self
+    where
+        T: (Fn(AnyHttpConnection, HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        match self {
+            AnyApp::Plain(app) => {
+                app.fallback(move |res, req| handler(AnyHttpConnection::Plain(res), req));
+            }
+            AnyApp::Ssl(app) => {
+                app.fallback(move |res, req| handler(AnyHttpConnection::Ssl(res), req));
+            }
+        }
+        self
+    }
+
+    pub fn ws<T, W, U>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+        connection_handler: T,
+        upgrade_hook: U,
+    ) -> Result<&mut Self, String>
+    where
+        T: (Fn(AnyWebsocket) -> W) + 'static + Send + Sync + Clone,
+        W: Future<Output = ()> + 'static + Send,
+        U: Fn(HttpRequest, AnyHttpConnection) + 'static + Send + Sync + Clone,
+    {
+        match self {
+            AnyApp::Plain(app) => {
+                app.ws(
+                    pattern,
+                    route_settings,
+                    move |ws| connection_handler(AnyWebsocket::Plain(ws)),
+                    move |req, res| upgrade_hook(req, AnyHttpConnection::Plain(res)),
+                )?;
+            }
+            AnyApp::Ssl(app) => {
+                app.ws(
+                    pattern,
+                    route_settings,
+                    move |ws| connection_handler(AnyWebsocket::Ssl(ws)),
+                    move |req, res| upgrade_hook(req, AnyHttpConnection::Ssl(res)),
+                )?;
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn ws_async_upgrade<T, W, U, V>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+        connection_handler: T,
+        upgrade_handler: U,
+    ) -> Result<&mut Self, String>
+    where
+        T: (Fn(AnyWebsocket) -> W) + 'static + Send + Sync + Clone,
+        W: Future<Output = ()> + 'static + Send,
+        U: (Fn(HttpRequest, AnyHttpConnection) -> V) + 'static + Send + Sync + Clone,
+        V: Future<Output = ()> + 'static + Send,
+    {
+        match self {
+            AnyApp::Plain(app) => {
+                app.ws_async_upgrade(
+                    pattern,
+                    route_settings,
+                    move |ws| connection_handler(AnyWebsocket::Plain(ws)),
+                    move |req, res| upgrade_handler(req, AnyHttpConnection::Plain(res)),
+                )?;
+            }
+            AnyApp::Ssl(app) => {
+                app.ws_async_upgrade(
+                    pattern,
+                    route_settings,
+                    move |ws| connection_handler(AnyWebsocket::Ssl(ws)),
+                    move |req, res| upgrade_handler(req, AnyHttpConnection::Ssl(res)),
+                )?;
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn ws_actor<A: WsActor>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+    ) -> Result<&mut Self, String> {
+        dispatch!(self, AnyApp, app => { app.ws_actor::<A>(pattern, route_settings)?; });
+        Ok(self)
+    }
+
+    pub fn cached_route<T, W>(&mut self, path: &str, ttl: Duration, generator: T) -> &mut Self
+    where
+        T: (Fn() -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        dispatch!(self, AnyApp, app => { app.cached_route(path, ttl, generator); });
+        self
+    }
+
+    pub fn coalesced_route<T, W>(
+        &mut self,
+        pattern: &str,
+        coalescer: Coalescer,
+        handler: T,
+    ) -> &mut Self
+    where
+        T: (Fn(HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        dispatch!(self, AnyApp, app => { app.coalesced_route(pattern, coalescer, handler); });
+        self
+    }
+
+    pub fn invalidate(&self, path: &str) {
+        dispatch!(self, AnyApp, app => app.invalidate(path))
+    }
+
+    pub fn response_cache(&self) -> ResponseCache {
+        dispatch!(self, AnyApp, app => app.response_cache())
+    }
+
+    pub fn serve_dir(&mut self, mount: &str, serve_dir: impl Into<ServeDir>) -> &mut Self {
+        let serve_dir = serve_dir.into();
+        dispatch!(self, AnyApp, app => { app.serve_dir(mount, serve_dir); });
+        self
+    }
+
+    pub fn serve_embedded(&mut self, mount: &str, assets: EmbeddedAssets) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.serve_embedded(mount, assets); });
+        self
+    }
+
+    pub fn listen(
+        &mut self,
+        port: u16,
+        handler: Option<impl FnOnce(ListenSocket) + Unpin + 'static>,
+    ) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.listen(port, handler); });
+        self
+    }
+
+    pub fn listen_sticky(
+        &mut self,
+        port: u16,
+        worker: usize,
+        workers: usize,
+    ) -> Result<&mut Self, String> {
+        dispatch!(self, AnyApp, app => { app.listen_sticky(port, worker, workers)?; });
+        Ok(self)
+    }
+
+    pub fn listen_configured(&mut self) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.listen_configured(); });
+        self
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        dispatch!(self, AnyApp, app => app.cancellation_token())
+    }
+
+    pub fn publish(&self, topic: &str, message: &[u8], opcode: Opcode, compress: bool) -> bool {
+        dispatch!(self, AnyApp, app => app.publish(topic, message, opcode, compress))
+    }
+
+    pub fn num_subscribers(&self, topic: &str) -> u32 {
+        dispatch!(self, AnyApp, app => app.num_subscribers(topic))
+    }
+
+    pub fn handle(&self) -> AppHandle {
+        dispatch!(self, AnyApp, app => app.handle())
+    }
+
+    pub fn backplane(&mut self, backplane: impl Backplane + 'static) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.backplane(backplane); });
+        self
+    }
+
+    pub fn broadcast(&self, bridge: impl BroadcastBridge + 'static) -> Broadcast {
+        dispatch!(self, AnyApp, app => app.broadcast(bridge))
+    }
+
+    pub fn presence(&self, bridge: impl BroadcastBridge + 'static) -> Presence {
+        dispatch!(self, AnyApp, app => app.presence(bridge))
+    }
+
+    pub fn rooms(&self) -> Rooms {
+        dispatch!(self, AnyApp, app => app.rooms())
+    }
+
+    pub fn connection_registry(&self) -> ConnectionRegistry {
+        dispatch!(self, AnyApp, app => app.connection_registry())
+    }
+
+    pub async fn send_to(
+        &self,
+        id: ConnectionId,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        dispatch!(self, AnyApp, app => app.send_to(id, message).await)
+    }
+
+    pub async fn disconnect(&self, id: ConnectionId, code: i32, reason: Option<&str>) -> bool {
+        dispatch!(self, AnyApp, app => app.disconnect(id, code, reason).await)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        dispatch!(self, AnyApp, app => app.shutdown_handle())
+    }
+
+    pub fn ws_shutdown(&mut self, ws_shutdown: WsShutdown) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.ws_shutdown(ws_shutdown); });
+        self
+    }
+
+    pub fn health(&mut self) -> Health {
+        dispatch!(self, AnyApp, app => app.health())
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        dispatch!(self, AnyApp, app => app.diagnostics())
+    }
+
+    pub fn debug_dump_route(&mut self, pattern: &str) -> &mut Self {
+        dispatch!(self, AnyApp, app => { app.debug_dump_route(pattern); });
+        self
+    }
+
+    pub fn accept_control(&self) -> AcceptControl {
+        dispatch!(self, AnyApp, app => app.accept_control())
+    }
+
+    pub fn run(&mut self) {
+        dispatch!(self, AnyApp, app => app.run())
+    }
+}
+
+impl AnyHttpConnection {
+    pub fn is_ssl(&self) -> bool {
+        matches!(self, AnyHttpConnection::Ssl(_))
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        dispatch!(self, AnyHttpConnection, res => res.is_aborted.load(Ordering::Relaxed))
+    }
+
+    pub async fn get_body(&mut self) -> Option<Vec<u8>> {
+        dispatch!(self, AnyHttpConnection, res => res.get_body().await)
+    }
+
+    pub fn get_body_stream(&mut self) -> Result<Receiver<BodyChunk>, String> {
+        dispatch!(self, AnyHttpConnection, res => res.get_body_stream())
+    }
+
+    pub fn body_stream(&mut self) -> Result<BodyStream, String> {
+        dispatch!(self, AnyHttpConnection, res => res.body_stream())
+    }
+
+    pub async fn get_body_limited(&mut self, limit: usize) -> Result<Vec<u8>, String> {
+        dispatch!(self, AnyHttpConnection, res => res.get_body_limited(limit).await)
+    }
+
+    #[cfg(feature = "json")]
+    pub async fn body_json<T: serde::de::DeserializeOwned>(
+        &mut self,
+        limit: usize,
+    ) -> Result<T, String> {
+        dispatch!(self, AnyHttpConnection, res => res.body_json(limit).await)
+    }
+
+    #[cfg(feature = "serde")]
+    pub async fn body_form<T: serde::de::DeserializeOwned>(
+        &mut self,
+        req: &HttpRequest,
+    ) -> Result<T, String> {
+        dispatch!(self, AnyHttpConnection, res => res.body_form(req).await)
+    }
+
+    pub fn body_limit(&self) -> Option<u64> {
+        dispatch!(self, AnyHttpConnection, res => res.body_limit())
+    }
+
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::Serialize + ?Sized>(self, value: &T) {
+        dispatch!(self, AnyHttpConnection, res => res.json(value).await)
+    }
+
+    pub fn multipart(&mut self, req: &HttpRequest) -> Result<Multipart, String> {
+        dispatch!(self, AnyHttpConnection, res => res.multipart(req))
+    }
+
+    pub fn on_upload_progress<F>(&mut self, hook: F)
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        dispatch!(self, AnyHttpConnection, res => res.on_upload_progress(hook))
+    }
+
+    pub fn on_download_progress<F>(&mut self, hook: F)
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        dispatch!(self, AnyHttpConnection, res => res.on_download_progress(hook))
+    }
+
+    pub fn replace_body(&mut self, body: Vec<u8>) {
+        dispatch!(self, AnyHttpConnection, res => res.replace_body(body))
+    }
+
+    pub fn data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        dispatch!(self, AnyHttpConnection, res => res.data::<T>())
+    }
+
+    pub async fn end_with(self, body: impl Into<Bytes>, close_connection: bool) {
+        dispatch!(self, AnyHttpConnection, res => res.end_with(body, close_connection).await)
+    }
+
+    pub async fn end(self, data: Option<Vec<u8>>, close_connection: bool) {
+        dispatch!(self, AnyHttpConnection, res => res.end(data, close_connection).await)
+    }
+
+    pub async fn send(self, response: impl IntoResponse) {
+        dispatch!(self, AnyHttpConnection, res => res.send(response).await)
+    }
+
+    pub async fn send_error(self, error: HttpError) {
+        dispatch!(self, AnyHttpConnection, res => res.send_error(error).await)
+    }
+
+    pub fn write_status(&mut self, status: String) {
+        dispatch!(self, AnyHttpConnection, res => res.write_status(status))
+    }
+
+    pub fn write_header(&mut self, key: String, value: String) {
+        dispatch!(self, AnyHttpConnection, res => res.write_header(key, value))
+    }
+
+    pub fn write_cache_control(&mut self, cache_control: CacheControl) {
+        dispatch!(self, AnyHttpConnection, res => res.write_cache_control(cache_control))
+    }
+
+    pub fn set_data<T: Send + Sync + Clone + 'static>(&mut self, data: T) {
+        dispatch!(self, AnyHttpConnection, res => res.set_data(data))
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        dispatch!(self, AnyHttpConnection, res => res.session())
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn claims(&self) -> Option<&JwtClaims> {
+        dispatch!(self, AnyHttpConnection, res => res.claims())
+    }
+
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        dispatch!(self, AnyHttpConnection, res => res.set_cookie(cookie))
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    pub fn set_signed_cookie(&mut self, cookie: Cookie) -> Result<(), String> {
+        dispatch!(self, AnyHttpConnection, res => res.set_signed_cookie(cookie))
+    }
+
+    #[cfg(feature = "private-cookies")]
+    pub fn set_private_cookie(&mut self, cookie: Cookie) -> Result<(), String> {
+        dispatch!(self, AnyHttpConnection, res => res.set_private_cookie(cookie))
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    pub fn signed_cookie(&self, req: &HttpRequest, name: &str) -> Option<String> {
+        dispatch!(self, AnyHttpConnection, res => res.signed_cookie(req, name))
+    }
+
+    #[cfg(feature = "private-cookies")]
+    pub fn private_cookie(&self, req: &HttpRequest, name: &str) -> Option<String> {
+        dispatch!(self, AnyHttpConnection, res => res.private_cookie(req, name))
+    }
+
+    pub fn set_default_cache_control(&mut self, cache_control: CacheControl) {
+        dispatch!(self, AnyHttpConnection, res => res.set_default_cache_control(cache_control))
+    }
+
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        dispatch!(self, AnyHttpConnection, res => res.alpn_protocol())
+    }
+
+    pub fn remote_address(&self) -> Option<&str> {
+        dispatch!(self, AnyHttpConnection, res => res.remote_address())
+    }
+
+    pub fn has_responded(&self) -> bool {
+        dispatch!(self, AnyHttpConnection, res => res.has_responded())
+    }
+
+    pub async fn send_headers(&mut self) -> Result<(), String> {
+        dispatch!(self, AnyHttpConnection, res => res.send_headers().await)
+    }
+
+    pub async fn write(&mut self, chunk: impl Into<Bytes>) -> Result<(), String> {
+        dispatch!(self, AnyHttpConnection, res => res.write(chunk).await)
+    }
+
+    pub async fn write_chunks<T>(self, chunks: Receiver<T>) -> Result<(), String>
+    where
+        T: Into<Bytes>,
+    {
+        dispatch!(self, AnyHttpConnection, res => res.write_chunks(chunks).await)
+    }
+
+    pub async fn try_end(
+        &mut self,
+        chunk: impl Into<Bytes>,
+        total_size: u64,
+    ) -> Result<bool, String> {
+        dispatch!(self, AnyHttpConnection, res => res.try_end(chunk, total_size).await)
+    }
+
+    pub async fn send_file(self, path: impl AsRef<Path>) -> Result<(), String> {
+        dispatch!(self, AnyHttpConnection, res => res.send_file(path).await)
+    }
+
+    pub async fn serve_file(self, req: &HttpRequest, path: impl AsRef<Path>) {
+        dispatch!(self, AnyHttpConnection, res => res.serve_file(req, path).await)
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        dispatch!(self, AnyHttpConnection, res => res.cancellation_token())
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        dispatch!(self, AnyHttpConnection, res => res.deadline())
+    }
+
+    pub async fn until_deadline<F: Future>(&self, future: F) -> Result<F::Output, HttpError> {
+        dispatch!(self, AnyHttpConnection, res => res.until_deadline(future).await)
+    }
+
+    pub fn first_byte_deadline(&self) -> Option<Instant> {
+        dispatch!(self, AnyHttpConnection, res => res.first_byte_deadline())
+    }
+
+    pub fn response_state(&self) -> ResponseState {
+        dispatch!(self, AnyHttpConnection, res => res.response_state())
+    }
+
+    pub fn state_handle(&self) -> ResponseStateHandle {
+        dispatch!(self, AnyHttpConnection, res => res.state_handle())
+    }
+
+    pub fn upgrade(
+        self,
+        ws_key_string: String,
+        ws_protocol: Option<String>,
+        ws_extensions: Option<String>,
+        user_data: Option<SharedDataStorage>,
+    ) {
+        dispatch!(self, AnyHttpConnection, res => {
+            res.upgrade(ws_key_string, ws_protocol, ws_extensions, user_data)
+        })
+    }
+
+    pub fn reject_upgrade(
+        self,
+        status: &str,
+        headers: Vec<(String, String)>,
+        body: impl Into<Bytes>,
+    ) {
+        dispatch!(self, AnyHttpConnection, res => res.reject_upgrade(status, headers, body))
+    }
+
+    pub fn default_upgrade(req: HttpRequest, res: AnyHttpConnection) {
+        match res {
+            AnyHttpConnection::Plain(res) => HttpConnection::default_upgrade(req, res),
+            AnyHttpConnection::Ssl(res) => HttpConnection::default_upgrade(req, res),
+        }
+    }
+}
+
+impl AnyNext {
+    // `res` is the connection the middleware got, so it's always of the same kind
+    pub fn run(self, res: AnyHttpConnection, req: HttpRequest) -> BoxedHandlerFuture {
+        match (self, res) {
+            (AnyNext::Plain(next), AnyHttpConnection::Plain(res)) => next.run(res, req),
+            (AnyNext::Ssl(next), AnyHttpConnection::Ssl(res)) => next.run(res, req),
+            _ => unreachable!("[async_uws] Middleware passed on a connection of the other app"),
+        }
+    }
+}
+
+impl AnyWebsocket {
+    pub fn is_ssl(&self) -> bool {
+        matches!(self, AnyWebsocket::Ssl(_))
+    }
+
+    // Same as Websocket::stream
+    pub fn stream(&mut self) -> &mut UnboundedReceiver<WsMessage> {
+        dispatch!(self, AnyWebsocket, ws => &mut ws.stream)
+    }
+
+    pub fn split(self) -> (WsSink, WsStream) {
+        dispatch!(self, AnyWebsocket, ws => ws.split())
+    }
+
+    pub fn data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        dispatch!(self, AnyWebsocket, ws => ws.data::<T>())
+    }
+
+    pub fn connection_data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        dispatch!(self, AnyWebsocket, ws => ws.connection_data::<T>())
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        dispatch!(self, AnyWebsocket, ws => ws.cancellation_token())
+    }
+
+    pub fn is_open(&self) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.is_open())
+    }
+
+    pub fn id(&self) -> ConnectionId {
+        dispatch!(self, AnyWebsocket, ws => ws.id())
+    }
+
+    pub async fn end(&self, code: i32, reason: Option<&str>) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.end(code, reason).await)
+    }
+
+    pub async fn terminate(&self) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.terminate().await)
+    }
+
+    pub async fn closed(&self) {
+        dispatch!(self, AnyWebsocket, ws => ws.closed().await)
+    }
+
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        dispatch!(self, AnyWebsocket, ws => ws.remote_address())
+    }
+
+    pub fn last_seen(&self) -> Instant {
+        dispatch!(self, AnyWebsocket, ws => ws.last_seen())
+    }
+
+    pub fn stats(&self) -> WsStats {
+        dispatch!(self, AnyWebsocket, ws => ws.stats())
+    }
+
+    pub async fn buffered_amount(&self) -> u32 {
+        dispatch!(self, AnyWebsocket, ws => ws.buffered_amount().await)
+    }
+
+    pub async fn subscribe(&self, topic: &str) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.subscribe(topic).await)
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.unsubscribe(topic).await)
+    }
+
+    pub async fn is_subscribed(&self, topic: &str) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.is_subscribed(topic).await)
+    }
+
+    pub async fn publish(
+        &self,
+        topic: &str,
+        message: impl Into<Bytes>,
+        opcode: Opcode,
+        compress: bool,
+    ) -> bool {
+        dispatch!(self, AnyWebsocket, ws => ws.publish(topic, message, opcode, compress).await)
+    }
+
+    pub async fn flush(&self, max_buffered: u32) {
+        dispatch!(self, AnyWebsocket, ws => ws.flush(max_buffered).await)
+    }
+
+    pub async fn send_file(&mut self, transfer: &FileTransfer) -> Result<u64, String> {
+        dispatch!(self, AnyWebsocket, ws => transfer.send(ws).await)
+    }
+
+    pub async fn send(&mut self, message: WsMessage) -> Result<SendStatus, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.send(message).await)
+    }
+
+    pub async fn ping(&mut self, payload: impl Into<Bytes>) -> Result<SendStatus, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.ping(payload).await)
+    }
+
+    pub async fn pong(&mut self, payload: impl Into<Bytes>) -> Result<SendStatus, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.pong(payload).await)
+    }
+
+    pub async fn ping_rtt(&mut self, timeout: Duration) -> Result<Duration, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.ping_rtt(timeout).await)
+    }
+
+    pub async fn send_with_backpressure(
+        &mut self,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.send_with_backpressure(message).await)
+    }
+
+    pub async fn send_with_options(
+        &mut self,
+        message: WsMessage,
+        compress: bool,
+        fin: bool,
+    ) -> Result<SendStatus, String> {
+        dispatch!(self, AnyWebsocket, ws => ws.send_with_options(message, compress, fin).await)
+    }
+}
@@ -1,8 +1,23 @@
+use std::collections::HashMap;
 use std::future::Future;
-use std::sync::Arc;
+use std::io;
+#[cfg(feature = "rustls")]
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::process::Child;
+use std::sync::{Arc, Mutex, OnceLock};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
+use libuwebsockets_sys::{us_socket_t, uws_get_native_handle, uws_res_get_native_handle};
+use log::error;
 use tokio::sync::oneshot::Receiver;
+use tokio::sync::watch;
+use tokio::time::{sleep_until, timeout_at};
 use uwebsockets_rs::app::Application as NativeApp;
 use uwebsockets_rs::app_close::app_close;
 use uwebsockets_rs::http_request::HttpRequest as SyncHttpRequest;
@@ -10,14 +25,77 @@ use uwebsockets_rs::http_response::HttpResponseStruct;
 use uwebsockets_rs::listen_socket::ListenSocket;
 use uwebsockets_rs::us_socket_context_options::UsSocketContextOptions;
 use uwebsockets_rs::uws_loop::{get_loop, UwsLoop};
+use uwebsockets_rs::websocket::Opcode;
 
+use crate::accept_control::{AcceptControl, Listeners};
+use crate::app_config::AppConfig;
+use crate::app_handle::{self, AppHandle};
+use crate::backplane::{Backplane, BackplaneLink};
 use crate::body_reader::BodyReader;
+use crate::broadcast::{Broadcast, BroadcastBridge};
+use crate::cancellation::CancellationToken;
+use crate::coalesce::Coalescer;
+use crate::compression::{Compression, Decompression};
+use crate::connection_registry::{ConnectionId, ConnectionRegistry};
+#[cfg(feature = "signed-cookies")]
+use crate::cookie::CookieKey;
+use crate::cors::Cors;
 use crate::data_storage::{DataStorage, SharedDataStorage};
+use crate::diagnostics::Diagnostics;
+use crate::embedded_assets::EmbeddedAssets;
+use crate::error::{ErrorHandler, HttpError};
+use crate::guard::Guard;
+use crate::health::Health;
 use crate::http_request::HttpRequest;
-use crate::http_connection::HttpConnection;
-use crate::send_ptr::SendPtr;
-use crate::websocket::Websocket;
-use crate::ws_behavior::{WebsocketBehavior, WsPerSocketUserDataStorage, WsRouteSettings};
+#[cfg(feature = "jwt")]
+use crate::jwt::JwtAuth;
+use crate::live_settings::{LiveSettings, SettingsHandle};
+use crate::loop_bound::mark_loop_thread;
+use crate::loop_defer_future::LoopDeferFuture;
+use crate::middleware::{boxed_middleware, with_middleware, Middleware, Next};
+use crate::http_connection::{
+    FallbackResponse, HttpConnection, ResponseState, ResponseStateHandle,
+};
+use crate::presence::Presence;
+use crate::progress::content_length;
+use crate::rate_limit::{RateLimitDecision, RateLimiter};
+use crate::relay::{bind_tcp, listen_unix, relay_listener, unix_socket_path};
+#[cfg(feature = "rustls")]
+use crate::relay::{AlpnStreamHandler, AlpnStreamHandlers};
+use crate::relay::{RelayMode, RelayTarget};
+use crate::remote_address;
+use crate::response::{Response, StatusCode};
+use crate::response_cache::{CachedResponse, ResponseCache};
+use crate::restart::spawn_replacement;
+use crate::rooms::Rooms;
+use crate::session::Sessions;
+use crate::route_pattern::{
+    match_params, native_pattern, specificity, RoutePattern, RouteTable, Unmatched,
+};
+use crate::router::{Method, RouteHandler, RouterStruct, ScopedRoute};
+use crate::shutdown::{
+    is_idle, wait_until, ws_handlers_done, GracefulShutdown, ShutdownHandle, WsShutdown,
+};
+use crate::socket_activation::listen_fds;
+use crate::static_files::ServeDir;
+use crate::sticky::HashRing;
+use crate::task;
+use crate::tcp_options::TcpOptions;
+use crate::tls_options::{negotiated_alpn, set_alpn_protocols, TlsSessionOptions};
+use crate::trusted_proxies::TrustedProxies;
+use crate::validate::Validation;
+use crate::websocket::{SendStatus, Websocket};
+use crate::ws_actor::{run_actor, WsActor};
+use crate::ws_behavior::{
+    end_handler_streams, terminate_websockets, IntervalTimer, WebsocketBehavior,
+    WsPerSocketUserDataStorage, WsRouteSettings,
+};
+use crate::ws_message::WsMessage;
+
+#[cfg(feature = "rustls")]
+pub use crate::relay::RustlsStream;
+
+pub type BoxedHandlerFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
 
 pub type App = AppStruct<false>;
 pub type AppSSL = AppStruct<true>;
@@ -29,6 +107,54 @@ pub struct AppStruct<const SSL: bool> {
     native_app: NativeApp<SSL>,
     ws_per_connection_user_data_storage: WsPerSocketUserDataStorage,
     shutdown_stream: Option<Receiver<()>>,
+    // Fires when shutdown starts, `cancellation` only once the grace period is over
+    shutdown_requested: CancellationToken,
+    cancellation: CancellationToken,
+    is_watching_shutdown: bool,
+    tcp_options: Option<TcpOptions>,
+    // Flips to true once the app is closed, stops relayed listeners
+    relay_shutdown: watch::Sender<bool>,
+    listeners: Arc<Mutex<Listeners>>,
+    accept_paused: watch::Sender<bool>,
+    // Duplicates of activated listeners, passed on to the replacement process
+    inherited_listeners: Vec<std::net::TcpListener>,
+    drain_timeout: Option<Duration>,
+    ws_shutdown: Option<WsShutdown>,
+    graceful_shutdown: GracefulShutdown,
+    // Rate limit timers of the ws routes, closed along with the app
+    ws_timers: Arc<Mutex<Vec<IntervalTimer>>>,
+    #[cfg(feature = "rustls")]
+    alpn_stream_handlers: AlpnStreamHandlers,
+    // Filled by from_config()
+    configured_ports: Vec<u16>,
+    default_ws_settings: WsRouteSettings,
+    fallback_response: Option<Arc<FallbackResponse>>,
+    panic_response: Arc<FallbackResponse>,
+    error_handler: Option<ErrorHandler>,
+    // Patterns and methods of the routes, see add_fallback_route()
+    routes: RouteTable,
+    // GET routes by pattern and their guards, HEAD requests are answered by them
+    head_routes: Vec<(String, Vec<Guard>, RouteHandler<SSL>)>,
+    route_guards: HashMap<String, Vec<Guard>>,
+    // Guards of the router scope() is adding routes of
+    scope_guards: Vec<Guard>,
+    fallback: Option<RouteHandler<SSL>>,
+    fallback_route_added: bool,
+    request_deadline: Option<Duration>,
+    route_deadlines: HashMap<String, Duration>,
+    route_validations: HashMap<String, Validation>,
+    first_byte_timeout: Option<Duration>,
+    route_first_byte_timeouts: HashMap<String, Duration>,
+    middleware: Vec<Middleware<SSL>>,
+    route_middleware: HashMap<String, Vec<Middleware<SSL>>>,
+    settings: SettingsHandle,
+    health: Health,
+    health_routes: bool,
+    response_cache: ResponseCache,
+    // Set by connection_registry(), read by the ws routes when a socket opens or closes
+    connections: Arc<OnceLock<ConnectionRegistry>>,
+    // Set by backplane(), publishes and ws subscriptions are handed to it
+    backplane: Arc<OnceLock<BackplaneLink>>,
 }
 
 impl<const SSL: bool> AppStruct<SSL> {
@@ -37,6 +163,7 @@ impl<const SSL: bool> AppStruct<SSL> {
         shutdown_stream: Option<Receiver<()>>,
     ) -> Self {
         let uws_loop = get_loop();
+        mark_loop_thread();
         let native_app = NativeApp::<SSL>::new(sockets_config);
         AppStruct {
             data_storage: Some(Default::default()),
@@ -45,9 +172,71 @@ impl<const SSL: bool> AppStruct<SSL> {
             native_app,
             ws_per_connection_user_data_storage: Default::default(),
             shutdown_stream,
+            shutdown_requested: Default::default(),
+            cancellation: Default::default(),
+            is_watching_shutdown: false,
+            tcp_options: None,
+            relay_shutdown: watch::channel(false).0,
+            listeners: Default::default(),
+            accept_paused: watch::channel(false).0,
+            inherited_listeners: Vec::new(),
+            drain_timeout: None,
+            ws_shutdown: None,
+            graceful_shutdown: Default::default(),
+            ws_timers: Default::default(),
+            #[cfg(feature = "rustls")]
+            alpn_stream_handlers: Default::default(),
+            configured_ports: Vec::new(),
+            default_ws_settings: Default::default(),
+            fallback_response: Some(Default::default()),
+            panic_response: Default::default(),
+            error_handler: None,
+            routes: Default::default(),
+            head_routes: Vec::new(),
+            route_guards: HashMap::new(),
+            scope_guards: Vec::new(),
+            fallback: None,
+            fallback_route_added: false,
+            request_deadline: None,
+            route_deadlines: HashMap::new(),
+            route_validations: HashMap::new(),
+            first_byte_timeout: None,
+            route_first_byte_timeouts: HashMap::new(),
+            middleware: Vec::new(),
+            route_middleware: HashMap::new(),
+            settings: SettingsHandle::new(uws_loop),
+            health: Health::new(),
+            health_routes: false,
+            response_cache: Default::default(),
+            connections: Default::default(),
+            backplane: Default::default(),
         }
     }
 
+    pub fn from_config(
+        config: AppConfig,
+        shutdown_stream: Option<Receiver<()>>,
+    ) -> Result<Self, String> {
+        let has_certificate = config
+            .tls
+            .as_ref()
+            .is_some_and(|tls| tls.key_file.is_some() && tls.cert_file.is_some());
+        if SSL && !has_certificate {
+            return Err("SSL app requires tls.key_file and tls.cert_file".to_string());
+        }
+
+        let mut app = Self::new(config.socket_context_options()?, shutdown_stream);
+        if let Some(tcp_options) = config.tcp_options() {
+            app.tcp_options(tcp_options);
+        }
+        if let Some(timeout) = config.drain_timeout {
+            app.drain_on_shutdown(Duration::from_secs(timeout));
+        }
+        app.configured_ports = config.listen.clone();
+        app.default_ws_settings = config.ws_route_settings();
+        Ok(app)
+    }
+
     pub fn data<T>(&mut self, data: T) -> &mut Self
     where
         T: Sync + Send + Clone + 'static,
@@ -59,6 +248,12 @@ impl<const SSL: bool> AppStruct<SSL> {
         self
     }
 
+    // Key of signed and private cookies, see CookieKey. Should be called before adding routes
+    #[cfg(feature = "signed-cookies")]
+    pub fn cookie_key(&mut self, key: CookieKey) -> &mut Self {
+        self.data(key)
+    }
+
     fn get_shared_data_storage(&mut self) -> SharedDataStorage {
         if let Some(shared_storage) = self.global_data_storage.as_ref() {
             return shared_storage.clone();
@@ -70,38 +265,157 @@ impl<const SSL: bool> AppStruct<SSL> {
         self.global_data_storage.as_ref().unwrap().clone()
     }
 
+    // Err without adding the route if `route_settings` don't pass WsRouteSettings::validate()
     pub fn ws<T, W, U>(
         &mut self,
         pattern: &str,
         route_settings: WsRouteSettings,
         connection_handler: T,
         upgrade_hook: U,
-    ) -> &mut Self
+    ) -> Result<&mut Self, String>
     where
         T: (Fn(Websocket<SSL>) -> W) + 'static + Send + Sync + Clone,
         W: Future<Output = ()> + 'static + Send,
         U: Fn(HttpRequest, HttpConnection<SSL>) + 'static + Send + Sync + Clone,
     {
-        let ws_behavior = WebsocketBehavior::new(
+        route_settings
+            .validate()
+            .map_err(|e| format!("Invalid ws route settings for {pattern}: {e}"))?;
+        let route = pattern.to_string();
+        let route_pattern = RoutePattern::parse(pattern);
+        let fallback = self.fallback_response.clone();
+        let panic_response = self.panic_response.clone();
+        let error_handler = self.error_handler.clone();
+        let cancellation = self.cancellation.clone();
+        let mut ws_behavior = WebsocketBehavior::new_named(
+            format!("async_uws ws {pattern}"),
             route_settings,
             self.uws_loop,
             self.ws_per_connection_user_data_storage.clone(),
             connection_handler,
-            upgrade_hook,
+            move |mut req: HttpRequest, mut res: HttpConnection<SSL>| {
+                req.route_params = route_pattern.params(&req);
+                if let Some(fallback) = fallback.as_ref() {
+                    res.set_fallback(route.clone(), fallback.clone());
+                }
+                res.set_panic_response(panic_response.clone());
+                if let Some(error_handler) = error_handler.as_ref() {
+                    res.set_error_handler(error_handler.clone());
+                }
+                res.set_cancellation(cancellation.child_token());
+                // Runs on the loop thread, a panic must not unwind into uWS
+                let upgrade = catch_unwind(AssertUnwindSafe(|| upgrade_hook(req, res)));
+                if let Err(payload) = upgrade {
+                    error!(
+                        "[async_uws] Upgrade hook for {route} panicked: {}",
+                        task::panic_message(&payload)
+                    );
+                }
+            },
             self.get_shared_data_storage(),
+            Some(self.settings.clone()),
+            self.connections.clone(),
+            self.backplane.clone(),
         );
-        self.native_app.ws(pattern, ws_behavior.native_ws_behaviour);
-        self
+        self.ws_timers
+            .lock()
+            .unwrap()
+            .append(&mut ws_behavior.timers);
+        self.native_app
+            .ws(&native_pattern(pattern), ws_behavior.native_ws_behaviour);
+        self.routes.add("GET", pattern);
+        Ok(self)
+    }
+
+    /***
+     * Same as ws() with an async upgrade handler, which can await a token check or a lookup
+     * before it accepts with res.upgrade() / HttpConnection::default_upgrade() or rejects with
+     * res.end(). The request stays open while it runs, an aborted one is not upgraded
+     ***/
+    pub fn ws_async_upgrade<T, W, U, V>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+        connection_handler: T,
+        upgrade_handler: U,
+    ) -> Result<&mut Self, String>
+    where
+        T: (Fn(Websocket<SSL>) -> W) + 'static + Send + Sync + Clone,
+        W: Future<Output = ()> + 'static + Send,
+        U: (Fn(HttpRequest, HttpConnection<SSL>) -> V) + 'static + Send + Sync + Clone,
+        V: Future<Output = ()> + 'static + Send,
+    {
+        let task_name = format!("async_uws ws upgrade {pattern}");
+        self.ws(
+            pattern,
+            route_settings,
+            connection_handler,
+            move |req, res: HttpConnection<SSL>| {
+                let panicked = res.panic_flag();
+                let upgrade_handler = upgrade_handler.clone();
+                let upgrade = task::catch_panic(
+                    task_name.clone(),
+                    move || upgrade_handler(req, res),
+                    panicked,
+                );
+                task::spawn(&task_name, upgrade);
+            },
+        )
+    }
+
+    // ws() running a new `A` for every connection, with drain events on for WsActor::on_drain()
+    pub fn ws_actor<A: WsActor>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+    ) -> Result<&mut Self, String> {
+        self.ws(
+            pattern,
+            route_settings.drain_events(true),
+            run_actor::<A, SSL>,
+            HttpConnection::default_upgrade,
+        )
+    }
+
+    // Same as ws() with the route settings from AppConfig
+    pub fn ws_default<T, W, U>(
+        &mut self,
+        pattern: &str,
+        connection_handler: T,
+        upgrade_hook: U,
+    ) -> Result<&mut Self, String>
+    where
+        T: (Fn(Websocket<SSL>) -> W) + 'static + Send + Sync + Clone,
+        W: Future<Output = ()> + 'static + Send,
+        U: Fn(HttpRequest, HttpConnection<SSL>) + 'static + Send + Sync + Clone,
+    {
+        let route_settings = self.default_ws_settings.clone();
+        self.ws(pattern, route_settings, connection_handler, upgrade_hook)
     }
 
+    // Also answers HEAD for `pattern` without the body, see add_fallback_route()
     pub fn get<T, W>(&mut self, pattern: &str, handler: T) -> &mut Self
     where
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.get(pattern, internal_handler);
+        let route: RouteHandler<SSL> = Arc::new(self.route_handler(pattern, handler));
+        let guards = self.guards_for(pattern);
+        self.head_routes
+            .push((pattern.to_string(), guards, route.clone()));
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http GET {pattern}"),
+                move |res, req| route(res, req),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .get(&native_pattern(pattern), internal_handler);
+        self.routes.add("GET", pattern);
+        self.routes.add("HEAD", pattern);
         self
     }
 
@@ -110,9 +424,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.post(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http POST {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .post(&native_pattern(pattern), internal_handler);
+        self.routes.add("POST", pattern);
         self
     }
 
@@ -121,9 +444,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.patch(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http PATCH {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .patch(&native_pattern(pattern), internal_handler);
+        self.routes.add("PATCH", pattern);
         self
     }
 
@@ -132,9 +464,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.delete(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http DELETE {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .delete(&native_pattern(pattern), internal_handler);
+        self.routes.add("DELETE", pattern);
         self
     }
 
@@ -143,9 +484,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.options(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http OPTIONS {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .options(&native_pattern(pattern), internal_handler);
+        self.routes.add("OPTIONS", pattern);
         self
     }
 
@@ -154,9 +504,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.put(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http PUT {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .put(&native_pattern(pattern), internal_handler);
+        self.routes.add("PUT", pattern);
         self
     }
 
@@ -165,9 +524,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.trace(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http TRACE {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .trace(&native_pattern(pattern), internal_handler);
+        self.routes.add("TRACE", pattern);
         self
     }
 
@@ -176,9 +544,18 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.connect(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http CONNECT {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .connect(&native_pattern(pattern), internal_handler);
+        self.routes.add("CONNECT", pattern);
         self
     }
 
@@ -187,34 +564,1111 @@ impl<const SSL: bool> AppStruct<SSL> {
         T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
         W: Future<Output = ()> + 'static + Send,
     {
-        let internal_handler =
-            wrap_http_handler(handler, self.uws_loop, self.get_shared_data_storage());
-        self.native_app.any(pattern, internal_handler);
+        let internal_handler = self.guarded(
+            pattern,
+            wrap_named_http_handler(
+                format!("async_uws http ANY {pattern}"),
+                self.route_handler(pattern, handler),
+                self.uws_loop,
+                self.get_shared_data_storage(),
+            ),
+        );
+        self.native_app
+            .any(&native_pattern(pattern), internal_handler);
+        self.routes.add("ANY", pattern);
+        self
+    }
+
+    // Handles requests no route matches instead of the 404 sent otherwise, see add_fallback_route()
+    pub fn fallback<T, W>(&mut self, handler: T) -> &mut Self
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        self.fallback = Some(Arc::new(move |res, req| Box::pin(handler(res, req))));
+        self
+    }
+
+    // Registers the routes of `router` under `prefix`, see RouterStruct
+    pub fn scope(&mut self, prefix: &str, router: RouterStruct<SSL>) -> &mut Self {
+        for route in router.into_routes(prefix) {
+            let ScopedRoute {
+                method,
+                pattern,
+                handler,
+                data,
+                guards,
+            } = route;
+            let data = Arc::new(data);
+            let handler = move |mut res: HttpConnection<SSL>, req| {
+                res.set_scope_data(data.clone());
+                handler(res, req)
+            };
+            self.scope_guards = guards;
+            match method {
+                Method::Get => self.get(&pattern, handler),
+                Method::Post => self.post(&pattern, handler),
+                Method::Patch => self.patch(&pattern, handler),
+                Method::Delete => self.delete(&pattern, handler),
+                Method::Options => self.options(&pattern, handler),
+                Method::Put => self.put(&pattern, handler),
+                Method::Trace => self.trace(&pattern, handler),
+                Method::Connect => self.connect(&pattern, handler),
+                Method::Any => self.any(&pattern, handler),
+            };
+            self.scope_guards = Vec::new();
+        }
+        self
+    }
+
+    // Routes of `pattern` added afterwards only take requests `guard` passes, see Guard.
+    // Guards of the same pattern add up
+    pub fn route_guard(&mut self, pattern: &str, guard: Guard) -> &mut Self {
+        self.route_guards
+            .entry(pattern.to_string())
+            .or_default()
+            .push(guard);
+        self
+    }
+
+    fn guards_for(&self, pattern: &str) -> Vec<Guard> {
+        let route_guards = self.route_guards.get(pattern).into_iter().flatten();
+        self.scope_guards
+            .iter()
+            .chain(route_guards)
+            .cloned()
+            .collect()
+    }
+
+    // Yields the request to the next route matching its url unless the guards pass
+    fn guarded(
+        &self,
+        pattern: &str,
+        handler: Box<dyn Fn(HttpResponseStruct<SSL>, SyncHttpRequest)>,
+    ) -> Box<dyn Fn(HttpResponseStruct<SSL>, SyncHttpRequest)> {
+        let guards = self.guards_for(pattern);
+        if guards.is_empty() {
+            return handler;
+        }
+        Box::new(move |res, mut req| {
+            let request = HttpRequest::from(&mut req);
+            if guards.iter().all(|guard| guard.check(&request)) {
+                handler(res, req);
+            } else {
+                req.set_yield(true);
+            }
+        })
+    }
+
+    /***
+     * GET `path` served from memory, `generator` runs again once `ttl` passed or after invalidate().
+     * Hits are written from the uWS callback and never reach a handler, so they skip rate_limit(),
+     * the body size cap and deadlines. `path` is matched as a whole, patterns with parameters share one response
+     ***/
+    pub fn cached_route<T, W>(&mut self, path: &str, ttl: Duration, generator: T) -> &mut Self
+    where
+        T: (Fn() -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        let cache = self.response_cache.clone();
+        cache.register(path, ttl, Arc::new(move || Box::pin(generator())));
+
+        let hit_cache = cache.clone();
+        let hit_path = path.to_string();
+        let miss_path = path.to_string();
+        let miss: RouteHandler<SSL> = Arc::new(self.route_handler(path, move |res, _| {
+            let cache = cache.clone();
+            let path = miss_path.clone();
+            async move {
+                if let Some(response) = cache.get_or_generate(&path).await {
+                    response.send(res).await;
+                }
+            }
+        }));
+        self.head_routes
+            .push((path.to_string(), Vec::new(), miss.clone()));
+        let on_miss = wrap_named_http_handler(
+            format!("async_uws http GET {path}"),
+            move |res, req| miss(res, req),
+            self.uws_loop,
+            self.get_shared_data_storage(),
+        );
+        let settings = self.settings.clone();
+        self.routes.add("GET", path);
+        self.routes.add("HEAD", path);
+        self.native_app.get(path, move |res, req| {
+            // Clients outside the allow list take the handler path, which rejects them
+            let address = res.get_remote_address_as_text();
+            let hit = settings
+                .current()
+                .is_allowed(Some(address))
+                .then(|| hit_cache.fresh(&hit_path))
+                .flatten();
+            match hit {
+                Some(response) => response.write_to(&res),
+                None => on_miss(res, req),
+            }
+        });
+        self
+    }
+
+    /***
+     * GET `pattern` where concurrent requests with the same Coalescer key share one run of
+     * `handler`, so a stampede of identical requests costs the backend a single call
+     ***/
+    pub fn coalesced_route<T, W>(
+        &mut self,
+        pattern: &str,
+        coalescer: Coalescer,
+        handler: T,
+    ) -> &mut Self
+    where
+        T: (Fn(HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        let handler = Arc::new(handler);
+        self.get(pattern, move |res, req| {
+            let coalescer = coalescer.clone();
+            let handler = handler.clone();
+            async move {
+                let key = coalescer.key_for(&req);
+                let response = coalescer.run(&key, move || handler(req)).await;
+                response.send(res).await;
+            }
+        })
+    }
+
+    // Drops the response of a cached_route(), see response_cache() for use from handlers
+    pub fn invalidate(&self, path: &str) {
+        self.response_cache.invalidate(path);
+    }
+
+    pub fn response_cache(&self) -> ResponseCache {
+        self.response_cache.clone()
+    }
+
+    // `app.serve_dir("/assets", "./public")`, or a configured ServeDir
+    pub fn serve_dir(&mut self, mount: &str, serve_dir: impl Into<ServeDir>) -> &mut Self {
+        let mount = mount.trim_end_matches('/').to_string();
+        let pattern = format!("{mount}/*");
+        let serve_dir = Arc::new(serve_dir.into());
+        self.get(&pattern, move |res, req| {
+            let serve_dir = serve_dir.clone();
+            let mount = mount.clone();
+            async move { serve_dir.serve(res, &req, &mount).await }
+        })
+    }
+
+    // Same as serve_dir() for files compiled into the binary
+    pub fn serve_embedded(&mut self, mount: &str, assets: EmbeddedAssets) -> &mut Self {
+        let mount = mount.trim_end_matches('/').to_string();
+        let pattern = format!("{mount}/*");
+        let assets = Arc::new(assets);
+        self.get(&pattern, move |res, req| {
+            let assets = assets.clone();
+            let mount = mount.clone();
+            async move { assets.serve(res, &req, &mount).await }
+        })
+    }
+
+    // Sent when a handler drops its response without ending it, None leaves such clients hanging.
+    // Should be called before adding routes
+    pub fn fallback_response(&mut self, fallback_response: Option<FallbackResponse>) -> &mut Self {
+        self.fallback_response = fallback_response.map(Arc::new);
+        self
+    }
+
+    // Sent when a handler panics before responding, 500 without a body by default. The panic is
+    // logged and the connection stays usable. Should be called before adding routes
+    pub fn panic_response(&mut self, panic_response: FallbackResponse) -> &mut Self {
+        self.panic_response = Arc::new(panic_response);
+        self
+    }
+
+    /***
+     * Maps every HttpError to the response sent for it: errors returned by try_handler() routes,
+     * send_error() and the app's own 403, 413, 408 and 504. Without one the status is sent with the
+     * message as text, `app.error_handler(problem_json)` sends application/problem+json instead.
+     * Should be called before adding routes
+     ***/
+    pub fn error_handler<F>(&mut self, error_handler: F) -> &mut Self
+    where
+        F: Fn(&HttpError) -> Response + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Arc::new(error_handler));
+        self
+    }
+
+    // Time a handler gets for the whole request, it's cancelled afterwards and 504 is sent (408 if
+    // the client hadn't sent the whole body yet). Should be called before adding routes
+    pub fn request_deadline(&mut self, deadline: Duration) -> &mut Self {
+        self.request_deadline = Some(deadline);
+        self
+    }
+
+    // Overrides request_deadline() for one route pattern, should be called before adding the route
+    pub fn route_deadline(&mut self, pattern: &str, deadline: Duration) -> &mut Self {
+        self.route_deadlines.insert(pattern.to_string(), deadline);
+        self
+    }
+
+    // Whether the Valid extractor runs Validate for one route pattern and the status it answers
+    // with, 422 by default. Should be called before adding the route
+    pub fn route_validation(&mut self, pattern: &str, validation: Validation) -> &mut Self {
+        self.route_validations
+            .insert(pattern.to_string(), validation);
+        self
+    }
+
+    // Runs around the handlers of routes added after it, in the order it was added. See Next
+    pub fn middleware<T, W>(&mut self, middleware: T) -> &mut Self
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        self.middleware.push(boxed_middleware(middleware));
+        self
+    }
+
+    // Runs after middleware() for one route pattern, should be called before adding the route
+    pub fn route_middleware<T, W>(&mut self, pattern: &str, middleware: T) -> &mut Self
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        self.route_middleware
+            .entry(pattern.to_string())
+            .or_default()
+            .push(boxed_middleware(middleware));
+        self
+    }
+
+    // Compresses the responses of routes added after it, see Compression
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.middleware(compression.into_middleware())
+    }
+
+    // Overrides compression() for one route pattern, should be called before adding the route
+    pub fn route_compression(&mut self, pattern: &str, compression: Compression) -> &mut Self {
+        self.route_middleware(pattern, compression.into_middleware())
+    }
+
+    /***
+     * CORS for routes added after it, with preflights answered for every path. A route's own
+     * options() handler still only gets OPTIONS requests that aren't preflights, see Cors
+     ***/
+    pub fn cors(&mut self, cors: Cors) -> &mut Self {
+        self.middleware(cors.into_middleware());
+        self.options("/*", |mut res, _| async move {
+            res.write_status("204 No Content".to_string());
+            res.end(None, false).await;
+        })
+    }
+
+    // Sessions for routes added after it, reachable with res.session(), see Sessions
+    pub fn sessions(&mut self, sessions: Sessions) -> &mut Self {
+        self.middleware(sessions.into_middleware())
+    }
+
+    // Bearer token authentication for routes added after it, see JwtAuth
+    #[cfg(feature = "jwt")]
+    pub fn jwt_auth(&mut self, auth: JwtAuth) -> &mut Self {
+        self.middleware(auth.into_middleware())
+    }
+
+    // jwt_auth() for one route pattern, should be called before adding the route
+    #[cfg(feature = "jwt")]
+    pub fn route_jwt_auth(&mut self, pattern: &str, auth: JwtAuth) -> &mut Self {
+        self.route_middleware(pattern, auth.into_middleware())
+    }
+
+    // Decodes compressed request bodies of routes added after it, see Decompression
+    pub fn decompression(&mut self, decompression: Decompression) -> &mut Self {
+        self.middleware(decompression.into_middleware())
+    }
+
+    // Overrides decompression() for one route pattern, should be called before adding the route
+    pub fn route_decompression(
+        &mut self,
+        pattern: &str,
+        decompression: Decompression,
+    ) -> &mut Self {
+        self.route_middleware(pattern, decompression.into_middleware())
+    }
+
+    // Time a handler gets to send status and headers, it's cancelled (and 504 / 408 sent) if it
+    // didn't. Unlike request_deadline() it doesn't limit streaming once the first byte is out.
+    // Should be called before adding routes
+    pub fn first_byte_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.first_byte_timeout = Some(timeout);
+        self
+    }
+
+    // Overrides first_byte_timeout() for one route pattern, should be called before adding the route
+    pub fn route_first_byte_timeout(&mut self, pattern: &str, timeout: Duration) -> &mut Self {
+        self.route_first_byte_timeouts
+            .insert(pattern.to_string(), timeout);
+        self
+    }
+
+    // Requests above the limit get 429 with "retry-after", counted per client IP across all routes.
+    // Can be changed later with update_settings()
+    pub fn rate_limit(&mut self, limiter: RateLimiter) -> &mut Self {
+        self.settings
+            .update_now(|settings| settings.rate_limit = Some(limiter));
+        self
+    }
+
+    // Overrides rate_limit() for one route pattern, counted per client IP for this route alone
+    pub fn route_rate_limit(&mut self, pattern: &str, limiter: RateLimiter) -> &mut Self {
+        self.settings.update_now(|settings| {
+            settings
+                .route_rate_limits
+                .insert(pattern.to_string(), limiter);
+        });
+        self
+    }
+
+    // HttpRequest::client_ip() reads the forwarding headers of these peers.
+    // Can be changed with update_settings()
+    pub fn trusted_proxies(&mut self, proxies: TrustedProxies) -> &mut Self {
+        self.settings
+            .update_now(|settings| settings.trusted_proxies = Some(proxies));
+        self
+    }
+
+    // Requests with a larger body get 413, right away if content-length declares it, otherwise
+    // once that many bytes came in, the rest is never buffered. Can be changed with update_settings()
+    pub fn max_body_size(&mut self, bytes: u64) -> &mut Self {
+        self.settings
+            .update_now(|settings| settings.max_body_size = Some(bytes));
+        self
+    }
+
+    // Overrides max_body_size() for one route pattern
+    pub fn route_max_body_size(&mut self, pattern: &str, bytes: u64) -> &mut Self {
+        self.settings.update_now(|settings| {
+            settings
+                .route_max_body_sizes
+                .insert(pattern.to_string(), bytes);
+        });
+        self
+    }
+
+    /***
+     * Swaps rate limits, the body size cap, the websocket idle timeout, the allow list and the
+     * trusted proxies on the running app, open connections are kept. Takes effect on the uWS loop
+     * right after the current callback, see settings_handle() to update from another thread
+     ***/
+    pub fn update_settings<F>(&self, update: F) -> &Self
+    where
+        F: FnOnce(&mut LiveSettings) + Send + 'static,
+    {
+        self.settings.update(update);
+        self
+    }
+
+    pub fn settings_handle(&self) -> SettingsHandle {
+        self.settings.clone()
+    }
+
+    fn route_handler<T, W>(
+        &self,
+        pattern: &str,
+        handler: T,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest) -> BoxedHandlerFuture + 'static + Send + Sync
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        let chain = self
+            .middleware
+            .iter()
+            .chain(self.route_middleware.get(pattern).into_iter().flatten())
+            .cloned()
+            .collect();
+        let handler = with_middleware(chain, Arc::new(move |res, req| Box::pin(handler(res, req))));
+        let route = pattern.to_string();
+        let fallback = self.fallback_response.clone();
+        let panic_response = self.panic_response.clone();
+        let cancellation = self.cancellation.clone();
+        let deadline = self
+            .route_deadlines
+            .get(pattern)
+            .copied()
+            .or(self.request_deadline);
+        let first_byte_timeout = self
+            .route_first_byte_timeouts
+            .get(pattern)
+            .copied()
+            .or(self.first_byte_timeout);
+        let validation = self
+            .route_validations
+            .get(pattern)
+            .copied()
+            .unwrap_or_default();
+        let settings = self.settings.clone();
+        let limited_route = route.clone();
+        let route_pattern = RoutePattern::parse(pattern);
+        let error_handler = self.error_handler.clone();
+        let handle = move |mut res: HttpConnection<SSL>, mut req: HttpRequest| {
+            if let Some(fallback) = fallback.as_ref() {
+                res.set_fallback(route.clone(), fallback.clone());
+            }
+            res.set_panic_response(panic_response.clone());
+            res.set_cancellation(cancellation.child_token());
+            res.set_validation(validation);
+            if deadline.is_none() && first_byte_timeout.is_none() {
+                return handler(res, req);
+            }
+
+            let now = Instant::now();
+            let deadline = deadline.map(|deadline| now + deadline);
+            let first_byte_deadline = first_byte_timeout.map(|timeout| now + timeout);
+            if let Some(deadline) = deadline {
+                res.set_deadline(deadline);
+                req.deadline = Some(deadline);
+            }
+            if let Some(first_byte_deadline) = first_byte_deadline {
+                res.set_first_byte_deadline(first_byte_deadline);
+            }
+            let state = res.state_handle();
+            let handler = handler(res, req);
+            // Dropping the handler future drops the response, which sends 504 / 408 if nothing was sent yet
+            Box::pin(async move {
+                let handler = with_first_byte_timeout(handler, state, first_byte_deadline);
+                match deadline {
+                    Some(deadline) => {
+                        let _ = timeout_at(deadline.into(), handler).await;
+                    }
+                    None => handler.await,
+                }
+            })
+        };
+        let handle = Arc::new(handle);
+        move |mut res: HttpConnection<SSL>, mut req: HttpRequest| {
+            req.route_params = route_pattern.params(&req);
+            if let Some(error_handler) = error_handler.as_ref() {
+                res.set_error_handler(error_handler.clone());
+            }
+            let settings = settings.current();
+            req.client_ip = settings.client_ip(&req);
+            if !settings.is_allowed(res.remote_address()) {
+                return Box::pin(res.reject(StatusCode::FORBIDDEN, false));
+            }
+            let max_body_size = settings.max_body_size_for(&limited_route);
+            let content_length = content_length(&req.headers).unwrap_or_default();
+            if max_body_size.is_some_and(|max| content_length > max) {
+                return Box::pin(res.reject(StatusCode::PAYLOAD_TOO_LARGE, true));
+            }
+            res.set_body_limit(max_body_size);
+
+            // Route limits count per route, the app wide one across routes
+            let rate_limit = match settings.route_rate_limits.get(&limited_route) {
+                Some(limiter) => Some((limiter.clone(), format!("{limited_route} "))),
+                None => settings.rate_limit.clone().zip(Some(String::new())),
+            };
+            let Some((limiter, key_prefix)) = rate_limit else {
+                return handle(res, req);
+            };
+            let handle = handle.clone();
+            Box::pin(async move {
+                let client = res.remote_address().unwrap_or("unknown");
+                let decision = limiter.check(&format!("{key_prefix}{client}")).await;
+                if decision.allowed {
+                    return handle(res, req).await;
+                }
+                reject_rate_limited(res, decision).await;
+            })
+        }
+    }
+
+    // Should be called before listen()
+    pub fn tcp_options(&mut self, tcp_options: TcpOptions) -> &mut Self {
+        self.tcp_options = Some(tcp_options);
         self
     }
 
     pub fn run(&mut self) {
+        self.add_fallback_route();
         self.native_app.run();
     }
 
+    /***
+     * Catch-all route added by run(), unless the app has a catch-all `any()` of its own. It takes
+     * what the other routes don't:
+     *  - HEAD of a get() route runs that route with the body left out, content-length is kept
+     *  - OPTIONS of a path with routes gets 204 with their methods in `allow`
+     *  - other methods of such a path get 405 with the same `allow`
+     *  - anything else goes to fallback(), 404 without one, as do requests the guards of the
+     *    method's routes turned down (see route_guard())
+     * 404 and 405 go through error_handler(). Explicit options() and any() routes take precedence
+     ***/
+    pub(crate) fn add_fallback_route(&mut self) {
+        if self.fallback_route_added || self.routes.contains("ANY", "/*") {
+            return;
+        }
+        self.fallback_route_added = true;
+        let routes = self.routes.clone();
+        let fallback = self.fallback.clone();
+        let handler = move |mut res: HttpConnection<SSL>, req: HttpRequest| -> BoxedHandlerFuture {
+            match routes.unmatched(&req.method, &req.url) {
+                Unmatched::Options(allowed) => {
+                    res.write_header("allow".to_string(), allowed.join(", "));
+                    res.write_status("204 No Content".to_string());
+                    Box::pin(res.end(None, false))
+                }
+                Unmatched::MethodNotAllowed(allowed) => {
+                    res.write_header("allow".to_string(), allowed.join(", "));
+                    Box::pin(res.send_error(StatusCode::METHOD_NOT_ALLOWED.into()))
+                }
+                Unmatched::NotFound => match fallback.as_ref() {
+                    Some(fallback) => fallback(res, req),
+                    None => Box::pin(res.send_error(HttpError::not_found())),
+                },
+            }
+        };
+        let unmatched = self.route_handler("/*", handler);
+        let head_routes = self.head_routes.clone();
+        let dispatch = move |mut res: HttpConnection<SSL>, mut req: HttpRequest| {
+            if req.method.eq_ignore_ascii_case("head") {
+                let get_route = head_routes
+                    .iter()
+                    .filter(|(_, guards, _)| guards.iter().all(|guard| guard.check(&req)))
+                    .filter_map(|(pattern, _, route)| {
+                        Some((pattern, route, match_params(pattern, &req.url)?))
+                    })
+                    .max_by_key(|(pattern, _, _)| specificity(pattern));
+                if let Some((_, route, parameters)) = get_route {
+                    req.parameters = parameters;
+                    res.set_head_request();
+                    return route(res, req);
+                }
+            }
+            unmatched(res, req)
+        };
+        let internal_handler = wrap_named_http_handler(
+            "async_uws http fallback".to_string(),
+            dispatch,
+            self.uws_loop,
+            self.get_shared_data_storage(),
+        );
+        self.native_app.any("/*", internal_handler);
+    }
+
     pub fn listen(
         &mut self,
         port: u16,
         handler: Option<impl FnOnce(ListenSocket) + Unpin + 'static>,
     ) -> &mut Self {
-        let shutdown_stream = self.shutdown_stream.take();
+        self.watch_shutdown();
+        let tcp_options = self.tcp_options.clone();
+        let listeners = self.listeners.clone();
+        let handler = move |listen_socket: ListenSocket| {
+            if !listen_socket.get_native().is_null() {
+                listeners
+                    .lock()
+                    .unwrap()
+                    .add(listen_socket.get_native(), SSL);
+            }
+            if let Some(tcp_options) = tcp_options {
+                if let Err(e) = tcp_options.apply(listen_socket) {
+                    error!("[async_uws] Can't apply tcp options on port {port}: {e:#?}");
+                }
+            }
+            if let Some(handler) = handler {
+                handler(listen_socket);
+            }
+        };
+        self.native_app.listen(port as i32, Some(handler));
+        self
+    }
+
+    // Listens on every port from AppConfig::listen
+    pub fn listen_configured(&mut self) -> &mut Self {
+        for port in self.configured_ports.clone() {
+            self.listen(port, None::<fn(ListenSocket)>);
+        }
+        self
+    }
+
+    // Serves the listeners passed by systemd socket activation, see socket_activation::listen_fds()
+    pub fn listen_activated(&mut self) -> Result<&mut Self, String> {
+        let listeners = listen_fds()?;
+        if listeners.is_empty() {
+            return Err("No sockets were passed via LISTEN_FDS".to_string());
+        }
+
+        self.watch_shutdown();
+        for (index, listener) in listeners.into_iter().enumerate() {
+            match listener.try_clone() {
+                Ok(duplicate) => self.inherited_listeners.push(duplicate),
+                Err(e) => error!("[async_uws] Can't keep activated socket for restarts: {e:#?}"),
+            }
+            self.relay(
+                listener,
+                &format!("activated-{index}"),
+                RelayMode::Plain { proxy_header: !SSL },
+            )?;
+        }
+        Ok(self)
+    }
+
+    // Accepts on `listener` outside of uWS and pipes connections to a unix socket the app listens on
+    fn relay(
+        &mut self,
+        listener: std::net::TcpListener,
+        name: &str,
+        mode: RelayMode,
+    ) -> Result<(), String> {
+        let path = unix_socket_path(name)?;
+        listen_unix::<SSL>(self.native_app.get_native_app().get_native(), &path)?;
+        self.relay_to(listener, name, RelayTarget::Single(path), mode);
+        Ok(())
+    }
+
+    fn relay_to(
+        &mut self,
+        listener: std::net::TcpListener,
+        name: &str,
+        target: RelayTarget,
+        mode: RelayMode,
+    ) {
+        if let Some(tcp_options) = self.tcp_options.as_ref() {
+            if let Err(e) = tcp_options.apply_to(&listener) {
+                error!("[async_uws] Can't apply tcp options on relayed socket {name}: {e:#?}");
+            }
+        }
+
+        task::spawn(
+            &format!("async_uws relay listener {name}"),
+            relay_listener(
+                listener,
+                target,
+                mode,
+                self.accept_paused.subscribe(),
+                self.relay_shutdown.subscribe(),
+            ),
+        );
+    }
+
+    /***
+     * Multi-worker mode where a client always lands on the same worker, picked by its IP with
+     * the same HashRing WorkerRelay uses. Every worker (one app per thread) calls this with its
+     * index, worker 0 accepts on `port` and relays each connection to its owner.
+     * Workers should be listening before clients arrive, connections to a missing one are dropped.
+     ***/
+    pub fn listen_sticky(
+        &mut self,
+        port: u16,
+        worker: usize,
+        workers: usize,
+    ) -> Result<&mut Self, String> {
+        if worker >= workers {
+            return Err(format!("Worker {worker} is out of 0..{workers}"));
+        }
+        let paths = (0..workers)
+            .map(|index| unix_socket_path(&format!("sticky-{port}-{index}")))
+            .collect::<Result<Vec<PathBuf>, String>>()?;
+        let app = self.native_app.get_native_app().get_native();
+        listen_unix::<SSL>(app, &paths[worker])?;
+        self.watch_shutdown();
+        if worker == 0 {
+            let listener =
+                bind_tcp(port).map_err(|e| format!("Can't listen on port {port}: {e}"))?;
+            let target = RelayTarget::Sticky(Arc::new(HashRing::new(workers)), Arc::new(paths));
+            self.relay_to(
+                listener,
+                &format!("sticky-{port}"),
+                target,
+                RelayMode::Plain { proxy_header: !SSL },
+            );
+        }
+        Ok(self)
+    }
+
+    // Handle to pause / resume accepting, can be used from handlers or other threads
+    pub fn accept_control(&self) -> AcceptControl {
+        AcceptControl {
+            listeners: self.listeners.clone(),
+            paused: self.accept_paused.clone(),
+            uws_loop: self.uws_loop,
+            ssl: SSL,
+        }
+    }
+
+    // Checks served on `/healthz` and `/readyz`, the routes are added on the first call
+    pub fn health(&mut self) -> Health {
+        if !self.health_routes {
+            self.health_routes = true;
+            let health = self.health.clone();
+            self.get("/healthz", move |res, _| {
+                let health = health.clone();
+                async move { health.respond(res, false).await }
+            });
+            let health = self.health.clone();
+            self.get("/readyz", move |res, _| {
+                let health = health.clone();
+                async move { health.respond(res, true).await }
+            });
+        }
+        self.health.clone()
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics {
+            ws_storage: self.ws_per_connection_user_data_storage.clone(),
+            uws_loop: self.uws_loop,
+            leak_after: Duration::from_secs(30),
+        }
+    }
+
+    // Serves Diagnostics::snapshot() as text, meant for an internal port or a protected path
+    pub fn debug_dump_route(&mut self, pattern: &str) -> &mut Self {
+        let diagnostics = self.diagnostics();
+        self.get(pattern, move |mut res, _| {
+            let diagnostics = diagnostics.clone();
+            async move {
+                let snapshot = diagnostics.snapshot().await;
+                res.write_header("content-type".to_string(), "text/plain".to_string());
+                res.write_header("cache-control".to_string(), "no-store".to_string());
+                res.end(Some(snapshot.to_string().into_bytes()), false)
+                    .await;
+            }
+        })
+    }
+
+    /***
+     * Fires once shutdown gave up waiting for requests and websockets, right before the app is
+     * closed. Also reachable from handlers via HttpConnection / Websocket::cancellation_token()
+     ***/
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
+    // Sends to every websocket of the app subscribed to `topic`, see Websocket::subscribe()
+    pub fn publish(&self, topic: &str, message: &[u8], opcode: Opcode, compress: bool) -> bool {
+        let app = self.native_app.get_native_app().get_native();
+        let published = app_handle::publish(app, SSL, topic, message, opcode.clone(), compress);
+        if let Some(backplane) = self.backplane.get() {
+            backplane.published(topic, Bytes::copy_from_slice(message), opcode, compress);
+        }
+        published
+    }
+
+    pub fn num_subscribers(&self, topic: &str) -> u32 {
+        let app = self.native_app.get_native_app().get_native();
+        app_handle::num_subscribers(app, SSL, topic)
+    }
+
+    // For publishing from other tasks and threads
+    pub fn handle(&self) -> AppHandle {
+        AppHandle::new(
+            self.native_app.get_native_app(),
+            SSL,
+            self.relay_shutdown.subscribe(),
+            self.uws_loop,
+            self.backplane.clone(),
+        )
+    }
+
+    /***
+     * Publishes of the app, its handles and websockets reach the subscribers on every node
+     * connected to `backplane`, see Backplane. Set it before the app serves websockets, the
+     * topics subscribed to before aren't received from the other nodes. There is no need for a
+     * Broadcast on top of it
+     ***/
+    pub fn backplane(&mut self, backplane: impl Backplane + 'static) -> &mut Self {
+        let link = BackplaneLink::start(backplane, self.handle(), self.cancellation.clone());
+        self.set_backplane(link);
+        self
+    }
+
+    pub(crate) fn set_backplane(&self, link: BackplaneLink) {
+        if self.backplane.set(link).is_err() {
+            panic!("[async_uws] The app already has a backplane");
+        }
+    }
+
+    // Publishes reaching the apps of every worker connected to `bridge`, see Broadcast
+    pub fn broadcast(&self, bridge: impl BroadcastBridge + 'static) -> Broadcast {
+        Broadcast::start(bridge, self.handle(), self.cancellation.clone())
+    }
+
+    // Members of rooms across every worker connected to `bridge`, see Presence
+    pub fn presence(&self, bridge: impl BroadcastBridge + 'static) -> Presence {
+        Presence::start(bridge, self.cancellation.clone())
+    }
+
+    // Rooms of this app's websockets, create them once and share them with data()
+    pub fn rooms(&self) -> Rooms {
+        Rooms::new(self.handle())
+    }
+
+    /***
+     * Starts keeping track of the open websockets, see ConnectionRegistry. Sockets opened before
+     * the first call aren't in it
+     ***/
+    pub fn connection_registry(&self) -> ConnectionRegistry {
+        self.connections.get_or_init(Default::default).clone()
+    }
+
+    // ConnectionRegistry::send_to(), Err if there is no registry
+    pub async fn send_to(
+        &self,
+        id: ConnectionId,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        match self.connections.get() {
+            Some(registry) => registry.send_to(id, message).await,
+            None => Err("App has no connection registry".to_string()),
+        }
+    }
+
+    // ConnectionRegistry::disconnect(), false if there is no registry
+    pub async fn disconnect(&self, id: ConnectionId, code: i32, reason: Option<&str>) -> bool {
+        match self.connections.get() {
+            Some(registry) => registry.disconnect(id, code, reason).await,
+            None => false,
+        }
+    }
+
+    // Stops the app from other tasks and threads, see ShutdownHandle
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(
+            self.shutdown_requested.clone(),
+            self.graceful_shutdown.clone(),
+            self.ws_per_connection_user_data_storage.clone(),
+        )
+    }
+
+    // Both apps of a DualApp report the same health and shut down together
+    pub(crate) fn share_with<const OTHER: bool>(&mut self, other: &AppStruct<OTHER>) {
+        self.health = other.health.clone();
+        self.shutdown_requested = other.shutdown_requested.clone();
+        self.cancellation = other.cancellation.clone();
+        self.graceful_shutdown = other.graceful_shutdown.clone();
+        self.response_cache = other.response_cache.clone();
+        self.settings = other.settings.clone();
+        self.connections = other.connections.clone();
+        self.backplane = other.backplane.clone();
+    }
+
+    /***
+     * On shutdown stop accepting first and give open websockets up to `timeout` to finish
+     * before the app is closed. Meant to be used together with spawn_replacement()
+     ***/
+    pub fn drain_on_shutdown(&mut self, timeout: Duration) -> &mut Self {
+        self.drain_timeout = Some(timeout);
+        self
+    }
+
+    /***
+     * On shutdown close every open websocket and give the handlers up to the deadline of
+     * `ws_shutdown` to handle the WsMessage::Close before the sockets are terminated. Without it
+     * the handlers' streams just end, and sockets are only closed by shutdown_graceful()
+     ***/
+    pub fn ws_shutdown(&mut self, ws_shutdown: WsShutdown) -> &mut Self {
+        self.ws_shutdown = Some(ws_shutdown);
+        self
+    }
+
+    // Starts a new instance of this binary that takes over the listeners, see restart::spawn_replacement()
+    pub fn spawn_replacement(&self) -> io::Result<Child> {
+        let listeners: Vec<BorrowedFd> = self
+            .inherited_listeners
+            .iter()
+            .map(|listener| listener.as_fd())
+            .collect();
+        spawn_replacement(&listeners)
+    }
+
+    /***
+     * Shutdown goes from the gentlest step to the hardest:
+     *   readiness turns false and shutdown_delay() passes, so balancers stop sending
+     *   listeners close, no new connections
+     *   websockets get their close frame (shutdown_graceful() or ws_shutdown())
+     *   requests and websockets get until the deadline to finish
+     *   the cancellation token fires, handler streams end and the app is closed
+     ***/
+    fn watch_shutdown(&mut self) {
+        if self.is_watching_shutdown {
+            return;
+        }
+        self.is_watching_shutdown = true;
+        let stream = self.shutdown_stream.take();
+        let shutdown_requested = self.shutdown_requested.clone();
+        let cancellation = self.cancellation.clone();
+        let uws_loop = self.uws_loop;
         let native = self.native_app.get_native_app();
-        tokio_uring::spawn(async move {
-            if let Some(stream) = shutdown_stream {
-                let _ = stream.await;
-                app_close::<SSL>(native);
+        let relay_shutdown = self.relay_shutdown.clone();
+        let listeners = self.listeners.clone();
+        let ws_storage = self.ws_per_connection_user_data_storage.clone();
+        let drain_timeout = self.drain_timeout;
+        let ws_shutdown = self.ws_shutdown.clone();
+        let graceful = self.graceful_shutdown.clone();
+        let health = self.health.clone();
+        let ws_timers = self.ws_timers.clone();
+        task::spawn("async_uws shutdown", async move {
+            // Cancelling the token itself skips the grace period
+            let stream = async {
+                match stream {
+                    Some(stream) => {
+                        let _ = stream.await;
+                    }
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = stream => {}
+                _ = shutdown_requested.cancelled() => {}
+                _ = cancellation.cancelled() => {}
             }
+            shutdown_requested.cancel();
+            health.begin_shutdown().await;
+
+            close_listeners::<SSL>(uws_loop, listeners.clone()).await;
+            let _ = relay_shutdown.send(true);
+
+            let graceful = graceful.lock().unwrap().take();
+            let ws_storages = [ws_storage.clone()];
+            let mut is_closing_websockets = true;
+            if let Some(timeout) = graceful {
+                ws_shutdown
+                    .unwrap_or_else(|| WsShutdown::new(timeout))
+                    .close_all::<SSL>(uws_loop, ws_storage.clone());
+                wait_until(Instant::now() + timeout, || is_idle(&ws_storages)).await;
+            } else if let Some(ws_shutdown) = ws_shutdown {
+                ws_shutdown.close_all::<SSL>(uws_loop, ws_storage.clone());
+                let deadline = Instant::now() + ws_shutdown.deadline();
+                wait_until(deadline, || ws_handlers_done(&ws_storage)).await;
+            } else if let Some(drain_timeout) = drain_timeout {
+                is_closing_websockets = false;
+                let deadline = Instant::now() + drain_timeout;
+                wait_until(deadline, || ws_storage.lock().unwrap().is_empty()).await;
+            } else {
+                is_closing_websockets = false;
+            }
+
+            cancellation.cancel();
+            end_handler_streams(uws_loop, ws_storage.clone());
+            if is_closing_websockets {
+                terminate_websockets::<SSL>(uws_loop, ws_storage.clone());
+            }
+            // Native calls, so they run on the loop thread like the rest of uWS
+            let close = move || {
+                for timer in ws_timers.lock().unwrap().drain(..) {
+                    timer.close();
+                }
+                app_close::<SSL>(native);
+            };
+            LoopDeferFuture::new(close, uws_loop).await;
+            let _ = relay_shutdown.send(true);
         });
-        self.native_app.listen(port as i32, handler);
+    }
+}
+
+async fn close_listeners<const SSL: bool>(uws_loop: UwsLoop, listeners: Arc<Mutex<Listeners>>) {
+    LoopDeferFuture::new(move || listeners.lock().unwrap().close_all(SSL), uws_loop).await;
+}
+
+impl AppStruct<true> {
+    pub fn tls_session_options(
+        &mut self,
+        options: TlsSessionOptions,
+    ) -> Result<&mut Self, String> {
+        let app = self.native_app.get_native_app().get_native();
+        let ssl_ctx = unsafe { uws_get_native_handle(1, app) };
+        options.apply(ssl_ctx)?;
+        Ok(self)
+    }
+
+    // Protocols offered via ALPN in order of preference, see HttpConnection::alpn_protocol().
+    // uWS only speaks HTTP/1.1, so `http/1.1` is the only one accepted, others are an Err
+    pub fn alpn_protocols(&mut self, protocols: &[&str]) -> Result<&mut Self, String> {
+        let app = self.native_app.get_native_app().get_native();
+        let ssl_ctx = unsafe { uws_get_native_handle(1, app) };
+        set_alpn_protocols(ssl_ctx, protocols)?;
+        Ok(self)
+    }
+}
+
+#[cfg(feature = "rustls")]
+impl AppStruct<false> {
+    /***
+     * Terminates TLS with rustls instead of OpenSSL, decrypted connections are served by this plain app.
+     * `config` is used as is, so custom cert resolvers, ALPN etc. are configured there.
+     * The decrypted bytes reach the app over a unix socket in a private directory, the port isn't
+     * bound unless that directory is ours
+     ***/
+    pub fn listen_rustls(
+        &mut self,
+        port: u16,
+        config: Arc<tokio_rustls::rustls::ServerConfig>,
+    ) -> Result<&mut Self, String> {
+        let name = format!("rustls-{port}");
+        let path = unix_socket_path(&name)?;
+        let listener =
+            bind_tcp(port).map_err(|e| format!("Can't listen on port {port}: {e}"))?;
+        self.watch_shutdown();
+        listen_unix::<false>(self.native_app.get_native_app().get_native(), &path)?;
+        self.relay_to(
+            listener,
+            &name,
+            RelayTarget::Single(path),
+            RelayMode::Rustls(config.into(), Arc::new(self.alpn_stream_handlers.clone())),
+        );
+        Ok(self)
+    }
+
+    /***
+     * Takes over rustls connections that negotiated `protocol` (e.g. "h2"), the handler gets the
+     * decrypted stream instead of the app. Should be called before listen_rustls(),
+     * `protocol` has to be offered in `ServerConfig::alpn_protocols` too.
+     ***/
+    pub fn alpn_stream_handler<T, W>(&mut self, protocol: &str, handler: T) -> &mut Self
+    where
+        T: (Fn(RustlsStream, SocketAddr) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        let handler: AlpnStreamHandler =
+            Arc::new(move |stream, peer| Box::pin(handler(stream, peer)));
+        self.alpn_stream_handlers
+            .insert(protocol.as_bytes().to_vec(), handler);
         self
     }
 }
 
+async fn reject_rate_limited<const SSL: bool>(
+    mut res: HttpConnection<SSL>,
+    decision: RateLimitDecision,
+) {
+    let retry_after = decision.retry_after.as_secs_f64().ceil().max(1.0) as u64;
+    res.write_status("429 Too Many Requests".to_string());
+    res.write_header("retry-after".to_string(), retry_after.to_string());
+    res.end(None, false).await;
+}
+
+// Cancels the handler if it hasn't started responding by `deadline`
+async fn with_first_byte_timeout(
+    handler: impl Future<Output = ()>,
+    state: ResponseStateHandle,
+    deadline: Option<Instant>,
+) {
+    let Some(deadline) = deadline else {
+        return handler.await;
+    };
+    tokio::pin!(handler);
+    tokio::select! {
+        _ = &mut handler => return,
+        _ = sleep_until(deadline.into()) => {}
+    }
+    if state.get() == ResponseState::NotStarted {
+        return;
+    }
+    handler.await
+}
+
 pub fn wrap_http_handler<T, R, const SSL: bool>(
     handler: T,
     uws_loop: UwsLoop,
@@ -224,10 +1678,26 @@ where
     T: (Fn(HttpConnection<SSL>, HttpRequest) -> R) + 'static + Send + Sync,
     R: Future<Output = ()> + 'static + Send,
 {
-    let handler = Box::new(handler);
-    let handler_wrapper = SendPtr {
-        ptr: Box::into_raw(handler),
-    };
+    wrap_named_http_handler(
+        "async_uws http".to_string(),
+        handler,
+        uws_loop,
+        data_storage,
+    )
+}
+
+// Every request runs in its own task, named after the route
+pub(crate) fn wrap_named_http_handler<T, R, const SSL: bool>(
+    task_name: String,
+    handler: T,
+    uws_loop: UwsLoop,
+    data_storage: SharedDataStorage,
+) -> Box<dyn Fn(HttpResponseStruct<SSL>, SyncHttpRequest)>
+where
+    T: (Fn(HttpConnection<SSL>, HttpRequest) -> R) + 'static + Send + Sync,
+    R: Future<Output = ()> + 'static + Send,
+{
+    let handler = Arc::new(handler);
 
     let handler = move |mut res: HttpResponseStruct<SSL>, mut req: SyncHttpRequest| {
         let data_storage = data_storage.clone();
@@ -237,29 +1707,45 @@ where
             is_aborted_to_move.store(true, Ordering::Relaxed);
         });
 
-        let async_http_request = HttpRequest::from(&mut req);
-        let does_have_body = async_http_request.get_header("content-length").is_some();
+        let mut async_http_request = HttpRequest::from(&mut req);
+        let content_length = async_http_request.get_header("content-length");
+        // Chunked bodies have no content-length, only max_body_size() caps them
+        let does_have_body =
+            content_length.is_some() || async_http_request.get_header("transfer-encoding").is_some();
 
         let body_reader = if does_have_body {
-            Some(BodyReader::new(res.clone()))
+            let total = content_length.and_then(|value| value.trim().parse().ok());
+            Some(BodyReader::new(res.clone(), total))
+        } else {
+            None
+        };
+        let alpn_protocol = if SSL {
+            negotiated_alpn(unsafe { uws_res_get_native_handle(1, res.get_native()) })
         } else {
             None
         };
+        let remote_address = res.get_remote_address_as_text().to_string();
+        async_http_request.remote_address =
+            remote_address::remote_address(res.get_native() as *mut us_socket_t, &remote_address);
 
-        tokio_uring::spawn(async move {
-            let res = HttpConnection::new(
-                res,
-                uws_loop,
-                is_aborted,
-                data_storage.clone(),
-                body_reader,
-                None,
-                None,
-            );
-            #[allow(clippy::redundant_locals)]
-            let handler_wrapper = handler_wrapper;
-            let handler = unsafe { handler_wrapper.ptr.as_ref().unwrap() };
-            handler(res, async_http_request).await;
+        // Built here on the loop thread, its native handle is bound to it. Only the handler
+        // future moves to the spawned task
+        let mut res = HttpConnection::new(
+            res,
+            uws_loop,
+            is_aborted,
+            data_storage,
+            body_reader,
+            None,
+            None,
+        );
+        res.set_alpn_protocol(alpn_protocol);
+        res.set_remote_address(remote_address);
+        let panicked = res.panic_flag();
+        let handler = handler.clone();
+        let name = task_name.clone();
+        task::spawn(&task_name, async move {
+            task::catch_panic(name, || handler(res, async_http_request), panicked).await;
         });
     };
     Box::new(handler)
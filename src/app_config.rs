@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+use uwebsockets_rs::us_socket_context_options::UsSocketContextOptions;
+
+use crate::tcp_options::{KeepaliveOptions, TcpOptions};
+use crate::tls_options::TlsConfig;
+use crate::ws_behavior::WsRouteSettings;
+
+/***
+ * Everything App needs that usually differs between deployments.
+ * With the `serde` feature it can be read from TOML / YAML / JSON by the crate of your choice,
+ * `from_env` covers the common subset without extra dependencies.
+ ***/
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default))]
+pub struct AppConfig {
+    pub listen: Vec<u16>,
+    pub tls: Option<TlsFilesConfig>,
+    pub tcp: Option<TcpConfig>,
+    // Seconds given to open websockets on shutdown, see App::drain_on_shutdown()
+    pub drain_timeout: Option<u64>,
+    // Used by routes registered with App::ws_default()
+    pub ws: Option<WsRouteConfig>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default))]
+pub struct TlsFilesConfig {
+    pub key_file: Option<String>,
+    pub cert_file: Option<String>,
+    pub passphrase: Option<String>,
+    pub dh_params_file: Option<String>,
+    pub ca_file: Option<String>,
+    pub ssl_ciphers: Option<String>,
+    pub ssl_prefer_low_memory_usage: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default))]
+pub struct TcpConfig {
+    pub nodelay: Option<bool>,
+    // Keepalive is enabled when the idle time is set, all values in seconds
+    pub keepalive_time: Option<u64>,
+    pub keepalive_interval: Option<u64>,
+    pub keepalive_retries: Option<u32>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+}
+
+// Same fields as WsRouteSettings, missing ones fall back to WsRouteSettings::default()
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default))]
+pub struct WsRouteConfig {
+    pub compression: Option<u32>,
+    pub max_payload_length: Option<u32>,
+    pub idle_timeout: Option<u16>,
+    pub max_backpressure: Option<u32>,
+    pub close_on_backpressure_limit: Option<bool>,
+    pub reset_idle_timeout_on_send: Option<bool>,
+    pub send_pings_automatically: Option<bool>,
+    pub max_lifetime: Option<u16>,
+    pub max_messages_per_interval: Option<u32>,
+    // Milliseconds
+    pub message_interval: Option<u64>,
+    pub drain_events: Option<bool>,
+}
+
+impl AppConfig {
+    /***
+     * Reads `{prefix}_LISTEN` (comma separated ports), `{prefix}_TLS_KEY_FILE`, `{prefix}_TLS_CERT_FILE`,
+     * `{prefix}_TLS_PASSPHRASE`, `{prefix}_TLS_CA_FILE`, `{prefix}_TCP_NODELAY`,
+     * `{prefix}_DRAIN_TIMEOUT`, `{prefix}_WS_MAX_PAYLOAD_LENGTH` and `{prefix}_WS_IDLE_TIMEOUT`
+     ***/
+    pub fn from_env(prefix: &str) -> Result<Self, String> {
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).ok();
+
+        let mut config = AppConfig::default();
+        if let Some(listen) = var("LISTEN") {
+            config.listen = listen
+                .split(',')
+                .map(|port| parse_env(prefix, "LISTEN", port))
+                .collect::<Result<_, _>>()?;
+        }
+
+        let tls = TlsFilesConfig {
+            key_file: var("TLS_KEY_FILE"),
+            cert_file: var("TLS_CERT_FILE"),
+            passphrase: var("TLS_PASSPHRASE"),
+            ca_file: var("TLS_CA_FILE"),
+            ..Default::default()
+        };
+        if tls.key_file.is_some() || tls.cert_file.is_some() {
+            config.tls = Some(tls);
+        }
+
+        if let Some(nodelay) = var("TCP_NODELAY") {
+            config.tcp = Some(TcpConfig {
+                nodelay: Some(parse_env(prefix, "TCP_NODELAY", &nodelay)?),
+                ..Default::default()
+            });
+        }
+        if let Some(timeout) = var("DRAIN_TIMEOUT") {
+            config.drain_timeout = Some(parse_env(prefix, "DRAIN_TIMEOUT", &timeout)?);
+        }
+
+        let max_payload_length = var("WS_MAX_PAYLOAD_LENGTH");
+        let idle_timeout = var("WS_IDLE_TIMEOUT");
+        if max_payload_length.is_some() || idle_timeout.is_some() {
+            config.ws = Some(WsRouteConfig {
+                max_payload_length: max_payload_length
+                    .map(|value| parse_env(prefix, "WS_MAX_PAYLOAD_LENGTH", &value))
+                    .transpose()?,
+                idle_timeout: idle_timeout
+                    .map(|value| parse_env(prefix, "WS_IDLE_TIMEOUT", &value))
+                    .transpose()?,
+                ..Default::default()
+            });
+        }
+
+        Ok(config)
+    }
+
+    pub fn tls_config(&self) -> TlsConfig {
+        let tls = self.tls.clone().unwrap_or_default();
+        let mut config = TlsConfig::new();
+        if let Some(key_file) = tls.key_file {
+            config = config.key(key_file);
+        }
+        if let Some(cert_file) = tls.cert_file {
+            config = config.cert(cert_file);
+        }
+        if let Some(passphrase) = tls.passphrase {
+            config = config.passphrase(passphrase);
+        }
+        if let Some(dh_params_file) = tls.dh_params_file {
+            config = config.dh_params(dh_params_file);
+        }
+        if let Some(ca_file) = tls.ca_file {
+            config = config.ca(ca_file);
+        }
+        if let Some(ssl_ciphers) = tls.ssl_ciphers {
+            config = config.ciphers(ssl_ciphers);
+        }
+        if let Some(prefer) = tls.ssl_prefer_low_memory_usage {
+            config = config.prefer_low_memory_usage(prefer);
+        }
+        config
+    }
+
+    // Fails if any of the referenced TLS files can't be read
+    pub fn socket_context_options(&self) -> Result<UsSocketContextOptions, String> {
+        self.tls_config().build()
+    }
+
+    pub fn tcp_options(&self) -> Option<TcpOptions> {
+        let tcp = self.tcp.as_ref()?;
+        let mut options = TcpOptions::new();
+        if let Some(nodelay) = tcp.nodelay {
+            options = options.nodelay(nodelay);
+        }
+        if let Some(time) = tcp.keepalive_time {
+            let defaults = KeepaliveOptions::default();
+            options = options.keepalive(KeepaliveOptions {
+                time: Duration::from_secs(time),
+                interval: tcp
+                    .keepalive_interval
+                    .map(Duration::from_secs)
+                    .unwrap_or(defaults.interval),
+                retries: tcp.keepalive_retries.unwrap_or(defaults.retries),
+            });
+        }
+        if let Some(size) = tcp.send_buffer_size {
+            options = options.send_buffer_size(size);
+        }
+        if let Some(size) = tcp.recv_buffer_size {
+            options = options.recv_buffer_size(size);
+        }
+        Some(options)
+    }
+
+    pub fn ws_route_settings(&self) -> WsRouteSettings {
+        let defaults = WsRouteSettings::default();
+        let Some(ws) = self.ws.as_ref() else {
+            return defaults;
+        };
+
+        WsRouteSettings {
+            compression: ws.compression.or(defaults.compression),
+            max_payload_length: ws.max_payload_length.or(defaults.max_payload_length),
+            idle_timeout: ws.idle_timeout.or(defaults.idle_timeout),
+            max_backpressure: ws.max_backpressure.or(defaults.max_backpressure),
+            close_on_backpressure_limit: ws
+                .close_on_backpressure_limit
+                .or(defaults.close_on_backpressure_limit),
+            reset_idle_timeout_on_send: ws
+                .reset_idle_timeout_on_send
+                .or(defaults.reset_idle_timeout_on_send),
+            send_pings_automatically: ws
+                .send_pings_automatically
+                .or(defaults.send_pings_automatically),
+            max_lifetime: ws.max_lifetime.or(defaults.max_lifetime),
+            payload_limit_policy: defaults.payload_limit_policy,
+            max_messages_per_interval: ws
+                .max_messages_per_interval
+                .or(defaults.max_messages_per_interval),
+            message_interval: ws
+                .message_interval
+                .map(Duration::from_millis)
+                .or(defaults.message_interval),
+            rate_limiter: defaults.rate_limiter,
+            message_rate: defaults.message_rate,
+            outbound_queue_capacity: defaults.outbound_queue_capacity,
+            outbound_overflow: defaults.outbound_overflow,
+            batch_sends: defaults.batch_sends,
+            heartbeat: defaults.heartbeat,
+            drain_events: ws.drain_events.or(defaults.drain_events),
+            hooks: defaults.hooks,
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(prefix: &str, name: &str, value: &str) -> Result<T, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid value {value:?} in {prefix}_{name}"))
+}
@@ -0,0 +1,157 @@
+use std::ffi::{c_char, c_int};
+use std::sync::{Arc, OnceLock};
+
+use bytes::Bytes;
+use libuwebsockets_sys::{uws_app_t, uws_num_subscribers, uws_publish};
+use tokio::sync::{oneshot, watch};
+use uwebsockets_rs::app::NativeApp;
+use uwebsockets_rs::uws_loop::UwsLoop;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::backplane::BackplaneLink;
+use crate::diagnostics::loop_defer;
+use crate::loop_bound::LoopBound;
+
+/***
+ * Cloneable handle for publishing from outside of handlers, e.g. a queue consumer running in
+ * another task or thread. Calls are deferred onto the uWS loop, once the app is closed they
+ * return false / 0. The handle of a DualApp publishes on both apps, publishes reach the other
+ * nodes too if the app has a backplane.
+ ***/
+#[derive(Clone)]
+pub struct AppHandle {
+    apps: Vec<AppRef>,
+    uws_loop: UwsLoop,
+    backplane: Arc<OnceLock<BackplaneLink>>,
+}
+
+#[derive(Clone)]
+struct AppRef {
+    native_app: LoopBound<NativeApp>,
+    ssl: bool,
+    // App::relay_shutdown, true once the app is closed
+    closed: watch::Receiver<bool>,
+}
+
+impl AppHandle {
+    pub(crate) fn new(
+        native_app: NativeApp,
+        ssl: bool,
+        closed: watch::Receiver<bool>,
+        uws_loop: UwsLoop,
+        backplane: Arc<OnceLock<BackplaneLink>>,
+    ) -> Self {
+        let app = AppRef {
+            native_app: LoopBound::new(native_app),
+            ssl,
+            closed,
+        };
+        AppHandle {
+            apps: vec![app],
+            uws_loop,
+            backplane,
+        }
+    }
+
+    pub(crate) fn join(mut self, other: AppHandle) -> Self {
+        self.apps.extend(other.apps);
+        self
+    }
+
+    // True if any local subscriber was sent the message
+    pub async fn publish(
+        &self,
+        topic: &str,
+        message: impl Into<Bytes>,
+        opcode: Opcode,
+        compress: bool,
+    ) -> bool {
+        let message = message.into();
+        let published = self
+            .publish_local(topic, message.clone(), opcode.clone(), compress)
+            .await;
+        if let Some(backplane) = self.backplane.get() {
+            backplane.published(topic, message, opcode, compress);
+        }
+        published
+    }
+
+    // Leaves out the backplane, for messages coming from it
+    pub(crate) async fn publish_local(
+        &self,
+        topic: &str,
+        message: Bytes,
+        opcode: Opcode,
+        compress: bool,
+    ) -> bool {
+        let topic = topic.to_string();
+        self.on_loop(move |apps| {
+            apps.fold(false, |published, (app, ssl)| {
+                publish(app, ssl, &topic, &message, opcode.clone(), compress) || published
+            })
+        })
+        .await
+    }
+
+    pub async fn num_subscribers(&self, topic: &str) -> u32 {
+        let topic = topic.to_string();
+        self.on_loop(move |apps| {
+            apps.map(|(app, ssl)| num_subscribers(app, ssl, &topic))
+                .sum()
+        })
+        .await
+    }
+
+    // Runs `f` on the loop with the apps that are still open
+    async fn on_loop<R, F>(&self, f: F) -> R
+    where
+        R: Default + Send + 'static,
+        F: FnOnce(&mut dyn Iterator<Item = (*mut uws_app_t, bool)>) -> R + Send + 'static,
+    {
+        let (sink, stream) = oneshot::channel();
+        let apps = self.apps.clone();
+        loop_defer(self.uws_loop, move || {
+            let mut open = apps
+                .iter()
+                .filter(|app| !*app.closed.borrow())
+                .map(|app| (app.native_app.get().get_native(), app.ssl));
+            let _ = sink.send(f(&mut open));
+        });
+        stream.await.unwrap_or_default()
+    }
+}
+
+// Must be called on the loop thread
+pub(crate) fn publish(
+    app: *mut uws_app_t,
+    ssl: bool,
+    topic: &str,
+    message: &[u8],
+    opcode: Opcode,
+    compress: bool,
+) -> bool {
+    unsafe {
+        uws_publish(
+            ssl as c_int,
+            app,
+            topic.as_ptr() as *const c_char,
+            topic.len(),
+            message.as_ptr() as *const c_char,
+            message.len(),
+            opcode.into(),
+            compress,
+        )
+    }
+}
+
+// Must be called on the loop thread
+pub(crate) fn num_subscribers(app: *mut uws_app_t, ssl: bool, topic: &str) -> u32 {
+    unsafe {
+        uws_num_subscribers(
+            ssl as c_int,
+            app,
+            topic.as_ptr() as *const c_char,
+            topic.len(),
+        )
+    }
+}
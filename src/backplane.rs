@@ -0,0 +1,348 @@
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use log::error;
+use tokio::sync::mpsc;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::app_handle::AppHandle;
+use crate::broadcast::{BridgeFuture, BroadcastBridge, BroadcastMessage};
+use crate::cancellation::CancellationToken;
+use crate::task;
+
+// Wait before receiving again after a backplane failed
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/***
+ * Connects the pub/sub of the nodes of a deployment, see App::backplane(). Unlike a Broadcast,
+ * nothing has to publish through it: every publish of the app and its websockets is handed to
+ * publish(), and subscribe() / unsubscribe() follow the topics the node's sockets subscribe to.
+ *
+ * Every BroadcastBridge is a backplane that delivers all topics to every node. RedisBackplane
+ * (with the "redis" feature) only delivers the topics a node has subscribers of, other
+ * transports (NATS, Kafka...) implement this trait.
+ ***/
+pub trait Backplane: Send + Sync {
+    // Called for every publish of this node, its own subscribers already got the message
+    fn publish<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a>;
+
+    // This node got its first subscriber of `topic`
+    fn subscribe<'a>(&'a self, topic: &'a str) -> BridgeFuture<'a> {
+        let _ = topic;
+        Box::pin(async { Ok(()) })
+    }
+
+    // This node lost its last subscriber of `topic`
+    fn unsubscribe<'a>(&'a self, topic: &'a str) -> BridgeFuture<'a> {
+        let _ = topic;
+        Box::pin(async { Ok(()) })
+    }
+
+    // Feeds the publishes of the subscribed topics into `sink`, the ones of this node included.
+    // Returns Ok once `sink` is closed and Err if the connection is lost, then it's called again
+    fn receive(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_>;
+}
+
+impl<B: BroadcastBridge> Backplane for B {
+    fn publish<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a> {
+        self.send(message)
+    }
+
+    fn receive(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_> {
+        self.subscribe(sink)
+    }
+}
+
+enum Command {
+    Publish(BroadcastMessage),
+    Subscribe(String),
+    Unsubscribe(String),
+}
+
+// The backplane of an app, shared by both apps of a DualApp
+pub(crate) struct BackplaneLink {
+    origin: u64,
+    // Handled one at a time so an unsubscribe never overtakes the subscribe before it
+    commands: mpsc::UnboundedSender<Command>,
+    // Apps of this node with subscribers of the topic
+    topics: Mutex<HashMap<String, usize>>,
+}
+
+impl BackplaneLink {
+    // Publishes of other nodes are published on the apps of `handle` until `cancellation`
+    pub(crate) fn start(
+        backplane: impl Backplane + 'static,
+        handle: AppHandle,
+        cancellation: CancellationToken,
+    ) -> Self {
+        let backplane: Arc<dyn Backplane> = Arc::new(backplane);
+        let origin = RandomState::new().build_hasher().finish();
+        let (commands, pending) = mpsc::unbounded_channel();
+        task::spawn("async_uws backplane", async move {
+            tokio::select! {
+                _ = cancellation.cancelled() => {}
+                _ = forward(&*backplane, pending) => {}
+                _ = receive(&*backplane, origin, &handle) => {}
+            }
+        });
+        BackplaneLink {
+            origin,
+            commands,
+            topics: Default::default(),
+        }
+    }
+
+    // Called once the local subscribers got the message
+    pub(crate) fn published(&self, topic: &str, message: Bytes, opcode: Opcode, compress: bool) {
+        let message = BroadcastMessage {
+            origin: self.origin,
+            topic: topic.to_string(),
+            message,
+            opcode,
+            compress,
+        };
+        // Closed once the app shut down
+        let _ = self.commands.send(Command::Publish(message));
+    }
+
+    // uWS subscription event, the counts are the subscribers of `topic` on one app
+    pub(crate) fn subscription(&self, topic: &str, new_count: i32, old_count: i32) {
+        let command = {
+            let mut topics = self.topics.lock().unwrap();
+            if old_count == 0 && new_count > 0 {
+                let apps = topics.entry(topic.to_string()).or_default();
+                *apps += 1;
+                (*apps == 1).then(|| Command::Subscribe(topic.to_string()))
+            } else if new_count == 0 && old_count > 0 {
+                match topics.get_mut(topic) {
+                    Some(apps) if *apps > 1 => {
+                        *apps -= 1;
+                        None
+                    }
+                    Some(_) => {
+                        topics.remove(topic);
+                        Some(Command::Unsubscribe(topic.to_string()))
+                    }
+                    None => None,
+                }
+            } else {
+                None
+            }
+        };
+        if let Some(command) = command {
+            let _ = self.commands.send(command);
+        }
+    }
+}
+
+async fn forward(backplane: &dyn Backplane, mut commands: mpsc::UnboundedReceiver<Command>) {
+    while let Some(command) = commands.recv().await {
+        match command {
+            Command::Publish(message) => {
+                if let Err(e) = backplane.publish(&message).await {
+                    error!(
+                        "[async_uws] Backplane publish to {} failed: {e}",
+                        message.topic
+                    );
+                }
+            }
+            Command::Subscribe(topic) => {
+                if let Err(e) = backplane.subscribe(&topic).await {
+                    error!("[async_uws] Backplane subscription to {topic} failed: {e}");
+                }
+            }
+            Command::Unsubscribe(topic) => {
+                if let Err(e) = backplane.unsubscribe(&topic).await {
+                    error!("[async_uws] Backplane unsubscription from {topic} failed: {e}");
+                }
+            }
+        }
+    }
+}
+
+async fn receive(backplane: &dyn Backplane, origin: u64, handle: &AppHandle) {
+    let (sink, mut messages) = mpsc::unbounded_channel();
+    let subscribe = async {
+        while let Err(e) = backplane.receive(sink.clone()).await {
+            error!("[async_uws] Backplane connection failed: {e}");
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    };
+    let deliver = async {
+        while let Some(message) = messages.recv().await {
+            if message.origin != origin {
+                handle
+                    .publish_local(
+                        &message.topic,
+                        message.message,
+                        message.opcode,
+                        message.compress,
+                    )
+                    .await;
+            }
+        }
+    };
+    tokio::select! {
+        _ = subscribe => {}
+        _ = deliver => {}
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_backplane::RedisBackplane;
+
+#[cfg(feature = "redis")]
+mod redis_backplane {
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+    use log::error;
+    use redis::aio::{MultiplexedConnection, PubSubSink};
+    use redis::{AsyncCommands, Client};
+    use tokio::sync::{mpsc, Mutex, OnceCell};
+
+    use super::Backplane;
+    use crate::broadcast::{BridgeFuture, BroadcastMessage};
+
+    // Every topic is the Redis channel `prefix` + topic, a node only subscribes to the channels
+    // of its sockets' topics. Connects on first use
+    #[derive(Clone)]
+    pub struct RedisBackplane {
+        client: Client,
+        connection: Arc<OnceCell<MultiplexedConnection>>,
+        prefix: String,
+        subscriptions: Arc<Mutex<Subscriptions>>,
+    }
+
+    #[derive(Default)]
+    struct Subscriptions {
+        topics: HashSet<String>,
+        // Of the running receive(), later topics are subscribed through it
+        sink: Option<PubSubSink>,
+    }
+
+    impl RedisBackplane {
+        // e.g. "redis://127.0.0.1:6379/0" and "chat/"
+        pub fn new(url: &str, prefix: &str) -> Result<Self, String> {
+            let client = Client::open(url).map_err(|e| format!("Invalid Redis url: {e}"))?;
+            Ok(RedisBackplane {
+                client,
+                connection: Arc::new(OnceCell::new()),
+                prefix: prefix.to_string(),
+                subscriptions: Default::default(),
+            })
+        }
+
+        fn channel(&self, topic: &str) -> String {
+            format!("{}{topic}", self.prefix)
+        }
+
+        async fn publish_message(&self, message: &BroadcastMessage) -> Result<(), String> {
+            let connection = self
+                .connection
+                .get_or_try_init(|| self.client.get_multiplexed_async_connection())
+                .await
+                .map_err(|e| format!("Can't connect to Redis: {e}"))?;
+            let mut connection = connection.clone();
+            connection
+                .publish::<_, _, i64>(self.channel(&message.topic), message.encode())
+                .await
+                .map_err(|e| format!("Redis publish failed: {e}"))?;
+            Ok(())
+        }
+
+        async fn subscribe_topic(&self, topic: &str) -> Result<(), String> {
+            let mut subscriptions = self.subscriptions.lock().await;
+            subscriptions.topics.insert(topic.to_string());
+            if let Some(sink) = subscriptions.sink.as_mut() {
+                let channel = self.channel(topic);
+                sink.subscribe(&channel)
+                    .await
+                    .map_err(|e| format!("Can't subscribe to {channel}: {e}"))?;
+            }
+            Ok(())
+        }
+
+        async fn unsubscribe_topic(&self, topic: &str) -> Result<(), String> {
+            let mut subscriptions = self.subscriptions.lock().await;
+            subscriptions.topics.remove(topic);
+            if let Some(sink) = subscriptions.sink.as_mut() {
+                let channel = self.channel(topic);
+                sink.unsubscribe(&channel)
+                    .await
+                    .map_err(|e| format!("Can't unsubscribe from {channel}: {e}"))?;
+            }
+            Ok(())
+        }
+
+        async fn receive_messages(
+            &self,
+            sink: mpsc::UnboundedSender<BroadcastMessage>,
+        ) -> Result<(), String> {
+            let result = self.deliver(sink).await;
+            self.subscriptions.lock().await.sink = None;
+            result
+        }
+
+        async fn deliver(
+            &self,
+            sink: mpsc::UnboundedSender<BroadcastMessage>,
+        ) -> Result<(), String> {
+            let pubsub = self
+                .client
+                .get_async_pubsub()
+                .await
+                .map_err(|e| format!("Can't connect to Redis: {e}"))?;
+            let (mut pubsub_sink, mut messages) = pubsub.split();
+            {
+                // Topics subscribed while there was no connection
+                let mut subscriptions = self.subscriptions.lock().await;
+                for topic in &subscriptions.topics {
+                    let channel = self.channel(topic);
+                    pubsub_sink
+                        .subscribe(&channel)
+                        .await
+                        .map_err(|e| format!("Can't subscribe to {channel}: {e}"))?;
+                }
+                subscriptions.sink = Some(pubsub_sink);
+            }
+            while let Some(message) = messages.next().await {
+                match BroadcastMessage::decode(message.get_payload_bytes()) {
+                    Ok(message) => {
+                        if sink.send(message).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => error!(
+                        "[async_uws] {e} on Redis channel {}",
+                        message.get_channel_name()
+                    ),
+                }
+            }
+            Err("Redis backplane subscription closed".to_string())
+        }
+    }
+
+    impl Backplane for RedisBackplane {
+        fn publish<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a> {
+            Box::pin(self.publish_message(message))
+        }
+
+        fn subscribe<'a>(&'a self, topic: &'a str) -> BridgeFuture<'a> {
+            Box::pin(self.subscribe_topic(topic))
+        }
+
+        fn unsubscribe<'a>(&'a self, topic: &'a str) -> BridgeFuture<'a> {
+            Box::pin(self.unsubscribe_topic(topic))
+        }
+
+        fn receive(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_> {
+            Box::pin(self.receive_messages(sink))
+        }
+    }
+}
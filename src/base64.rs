@@ -0,0 +1,35 @@
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// URL-safe base64 without padding, as cookies and JWTs use it
+#[cfg_attr(not(feature = "signed-cookies"), allow(dead_code))]
+pub(crate) fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (index, byte)| {
+            bits | ((*byte as u32) << (16 - index * 8))
+        });
+        for index in 0..=chunk.len() {
+            encoded.push(BASE64_ALPHABET[((bits >> (18 - index * 6)) & 0x3f) as usize] as char);
+        }
+    }
+    encoded
+}
+
+pub(crate) fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 3 / 4);
+    for chunk in encoded.as_bytes().chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (index, symbol) in chunk.iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|c| c == symbol)? as u32;
+            bits |= value << (18 - index * 6);
+        }
+        for index in 0..chunk.len() - 1 {
+            decoded.push((bits >> (16 - index * 8)) as u8);
+        }
+    }
+    Some(decoded)
+}
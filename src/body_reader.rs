@@ -1,50 +1,129 @@
 // TODO: use async iterator as soon as it's stable
 // use std::async_iter::AsyncIterator;
 
-use std::time::Duration;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
-use log::error;
+use bytes::Bytes;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
 use uwebsockets_rs::http_response::HttpResponseStruct;
 
-pub type BodyChunk = (Vec<u8>, bool);
+use crate::cancellation::CancellationToken;
+use crate::progress::{Progress, ProgressSlot};
+use crate::task;
+
+pub type BodyChunk = (Bytes, bool);
 
 pub struct BodyReader<const SSL: bool> {
     body_stream: Receiver<BodyChunk>,
+    progress: ProgressSlot,
+    content_length: Option<u64>,
+    // Set once the last chunk came in, whether the handler reads it or not
+    complete: Arc<AtomicBool>,
+    // Max body size of the route, u64::MAX without one
+    limit: Arc<AtomicU64>,
+    // Set once more than `limit` bytes came in
+    overflow: Arc<AtomicBool>,
 }
 
 impl<const SSL: bool> BodyReader<SSL> {
-    pub fn new(mut response: HttpResponseStruct<SSL>) -> Self {
-        let (sink, stream) = mpsc::channel(1);
+    pub fn new(mut response: HttpResponseStruct<SSL>, content_length: Option<u64>) -> Self {
+        let (sink, stream) = body_channel();
+        let progress = ProgressSlot::default();
+        let progress_to_move = progress.clone();
+        let received = Cell::new(0u64);
+        let complete = Arc::new(AtomicBool::new(false));
+        let complete_to_move = complete.clone();
+        let limit = Arc::new(AtomicU64::new(u64::MAX));
+        let limit_to_move = limit.clone();
+        let overflow = Arc::new(AtomicBool::new(false));
+        let overflow_to_move = overflow.clone();
+        let sink = RefCell::new(Some(sink));
         response.on_data(move |chunk, end| {
-            let chunk = chunk.to_vec();
-            let sink = sink.clone();
-            tokio_uring::spawn(async move {
-                let res = sink.send_timeout((chunk, end), Duration::from_millis(50))
-                    .await;
-                if let Err(e) = res {
-                    error!("[async_uws] Error sending body chunk to stream: {e:#?}");
-                }
-
+            if end {
+                complete_to_move.store(true, Ordering::Relaxed);
+            }
+            received.set(received.get() + chunk.len() as u64);
+            progress_to_move.report(Progress {
+                transferred: received.get(),
+                total: content_length,
             });
+
+            // Past the limit the rest is dropped, the stream ends without its last chunk
+            if received.get() > limit_to_move.load(Ordering::Relaxed) {
+                overflow_to_move.store(true, Ordering::Relaxed);
+                sink.borrow_mut().take();
+                return;
+            }
+            // Queued right here so the chunks keep the order uWS hands them in
+            if let Some(sink) = sink.borrow().as_ref() {
+                let _ = sink.send((Bytes::copy_from_slice(chunk), end));
+            }
         });
 
         BodyReader {
             body_stream: stream,
+            progress,
+            content_length,
+            complete,
+            limit,
+            overflow,
         }
     }
 
+    pub(crate) fn progress(&self) -> ProgressSlot {
+        self.progress.clone()
+    }
+
+    pub(crate) fn completion(&self) -> Arc<AtomicBool> {
+        self.complete.clone()
+    }
+
+    pub(crate) fn set_limit(&self, limit: Option<u64>) {
+        self.limit
+            .store(limit.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub(crate) fn overflow(&self) -> Arc<AtomicBool> {
+        self.overflow.clone()
+    }
+
+    pub(crate) fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
     pub fn take_stream(self) -> Receiver<BodyChunk> {
         self.body_stream
     }
 
+    // Stream that ends as soon as the token fires, even if the client is still sending
+    pub fn take_stream_until(self, cancellation: CancellationToken) -> Receiver<BodyChunk> {
+        let (sink, stream) = mpsc::channel(1);
+        let mut body_stream = self.body_stream;
+        task::spawn("async_uws body stream", async move {
+            loop {
+                let chunk = tokio::select! {
+                    chunk = body_stream.recv() => chunk,
+                    _ = cancellation.cancelled() => None,
+                };
+                let Some(chunk) = chunk else {
+                    break;
+                };
+                if sink.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        stream
+    }
+
     pub async fn collect(self) -> Option<Vec<u8>> {
         let mut data_collector = Vec::<u8>::new();
         let mut stream = self.take_stream();
         while let Some((chunk, is_fin)) = stream.recv().await {
-            // TODO: Consider use append instead of extend, in order to avoid additional memory allocation
-            data_collector.extend(&chunk);
+            data_collector.extend_from_slice(&chunk);
             if is_fin {
                 break;
             }
@@ -57,3 +136,91 @@ impl<const SSL: bool> BodyReader<SSL> {
         }
     }
 }
+
+// Chunks queued by on_data() and moved to the handler's stream by a single task, in order. A slow
+// handler leaves them queued rather than losing any, the route's max body size caps the queue
+fn body_channel() -> (mpsc::UnboundedSender<BodyChunk>, Receiver<BodyChunk>) {
+    let (sink, mut queue) = mpsc::unbounded_channel::<BodyChunk>();
+    let (stream_sink, stream) = mpsc::channel(1);
+    task::spawn("async_uws body chunks", async move {
+        while let Some(chunk) = queue.recv().await {
+            // The handler dropped the stream, nobody reads the rest
+            if stream_sink.send(chunk).await.is_err() {
+                break;
+            }
+        }
+    });
+    (sink, stream)
+}
+
+// Request body as it arrives, see HttpConnection::body_stream()
+pub struct BodyStream {
+    chunks: Receiver<BodyChunk>,
+    is_finished: bool,
+}
+
+impl BodyStream {
+    pub(crate) fn new(chunks: Receiver<BodyChunk>) -> Self {
+        BodyStream {
+            chunks,
+            is_finished: false,
+        }
+    }
+
+    // None once the whole body is read, or if the client went away before sending it
+    pub async fn next(&mut self) -> Option<Bytes> {
+        while !self.is_finished {
+            let (chunk, is_fin) = self.chunks.recv().await?;
+            self.is_finished = is_fin;
+            if !chunk.is_empty() {
+                return Some(chunk);
+            }
+        }
+        None
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn slow_consumer_gets_every_chunk_in_order() {
+        let (sink, stream) = body_channel();
+        let mut sent = Vec::new();
+        for i in 0..64u8 {
+            let chunk = vec![i; 1 + i as usize];
+            sent.extend_from_slice(&chunk);
+            sink.send((Bytes::from(chunk), i == 63)).unwrap();
+        }
+
+        let mut stream = BodyStream::new(stream);
+        let mut collected = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            if collected.len() < 200 {
+                tokio::time::sleep(Duration::from_millis(60)).await;
+            }
+            collected.extend_from_slice(&chunk);
+        }
+        assert!(stream.is_finished());
+        assert_eq!(collected, sent);
+    }
+
+    #[tokio::test]
+    async fn stream_ends_when_the_client_goes_away() {
+        let (sink, stream) = body_channel();
+        sink.send((Bytes::from_static(b"partial"), false)).unwrap();
+        drop(sink);
+
+        let mut stream = BodyStream::new(stream);
+        assert_eq!(stream.next().await, Some(Bytes::from_static(b"partial")));
+        assert_eq!(stream.next().await, None);
+        assert!(!stream.is_finished());
+    }
+}
@@ -0,0 +1,314 @@
+use std::collections::hash_map::RandomState;
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use log::{error, warn};
+use tokio::sync::{broadcast, mpsc};
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::app_handle::AppHandle;
+use crate::cancellation::CancellationToken;
+use crate::task;
+
+pub type BridgeFuture<'a> = Pin<Box<dyn Future<Output = Result<(), String>> + Send + 'a>>;
+
+// Wait before subscribing again after a bridge failed
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/***
+ * Carries publishes between workers, see App::broadcast(). InProcessBridge connects the apps of
+ * one process, RedisBroadcastBridge (with the "redis" feature) those of every process using the
+ * same Redis channel. Other transports (NATS, Kafka...) implement this trait, with
+ * BroadcastMessage::encode() / decode() as the wire format.
+ ***/
+pub trait BroadcastBridge: Send + Sync {
+    // Hands `message` to every subscriber of the bridge, the sender's own included
+    fn send<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a>;
+
+    // Feeds every message sent through the bridge into `sink`, returns Ok once `sink` is
+    // closed and Err if the subscription is lost, in which case it's made again
+    fn subscribe(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BroadcastMessage {
+    // Broadcast that sent the message, it already published it locally
+    pub origin: u64,
+    pub topic: String,
+    pub message: Bytes,
+    pub opcode: Opcode,
+    pub compress: bool,
+}
+
+impl BroadcastMessage {
+    // origin (8 bytes), opcode, compress, topic length (4 bytes), topic, message
+    pub fn encode(&self) -> Vec<u8> {
+        let opcode: u32 = self.opcode.clone().into();
+        let mut encoded = Vec::with_capacity(14 + self.topic.len() + self.message.len());
+        encoded.extend_from_slice(&self.origin.to_be_bytes());
+        encoded.push(opcode as u8);
+        encoded.push(self.compress as u8);
+        encoded.extend_from_slice(&(self.topic.len() as u32).to_be_bytes());
+        encoded.extend_from_slice(self.topic.as_bytes());
+        encoded.extend_from_slice(&self.message);
+        encoded
+    }
+
+    pub fn decode(encoded: &[u8]) -> Result<Self, String> {
+        let invalid = || "Invalid broadcast message".to_string();
+        if encoded.len() < 14 {
+            return Err(invalid());
+        }
+        let origin = u64::from_be_bytes(encoded[..8].try_into().map_err(|_| invalid())?);
+        let opcode = match encoded[8] {
+            1 => Opcode::Text,
+            2 => Opcode::Binary,
+            opcode => return Err(format!("Invalid broadcast message opcode {opcode}")),
+        };
+        let compress = encoded[9] != 0;
+        let topic_len = u32::from_be_bytes(encoded[10..14].try_into().map_err(|_| invalid())?);
+        let rest = &encoded[14..];
+        if rest.len() < topic_len as usize {
+            return Err(invalid());
+        }
+        let (topic, message) = rest.split_at(topic_len as usize);
+        let topic = String::from_utf8(topic.to_vec()).map_err(|_| invalid())?;
+        Ok(BroadcastMessage {
+            origin,
+            topic,
+            message: Bytes::copy_from_slice(message),
+            opcode,
+            compress,
+        })
+    }
+}
+
+/***
+ * Publishes to a topic on every worker connected to the bridge, see App::broadcast():
+ *
+ *   let bridge = InProcessBridge::new(1024);
+ *   // on every worker thread
+ *   let broadcast = app.broadcast(bridge.clone());
+ *   app.data(broadcast);
+ *   // in a handler
+ *   let broadcast = res.data::<Broadcast>().unwrap();
+ *   broadcast.publish("chat", "hello", Opcode::Text, false).await?;
+ *
+ * The message is published on the local app right away and on the others once the bridge
+ * delivers it. Messages of the other workers are received until the app shuts down.
+ ***/
+#[derive(Clone)]
+pub struct Broadcast {
+    bridge: Arc<dyn BroadcastBridge>,
+    handle: AppHandle,
+    origin: u64,
+}
+
+impl Broadcast {
+    pub(crate) fn start(
+        bridge: impl BroadcastBridge + 'static,
+        handle: AppHandle,
+        cancellation: CancellationToken,
+    ) -> Self {
+        let broadcast = Broadcast {
+            bridge: Arc::new(bridge),
+            handle,
+            origin: RandomState::new().build_hasher().finish(),
+        };
+        let receiver = broadcast.clone();
+        task::spawn("async_uws broadcast", async move {
+            tokio::select! {
+                _ = cancellation.cancelled() => {}
+                _ = receiver.receive() => {}
+            }
+        });
+        broadcast
+    }
+
+    // Err if the bridge failed, local subscribers got the message either way
+    pub async fn publish(
+        &self,
+        topic: &str,
+        message: impl Into<Bytes>,
+        opcode: Opcode,
+        compress: bool,
+    ) -> Result<(), String> {
+        let message = BroadcastMessage {
+            origin: self.origin,
+            topic: topic.to_string(),
+            message: message.into(),
+            opcode,
+            compress,
+        };
+        self.handle
+            .publish_local(
+                &message.topic,
+                message.message.clone(),
+                message.opcode.clone(),
+                compress,
+            )
+            .await;
+        self.bridge.send(&message).await
+    }
+
+    async fn receive(&self) {
+        let (sink, mut messages) = mpsc::unbounded_channel();
+        let subscribe = async {
+            while let Err(e) = self.bridge.subscribe(sink.clone()).await {
+                error!("[async_uws] Broadcast bridge subscription failed: {e}");
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            }
+        };
+        let deliver = async {
+            while let Some(message) = messages.recv().await {
+                if message.origin != self.origin {
+                    self.handle
+                        .publish_local(
+                            &message.topic,
+                            message.message,
+                            message.opcode,
+                            message.compress,
+                        )
+                        .await;
+                }
+            }
+        };
+        tokio::select! {
+            _ = subscribe => {}
+            _ = deliver => {}
+        }
+    }
+}
+
+// Workers of one process, clones share the channel. Messages beyond `capacity` that a worker
+// hasn't received yet are dropped for it
+#[derive(Clone)]
+pub struct InProcessBridge {
+    sender: broadcast::Sender<BroadcastMessage>,
+}
+
+impl InProcessBridge {
+    pub fn new(capacity: usize) -> Self {
+        InProcessBridge {
+            sender: broadcast::channel(capacity.max(1)).0,
+        }
+    }
+}
+
+impl BroadcastBridge for InProcessBridge {
+    fn send<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a> {
+        // No receivers only means no other worker is running yet
+        let _ = self.sender.send(message.clone());
+        Box::pin(async { Ok(()) })
+    }
+
+    fn subscribe(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_> {
+        let mut receiver = self.sender.subscribe();
+        Box::pin(async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(message) => {
+                        if sink.send(message).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("[async_uws] Broadcast receiver lagged, {skipped} messages dropped");
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_bridge::RedisBroadcastBridge;
+
+#[cfg(feature = "redis")]
+mod redis_bridge {
+    use std::sync::Arc;
+
+    use futures_util::StreamExt;
+    use log::error;
+    use redis::aio::MultiplexedConnection;
+    use redis::{AsyncCommands, Client};
+    use tokio::sync::{mpsc, OnceCell};
+
+    use super::{BridgeFuture, BroadcastBridge, BroadcastMessage};
+
+    // Every process subscribed to `channel` on the same Redis, connects on the first send
+    #[derive(Clone)]
+    pub struct RedisBroadcastBridge {
+        client: Client,
+        connection: Arc<OnceCell<MultiplexedConnection>>,
+        channel: String,
+    }
+
+    impl RedisBroadcastBridge {
+        // e.g. "redis://127.0.0.1:6379/0"
+        pub fn new(url: &str, channel: &str) -> Result<Self, String> {
+            let client = Client::open(url).map_err(|e| format!("Invalid Redis url: {e}"))?;
+            Ok(RedisBroadcastBridge {
+                client,
+                connection: Arc::new(OnceCell::new()),
+                channel: channel.to_string(),
+            })
+        }
+
+        async fn publish(&self, message: &BroadcastMessage) -> Result<(), String> {
+            let connection = self
+                .connection
+                .get_or_try_init(|| self.client.get_multiplexed_async_connection())
+                .await
+                .map_err(|e| format!("Can't connect to Redis: {e}"))?;
+            let mut connection = connection.clone();
+            connection
+                .publish::<_, _, i64>(&self.channel, message.encode())
+                .await
+                .map_err(|e| format!("Redis publish failed: {e}"))?;
+            Ok(())
+        }
+
+        async fn receive(
+            &self,
+            sink: mpsc::UnboundedSender<BroadcastMessage>,
+        ) -> Result<(), String> {
+            let mut pubsub = self
+                .client
+                .get_async_pubsub()
+                .await
+                .map_err(|e| format!("Can't connect to Redis: {e}"))?;
+            pubsub
+                .subscribe(&self.channel)
+                .await
+                .map_err(|e| format!("Can't subscribe to {}: {e}", self.channel))?;
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                match BroadcastMessage::decode(message.get_payload_bytes()) {
+                    Ok(message) => {
+                        if sink.send(message).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => error!("[async_uws] {e} on Redis channel {}", self.channel),
+                }
+            }
+            Err(format!("Redis subscription to {} closed", self.channel))
+        }
+    }
+
+    impl BroadcastBridge for RedisBroadcastBridge {
+        fn send<'a>(&'a self, message: &'a BroadcastMessage) -> BridgeFuture<'a> {
+            Box::pin(self.publish(message))
+        }
+
+        fn subscribe(&self, sink: mpsc::UnboundedSender<BroadcastMessage>) -> BridgeFuture<'_> {
+            Box::pin(self.receive(sink))
+        }
+    }
+}
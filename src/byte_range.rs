@@ -0,0 +1,305 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::fs;
+use crate::http_connection::HttpConnection;
+use crate::http_date::parse_http_date;
+
+// More ranges than this in one request are answered with the whole file
+const MAX_RANGES: usize = 16;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum RangeRequest {
+    // No range header, one that isn't `bytes=` or one that doesn't parse
+    Full,
+    Partial(Vec<Range<u64>>),
+    Unsatisfiable,
+}
+
+/***
+ * `bytes=0-499, 1000-, -200` of a file of `len` bytes into half open ranges. Ranges starting past
+ * the end are dropped, ends past it are cut, so a request is only unsatisfiable if nothing is left.
+ * Overlapping ranges are kept as they are, as RFC 9110 allows
+ ***/
+pub(crate) fn parse_range(header: &str, len: u64) -> RangeRequest {
+    let Some(specs) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let mut ranges = Vec::new();
+    let mut specs_count = 0;
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        specs_count += 1;
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+        let range = match (start.trim(), end.trim()) {
+            ("", suffix) => match suffix.parse::<u64>() {
+                Ok(0) => None,
+                Ok(suffix) => Some(len.saturating_sub(suffix)..len),
+                Err(_) => return RangeRequest::Full,
+            },
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match end {
+                    "" => len,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end.saturating_add(1).min(len),
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                Some(start..end)
+            }
+        };
+        if let Some(range) = range.filter(|range| range.start < len) {
+            ranges.push(range);
+        }
+    }
+
+    match (specs_count, ranges.len()) {
+        (0, _) => RangeRequest::Full,
+        (count, _) if count > MAX_RANGES => RangeRequest::Full,
+        (_, 0) => RangeRequest::Unsatisfiable,
+        _ => RangeRequest::Partial(ranges),
+    }
+}
+
+// if-range holds a strong entity tag or the exact last-modified date of the file
+pub(crate) fn if_range_matches(if_range: &str, etag: &str, modified: Option<SystemTime>) -> bool {
+    let if_range = if_range.trim();
+    if if_range.starts_with('"') {
+        return if_range == etag && !etag.starts_with("W/");
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    match (parse_http_date(if_range), modified) {
+        (Some(date), Some(modified)) => {
+            let modified = modified
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |since| since.as_secs());
+            date.duration_since(SystemTime::UNIX_EPOCH)
+                .is_ok_and(|date| date.as_secs() == modified)
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{len}", range.start, range.end - 1)
+}
+
+/***
+ * multipart/byteranges body of `ranges`, every part with its own content-type and
+ * content-range. The length is known up front, so it's sent with try_end() like a single range
+ ***/
+pub(crate) async fn send_ranges<const SSL: bool>(
+    res: &mut HttpConnection<SSL>,
+    path: PathBuf,
+    ranges: &[Range<u64>],
+    content_type: &str,
+    len: u64,
+) -> Result<(), String> {
+    let parts = MultipartRanges::new(&path, ranges, content_type, len);
+    res.write_status("206 Partial Content".to_string());
+    res.write_header("content-type".to_string(), parts.content_type());
+    for (head, range) in parts.heads.iter().zip(ranges) {
+        res.try_end(head.clone(), parts.total).await?;
+        let mut chunks = fs::read_chunks(path.clone(), range.clone(), CHUNK_SIZE);
+        let mut sent = 0;
+        while let Some(chunk) = chunks.recv().await {
+            let chunk = chunk.map_err(|e| format!("Can't read {}: {e}", path.display()))?;
+            sent += chunk.len() as u64;
+            res.try_end(chunk, parts.total).await?;
+        }
+        if sent < range.end - range.start {
+            return Err(format!("{} ended early", path.display()));
+        }
+    }
+    res.try_end(parts.tail, parts.total).await?;
+    Ok(())
+}
+
+// send_ranges() for content in memory, returns the content-type and the body
+pub(crate) fn ranges_body(
+    name: &str,
+    data: &[u8],
+    ranges: &[Range<u64>],
+    content_type: &str,
+) -> (String, Vec<u8>) {
+    let parts = MultipartRanges::new(Path::new(name), ranges, content_type, data.len() as u64);
+    let mut body = Vec::with_capacity(parts.total as usize);
+    for (head, range) in parts.heads.iter().zip(ranges) {
+        body.extend_from_slice(head.as_bytes());
+        body.extend_from_slice(&data[range.start as usize..range.end as usize]);
+    }
+    body.extend_from_slice(parts.tail.as_bytes());
+    (parts.content_type(), body)
+}
+
+struct MultipartRanges {
+    boundary: String,
+    // Boundary and headers in front of every range
+    heads: Vec<String>,
+    tail: String,
+    total: u64,
+}
+
+impl MultipartRanges {
+    fn new(path: &Path, ranges: &[Range<u64>], content_type: &str, len: u64) -> Self {
+        let boundary = boundary(path, len);
+        let heads: Vec<String> = ranges
+            .iter()
+            .map(|range| {
+                format!(
+                    "\r\n--{boundary}\r\ncontent-type: {content_type}\r\ncontent-range: {}\r\n\r\n",
+                    content_range(range, len)
+                )
+            })
+            .collect();
+        let tail = format!("\r\n--{boundary}--\r\n");
+        let total = heads
+            .iter()
+            .zip(ranges)
+            .map(|(head, range)| head.len() as u64 + range.end - range.start)
+            .sum::<u64>()
+            + tail.len() as u64;
+        MultipartRanges {
+            boundary,
+            heads,
+            tail,
+            total,
+        }
+    }
+
+    fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+}
+
+// FNV-1a of the path, size and current time, unlikely enough to show up in the file itself
+fn boundary(path: &Path, len: u64) -> String {
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos() as u64);
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let bytes = path.as_os_str().as_encoded_bytes().iter();
+    for byte in bytes.chain(&len.to_le_bytes()).chain(&now.to_le_bytes()) {
+        hash = (hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+    }
+    format!("async_uws_{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn partial(ranges: &[Range<u64>]) -> RangeRequest {
+        RangeRequest::Partial(ranges.to_vec())
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-499", 1000), partial(&[0..500]));
+        assert_eq!(parse_range("bytes=500-", 1000), partial(&[500..1000]));
+        assert_eq!(parse_range(" bytes=0-0 ", 1000), partial(&[0..1]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(parse_range("bytes=-200", 1000), partial(&[800..1000]));
+        // A suffix longer than the file is the whole file
+        assert_eq!(parse_range("bytes=-5000", 1000), partial(&[0..1000]));
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-1", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn keeps_overlapping_ranges() {
+        assert_eq!(
+            parse_range("bytes=0-99, 50-149, -100", 1000),
+            partial(&[0..100, 50..150, 900..1000])
+        );
+        assert_eq!(parse_range("bytes=0-9,0-9", 1000), partial(&[0..10, 0..10]));
+    }
+
+    #[test]
+    fn cuts_ranges_at_the_end() {
+        assert_eq!(parse_range("bytes=900-1999", 1000), partial(&[900..1000]));
+        assert_eq!(
+            parse_range("bytes=0-18446744073709551615", 1000),
+            partial(&[0..1000])
+        );
+        // Ranges starting past the end are dropped
+        assert_eq!(parse_range("bytes=0-9, 1000-1009", 1000), partial(&[0..10]));
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_malformed_headers() {
+        for header in [
+            "items=0-9",
+            "bytes=",
+            "bytes=,",
+            "bytes=5",
+            "bytes=a-9",
+            "bytes=0-b",
+            "bytes=9-5",
+            "bytes=-x",
+            "bytes=--5",
+            "bytes=0-9, x",
+        ] {
+            assert_eq!(parse_range(header, 1000), RangeRequest::Full, "{header}");
+        }
+    }
+
+    #[test]
+    fn too_many_ranges_get_the_whole_file() {
+        let specs: Vec<String> = (0..=MAX_RANGES).map(|i| format!("{i}-{i}")).collect();
+        let header = format!("bytes={}", specs.join(","));
+        assert_eq!(parse_range(&header, 1000), RangeRequest::Full);
+
+        let header = format!("bytes={}", specs[..MAX_RANGES].join(","));
+        assert!(
+            matches!(parse_range(&header, 1000), RangeRequest::Partial(ranges) if ranges.len() == MAX_RANGES)
+        );
+    }
+
+    #[test]
+    fn formats_content_range() {
+        assert_eq!(content_range(&(0..500), 1000), "bytes 0-499/1000");
+        assert_eq!(content_range(&(999..1000), 1000), "bytes 999-999/1000");
+    }
+
+    #[test]
+    fn if_range_needs_a_strong_match() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert!(if_range_matches("\"abc\"", "\"abc\"", None));
+        assert!(!if_range_matches("\"abc\"", "\"abd\"", None));
+        assert!(!if_range_matches("W/\"abc\"", "W/\"abc\"", None));
+        assert!(!if_range_matches("\"abc\"", "W/\"abc\"", None));
+
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        assert!(if_range_matches(date, "\"abc\"", Some(modified)));
+        assert!(!if_range_matches(
+            date,
+            "\"abc\"",
+            Some(modified + Duration::from_secs(1))
+        ));
+        assert!(!if_range_matches(date, "\"abc\"", None));
+        assert!(!if_range_matches("yesterday", "\"abc\"", Some(modified)));
+    }
+}
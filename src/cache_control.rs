@@ -0,0 +1,166 @@
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Visibility {
+    Public,
+    Private,
+}
+
+/***
+ * Typed `cache-control` header value:
+ * `CacheControl::public().max_age(3600).immutable()` renders `public, max-age=3600, immutable`
+ ***/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    visibility: Option<Visibility>,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    must_revalidate: bool,
+    proxy_revalidate: bool,
+    immutable: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+    stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn public() -> Self {
+        CacheControl {
+            visibility: Some(Visibility::Public),
+            ..Default::default()
+        }
+    }
+
+    pub fn private() -> Self {
+        CacheControl {
+            visibility: Some(Visibility::Private),
+            ..Default::default()
+        }
+    }
+
+    pub fn no_cache() -> Self {
+        CacheControl {
+            no_cache: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn no_store() -> Self {
+        CacheControl {
+            no_store: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn s_maxage(mut self, seconds: u64) -> Self {
+        self.s_maxage = Some(seconds);
+        self
+    }
+
+    pub fn stale_while_revalidate(mut self, seconds: u64) -> Self {
+        self.stale_while_revalidate = Some(seconds);
+        self
+    }
+
+    pub fn stale_if_error(mut self, seconds: u64) -> Self {
+        self.stale_if_error = Some(seconds);
+        self
+    }
+
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    pub fn proxy_revalidate(mut self) -> Self {
+        self.proxy_revalidate = true;
+        self
+    }
+
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    pub fn header_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for CacheControl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut directives: Vec<String> = Vec::new();
+        match self.visibility {
+            Some(Visibility::Public) => directives.push("public".to_string()),
+            Some(Visibility::Private) => directives.push("private".to_string()),
+            None => {}
+        }
+        if self.no_cache {
+            directives.push("no-cache".to_string());
+        }
+        if self.no_store {
+            directives.push("no-store".to_string());
+        }
+        if self.no_transform {
+            directives.push("no-transform".to_string());
+        }
+        if self.must_revalidate {
+            directives.push("must-revalidate".to_string());
+        }
+        if self.proxy_revalidate {
+            directives.push("proxy-revalidate".to_string());
+        }
+        if let Some(seconds) = self.max_age {
+            directives.push(format!("max-age={seconds}"));
+        }
+        if let Some(seconds) = self.s_maxage {
+            directives.push(format!("s-maxage={seconds}"));
+        }
+        if let Some(seconds) = self.stale_while_revalidate {
+            directives.push(format!("stale-while-revalidate={seconds}"));
+        }
+        if let Some(seconds) = self.stale_if_error {
+            directives.push(format!("stale-if-error={seconds}"));
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
+// Applies `cache_control` to every response of the route unless the handler writes its own header
+pub fn with_cache_control<T, W, const SSL: bool>(
+    cache_control: CacheControl,
+    handler: T,
+) -> impl (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync
+where
+    T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
+    W: Future<Output = ()> + 'static + Send,
+{
+    move |mut res: HttpConnection<SSL>, req: HttpRequest| {
+        res.set_default_cache_control(cache_control.clone());
+        handler(res, req)
+    }
+}
@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/***
+ * Fires once shutdown is past its grace period, see App::cancellation_token().
+ * Handlers get a child_token() of it: cancelling theirs stops what they passed it to, the app
+ * is only shut down through App::shutdown_handle().
+ * Clones share the same state.
+ ***/
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    cancelled: watch::Sender<bool>,
+    // Set on child tokens, which are cancelled along with it
+    parent: Option<Arc<CancellationToken>>,
+}
+
+impl Default for CancellationToken {
+    fn default() -> Self {
+        CancellationToken {
+            cancelled: watch::channel(false).0,
+            parent: None,
+        }
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Cancelled when this one is, cancelling the child leaves this one alone
+    pub fn child_token(&self) -> Self {
+        CancellationToken {
+            cancelled: watch::channel(false).0,
+            parent: Some(Arc::new(self.clone())),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        let parent = self.parent.as_ref();
+        *self.cancelled.borrow() || parent.is_some_and(|parent| parent.is_cancelled())
+    }
+
+    // Resolves right away if the token is already cancelled
+    pub async fn cancelled(&self) {
+        let mut cancelled = self.cancelled.subscribe();
+        match &self.parent {
+            Some(parent) => tokio::select! {
+                _ = cancelled.wait_for(|cancelled| *cancelled) => {}
+                _ = Box::pin(parent.cancelled()) => {}
+            },
+            None => {
+                let _ = cancelled.wait_for(|cancelled| *cancelled).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+
+use crate::http_request::HttpRequest;
+use crate::response_cache::CachedResponse;
+
+pub type CoalesceKey = Arc<dyn Fn(&HttpRequest) -> String + Send + Sync>;
+
+/***
+ * Single flight for idempotent requests, see App::coalesced_route(). While a request for a key
+ * is being handled, further requests with the same key wait for its response instead of running
+ * the handler again. Nothing is kept once the response is out, combine with cached_route() for
+ * that. Clones share the requests in flight.
+ *
+ * The default key is the full url with its query, requests whose response depends on headers
+ * (authorization, cookies, accept-encoding) need those in the key.
+ ***/
+#[derive(Clone)]
+pub struct Coalescer {
+    // Key -> requests waiting for the one in flight
+    in_flight: Arc<Mutex<HashMap<String, Vec<oneshot::Sender<CachedResponse>>>>>,
+    key: CoalesceKey,
+}
+
+impl Default for Coalescer {
+    fn default() -> Self {
+        Coalescer {
+            in_flight: Default::default(),
+            key: Arc::new(|req| req.full_url.clone()),
+        }
+    }
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn key<F>(mut self, key: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> String + Send + Sync + 'static,
+    {
+        self.key = Arc::new(key);
+        self
+    }
+
+    pub fn key_for(&self, req: &HttpRequest) -> String {
+        (self.key)(req)
+    }
+
+    // Number of keys with a request in flight
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.lock().unwrap().len()
+    }
+
+    /***
+     * Runs `generate` unless a request for `key` is already in flight, in which case its response
+     * is returned. If that request is dropped before finishing, one of the waiters takes over.
+     ***/
+    pub async fn run<T, W>(&self, key: &str, generate: T) -> CachedResponse
+    where
+        T: FnOnce() -> W,
+        W: Future<Output = CachedResponse>,
+    {
+        loop {
+            let waiting = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get_mut(key) {
+                    Some(waiters) => {
+                        let (sink, stream) = oneshot::channel();
+                        waiters.push(sink);
+                        Some(stream)
+                    }
+                    None => {
+                        in_flight.insert(key.to_string(), Vec::new());
+                        None
+                    }
+                }
+            };
+
+            match waiting {
+                Some(stream) => {
+                    if let Ok(response) = stream.await {
+                        return response;
+                    }
+                }
+                None => {
+                    let leader = Leader {
+                        in_flight: &self.in_flight,
+                        key,
+                    };
+                    let response = generate().await;
+                    for waiter in leader.finish() {
+                        let _ = waiter.send(response.clone());
+                    }
+                    return response;
+                }
+            }
+        }
+    }
+}
+
+// Takes the key out of flight even if the generating request is dropped, its waiters then retry
+struct Leader<'a> {
+    in_flight: &'a Mutex<HashMap<String, Vec<oneshot::Sender<CachedResponse>>>>,
+    key: &'a str,
+}
+
+impl Leader<'_> {
+    fn finish(self) -> Vec<oneshot::Sender<CachedResponse>> {
+        let waiters = self.in_flight.lock().unwrap().remove(self.key);
+        // A new request for the key may be in flight by the time drop() would run
+        std::mem::forget(self);
+        waiters.unwrap_or_default()
+    }
+}
+
+impl Drop for Leader<'_> {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(self.key);
+    }
+}
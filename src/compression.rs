@@ -0,0 +1,437 @@
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use bytes::Bytes;
+use log::{debug, error};
+
+use crate::app::BoxedHandlerFuture;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::middleware::Next;
+use crate::static_files::encoding_quality;
+
+/***
+ * Compresses bodies of responses ended with end() / end_with() / json() in the encoding the
+ * client prefers by accept-encoding, see App::compression() and App::route_compression().
+ * Encoders come with the `brotli`, `zstd` and `gzip` features, without any of them nothing is
+ * compressed. Bodies sent with write() / try_end() and files go out as they are,
+ * ServeDir::precompressed() covers static files:
+ *
+ *   app.compression(Compression::new().min_size(512).brotli_quality(5));
+ *   app.route_compression("/export", Compression::new().content_types(&["text/csv"]));
+ *
+ * Responses with a content-encoding of their own, `cache-control: no-transform`, no content-type
+ * or statuses without a body are left alone
+ ***/
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: usize,
+    content_types: Vec<String>,
+    gzip_level: u32,
+    brotli_quality: u32,
+    zstd_level: i32,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        let content_types = [
+            "text/",
+            "application/json",
+            "application/javascript",
+            "application/xml",
+            "application/wasm",
+            "image/svg+xml",
+            "+json",
+            "+xml",
+        ];
+        Compression {
+            min_size: 1024,
+            content_types: content_types.iter().map(|mime| mime.to_string()).collect(),
+            gzip_level: 6,
+            brotli_quality: 4,
+            zstd_level: 3,
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Smaller bodies aren't worth the CPU and the extra header, 1 KiB by default
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /***
+     * Replaces the allowlist of compressed content types. `text/` matches every text type,
+     * `+json` every type with that suffix, anything else the exact mime. Defaults to text, JSON,
+     * JavaScript, XML, wasm and SVG
+     ***/
+    pub fn content_types(mut self, content_types: &[&str]) -> Self {
+        self.content_types = content_types
+            .iter()
+            .map(|mime| mime.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    // 0 - 9, 6 by default
+    pub fn gzip_level(mut self, level: u32) -> Self {
+        self.gzip_level = level.min(9);
+        self
+    }
+
+    // 0 - 11, 4 by default, as higher ones are too slow for bodies made per request
+    pub fn brotli_quality(mut self, quality: u32) -> Self {
+        self.brotli_quality = quality.min(11);
+        self
+    }
+
+    // 1 - 22, 3 by default
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        self.zstd_level = level.clamp(1, 22);
+        self
+    }
+
+    // Negotiates the encoding of every request and leaves the compressing to end()
+    pub(crate) fn into_middleware<const SSL: bool>(
+        self,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync + 'static
+    {
+        let compression = Arc::new(self);
+        move |mut res, req, next| {
+            let encoding =
+                Encoding::negotiate(req.get_header("accept-encoding").unwrap_or_default());
+            res.set_compression(compression.clone(), encoding);
+            next.run(res, req)
+        }
+    }
+
+    /***
+     * `body` of a response with `status` and `headers` in `encoding`, the headers get
+     * content-encoding and vary, a content-length of the handler is dropped for uWS to set the
+     * new one and a strong etag turns weak. Bodies that don't shrink are sent as they are
+     ***/
+    pub(crate) fn compress(
+        &self,
+        encoding: Option<Encoding>,
+        status: Option<&str>,
+        headers: &mut Vec<(String, String)>,
+        body: Bytes,
+    ) -> Bytes {
+        if body.len() < self.min_size || !has_body(status) || !self.is_compressible(headers) {
+            return body;
+        }
+        let has_vary = header(headers, "vary").is_some_and(|vary| {
+            vary.split(',').any(|name| {
+                name.trim() == "*" || name.trim().eq_ignore_ascii_case("accept-encoding")
+            })
+        });
+        if !has_vary {
+            headers.push(("vary".to_string(), "accept-encoding".to_string()));
+        }
+        let Some(encoding) = encoding else {
+            return body;
+        };
+
+        let compressed = match encoding.compress(self, &body) {
+            Ok(compressed) if compressed.len() < body.len() => compressed,
+            Ok(_) => return body,
+            Err(e) => {
+                error!(
+                    "[async_uws] Can't compress response with {}: {e}",
+                    encoding.name()
+                );
+                return body;
+            }
+        };
+        headers.retain(|(key, _)| !key.eq_ignore_ascii_case("content-length"));
+        for (key, value) in headers.iter_mut() {
+            if key.eq_ignore_ascii_case("etag") && value.starts_with('"') {
+                *value = format!("W/{value}");
+            }
+        }
+        headers.push(("content-encoding".to_string(), encoding.name().to_string()));
+        Bytes::from(compressed)
+    }
+
+    fn is_compressible(&self, headers: &[(String, String)]) -> bool {
+        if header(headers, "content-encoding").is_some() {
+            return false;
+        }
+        let no_transform = header(headers, "cache-control").is_some_and(|cache_control| {
+            cache_control
+                .split(',')
+                .any(|directive| directive.trim().eq_ignore_ascii_case("no-transform"))
+        });
+        if no_transform {
+            return false;
+        }
+        let Some(content_type) = header(headers, "content-type") else {
+            return false;
+        };
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| {
+            if allowed.starts_with('+') {
+                mime.ends_with(allowed.as_str())
+            } else if allowed.ends_with('/') {
+                mime.starts_with(allowed.as_str())
+            } else {
+                mime == *allowed
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "zstd")]
+    Zstd,
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+impl Encoding {
+    // Ties in the client's preference go to the first one
+    const ENABLED: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        #[cfg(feature = "zstd")]
+        Encoding::Zstd,
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip,
+    ];
+
+    pub(crate) fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut selected: Option<(f32, Encoding)> = None;
+        for encoding in Encoding::ENABLED {
+            let quality = encoding_quality(accept_encoding, encoding.name());
+            if quality > 0.0 && selected.is_none_or(|(best, _)| quality > best) {
+                selected = Some((quality, *encoding));
+            }
+        }
+        selected.map(|(_, encoding)| encoding)
+    }
+
+    pub(crate) fn name(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => "zstd",
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    #[allow(unused_variables)]
+    fn compress(&self, compression: &Compression, body: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => {
+                use std::io::Write;
+                let quality = compression.brotli_quality;
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, quality, 22);
+                encoder.write_all(body)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => zstd::bulk::compress(body, compression.zstd_level),
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => {
+                use std::io::Write;
+                let level = flate2::Compression::new(compression.gzip_level);
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), level);
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/***
+ * Decodes request bodies sent with `content-encoding: gzip`, `deflate` (with the `gzip` feature),
+ * `br` (`brotli`) or `zstd` (`zstd`) before the handler reads them, see App::decompression().
+ * The body is collected up to the route's max_body_size, decoded up to max_size() and handed on
+ * through HttpConnection::replace_body(), so get_body(), body_json() and the rest see plain bytes.
+ * Unknown encodings get 415 with the supported ones in accept-encoding, bodies decoding to more
+ * than max_size() 413 and broken ones 400
+ ***/
+#[derive(Debug, Clone)]
+pub struct Decompression {
+    max_size: u64,
+}
+
+impl Default for Decompression {
+    fn default() -> Self {
+        Decompression {
+            max_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl Decompression {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Decoded size at which a body is refused, guards against zip bombs. 16 MiB by default
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = bytes;
+        self
+    }
+
+    pub(crate) fn into_middleware<const SSL: bool>(
+        self,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync + 'static
+    {
+        let decompression = Arc::new(self);
+        move |res, req, next| {
+            let decompression = decompression.clone();
+            Box::pin(async move { decompression.handle(res, req, next).await })
+        }
+    }
+
+    async fn handle<const SSL: bool>(
+        &self,
+        mut res: HttpConnection<SSL>,
+        mut req: HttpRequest,
+        next: Next<SSL>,
+    ) {
+        // Listed in the order they were applied
+        let encodings: Vec<String> = req
+            .get_header("content-encoding")
+            .unwrap_or_default()
+            .split(',')
+            .map(|encoding| encoding.trim().to_ascii_lowercase())
+            .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+            .collect();
+        if encodings.is_empty() {
+            return next.run(res, req).await;
+        }
+        if let Some(encoding) = encodings.iter().find(|encoding| !is_decodable(encoding)) {
+            debug!(
+                "[async_uws] Unsupported content-encoding {encoding} of {}",
+                req.url
+            );
+            res.write_status("415 Unsupported Media Type".to_string());
+            res.write_header("accept-encoding".to_string(), DECODABLE.join(", "));
+            return res.end(None, false).await;
+        }
+
+        let limit = res.body_limit().unwrap_or(self.max_size);
+        let Ok(mut body) = res.get_body_limited(limit as usize).await else {
+            return;
+        };
+        if !body.is_empty() {
+            for encoding in encodings.iter().rev() {
+                body = match decompress(encoding, &body, self.max_size) {
+                    Ok(body) => body,
+                    Err(status) => {
+                        debug!(
+                            "[async_uws] Can't decode {encoding} body of {}: {status}",
+                            req.url
+                        );
+                        res.write_status(status.to_string());
+                        return res.end(None, false).await;
+                    }
+                };
+            }
+        }
+
+        req.headers.retain(|(key, _)| {
+            !key.eq_ignore_ascii_case("content-encoding")
+                && !key.eq_ignore_ascii_case("content-length")
+        });
+        req.headers
+            .push(("content-length".to_string(), body.len().to_string()));
+        res.replace_body(body);
+        next.run(res, req).await
+    }
+}
+
+const DECODABLE: &[&str] = &[
+    #[cfg(feature = "gzip")]
+    "gzip",
+    #[cfg(feature = "gzip")]
+    "deflate",
+    #[cfg(feature = "brotli")]
+    "br",
+    #[cfg(feature = "zstd")]
+    "zstd",
+];
+
+fn is_decodable(encoding: &str) -> bool {
+    DECODABLE.contains(&encoding) || (encoding == "x-gzip" && DECODABLE.contains(&"gzip"))
+}
+
+// `body` decoded up to `limit` bytes, the status to answer with otherwise
+#[allow(unused_variables)]
+fn decompress(encoding: &str, body: &[u8], limit: u64) -> Result<Vec<u8>, &'static str> {
+    let decoder: Option<Box<dyn Read + '_>> = match encoding {
+        #[cfg(feature = "gzip")]
+        "gzip" | "x-gzip" => Some(Box::new(flate2::read::MultiGzDecoder::new(body))),
+        // Meant to be zlib wrapped, some clients send raw deflate anyway
+        #[cfg(feature = "gzip")]
+        "deflate" if is_zlib(body) => Some(Box::new(flate2::read::ZlibDecoder::new(body))),
+        #[cfg(feature = "gzip")]
+        "deflate" => Some(Box::new(flate2::read::DeflateDecoder::new(body))),
+        #[cfg(feature = "brotli")]
+        "br" => Some(Box::new(brotli::Decompressor::new(body, 4096))),
+        #[cfg(feature = "zstd")]
+        "zstd" => zstd::stream::read::Decoder::new(body)
+            .ok()
+            .map(|decoder| Box::new(decoder) as Box<dyn Read + '_>),
+        _ => None,
+    };
+    let Some(decoder) = decoder else {
+        return Err("415 Unsupported Media Type");
+    };
+
+    let mut decoded = Vec::new();
+    if decoder.take(limit + 1).read_to_end(&mut decoded).is_err() {
+        return Err("400 Bad Request");
+    }
+    if decoded.len() as u64 > limit {
+        return Err("413 Payload Too Large");
+    }
+    Ok(decoded)
+}
+
+// Two byte zlib header: deflate method and a checksum that is a multiple of 31
+#[cfg(feature = "gzip")]
+fn is_zlib(body: &[u8]) -> bool {
+    match body {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31),
+        _ => false,
+    }
+}
+
+// 1xx, 204, 206 and 304 responses have no body to compress (or one that's a part of something)
+fn has_body(status: Option<&str>) -> bool {
+    let Some(status) = status else {
+        return true;
+    };
+    !(status.starts_with('1')
+        || status.starts_with("204")
+        || status.starts_with("206")
+        || status.starts_with("304"))
+}
+
+fn header<'a>(headers: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.as_str())
+}
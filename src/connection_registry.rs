@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use uwebsockets_rs::uws_loop::UwsLoop;
+use uwebsockets_rs::websocket::WebSocketStruct;
+
+use crate::diagnostics::loop_defer;
+use crate::loop_bound::LoopBound;
+use crate::websocket::{send_to_socket, SendStatus};
+use crate::ws_message::WsMessage;
+use crate::ws_stats::{WsCounters, WsStats};
+
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+// Unique for every websocket of the process, see Websocket::id()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl ConnectionId {
+    pub(crate) fn next() -> Self {
+        ConnectionId(NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+type SendFuture = Pin<Box<dyn Future<Output = Result<SendStatus, String>> + Send>>;
+type EndFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+// Socket as seen from outside its handler, plain or SSL
+trait RegisteredSocket: Send + Sync {
+    fn send(&self, message: WsMessage) -> SendFuture;
+    fn end(&self, code: i32, reason: Option<String>) -> EndFuture;
+    fn remote_address(&self) -> Option<SocketAddr>;
+    fn stats(&self) -> WsStats;
+}
+
+struct Socket<const SSL: bool> {
+    native: LoopBound<WebSocketStruct<SSL>>,
+    uws_loop: UwsLoop,
+    is_open: Arc<AtomicBool>,
+    remote_address: Option<SocketAddr>,
+    stats: Arc<WsCounters>,
+}
+
+impl<const SSL: bool> RegisteredSocket for Socket<SSL> {
+    fn send(&self, message: WsMessage) -> SendFuture {
+        Box::pin(send_to_socket(
+            message,
+            false,
+            true,
+            self.native.clone(),
+            self.uws_loop,
+            self.is_open.clone(),
+        ))
+    }
+
+    fn end(&self, code: i32, reason: Option<String>) -> EndFuture {
+        let (sink, stream) = oneshot::channel();
+        let native = self.native.clone();
+        let is_open = self.is_open.clone();
+        loop_defer(self.uws_loop, move || {
+            let is_open = is_open.load(Ordering::Relaxed);
+            if is_open {
+                native.get().end(code, reason.as_deref());
+            }
+            let _ = sink.send(is_open);
+        });
+        Box::pin(async move { stream.await.unwrap_or_default() })
+    }
+
+    fn remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
+    fn stats(&self) -> WsStats {
+        self.stats.get()
+    }
+}
+
+/***
+ * Open websockets of an app by ConnectionId, see App::connection_registry(). Lets any part of
+ * the application reach one connection, e.g. to push a notification to a user whose id was
+ * stored at login:
+ *
+ *   let registry = app.connection_registry();
+ *   app.data(registry);
+ *   // in the websocket handler
+ *   sessions.insert(user, ws.id());
+ *   // anywhere else
+ *   registry.send_to(id, "you have mail".into()).await?;
+ *
+ * Sockets are added once open and removed as soon as they close.
+ ***/
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    sockets: Arc<Mutex<HashMap<ConnectionId, Arc<dyn RegisteredSocket>>>>,
+}
+
+impl ConnectionRegistry {
+    // Must be called on the loop thread, from the open callback
+    pub(crate) fn register<const SSL: bool>(
+        &self,
+        id: ConnectionId,
+        native: WebSocketStruct<SSL>,
+        uws_loop: UwsLoop,
+        is_open: Arc<AtomicBool>,
+        remote_address: Option<SocketAddr>,
+        stats: Arc<WsCounters>,
+    ) {
+        let socket = Socket {
+            native: LoopBound::new(native),
+            uws_loop,
+            is_open,
+            remote_address,
+            stats,
+        };
+        self.sockets.lock().unwrap().insert(id, Arc::new(socket));
+    }
+
+    pub(crate) fn unregister(&self, id: ConnectionId) {
+        self.sockets.lock().unwrap().remove(&id);
+    }
+
+    // Err if there is no such connection (anymore)
+    pub async fn send_to(
+        &self,
+        id: ConnectionId,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        let socket = self
+            .socket(id)
+            .ok_or_else(|| format!("No connection {id}"))?;
+        socket.send(message).await
+    }
+
+    // Closes the connection with `code`, false if it isn't open
+    pub async fn disconnect(&self, id: ConnectionId, code: i32, reason: Option<&str>) -> bool {
+        match self.socket(id) {
+            Some(socket) => socket.end(code, reason.map(String::from)).await,
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, id: ConnectionId) -> bool {
+        self.sockets.lock().unwrap().contains_key(&id)
+    }
+
+    pub fn remote_address(&self, id: ConnectionId) -> Option<SocketAddr> {
+        self.socket(id)?.remote_address()
+    }
+
+    pub fn stats(&self, id: ConnectionId) -> Option<WsStats> {
+        Some(self.socket(id)?.stats())
+    }
+
+    // Stats of every open connection, e.g. for a dashboard
+    pub fn all_stats(&self) -> Vec<(ConnectionId, WsStats)> {
+        let sockets = self.sockets.lock().unwrap();
+        sockets
+            .iter()
+            .map(|(id, socket)| (*id, socket.stats()))
+            .collect()
+    }
+
+    // Snapshot of the open connections, in no particular order
+    pub fn ids(&self) -> Vec<ConnectionId> {
+        self.sockets.lock().unwrap().keys().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sockets.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn socket(&self, id: ConnectionId) -> Option<Arc<dyn RegisteredSocket>> {
+        self.sockets.lock().unwrap().get(&id).cloned()
+    }
+}
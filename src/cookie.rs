@@ -0,0 +1,475 @@
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "signed-cookies")]
+use hmac::{Hmac, Mac};
+#[cfg(feature = "signed-cookies")]
+use sha2::Sha256;
+
+#[cfg(feature = "signed-cookies")]
+use crate::base64::{base64_decode, base64_encode};
+use crate::http_date::format_http_date;
+use crate::http_request::HttpRequest;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    // Needs secure(true), browsers drop such cookies otherwise
+    None,
+}
+
+/***
+ * Cookie sent with HttpConnection::set_cookie():
+ * `Cookie::new("theme", "dark").path("/").max_age(Duration::from_secs(86400)).same_site(SameSite::Lax)`
+ * renders `theme=dark; Path=/; Max-Age=86400; SameSite=Lax`.
+ * The value is sent as it is, so it has to be cookie-safe (no whitespace, quotes, `,`, `;` or `\`),
+ * signed and private values always are
+ ***/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+    max_age: Option<Duration>,
+    expires: Option<SystemTime>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            path: None,
+            domain: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+            max_age: None,
+            expires: None,
+        }
+    }
+
+    // Deletes the cookie in the browser, path and domain have to match the ones it was set with
+    pub fn removal(name: impl Into<String>) -> Self {
+        Cookie::new(name, "")
+            .max_age(Duration::ZERO)
+            .expires(SystemTime::UNIX_EPOCH)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn domain(mut self, domain: impl Into<String>) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    // Without max_age() or expires() the cookie ends with the browser session
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn expires(mut self, expires: SystemTime) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub(crate) fn with_value(mut self, value: String) -> Self {
+        self.value = value;
+        self
+    }
+
+    pub fn header_value(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl Display for Cookie {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = self.path.as_ref() {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = self.domain.as_ref() {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", format_http_date(expires))?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+// Cookies the request came with, see HttpRequest::cookies()
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    // Every `cookie` header, `a=1; b=2`, quotes around values are dropped
+    pub(crate) fn from_request(req: &HttpRequest) -> Self {
+        let cookies = req
+            .headers
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                let name = name.trim();
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some((name.to_string(), value.to_string())).filter(|_| !name.is_empty())
+            })
+            .collect();
+        CookieJar { cookies }
+    }
+
+    // First cookie called `name`, browsers send the one with the longest path first
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.cookies
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    // Value of a cookie set with HttpConnection::set_signed_cookie(), None if it was tampered with
+    #[cfg(feature = "signed-cookies")]
+    pub fn get_signed(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.verify(name, self.get(name)?)
+    }
+
+    // Value of a cookie set with HttpConnection::set_private_cookie()
+    #[cfg(feature = "private-cookies")]
+    pub fn get_private(&self, name: &str, key: &CookieKey) -> Option<String> {
+        key.decrypt(name, self.get(name)?)
+    }
+}
+
+/***
+ * Secret behind signed and private cookies, see App::cookie_key(). Signed cookies can be read but
+ * not changed by the client (HMAC-SHA256), private ones (`private-cookies` feature) can't be read
+ * either (AES-256-GCM). Both are bound to the cookie's name, so a value can't be moved to another
+ * cookie. Separate keys for the two are derived from the secret, which has to stay the same across
+ * restarts and instances for cookies to remain valid
+ ***/
+#[cfg(feature = "signed-cookies")]
+type HmacSha256 = Hmac<Sha256>;
+
+#[cfg(feature = "signed-cookies")]
+#[derive(Clone)]
+pub struct CookieKey {
+    signing: [u8; 32],
+    #[cfg(feature = "private-cookies")]
+    encryption: [u8; 32],
+}
+
+#[cfg(feature = "signed-cookies")]
+impl CookieKey {
+    // `secret` should be at least 32 random bytes
+    pub fn new(secret: &[u8]) -> Result<Self, String> {
+        if secret.len() < 32 {
+            return Err("Cookie key secret must be at least 32 bytes".to_string());
+        }
+        Ok(CookieKey {
+            signing: derive_key(secret, b"async_uws cookie signing"),
+            #[cfg(feature = "private-cookies")]
+            encryption: derive_key(secret, b"async_uws cookie encryption"),
+        })
+    }
+
+    // `value.signature`
+    pub(crate) fn sign(&self, name: &str, value: &str) -> String {
+        let signature = base64_encode(&self.mac(name, value).finalize().into_bytes());
+        format!("{value}.{signature}")
+    }
+
+    pub(crate) fn verify(&self, name: &str, signed: &str) -> Option<String> {
+        let (value, signature) = signed.rsplit_once('.')?;
+        let signature = base64_decode(signature)?;
+        self.mac(name, value).verify_slice(&signature).ok()?;
+        Some(value.to_string())
+    }
+
+    fn mac(&self, name: &str, value: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.signing).expect("HMAC takes keys of any size");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    // base64 of nonce + ciphertext, the name is authenticated along with it
+    #[cfg(feature = "private-cookies")]
+    pub(crate) fn encrypt(&self, name: &str, value: &str) -> Result<String, String> {
+        use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&self.encryption)
+            .map_err(|e| format!("Invalid cookie encryption key: {e}"))?;
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let ciphertext = cipher
+            .encrypt(&nonce, payload)
+            .map_err(|_| "Can't encrypt cookie".to_string())?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        Ok(base64_encode(&sealed))
+    }
+
+    #[cfg(feature = "private-cookies")]
+    pub(crate) fn decrypt(&self, name: &str, sealed: &str) -> Option<String> {
+        use aes_gcm::aead::{Aead, KeyInit, Payload};
+        let sealed = base64_decode(sealed)?;
+        if sealed.len() < 12 {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let cipher = aes_gcm::Aes256Gcm::new_from_slice(&self.encryption).ok()?;
+        let payload = Payload {
+            msg: ciphertext,
+            aad: name.as_bytes(),
+        };
+        let value = cipher
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), payload)
+            .ok()?;
+        String::from_utf8(value).ok()
+    }
+}
+
+#[cfg(feature = "signed-cookies")]
+impl std::fmt::Debug for CookieKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CookieKey(..)")
+    }
+}
+
+#[cfg(feature = "signed-cookies")]
+fn derive_key(secret: &[u8], purpose: &[u8]) -> [u8; 32] {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("HMAC takes keys of any size");
+    mac.update(purpose);
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(cookie_headers: &[&str]) -> HttpRequest {
+        HttpRequest {
+            headers: cookie_headers
+                .iter()
+                .map(|value| ("cookie".to_string(), value.to_string()))
+                .collect(),
+            full_url: "/".to_string(),
+            url: "/".to_string(),
+            method: "get".to_string(),
+            case_sensitive_method: "GET".to_string(),
+            parameters: Vec::new(),
+            route_params: Vec::new(),
+            deadline: None,
+            remote_address: None,
+            client_ip: None,
+        }
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    fn key() -> CookieKey {
+        CookieKey::new(b"0123456789abcdef0123456789abcdef").unwrap()
+    }
+
+    #[test]
+    fn renders_attributes() {
+        let cookie = Cookie::new("theme", "dark")
+            .path("/")
+            .domain("example.com")
+            .max_age(Duration::from_secs(86400))
+            .secure(true)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.header_value(),
+            "theme=dark; Path=/; Domain=example.com; Max-Age=86400; Secure; HttpOnly; SameSite=Lax"
+        );
+        assert_eq!(Cookie::new("a", "1").to_string(), "a=1");
+    }
+
+    #[test]
+    fn removal_expires_right_away() {
+        assert_eq!(
+            Cookie::removal("sid").path("/").to_string(),
+            "sid=; Path=/; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+    }
+
+    #[test]
+    fn parses_every_cookie_header() {
+        let jar =
+            CookieJar::from_request(&request(&["a=1; b=\"two\";c = 3=4 ;; =x; novalue", "a=5"]));
+        let cookies: Vec<(&str, &str)> = jar.iter().collect();
+        assert_eq!(
+            cookies,
+            vec![("a", "1"), ("b", "two"), ("c", "3=4"), ("a", "5")]
+        );
+        assert_eq!(jar.get("a"), Some("1"));
+        assert_eq!(jar.get("novalue"), None);
+        assert_eq!(jar.len(), 4);
+        assert!(CookieJar::from_request(&request(&[])).is_empty());
+    }
+
+    #[test]
+    fn keeps_unbalanced_quotes() {
+        let jar = CookieJar::from_request(&request(&["a=\"open; b=close\"; c=\""]));
+        assert_eq!(jar.get("a"), Some("\"open"));
+        assert_eq!(jar.get("b"), Some("close\""));
+        assert_eq!(jar.get("c"), Some("\""));
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    #[test]
+    fn needs_a_long_secret() {
+        assert!(CookieKey::new(&[7; 31]).is_err());
+        assert!(CookieKey::new(&[7; 32]).is_ok());
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    #[test]
+    fn signed_values_round_trip() {
+        let signed = key().sign("session", "user.7");
+        assert!(signed.starts_with("user.7."));
+        let header = format!("session={signed}");
+        let jar = CookieJar::from_request(&request(&[header.as_str()]));
+        assert_eq!(jar.get_signed("session", &key()).as_deref(), Some("user.7"));
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    #[test]
+    fn rejects_tampered_signed_values() {
+        let key = key();
+        let signed = key.sign("session", "user.7");
+        let (_, signature) = signed.rsplit_once('.').unwrap();
+
+        // Another value under the same signature
+        assert_eq!(key.verify("session", &format!("user.1.{signature}")), None);
+        // The same value moved to another cookie
+        assert_eq!(key.verify("admin", &signed), None);
+        // A changed signature
+        let mut bytes = base64_decode(signature).unwrap();
+        bytes[0] ^= 1;
+        let forged = format!("user.7.{}", base64_encode(&bytes));
+        assert_eq!(key.verify("session", &forged), None);
+        // Cut, missing or not base64
+        assert_eq!(key.verify("session", &signed[..signed.len() - 3]), None);
+        assert_eq!(key.verify("session", "user"), None);
+        assert_eq!(key.verify("session", "user.7.*"), None);
+        // Signed with another secret
+        let other = CookieKey::new(&[9; 32]).unwrap();
+        assert_eq!(other.verify("session", &signed), None);
+    }
+
+    #[cfg(feature = "private-cookies")]
+    #[test]
+    fn private_values_round_trip() {
+        let key = key();
+        let sealed = key.encrypt("session", "user 7; admin").unwrap();
+        // A fresh nonce every time
+        assert_ne!(sealed, key.encrypt("session", "user 7; admin").unwrap());
+        let header = format!("session={sealed}");
+        let jar = CookieJar::from_request(&request(&[header.as_str()]));
+        assert_eq!(
+            jar.get_private("session", &key).as_deref(),
+            Some("user 7; admin")
+        );
+    }
+
+    #[cfg(feature = "private-cookies")]
+    #[test]
+    fn rejects_tampered_private_values() {
+        let key = key();
+        let sealed = key.encrypt("session", "user 7").unwrap();
+        assert_eq!(key.decrypt("admin", &sealed), None);
+
+        let bytes = base64_decode(&sealed).unwrap();
+        // Every byte is covered, the nonce as well as the ciphertext and its tag
+        for index in [0, 11, 12, bytes.len() - 1] {
+            let mut tampered = bytes.clone();
+            tampered[index] ^= 1;
+            assert_eq!(key.decrypt("session", &base64_encode(&tampered)), None);
+        }
+        assert_eq!(
+            key.decrypt("session", &base64_encode(&bytes[..bytes.len() - 1])),
+            None
+        );
+        assert_eq!(key.decrypt("session", &base64_encode(&bytes[..11])), None);
+        assert_eq!(key.decrypt("session", "not base64!"), None);
+
+        let other = CookieKey::new(&[9; 32]).unwrap();
+        assert_eq!(other.decrypt("session", &sealed), None);
+        // A signed value isn't a private one
+        assert_eq!(key.decrypt("session", &key.sign("session", "user 7")), None);
+    }
+}
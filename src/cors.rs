@@ -0,0 +1,240 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::app::BoxedHandlerFuture;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::middleware::Next;
+
+/***
+ * Cross-origin access for browsers, see App::cors() and Router::cors():
+ *
+ *   app.cors(
+ *       Cors::new()
+ *           .allow_origin("https://app.example.com")
+ *           .allow_origin("https://admin.example.com")
+ *           .allow_credentials(true)
+ *           .max_age(Duration::from_secs(600)),
+ *   );
+ *
+ * Preflights (OPTIONS with access-control-request-method) are answered with 204 right away and
+ * never reach a handler, other responses to an allowed origin get access-control-allow-origin.
+ * A disallowed origin, method or header only leaves the CORS headers out, the browser blocks
+ * the request then. Nothing is allowed until allow_origin() or allow_any_origin()
+ ***/
+#[derive(Debug, Clone)]
+pub struct Cors {
+    // None allows any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    // None allows the headers the preflight asks for
+    headers: Option<Vec<String>>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Default for Cors {
+    fn default() -> Self {
+        let methods = ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE"];
+        Cors {
+            origins: Some(Vec::new()),
+            methods: methods.iter().map(|method| method.to_string()).collect(),
+            headers: None,
+            expose_headers: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // `https://app.example.com`, or `https://*.example.com` for its subdomains
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let origin = origin.trim_end_matches('/').to_ascii_lowercase();
+        self.origins.get_or_insert_with(Vec::new).push(origin);
+        self
+    }
+
+    // Sent as `*`, or as the request's origin with allow_credentials()
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = None;
+        self
+    }
+
+    // Replaces the allowed methods, GET, HEAD, POST, PUT, PATCH and DELETE by default
+    pub fn allow_methods(mut self, methods: &[&str]) -> Self {
+        self.methods = methods
+            .iter()
+            .map(|method| method.to_ascii_uppercase())
+            .collect();
+        self
+    }
+
+    // Request headers a preflight may ask for, any by default
+    pub fn allow_headers(mut self, headers: &[&str]) -> Self {
+        self.headers = Some(
+            headers
+                .iter()
+                .map(|header| header.to_ascii_lowercase())
+                .collect(),
+        );
+        self
+    }
+
+    // Response headers besides the safelisted ones scripts may read
+    pub fn expose_headers(mut self, headers: &[&str]) -> Self {
+        self.expose_headers = headers.iter().map(|header| header.to_string()).collect();
+        self
+    }
+
+    // Lets requests carry cookies and authorization
+    pub fn allow_credentials(mut self, credentials: bool) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    // How long browsers may cache a preflight result
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub(crate) fn into_middleware<const SSL: bool>(
+        self,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync + 'static
+    {
+        let cors = Arc::new(self);
+        move |mut res, req, next| {
+            if is_preflight(&req) {
+                cors.write_preflight_headers(&mut res, &req);
+                res.write_status("204 No Content".to_string());
+                return Box::pin(res.end(None, false));
+            }
+            cors.write_headers(&mut res, &req);
+            next.run(res, req)
+        }
+    }
+
+    fn write_headers<const SSL: bool>(&self, res: &mut HttpConnection<SSL>, req: &HttpRequest) {
+        if self.origins.is_some() || self.credentials {
+            res.write_header("vary".to_string(), "origin".to_string());
+        }
+        let Some(allow_origin) = self.allow_origin(req) else {
+            return;
+        };
+        res.write_header("access-control-allow-origin".to_string(), allow_origin);
+        if self.credentials {
+            res.write_header(
+                "access-control-allow-credentials".to_string(),
+                "true".to_string(),
+            );
+        }
+        if !self.expose_headers.is_empty() {
+            res.write_header(
+                "access-control-expose-headers".to_string(),
+                self.expose_headers.join(", "),
+            );
+        }
+    }
+
+    fn write_preflight_headers<const SSL: bool>(
+        &self,
+        res: &mut HttpConnection<SSL>,
+        req: &HttpRequest,
+    ) {
+        res.write_header(
+            "vary".to_string(),
+            "origin, access-control-request-method, access-control-request-headers".to_string(),
+        );
+        let Some(allow_origin) = self.allow_origin(req) else {
+            return;
+        };
+        let method = req
+            .get_header("access-control-request-method")
+            .unwrap_or_default()
+            .trim();
+        if !self.methods.iter().any(|allowed| allowed == method) {
+            return;
+        }
+        let requested_headers = req
+            .get_header("access-control-request-headers")
+            .unwrap_or_default();
+        let requested_headers: Vec<String> = requested_headers
+            .split(',')
+            .map(|header| header.trim().to_ascii_lowercase())
+            .filter(|header| !header.is_empty())
+            .collect();
+        if let Some(headers) = self.headers.as_ref() {
+            if !requested_headers
+                .iter()
+                .all(|header| headers.contains(header))
+            {
+                return;
+            }
+        }
+
+        res.write_header("access-control-allow-origin".to_string(), allow_origin);
+        if self.credentials {
+            res.write_header(
+                "access-control-allow-credentials".to_string(),
+                "true".to_string(),
+            );
+        }
+        res.write_header(
+            "access-control-allow-methods".to_string(),
+            self.methods.join(", "),
+        );
+        if !requested_headers.is_empty() {
+            res.write_header(
+                "access-control-allow-headers".to_string(),
+                requested_headers.join(", "),
+            );
+        }
+        if let Some(max_age) = self.max_age {
+            res.write_header(
+                "access-control-max-age".to_string(),
+                max_age.as_secs().to_string(),
+            );
+        }
+    }
+
+    // access-control-allow-origin for the request, None if it has no allowed origin
+    fn allow_origin(&self, req: &HttpRequest) -> Option<String> {
+        let origin = req.get_header("origin")?.trim();
+        match self.origins.as_ref() {
+            None if self.credentials => Some(origin.to_string()),
+            None => Some("*".to_string()),
+            Some(origins) => origins
+                .iter()
+                .any(|allowed| origin_matches(allowed, origin))
+                .then(|| origin.to_string()),
+        }
+    }
+}
+
+fn is_preflight(req: &HttpRequest) -> bool {
+    req.method.eq_ignore_ascii_case("options")
+        && req.get_header("origin").is_some()
+        && req.get_header("access-control-request-method").is_some()
+}
+
+fn origin_matches(allowed: &str, origin: &str) -> bool {
+    let origin = origin.to_ascii_lowercase();
+    let Some((scheme, host)) = allowed.split_once("://*.") else {
+        return origin == allowed;
+    };
+    let Some(origin_host) = origin
+        .strip_prefix(scheme)
+        .and_then(|rest| rest.strip_prefix("://"))
+    else {
+        return false;
+    };
+    origin_host
+        .strip_suffix(host)
+        .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.'))
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use uwebsockets_rs::uws_loop::{loop_defer as native_loop_defer, UwsLoop};
+
+use crate::loop_defer_future::LoopDeferFuture;
+use crate::ws_behavior::WsPerSocketUserDataStorage;
+
+// Process wide, every app shares the same uWS loop
+static PENDING_DEFERS: AtomicUsize = AtomicUsize::new(0);
+static IN_FLIGHT_RESPONSES: AtomicUsize = AtomicUsize::new(0);
+static RUNNING_WS_HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+// Same as uwebsockets_rs loop_defer, but counted in Diagnostics::pending_defers()
+pub(crate) fn loop_defer<C>(uws_loop: UwsLoop, callback: C)
+where
+    C: FnOnce() + Send + 'static,
+{
+    PENDING_DEFERS.fetch_add(1, Ordering::Relaxed);
+    native_loop_defer(uws_loop, move || {
+        PENDING_DEFERS.fetch_sub(1, Ordering::Relaxed);
+        callback();
+    });
+}
+
+pub(crate) fn response_started() {
+    IN_FLIGHT_RESPONSES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn response_dropped() {
+    IN_FLIGHT_RESPONSES.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub(crate) fn in_flight_responses() -> usize {
+    IN_FLIGHT_RESPONSES.load(Ordering::Relaxed)
+}
+
+// Held by the task of a websocket handler until it returns or is dropped
+pub(crate) struct RunningWsHandler;
+
+impl RunningWsHandler {
+    pub(crate) fn new() -> Self {
+        RUNNING_WS_HANDLERS.fetch_add(1, Ordering::Relaxed);
+        RunningWsHandler
+    }
+}
+
+impl Drop for RunningWsHandler {
+    fn drop(&mut self) {
+        RUNNING_WS_HANDLERS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub(crate) fn running_ws_handlers() -> usize {
+    RUNNING_WS_HANDLERS.load(Ordering::Relaxed)
+}
+
+/***
+ * Gauges for the app's internal registries, see App::diagnostics() and App::debug_dump_route().
+ * A steadily growing websocket registry or in-flight count usually means failed upgrades
+ * or handlers that never finish.
+ ***/
+#[derive(Clone)]
+pub struct Diagnostics {
+    pub(crate) ws_storage: WsPerSocketUserDataStorage,
+    pub(crate) uws_loop: UwsLoop,
+    pub(crate) leak_after: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuspectedLeak {
+    // Upgrade was sent but uWS never opened the socket, the entry is never removed
+    UpgradeNeverOpened,
+    // The socket is closed but still registered
+    ClosedNotRemoved,
+    // The handler dropped its stream while the socket is still open
+    HandlerDroppedStream,
+}
+
+#[derive(Debug, Clone)]
+pub struct WsConnectionInfo {
+    pub id: usize,
+    pub age: Duration,
+    pub is_open: bool,
+    // Messages sent through the split() sink that aren't written to the socket yet
+    pub outbound_queued: usize,
+    pub suspected_leak: Option<SuspectedLeak>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiagnosticsSnapshot {
+    pub ws_connections: Vec<WsConnectionInfo>,
+    pub pending_defers: usize,
+    pub in_flight_responses: usize,
+}
+
+impl Diagnostics {
+    pub fn ws_registry_size(&self) -> usize {
+        self.ws_storage.lock().unwrap().len()
+    }
+
+    pub fn pending_defers(&self) -> usize {
+        PENDING_DEFERS.load(Ordering::Relaxed)
+    }
+
+    // HttpConnections that are neither ended nor dropped, includes upgrade requests
+    pub fn in_flight_responses(&self) -> usize {
+        in_flight_responses()
+    }
+
+    // Websocket handlers that didn't return yet, of every app of the process
+    pub fn running_ws_handlers(&self) -> usize {
+        running_ws_handlers()
+    }
+
+    // Age after which a websocket that never opened is reported, 30 seconds by default
+    pub fn leak_after(mut self, leak_after: Duration) -> Self {
+        self.leak_after = leak_after;
+        self
+    }
+
+    // Per socket data is written by uWS callbacks, so it's read on the loop thread too
+    pub async fn snapshot(&self) -> DiagnosticsSnapshot {
+        let connections: Arc<Mutex<Vec<WsConnectionInfo>>> = Default::default();
+        let connections_to_move = connections.clone();
+        let ws_storage = self.ws_storage.clone();
+        let leak_after = self.leak_after;
+        LoopDeferFuture::new(
+            move || {
+                let storage = ws_storage.lock().unwrap();
+                let mut connections = connections_to_move.lock().unwrap();
+                for (id, user_data) in storage.iter() {
+                    let age = user_data.created.elapsed();
+                    let is_open = user_data.is_open.load(Ordering::Relaxed);
+                    let suspected_leak = if !is_open {
+                        Some(SuspectedLeak::ClosedNotRemoved)
+                    } else if user_data.stream.is_some() && age >= leak_after {
+                        Some(SuspectedLeak::UpgradeNeverOpened)
+                    } else if user_data.stream.is_none() && user_data.sink.is_closed() {
+                        Some(SuspectedLeak::HandlerDroppedStream)
+                    } else {
+                        None
+                    };
+                    connections.push(WsConnectionInfo {
+                        id: *id,
+                        age,
+                        is_open,
+                        outbound_queued: user_data.outbound_queued.load(Ordering::Relaxed),
+                        suspected_leak,
+                    });
+                }
+            },
+            self.uws_loop,
+        )
+        .await;
+
+        let mut ws_connections = std::mem::take(&mut *connections.lock().unwrap());
+        ws_connections.sort_by_key(|connection| std::cmp::Reverse(connection.age));
+        DiagnosticsSnapshot {
+            ws_connections,
+            pending_defers: self.pending_defers(),
+            in_flight_responses: self.in_flight_responses(),
+        }
+    }
+}
+
+impl DiagnosticsSnapshot {
+    pub fn suspected_leaks(&self) -> impl Iterator<Item = &WsConnectionInfo> {
+        self.ws_connections
+            .iter()
+            .filter(|connection| connection.suspected_leak.is_some())
+    }
+}
+
+// Gauges in Prometheus text format followed by one comment line per websocket
+impl fmt::Display for DiagnosticsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outbound_queued: usize = self
+            .ws_connections
+            .iter()
+            .map(|connection| connection.outbound_queued)
+            .sum();
+        writeln!(f, "async_uws_ws_connections {}", self.ws_connections.len())?;
+        writeln!(f, "async_uws_ws_outbound_queued {outbound_queued}")?;
+        writeln!(
+            f,
+            "async_uws_ws_suspected_leaks {}",
+            self.suspected_leaks().count()
+        )?;
+        writeln!(f, "async_uws_pending_defers {}", self.pending_defers)?;
+        writeln!(
+            f,
+            "async_uws_in_flight_responses {}",
+            self.in_flight_responses
+        )?;
+        for connection in self.ws_connections.iter() {
+            write!(
+                f,
+                "# ws id={:#x} age_ms={} open={} outbound_queued={}",
+                connection.id,
+                connection.age.as_millis(),
+                connection.is_open,
+                connection.outbound_queued,
+            )?;
+            match connection.suspected_leak {
+                Some(leak) => writeln!(f, " suspected_leak={leak:?}")?,
+                None => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
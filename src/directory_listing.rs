@@ -0,0 +1,178 @@
+use std::cmp::Ordering;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::fs;
+use crate::percent_encoding::percent_encode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingSort {
+    Name,
+    Size,
+    Modified,
+}
+
+#[derive(Debug, Clone)]
+pub struct DirectoryListing {
+    pub sort: ListingSort,
+    pub descending: bool,
+    pub directories_first: bool,
+    pub show_hidden: bool,
+}
+
+impl Default for DirectoryListing {
+    fn default() -> Self {
+        DirectoryListing {
+            sort: ListingSort::Name,
+            descending: false,
+            directories_first: true,
+            show_hidden: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListingEntry {
+    pub name: String,
+    pub is_dir: bool,
+    pub size: u64,
+    // Seconds since unix epoch
+    pub modified: Option<u64>,
+}
+
+impl DirectoryListing {
+    pub async fn read_entries(&self, dir: &Path) -> io::Result<Vec<ListingEntry>> {
+        let mut entries = Vec::new();
+        for (name, metadata) in fs::read_dir(dir).await? {
+            let name = name.to_string_lossy().to_string();
+            if !self.show_hidden && name.starts_with('.') {
+                continue;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs());
+
+            entries.push(ListingEntry {
+                name,
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified,
+            });
+        }
+
+        entries.sort_by(|a, b| self.compare(a, b));
+        Ok(entries)
+    }
+
+    fn compare(&self, a: &ListingEntry, b: &ListingEntry) -> Ordering {
+        if self.directories_first && a.is_dir != b.is_dir {
+            return b.is_dir.cmp(&a.is_dir);
+        }
+
+        let ordering = match self.sort {
+            ListingSort::Name => a.name.cmp(&b.name),
+            ListingSort::Size => a.size.cmp(&b.size),
+            ListingSort::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.cmp(&b.name));
+
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    // `base_url` is the url of the listed directory as requested by the client
+    pub fn render_html(&self, base_url: &str, entries: &[ListingEntry]) -> String {
+        let base_url = base_url.trim_end_matches('/');
+        let title = escape_html(if base_url.is_empty() { "/" } else { base_url });
+        let mut html = String::new();
+        let _ = write!(
+            html,
+            "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Index of {title}</title></head><body><h1>Index of {title}</h1><table><tr><th>Name</th><th>Size</th><th>Modified</th></tr>"
+        );
+        if !base_url.is_empty() {
+            let _ = write!(
+                html,
+                "<tr><td><a href=\"{}/..\">../</a></td><td></td><td></td></tr>",
+                escape_html(base_url)
+            );
+        }
+        for entry in entries {
+            let suffix = if entry.is_dir { "/" } else { "" };
+            let size = if entry.is_dir {
+                "-".to_string()
+            } else {
+                entry.size.to_string()
+            };
+            let modified = entry.modified.map(|m| m.to_string()).unwrap_or_default();
+            let _ = write!(
+                html,
+                "<tr><td><a href=\"{}/{}{suffix}\">{}{suffix}</a></td><td>{size}</td><td>{modified}</td></tr>",
+                escape_html(base_url),
+                escape_html(&percent_encode(&entry.name)),
+                escape_html(&entry.name),
+            );
+        }
+        html.push_str("</table></body></html>");
+        html
+    }
+
+    pub fn render_json(&self, entries: &[ListingEntry]) -> String {
+        let items: Vec<String> = entries
+            .iter()
+            .map(|entry| {
+                let modified = entry
+                    .modified
+                    .map(|m| m.to_string())
+                    .unwrap_or_else(|| "null".to_string());
+                format!(
+                    "{{\"name\":\"{}\",\"is_dir\":{},\"size\":{},\"modified\":{modified}}}",
+                    escape_json(&entry.name),
+                    entry.is_dir,
+                    entry.size
+                )
+            })
+            .collect();
+
+        format!("[{}]", items.join(","))
+    }
+}
+
+fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn escape_json(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for ch in input.chars() {
+        match ch {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if (ch as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", ch as u32);
+            }
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,581 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+use tokio::sync::oneshot::Receiver;
+use uwebsockets_rs::listen_socket::ListenSocket;
+use uwebsockets_rs::us_socket_context_options::UsSocketContextOptions;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::app::{App, AppSSL, AppStruct, BoxedHandlerFuture};
+use crate::app_handle::AppHandle;
+use crate::backplane::{Backplane, BackplaneLink};
+use crate::broadcast::{Broadcast, BroadcastBridge};
+use crate::cancellation::CancellationToken;
+use crate::coalesce::Coalescer;
+use crate::compression::{Compression, Decompression};
+use crate::connection_registry::{ConnectionId, ConnectionRegistry};
+#[cfg(feature = "signed-cookies")]
+use crate::cookie::CookieKey;
+use crate::cors::Cors;
+use crate::embedded_assets::EmbeddedAssets;
+use crate::error::HttpError;
+use crate::guard::Guard;
+use crate::health::Health;
+use crate::http_connection::{FallbackResponse, HttpConnection};
+use crate::http_request::HttpRequest;
+#[cfg(feature = "jwt")]
+use crate::jwt::JwtAuth;
+use crate::live_settings::{LiveSettings, SettingsHandle};
+use crate::middleware::Next;
+use crate::presence::Presence;
+use crate::rate_limit::RateLimiter;
+use crate::response::Response;
+use crate::response_cache::CachedResponse;
+use crate::rooms::Rooms;
+use crate::session::Sessions;
+use crate::shutdown::{ShutdownHandle, WsShutdown};
+use crate::static_files::ServeDir;
+use crate::task;
+use crate::tcp_options::TcpOptions;
+use crate::trusted_proxies::TrustedProxies;
+use crate::validate::Validation;
+use crate::websocket::{SendStatus, Websocket};
+use crate::ws_actor::WsActor;
+use crate::ws_behavior::WsRouteSettings;
+use crate::ws_message::WsMessage;
+
+// Closures can't be generic over SSL, so handlers shared by both apps implement these traits instead
+pub trait HttpHandler: Send + Sync + 'static {
+    fn handle<const SSL: bool>(
+        &self,
+        res: HttpConnection<SSL>,
+        req: HttpRequest,
+    ) -> BoxedHandlerFuture;
+}
+
+pub trait HttpMiddleware: Send + Sync + 'static {
+    fn handle<const SSL: bool>(
+        &self,
+        res: HttpConnection<SSL>,
+        req: HttpRequest,
+        next: Next<SSL>,
+    ) -> BoxedHandlerFuture;
+}
+
+pub trait WsHandler: Send + Sync + 'static {
+    fn handle<const SSL: bool>(&self, ws: Websocket<SSL>) -> BoxedHandlerFuture;
+
+    fn upgrade<const SSL: bool>(&self, req: HttpRequest, res: HttpConnection<SSL>) {
+        HttpConnection::default_upgrade(req, res);
+    }
+}
+
+// WsHandler whose upgrade can await, see App::ws_async_upgrade()
+pub trait AsyncWsHandler: Send + Sync + 'static {
+    fn handle<const SSL: bool>(&self, ws: Websocket<SSL>) -> BoxedHandlerFuture;
+
+    fn upgrade<const SSL: bool>(
+        &self,
+        req: HttpRequest,
+        res: HttpConnection<SSL>,
+    ) -> BoxedHandlerFuture;
+}
+
+/***
+ * Plain and SSL app sharing one route table.
+ * Every route is registered on both underlying uWS apps, `listen` and `listen_ssl` pick the app.
+ * Both apps live on the same loop, so a single `run` serves them.
+ ***/
+pub struct DualApp {
+    plain: App,
+    ssl: AppSSL,
+}
+
+macro_rules! dual_http_route {
+    ($($method:ident),*) => {
+        $(
+            pub fn $method<H: HttpHandler>(&mut self, pattern: &str, handler: H) -> &mut Self {
+                let handler = Arc::new(handler);
+                let plain_handler = handler.clone();
+                self.plain
+                    .$method(pattern, move |res, req| plain_handler.handle(res, req));
+                self.ssl
+                    .$method(pattern, move |res, req| handler.handle(res, req));
+                self
+            }
+        )*
+    };
+}
+
+impl DualApp {
+    pub fn new(ssl_config: UsSocketContextOptions, shutdown_stream: Option<Receiver<()>>) -> Self {
+        let (plain_shutdown, ssl_shutdown) = match shutdown_stream {
+            Some(stream) => {
+                let (plain_sink, plain_stream) = oneshot::channel();
+                let (ssl_sink, ssl_stream) = oneshot::channel();
+                task::spawn("async_uws shutdown fan-out", async move {
+                    let _ = stream.await;
+                    let _ = plain_sink.send(());
+                    let _ = ssl_sink.send(());
+                });
+                (Some(plain_stream), Some(ssl_stream))
+            }
+            None => (None, None),
+        };
+
+        let plain_config = UsSocketContextOptions {
+            key_file_name: None,
+            cert_file_name: None,
+            passphrase: None,
+            dh_params_file_name: None,
+            ca_file_name: None,
+            ssl_ciphers: None,
+            ssl_prefer_low_memory_usage: None,
+        };
+
+        let plain = AppStruct::new(plain_config, plain_shutdown);
+        let mut ssl = AppStruct::new(ssl_config, ssl_shutdown);
+        ssl.share_with(&plain);
+        DualApp { plain, ssl }
+    }
+
+    pub fn data<T>(&mut self, data: T) -> &mut Self
+    where
+        T: Sync + Send + Clone + 'static,
+    {
+        self.plain.data(data.clone());
+        self.ssl.data(data);
+        self
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    pub fn cookie_key(&mut self, key: CookieKey) -> &mut Self {
+        self.plain.cookie_key(key.clone());
+        self.ssl.cookie_key(key);
+        self
+    }
+
+    pub fn tcp_options(&mut self, tcp_options: TcpOptions) -> &mut Self {
+        self.plain.tcp_options(tcp_options.clone());
+        self.ssl.tcp_options(tcp_options);
+        self
+    }
+
+    pub fn fallback_response(&mut self, fallback_response: Option<FallbackResponse>) -> &mut Self {
+        self.plain.fallback_response(fallback_response.clone());
+        self.ssl.fallback_response(fallback_response);
+        self
+    }
+
+    pub fn panic_response(&mut self, panic_response: FallbackResponse) -> &mut Self {
+        self.plain.panic_response(panic_response.clone());
+        self.ssl.panic_response(panic_response);
+        self
+    }
+
+    pub fn error_handler<F>(&mut self, error_handler: F) -> &mut Self
+    where
+        F: Fn(&HttpError) -> Response + Send + Sync + 'static,
+    {
+        let error_handler = Arc::new(error_handler);
+        let plain_handler = error_handler.clone();
+        self.plain.error_handler(move |error| plain_handler(error));
+        self.ssl.error_handler(move |error| error_handler(error));
+        self
+    }
+
+    pub fn request_deadline(&mut self, deadline: Duration) -> &mut Self {
+        self.plain.request_deadline(deadline);
+        self.ssl.request_deadline(deadline);
+        self
+    }
+
+    pub fn route_deadline(&mut self, pattern: &str, deadline: Duration) -> &mut Self {
+        self.plain.route_deadline(pattern, deadline);
+        self.ssl.route_deadline(pattern, deadline);
+        self
+    }
+
+    pub fn route_validation(&mut self, pattern: &str, validation: Validation) -> &mut Self {
+        self.plain.route_validation(pattern, validation);
+        self.ssl.route_validation(pattern, validation);
+        self
+    }
+
+    pub fn route_guard(&mut self, pattern: &str, guard: Guard) -> &mut Self {
+        self.plain.route_guard(pattern, guard.clone());
+        self.ssl.route_guard(pattern, guard);
+        self
+    }
+
+    pub fn middleware<M: HttpMiddleware>(&mut self, middleware: M) -> &mut Self {
+        let middleware = Arc::new(middleware);
+        let plain_middleware = middleware.clone();
+        self.plain
+            .middleware(move |res, req, next| plain_middleware.handle(res, req, next));
+        self.ssl
+            .middleware(move |res, req, next| middleware.handle(res, req, next));
+        self
+    }
+
+    pub fn route_middleware<M: HttpMiddleware>(
+        &mut self,
+        pattern: &str,
+        middleware: M,
+    ) -> &mut Self {
+        let middleware = Arc::new(middleware);
+        let plain_middleware = middleware.clone();
+        self.plain.route_middleware(pattern, move |res, req, next| {
+            plain_middleware.handle(res, req, next)
+        });
+        self.ssl.route_middleware(pattern, move |res, req, next| {
+            middleware.handle(res, req, next)
+        });
+        self
+    }
+
+    pub fn cors(&mut self, cors: Cors) -> &mut Self {
+        self.plain.cors(cors.clone());
+        self.ssl.cors(cors);
+        self
+    }
+
+    pub fn sessions(&mut self, sessions: Sessions) -> &mut Self {
+        self.plain.sessions(sessions.clone());
+        self.ssl.sessions(sessions);
+        self
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn jwt_auth(&mut self, auth: JwtAuth) -> &mut Self {
+        self.plain.jwt_auth(auth.clone());
+        self.ssl.jwt_auth(auth);
+        self
+    }
+
+    #[cfg(feature = "jwt")]
+    pub fn route_jwt_auth(&mut self, pattern: &str, auth: JwtAuth) -> &mut Self {
+        self.plain.route_jwt_auth(pattern, auth.clone());
+        self.ssl.route_jwt_auth(pattern, auth);
+        self
+    }
+
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.plain.compression(compression.clone());
+        self.ssl.compression(compression);
+        self
+    }
+
+    pub fn route_compression(&mut self, pattern: &str, compression: Compression) -> &mut Self {
+        self.plain.route_compression(pattern, compression.clone());
+        self.ssl.route_compression(pattern, compression);
+        self
+    }
+
+    pub fn decompression(&mut self, decompression: Decompression) -> &mut Self {
+        self.plain.decompression(decompression.clone());
+        self.ssl.decompression(decompression);
+        self
+    }
+
+    pub fn route_decompression(
+        &mut self,
+        pattern: &str,
+        decompression: Decompression,
+    ) -> &mut Self {
+        self.plain
+            .route_decompression(pattern, decompression.clone());
+        self.ssl.route_decompression(pattern, decompression);
+        self
+    }
+
+    pub fn rate_limit(&mut self, limiter: RateLimiter) -> &mut Self {
+        self.plain.rate_limit(limiter.clone());
+        self.ssl.rate_limit(limiter);
+        self
+    }
+
+    pub fn route_rate_limit(&mut self, pattern: &str, limiter: RateLimiter) -> &mut Self {
+        self.plain.route_rate_limit(pattern, limiter.clone());
+        self.ssl.route_rate_limit(pattern, limiter);
+        self
+    }
+
+    pub fn trusted_proxies(&mut self, proxies: TrustedProxies) -> &mut Self {
+        self.plain.trusted_proxies(proxies.clone());
+        self.ssl.trusted_proxies(proxies);
+        self
+    }
+
+    pub fn max_body_size(&mut self, bytes: u64) -> &mut Self {
+        self.plain.max_body_size(bytes);
+        self.ssl.max_body_size(bytes);
+        self
+    }
+
+    pub fn route_max_body_size(&mut self, pattern: &str, bytes: u64) -> &mut Self {
+        self.plain.route_max_body_size(pattern, bytes);
+        self.ssl.route_max_body_size(pattern, bytes);
+        self
+    }
+
+    // Both apps share their settings
+    pub fn update_settings<F>(&self, update: F) -> &Self
+    where
+        F: FnOnce(&mut LiveSettings) + Send + 'static,
+    {
+        self.plain.update_settings(update);
+        self
+    }
+
+    pub fn settings_handle(&self) -> SettingsHandle {
+        self.plain.settings_handle()
+    }
+
+    pub fn first_byte_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.plain.first_byte_timeout(timeout);
+        self.ssl.first_byte_timeout(timeout);
+        self
+    }
+
+    pub fn route_first_byte_timeout(&mut self, pattern: &str, timeout: Duration) -> &mut Self {
+        self.plain.route_first_byte_timeout(pattern, timeout);
+        self.ssl.route_first_byte_timeout(pattern, timeout);
+        self
+    }
+
+    // Shared by both apps
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.plain.cancellation_token()
+    }
+
+    // Topics are per uWS app, so this publishes on both
+    pub fn publish(&self, topic: &str, message: &[u8], opcode: Opcode, compress: bool) -> bool {
+        let plain = self.plain.publish(topic, message, opcode.clone(), compress);
+        let ssl = self.ssl.publish(topic, message, opcode, compress);
+        plain || ssl
+    }
+
+    pub fn num_subscribers(&self, topic: &str) -> u32 {
+        self.plain.num_subscribers(topic) + self.ssl.num_subscribers(topic)
+    }
+
+    pub fn handle(&self) -> AppHandle {
+        self.plain.handle().join(self.ssl.handle())
+    }
+
+    // Messages of the other nodes are published on both apps, see App::backplane()
+    pub fn backplane(&mut self, backplane: impl Backplane + 'static) -> &mut Self {
+        let link = BackplaneLink::start(backplane, self.handle(), self.plain.cancellation_token());
+        // Shared with the ssl app
+        self.plain.set_backplane(link);
+        self
+    }
+
+    // Publishes locally on both apps, see App::broadcast()
+    pub fn broadcast(&self, bridge: impl BroadcastBridge + 'static) -> Broadcast {
+        Broadcast::start(bridge, self.handle(), self.plain.cancellation_token())
+    }
+
+    pub fn presence(&self, bridge: impl BroadcastBridge + 'static) -> Presence {
+        self.plain.presence(bridge)
+    }
+
+    // Broadcasts reach the members on both apps
+    pub fn rooms(&self) -> Rooms {
+        Rooms::new(self.handle())
+    }
+
+    // One registry holds the sockets of both apps
+    pub fn connection_registry(&self) -> ConnectionRegistry {
+        self.plain.connection_registry()
+    }
+
+    pub async fn send_to(
+        &self,
+        id: ConnectionId,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        self.plain.send_to(id, message).await
+    }
+
+    pub async fn disconnect(&self, id: ConnectionId, code: i32, reason: Option<&str>) -> bool {
+        self.plain.disconnect(id, code, reason).await
+    }
+
+    // Shuts down both apps
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.plain
+            .shutdown_handle()
+            .join(self.ssl.shutdown_handle())
+    }
+
+    // Both apps drain their websockets, see App::ws_shutdown()
+    pub fn ws_shutdown(&mut self, ws_shutdown: WsShutdown) -> &mut Self {
+        self.plain.ws_shutdown(ws_shutdown.clone());
+        self.ssl.ws_shutdown(ws_shutdown);
+        self
+    }
+
+    // Both apps serve the same checks and readiness
+    pub fn health(&mut self) -> Health {
+        self.ssl.health();
+        self.plain.health()
+    }
+
+    dual_http_route!(get, post, patch, delete, options, put, trace, connect, any);
+
+    pub fn fallback<H: HttpHandler>(&mut self, handler: H) -> &mut Self {
+        let handler = Arc::new(handler);
+        let plain_handler = handler.clone();
+        self.plain
+            .fallback(move |res, req| plain_handler.handle(res, req));
+        self.ssl.fallback(move |res, req| handler.handle(res, req));
+        self
+    }
+
+    pub fn ws<H: WsHandler>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+        handler: H,
+    ) -> Result<&mut Self, String> {
+        let handler = Arc::new(handler);
+        let plain_handler = handler.clone();
+        let plain_upgrade = handler.clone();
+        let ssl_upgrade = handler.clone();
+        self.plain.ws(
+            pattern,
+            route_settings.clone(),
+            move |ws| plain_handler.handle(ws),
+            move |req, res| plain_upgrade.upgrade(req, res),
+        )?;
+        self.ssl.ws(
+            pattern,
+            route_settings,
+            move |ws| handler.handle(ws),
+            move |req, res| ssl_upgrade.upgrade(req, res),
+        )?;
+        Ok(self)
+    }
+
+    pub fn ws_async_upgrade<H: AsyncWsHandler>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+        handler: H,
+    ) -> Result<&mut Self, String> {
+        let handler = Arc::new(handler);
+        let plain_handler = handler.clone();
+        let plain_upgrade = handler.clone();
+        let ssl_upgrade = handler.clone();
+        self.plain.ws_async_upgrade(
+            pattern,
+            route_settings.clone(),
+            move |ws| plain_handler.handle(ws),
+            move |req, res| plain_upgrade.upgrade(req, res),
+        )?;
+        self.ssl.ws_async_upgrade(
+            pattern,
+            route_settings,
+            move |ws| handler.handle(ws),
+            move |req, res| ssl_upgrade.upgrade(req, res),
+        )?;
+        Ok(self)
+    }
+
+    pub fn ws_actor<A: WsActor>(
+        &mut self,
+        pattern: &str,
+        route_settings: WsRouteSettings,
+    ) -> Result<&mut Self, String> {
+        self.plain.ws_actor::<A>(pattern, route_settings.clone())?;
+        self.ssl.ws_actor::<A>(pattern, route_settings)?;
+        Ok(self)
+    }
+
+    // Both apps serve the same cached response, generated once
+    pub fn cached_route<T, W>(&mut self, path: &str, ttl: Duration, generator: T) -> &mut Self
+    where
+        T: (Fn() -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        let generator = Arc::new(generator);
+        let plain_generator = generator.clone();
+        self.plain
+            .cached_route(path, ttl, move || plain_generator());
+        self.ssl.cached_route(path, ttl, move || generator());
+        self
+    }
+
+    // Both apps share `coalescer`, identical requests over http and https run the handler once
+    pub fn coalesced_route<T, W>(
+        &mut self,
+        pattern: &str,
+        coalescer: Coalescer,
+        handler: T,
+    ) -> &mut Self
+    where
+        T: (Fn(HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = CachedResponse> + 'static + Send,
+    {
+        let handler = Arc::new(handler);
+        let plain_handler = handler.clone();
+        self.plain
+            .coalesced_route(pattern, coalescer.clone(), move |req| plain_handler(req));
+        self.ssl
+            .coalesced_route(pattern, coalescer, move |req| handler(req));
+        self
+    }
+
+    pub fn invalidate(&self, path: &str) {
+        self.plain.invalidate(path);
+    }
+
+    pub fn serve_dir(&mut self, mount: &str, serve_dir: impl Into<ServeDir>) -> &mut Self {
+        let serve_dir = serve_dir.into();
+        self.plain.serve_dir(mount, serve_dir.clone());
+        self.ssl.serve_dir(mount, serve_dir);
+        self
+    }
+
+    pub fn serve_embedded(&mut self, mount: &str, assets: EmbeddedAssets) -> &mut Self {
+        self.plain.serve_embedded(mount, assets.clone());
+        self.ssl.serve_embedded(mount, assets);
+        self
+    }
+
+    pub fn listen(
+        &mut self,
+        port: u16,
+        handler: Option<impl FnOnce(ListenSocket) + Unpin + 'static>,
+    ) -> &mut Self {
+        self.plain.listen(port, handler);
+        self
+    }
+
+    pub fn listen_ssl(
+        &mut self,
+        port: u16,
+        handler: Option<impl FnOnce(ListenSocket) + Unpin + 'static>,
+    ) -> &mut Self {
+        self.ssl.listen(port, handler);
+        self
+    }
+
+    pub fn plain_app(&mut self) -> &mut App {
+        &mut self.plain
+    }
+
+    pub fn ssl_app(&mut self) -> &mut AppSSL {
+        &mut self.ssl
+    }
+
+    pub fn run(&mut self) {
+        // The loop is shared, run() of the plain app doesn't reach the ssl one
+        self.ssl.add_fallback_route();
+        self.plain.run();
+    }
+}
@@ -0,0 +1,198 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+use bytes::Bytes;
+
+use crate::cache_control::CacheControl;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::percent_encoding::percent_decode;
+use crate::static_files::{
+    content_type, is_page_load, not_found, redirect_to_directory, respond_with_content,
+    select_precompressed, FileContent,
+};
+
+/***
+ * Files compiled into the binary, served by App::serve_embedded() the way ServeDir serves a
+ * directory, so a single binary deployment needs no public directory on disk. Fill it from
+ * include_dir or rust-embed:
+ *
+ *   static PUBLIC: include_dir::Dir = include_dir!("$CARGO_MANIFEST_DIR/public");
+ *   let assets: EmbeddedAssets = PUBLIC
+ *       .find("**")
+ *       .unwrap()
+ *       .filter_map(|entry| entry.as_file())
+ *       .map(|file| (file.path().to_string_lossy(), file.contents()))
+ *       .collect();
+ *
+ *   let assets: EmbeddedAssets = Public::iter()
+ *       .filter_map(|path| Some((path.clone(), Public::get(&path)?.data)))
+ *       .collect();
+ *
+ * Paths are relative to the mount and use `/`. Entity tags hash the content, so they stay the
+ * same across builds of unchanged files, last-modified is only sent once set (e.g. to the build
+ * time). Entries named `file.br` / `file.zst` / `file.gz` are precompressed variants of `file`
+ ***/
+#[derive(Debug, Clone)]
+pub struct EmbeddedAssets {
+    files: HashMap<String, EmbeddedFile>,
+    precompressed: bool,
+    cache_control: Option<CacheControl>,
+    last_modified: Option<SystemTime>,
+    index_file: Option<String>,
+    spa_fallback: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct EmbeddedFile {
+    data: Bytes,
+    etag: String,
+}
+
+impl Default for EmbeddedAssets {
+    fn default() -> Self {
+        EmbeddedAssets {
+            files: HashMap::new(),
+            precompressed: false,
+            cache_control: None,
+            last_modified: None,
+            index_file: Some("index.html".to_string()),
+            spa_fallback: None,
+        }
+    }
+}
+
+impl EmbeddedAssets {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // `&'static [u8]` from include_bytes! / include_dir is served without a copy
+    pub fn insert(&mut self, path: impl AsRef<str>, data: impl Into<Cow<'static, [u8]>>) {
+        let data = match data.into() {
+            Cow::Borrowed(data) => Bytes::from_static(data),
+            Cow::Owned(data) => Bytes::from(data),
+        };
+        let etag = format!("\"{:016x}-{:x}\"", fnv1a(&data), data.len());
+        let path = path.as_ref().trim_start_matches('/').to_string();
+        self.files.insert(path, EmbeddedFile { data, etag });
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    // Same as ServeDir::precompressed(), with the variants being entries of their own
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    pub fn cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    // Sent as last-modified of every asset, there is none by default
+    pub fn last_modified(mut self, last_modified: SystemTime) -> Self {
+        self.last_modified = Some(last_modified);
+        self
+    }
+
+    // See ServeDir::index_file()
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(str::to_string);
+        self
+    }
+
+    // See ServeDir::spa_fallback(), `path` is an entry of the assets
+    pub fn spa_fallback(mut self, path: &str) -> Self {
+        self.spa_fallback = Some(path.trim_start_matches('/').to_string());
+        self
+    }
+
+    pub async fn serve<const SSL: bool>(
+        &self,
+        mut res: HttpConnection<SSL>,
+        req: &HttpRequest,
+        mount: &str,
+    ) {
+        let Some(relative) = req.url.strip_prefix(mount).and_then(percent_decode) else {
+            return not_found(res).await;
+        };
+        let mut path = relative.trim_start_matches('/').to_string();
+        if let Some(index) = self.index_file.as_ref() {
+            if path.is_empty() || path.ends_with('/') {
+                path.push_str(index);
+            } else if !self.files.contains_key(&path)
+                && self.files.contains_key(&format!("{path}/{index}"))
+            {
+                return redirect_to_directory(res, req).await;
+            }
+        }
+
+        let mut cache_control = self.cache_control.clone();
+        if !self.files.contains_key(&path) {
+            match self.spa_fallback.as_ref() {
+                Some(fallback) if is_page_load(req) && self.files.contains_key(fallback) => {
+                    path = fallback.clone();
+                    cache_control = Some(CacheControl::no_cache());
+                }
+                _ => return not_found(res).await,
+            }
+        }
+
+        let mut file = &self.files[&path];
+        if self.precompressed {
+            res.write_header("vary".to_string(), "accept-encoding".to_string());
+            let accept_encoding = req.get_header("accept-encoding").unwrap_or_default();
+            let variant = select_precompressed(accept_encoding, |extension| {
+                self.files.contains_key(&format!("{path}.{extension}"))
+            });
+            if let Some((encoding, extension)) = variant {
+                file = &self.files[&format!("{path}.{extension}")];
+                res.write_header("content-encoding".to_string(), encoding.to_string());
+            }
+        }
+        if let Some(cache_control) = cache_control {
+            res.write_cache_control(cache_control);
+        }
+
+        respond_with_content(
+            res,
+            req,
+            FileContent::Memory(file.data.clone()),
+            file.data.len() as u64,
+            self.last_modified,
+            file.etag.clone(),
+            content_type(Path::new(&path)),
+        )
+        .await;
+    }
+}
+
+impl<P, D> FromIterator<(P, D)> for EmbeddedAssets
+where
+    P: AsRef<str>,
+    D: Into<Cow<'static, [u8]>>,
+{
+    fn from_iter<I: IntoIterator<Item = (P, D)>>(files: I) -> Self {
+        let mut assets = EmbeddedAssets::new();
+        for (path, data) in files {
+            assets.insert(path, data);
+        }
+        assets
+    }
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,150 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use crate::response::{Response, StatusCode};
+use crate::validate::FieldErrors;
+
+// Maps errors of handlers and the app's own (404, 413, timeouts) to responses, see App::error_handler()
+pub type ErrorHandler = Arc<dyn Fn(&HttpError) -> Response + Send + Sync>;
+
+/***
+ * Error a route answers with. Any std::error::Error turns into a 500 with `?`, keeping the
+ * original as source() so an App::error_handler() can map it with downcast_ref():
+ *
+ *   app.error_handler(|error| match error.downcast_ref::<DbError>() {
+ *       Some(DbError::NotFound) => HttpError::not_found().into_response(),
+ *       _ => problem_json(error),
+ *   });
+ *
+ * The message is sent to the client, the one of a converted error is just the status reason
+ ***/
+#[derive(Debug)]
+pub struct HttpError {
+    status: StatusCode,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync>>,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        HttpError {
+            status,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        HttpError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found() -> Self {
+        StatusCode::NOT_FOUND.into()
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn source(&self) -> Option<&(dyn Error + Send + Sync + 'static)> {
+        self.source.as_deref()
+    }
+
+    pub fn downcast_ref<T: Error + 'static>(&self) -> Option<&T> {
+        self.source.as_ref()?.downcast_ref()
+    }
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)?;
+        if let Some(source) = self.source.as_ref() {
+            write!(f, " ({source})")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<StatusCode> for HttpError {
+    fn from(status: StatusCode) -> Self {
+        HttpError::new(status, status.reason())
+    }
+}
+
+impl<E: Error + Send + Sync + 'static> From<E> for HttpError {
+    fn from(error: E) -> Self {
+        HttpError::from(StatusCode::INTERNAL_SERVER_ERROR).with_source(error)
+    }
+}
+
+// RFC 7807 `application/problem+json` body for the error, use as App::error_handler(problem_json)
+pub fn problem_json(error: &HttpError) -> Response {
+    let status = error.status();
+    let title = status.reason();
+    let mut body = format!(
+        "{{\"type\":\"about:blank\",\"title\":{},\"status\":{}",
+        json_string(title),
+        status.as_u16()
+    );
+    if error.message() != title {
+        body.push_str(&format!(",\"detail\":{}", json_string(error.message())));
+    }
+    if let Some(field_errors) = error.downcast_ref::<FieldErrors>() {
+        body.push_str(&format!(",\"errors\":{}", field_errors_json(field_errors)));
+    }
+    body.push('}');
+    Response::new(status)
+        .with_header("content-type", "application/problem+json")
+        .with_body(body)
+}
+
+// {"field":["message",..],..} in the order the fields first appear
+fn field_errors_json(field_errors: &FieldErrors) -> String {
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (field, message) in field_errors.iter() {
+        match fields.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, messages)) => messages.push(message),
+            None => fields.push((field, vec![message])),
+        }
+    }
+    let fields: Vec<String> = fields
+        .into_iter()
+        .map(|(field, messages)| {
+            let messages: Vec<String> = messages.into_iter().map(json_string).collect();
+            format!("{}:[{}]", json_string(field), messages.join(","))
+        })
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
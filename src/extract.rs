@@ -0,0 +1,125 @@
+use std::ops::Deref;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::HttpError;
+use crate::handler::{ExtractFuture, FromRequest};
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+#[cfg(feature = "json")]
+use crate::response::{Json, StatusCode};
+
+/***
+ * Route parameters as `T`:
+ *
+ *   // app.get("/users/:id/:name", ..)
+ *   let Path((id, name)) = Path::<(u32, String)>::extract(&req)?;
+ *
+ * A struct takes the parameters by name, a tuple in the order of the pattern.
+ ***/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl<T: DeserializeOwned> Path<T> {
+    pub fn extract(req: &HttpRequest) -> Result<Self, String> {
+        req.params_as().map(Path)
+    }
+}
+
+impl<T> Path<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// 400 when the parameters don't fit `T`
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Path<T> {
+    fn from_request<'a, const SSL: bool>(
+        _res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        let path = Path::extract(req)
+            .map_err(|e| HttpError::bad_request(format!("Invalid route parameters: {e}")));
+        Box::pin(async move { path })
+    }
+}
+
+/***
+ * Query string as `T`, keys as in HttpRequest::query_as():
+ *
+ *   #[derive(Deserialize)]
+ *   struct Filters { page: Option<u32>, tag: Vec<String> }
+ *   let Query(filters) = Query::<Filters>::extract(&req)?;
+ ***/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    pub fn extract(req: &HttpRequest) -> Result<Self, String> {
+        req.query_as().map(Query)
+    }
+}
+
+impl<T> Query<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Query<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+// 400 when the query string doesn't fit `T`
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Query<T> {
+    fn from_request<'a, const SSL: bool>(
+        _res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        let query = Query::extract(req)
+            .map_err(|e| HttpError::bad_request(format!("Invalid query string: {e}")));
+        Box::pin(async move { query })
+    }
+}
+
+/***
+ * JSON body, collected up to the route's max_body_size (1 MiB if there is none). Consumes the
+ * body, so it goes last. Other content types get 415, bodies that don't parse 400 and larger
+ * ones 413
+ ***/
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned + Send + 'static> FromRequest for Json<T> {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        Box::pin(async move {
+            let is_json = req.get_header("content-type").is_some_and(|content_type| {
+                let mime = content_type.split(';').next().unwrap_or_default().trim();
+                mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+            });
+            if !is_json {
+                let message = "Expected an application/json body";
+                return Err(HttpError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+            }
+            let limit = res.default_body_limit();
+            // body_json() has answered with 400 / 413 already
+            res.body_json(limit)
+                .await
+                .map(Json)
+                .map_err(HttpError::bad_request)
+        })
+    }
+}
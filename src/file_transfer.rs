@@ -0,0 +1,183 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::time::timeout;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::fs;
+use crate::progress::{Progress, ProgressHook};
+use crate::websocket::{SendStatus, Websocket};
+use crate::ws_message::WsMessage;
+
+/***
+ * Sends a file over an open websocket in numbered chunks, so a client that lost the connection
+ * can reconnect and continue where it stopped instead of starting over.
+ *
+ *   server: text   "offer <chunks> <chunk_size> <size>"
+ *   client: text   "resume <seq>"             (0 for a new transfer)
+ *   server: binary <seq: u64 big endian><data> for seq..chunks, one message per chunk
+ *   server: text   "done <chunks>"
+ *
+ * Each chunk is sent as fragments of `fragment_size`, waiting for uWS to drain below
+ * `max_buffered` before every fragment, so slow clients never hit max_backpressure.
+ * Pings and pongs received meanwhile are ignored, any other message fails the transfer.
+ ***/
+#[derive(Clone)]
+pub struct FileTransfer {
+    path: PathBuf,
+    chunk_size: usize,
+    fragment_size: usize,
+    max_buffered: u32,
+    handshake_timeout: Duration,
+    progress: Option<ProgressHook>,
+}
+
+impl FileTransfer {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileTransfer {
+            path: path.into(),
+            chunk_size: 64 * 1024,
+            fragment_size: 16 * 1024,
+            max_buffered: 16 * 1024,
+            handshake_timeout: Duration::from_secs(10),
+            progress: None,
+        }
+    }
+
+    // Clients resume at chunk granularity, 64 KiB by default
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    pub fn fragment_size(mut self, fragment_size: usize) -> Self {
+        self.fragment_size = fragment_size.max(1);
+        self
+    }
+
+    // Keep it below the route's max_backpressure minus fragment_size
+    pub fn max_buffered(mut self, max_buffered: u32) -> Self {
+        self.max_buffered = max_buffered;
+        self
+    }
+
+    // How long the client gets to answer the offer, 10 seconds by default
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    // Called after every chunk, `transferred` includes the part the client resumed from
+    pub fn on_progress<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.progress = Some(Arc::new(hook));
+        self
+    }
+
+    // Returns the number of bytes sent in this session
+    pub async fn send<const SSL: bool>(&self, ws: &mut Websocket<SSL>) -> Result<u64, String> {
+        let size = fs::metadata(&self.path)
+            .await
+            .map_err(|e| format!("Can't read {}: {e}", self.path.display()))?
+            .len();
+        let chunk_size = self.chunk_size as u64;
+        let chunks = size.div_ceil(chunk_size);
+
+        let offer = format!("offer {chunks} {chunk_size} {size}").into_bytes();
+        send_checked(ws, WsMessage::Message(offer.into(), Opcode::Text), true).await?;
+        let first = self.read_resume(ws).await?;
+        if first > chunks {
+            return Err(format!("Client resumes at chunk {first} of {chunks}"));
+        }
+
+        let mut file =
+            fs::read_chunks(self.path.clone(), first * chunk_size..size, self.chunk_size);
+        let mut sent = 0;
+        for seq in first..chunks {
+            let data = match file.recv().await {
+                Some(data) => {
+                    data.map_err(|e| format!("Can't read {}: {e}", self.path.display()))?
+                }
+                None => return Err(format!("{} ended early", self.path.display())),
+            };
+            let mut frame = Vec::with_capacity(8 + data.len());
+            frame.extend_from_slice(&seq.to_be_bytes());
+            frame.extend_from_slice(&data);
+            self.send_fragmented(ws, frame.into()).await?;
+
+            sent += data.len() as u64;
+            if let Some(progress) = self.progress.as_ref() {
+                progress(Progress {
+                    transferred: first * chunk_size + sent,
+                    total: Some(size),
+                });
+            }
+        }
+
+        let done = format!("done {chunks}").into_bytes();
+        send_checked(ws, WsMessage::Message(done.into(), Opcode::Text), true).await?;
+        Ok(sent)
+    }
+
+    async fn read_resume<const SSL: bool>(&self, ws: &mut Websocket<SSL>) -> Result<u64, String> {
+        let answer = async {
+            while let Some(message) = ws.stream.recv().await {
+                let WsMessage::Message(data, opcode) = message else {
+                    if let WsMessage::Close(code, _) = message {
+                        return Err(format!("Client closed the socket ({code}) before resuming"));
+                    }
+                    continue;
+                };
+                let text = String::from_utf8_lossy(&data);
+                return match (opcode, text.strip_prefix("resume ")) {
+                    (Opcode::Text, Some(seq)) => seq
+                        .trim()
+                        .parse()
+                        .map_err(|_| format!("Malformed resume message: {text}")),
+                    _ => Err("Unexpected message during file transfer handshake".to_string()),
+                };
+            }
+            Err("Websocket stream ended before resuming".to_string())
+        };
+        timeout(self.handshake_timeout, answer)
+            .await
+            .map_err(|_| "Client didn't answer the file transfer offer".to_string())?
+    }
+
+    async fn send_fragmented<const SSL: bool>(
+        &self,
+        ws: &mut Websocket<SSL>,
+        frame: Bytes,
+    ) -> Result<(), String> {
+        let fragments = frame.len().div_ceil(self.fragment_size);
+        for index in 0..fragments {
+            ws.flush(self.max_buffered).await;
+            let opcode = if index == 0 {
+                Opcode::Binary
+            } else {
+                Opcode::Continuation
+            };
+            let start = index * self.fragment_size;
+            let end = (start + self.fragment_size).min(frame.len());
+            let message = WsMessage::Message(frame.slice(start..end), opcode);
+            send_checked(ws, message, index == fragments - 1).await?;
+        }
+        Ok(())
+    }
+}
+
+// Backpressure is fine since the message is still queued, the next flush waits for it
+async fn send_checked<const SSL: bool>(
+    ws: &mut Websocket<SSL>,
+    message: WsMessage,
+    fin: bool,
+) -> Result<(), String> {
+    match ws.send_with_options(message, false, fin).await? {
+        SendStatus::Success | SendStatus::Backpressure => Ok(()),
+        status => Err(format!("File transfer stopped, send status: {status:?}")),
+    }
+}
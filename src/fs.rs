@@ -0,0 +1,169 @@
+/***
+ * Positional file IO for static files, file transfers and multipart uploads. With `io-uring`
+ * this is tokio_uring's File, which is !Send and has to be used from a local task, see
+ * task::spawn_local(). Without it the same calls run on tokio's blocking pool.
+ ***/
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use tokio::sync::mpsc;
+
+use crate::task;
+
+#[cfg(feature = "io-uring")]
+pub(crate) use tokio_uring::fs::File;
+
+#[cfg(not(feature = "io-uring"))]
+pub(crate) use blocking::File;
+
+// tokio_uring has no statx or getdents, so metadata and directories come from the blocking pool
+pub(crate) async fn metadata(path: impl AsRef<Path>) -> io::Result<Metadata> {
+    tokio::fs::metadata(path).await
+}
+
+pub(crate) async fn is_file(path: impl AsRef<Path>) -> bool {
+    metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+}
+
+pub(crate) async fn is_dir(path: impl AsRef<Path>) -> bool {
+    metadata(path).await.is_ok_and(|metadata| metadata.is_dir())
+}
+
+// Name and metadata of every entry, read in one go instead of a blocking call per entry
+pub(crate) async fn read_dir(path: impl AsRef<Path>) -> io::Result<Vec<(OsString, Metadata)>> {
+    let path = path.as_ref().to_path_buf();
+    let entries = tokio::task::spawn_blocking(move || -> io::Result<Vec<_>> {
+        std::fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                Ok((entry.file_name(), entry.metadata()?))
+            })
+            .collect()
+    });
+    entries.await.unwrap_or_else(|e| Err(io::Error::other(e)))
+}
+
+// Reads `range` of the file ahead in chunks of `chunk_size` in its own task, the last one may be short
+pub(crate) fn read_chunks(
+    path: PathBuf,
+    range: Range<u64>,
+    chunk_size: usize,
+) -> mpsc::Receiver<io::Result<Vec<u8>>> {
+    let (sink, stream) = mpsc::channel(2);
+    task::spawn_local("async_uws file read", async move {
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = sink.send(Err(e)).await;
+                return;
+            }
+        };
+        let mut position = range.start;
+        while position < range.end {
+            let want = chunk_size.min((range.end - position) as usize);
+            let mut chunk = Vec::with_capacity(want);
+            // read_at may return less than asked for, a chunk is only short at the end of the file
+            while chunk.len() < want {
+                let buf = Vec::with_capacity(want - chunk.len());
+                let (read, buf) = file.read_at(buf, position).await;
+                match read {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        chunk.extend_from_slice(&buf[..read]);
+                        position += read as u64;
+                    }
+                    Err(e) => {
+                        let _ = sink.send(Err(e)).await;
+                        return;
+                    }
+                }
+            }
+            let is_short = chunk.len() < want;
+            if chunk.is_empty() || sink.send(Ok(chunk)).await.is_err() || is_short {
+                break;
+            }
+        }
+        let _ = file.close().await;
+    });
+    stream
+}
+
+#[cfg(not(feature = "io-uring"))]
+mod blocking {
+    use std::io;
+    use std::os::unix::fs::FileExt;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    pub(crate) struct File(Arc<std::fs::File>);
+
+    impl File {
+        pub(crate) async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            blocking(move || std::fs::File::open(path)).await.map(wrap)
+        }
+
+        pub(crate) async fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+            let path = path.as_ref().to_path_buf();
+            blocking(move || std::fs::File::create(path))
+                .await
+                .map(wrap)
+        }
+
+        // Like tokio_uring, reads into the spare capacity of `buf`
+        pub(crate) async fn read_at(
+            &self,
+            mut buf: Vec<u8>,
+            position: u64,
+        ) -> (io::Result<usize>, Vec<u8>) {
+            let file = self.0.clone();
+            let read = blocking(move || {
+                let filled = buf.len();
+                buf.resize(buf.capacity(), 0);
+                let read = file.read_at(&mut buf[filled..], position);
+                buf.truncate(filled + *read.as_ref().unwrap_or(&0));
+                Ok((read, buf))
+            });
+            match read.await {
+                Ok(read) => read,
+                Err(e) => (Err(e), Vec::new()),
+            }
+        }
+
+        pub(crate) async fn write_at(
+            &self,
+            buf: Vec<u8>,
+            position: u64,
+        ) -> (io::Result<usize>, Vec<u8>) {
+            let file = self.0.clone();
+            let written = blocking(move || Ok((file.write_at(&buf, position), buf)));
+            match written.await {
+                Ok(written) => written,
+                Err(e) => (Err(e), Vec::new()),
+            }
+        }
+
+        pub(crate) async fn close(self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn wrap(file: std::fs::File) -> File {
+        File(Arc::new(file))
+    }
+
+    async fn blocking<T, F>(f: F) -> io::Result<T>
+    where
+        F: FnOnce() -> io::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .unwrap_or_else(|e| Err(io::Error::other(e)))
+    }
+}
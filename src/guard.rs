@@ -0,0 +1,96 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::http_request::HttpRequest;
+
+/***
+ * Condition a route only takes requests under. A request a guard turns down goes on to the next
+ * route matching its url, the way uWS orders them, and to App::fallback() / 404 in the end:
+ *
+ *   app.route_guard("/upload", Guard::content_type("multipart/form-data"));
+ *
+ *   let mut admin = Router::new();
+ *   admin.guard(Guard::host("admin.example.com")).get("/", admin_home);
+ *   app.scope("/", admin);
+ *   app.get("/", home);
+ *
+ * Guards run on the loop thread before the handler is spawned, so they should be cheap. Route
+ * parameters and client_ip() aren't resolved yet at that point
+ ***/
+#[derive(Clone)]
+pub struct Guard {
+    check: Arc<dyn Fn(&HttpRequest) -> bool + Send + Sync>,
+}
+
+impl Guard {
+    pub fn new<F>(check: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> bool + Send + Sync + 'static,
+    {
+        Guard {
+            check: Arc::new(check),
+        }
+    }
+
+    // Header `name` with exactly `value`
+    pub fn header(name: &str, value: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        let value = value.to_string();
+        Guard::new(move |req| req.get_header(&name) == Some(value.as_str()))
+    }
+
+    pub fn has_header(name: &str) -> Self {
+        let name = name.to_ascii_lowercase();
+        Guard::new(move |req| req.get_header(&name).is_some())
+    }
+
+    // Media type of the body without its parameters, `application/json` takes `; charset=utf-8` too
+    pub fn content_type(mime: &str) -> Self {
+        let mime = mime.to_string();
+        Guard::new(move |req| {
+            req.get_header("content-type").is_some_and(|content_type| {
+                let media_type = content_type.split(';').next().unwrap_or_default();
+                media_type.trim().eq_ignore_ascii_case(&mime)
+            })
+        })
+    }
+
+    // `host` header without the port
+    pub fn host(host: &str) -> Self {
+        let host = host.to_string();
+        Guard::new(move |req| {
+            req.get_header("host")
+                .is_some_and(|value| strip_port(value).eq_ignore_ascii_case(&host))
+        })
+    }
+
+    // Passes when one of `guards` does
+    pub fn any(guards: impl IntoIterator<Item = Guard>) -> Self {
+        let guards: Vec<Guard> = guards.into_iter().collect();
+        Guard::new(move |req| guards.iter().any(|guard| guard.check(req)))
+    }
+
+    pub fn not(guard: Guard) -> Self {
+        Guard::new(move |req| !guard.check(req))
+    }
+
+    pub fn check(&self, req: &HttpRequest) -> bool {
+        (self.check)(req)
+    }
+}
+
+impl Debug for Guard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Guard").finish_non_exhaustive()
+    }
+}
+
+// `example.com:8080` -> `example.com`, `[::1]:8080` -> `[::1]`
+fn strip_port(host: &str) -> &str {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.ends_with(':') && port.bytes().all(|b| b.is_ascii_digit()) => {
+            name
+        }
+        _ => host,
+    }
+}
@@ -0,0 +1,249 @@
+use std::any::type_name;
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use log::error;
+
+use crate::app::BoxedHandlerFuture;
+use crate::error::HttpError;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::response::IntoResponse;
+
+pub type ExtractFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, HttpError>> + Send + 'a>>;
+
+// An `async fn(&mut HttpConnection<SSL>, HttpRequest) -> Result<R, E>`, see try_handler()
+pub trait ResultHandler<'a, const SSL: bool, R, E>: Send + Sync + 'static {
+    type Future: Future<Output = Result<R, E>> + Send + 'a;
+
+    fn call(&self, res: &'a mut HttpConnection<SSL>, req: HttpRequest) -> Self::Future;
+}
+
+impl<'a, const SSL: bool, F, Fut, R, E> ResultHandler<'a, SSL, R, E> for F
+where
+    F: Fn(&'a mut HttpConnection<SSL>, HttpRequest) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<R, E>> + Send + 'a,
+{
+    type Future = Fut;
+
+    fn call(&self, res: &'a mut HttpConnection<SSL>, req: HttpRequest) -> Fut {
+        self(res, req)
+    }
+}
+
+/***
+ * Route handler from an async fn that returns what to send instead of ending the response:
+ *
+ *   async fn get_user(res: &mut HttpConnection<false>, req: HttpRequest) -> Result<String, HttpError> {
+ *       let users = res.data::<Users>().unwrap();
+ *       let user = users.find(req.param("id").unwrap_or_default()).await?;
+ *       Ok(user.name)
+ *   }
+ *   app.get("/users/:id", try_handler(get_user));
+ *
+ * Ok is sent with HttpConnection::send(), Err with send_error() through the app's
+ * error_handler(), 5xx errors are logged. The handler can still write headers or read the body
+ * through `res`, a response it ended itself is left as it is. Closures can't borrow `res` in
+ * their future, so it has to be an async fn
+ ***/
+pub fn try_handler<const SSL: bool, F, R, E>(
+    handler: F,
+) -> impl Fn(HttpConnection<SSL>, HttpRequest) -> BoxedHandlerFuture + Send + Sync + 'static
+where
+    F: for<'a> ResultHandler<'a, SSL, R, E>,
+    R: IntoResponse + Send + 'static,
+    E: Into<HttpError> + Send + 'static,
+{
+    let handler = Arc::new(handler);
+    move |mut res, req| {
+        let handler = handler.clone();
+        Box::pin(async move {
+            let result = handler.call(&mut res, req).await;
+            match result {
+                Ok(response) => res.send(response).await,
+                Err(e) => send_error(res, e.into()).await,
+            }
+        })
+    }
+}
+
+/***
+ * Argument of a handler() fn, taken from the request before the handler runs. An Err is sent
+ * through the app's error_handler() instead of calling the handler:
+ *
+ *   struct Admin(Claims);
+ *
+ *   impl FromRequest for Admin {
+ *       fn from_request<'a, const SSL: bool>(
+ *           res: &'a mut HttpConnection<SSL>,
+ *           _req: &'a HttpRequest,
+ *       ) -> ExtractFuture<'a, Self> {
+ *           let admin = match res.data::<Claims>() {
+ *               Some(claims) if claims.admin => Ok(Admin(claims.clone())),
+ *               _ => Err(StatusCode::FORBIDDEN.into()),
+ *           };
+ *           Box::pin(async move { admin })
+ *       }
+ *   }
+ *
+ * Extractors reading the body (Json, String, Bytes) consume it and have to be the last argument
+ ***/
+pub trait FromRequest: Sized + Send + 'static {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self>;
+}
+
+// Clone of app, scope or request data as HttpConnection::data() finds it, 500 if there is none
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct State<T>(pub T);
+
+impl<T> State<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Send + Sync + Clone + 'static> FromRequest for State<T> {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        _req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        let state =
+            res.data::<T>().cloned().map(State).ok_or_else(|| {
+                HttpError::internal(format!("No {} in the app data", type_name::<T>()))
+            });
+        Box::pin(async move { state })
+    }
+}
+
+impl FromRequest for HttpRequest {
+    fn from_request<'a, const SSL: bool>(
+        _res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        let req = req.clone();
+        Box::pin(async move { Ok(req) })
+    }
+}
+
+// Whole body up to the route's max_body_size (1 MiB if there is none), larger ones get 413
+impl FromRequest for Bytes {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        _req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        Box::pin(async move {
+            let limit = res.default_body_limit();
+            // get_body_limited() has answered with 413 already
+            res.get_body_limited(limit)
+                .await
+                .map(Bytes::from)
+                .map_err(HttpError::bad_request)
+        })
+    }
+}
+
+// Body as Bytes does, 400 if it isn't UTF-8
+impl FromRequest for String {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        Box::pin(async move {
+            let body = Bytes::from_request(res, req).await?;
+            String::from_utf8(body.to_vec())
+                .map_err(|_| HttpError::bad_request("Request body is not UTF-8"))
+        })
+    }
+}
+
+// An async fn of FromRequest arguments, see handler()
+pub trait Handler<const SSL: bool, Args>: Send + Sync + 'static {
+    fn call(self: Arc<Self>, res: HttpConnection<SSL>, req: HttpRequest) -> BoxedHandlerFuture;
+}
+
+macro_rules! impl_handler {
+    ($($arg:ident),*) => {
+        #[allow(non_snake_case, unused_mut)]
+        impl<const SSL: bool, F, Fut, $($arg,)*> Handler<SSL, ($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse + Send,
+            $($arg: FromRequest,)*
+        {
+            fn call(
+                self: Arc<Self>,
+                mut res: HttpConnection<SSL>,
+                req: HttpRequest,
+            ) -> BoxedHandlerFuture {
+                Box::pin(async move {
+                    $(
+                        let $arg = match $arg::from_request(&mut res, &req).await {
+                            Ok(value) => value,
+                            Err(e) => return send_error(res, e).await,
+                        };
+                    )*
+                    let response = self($($arg),*).await;
+                    res.send(response).await
+                })
+            }
+        }
+    };
+}
+
+impl_handler!();
+impl_handler!(T1);
+impl_handler!(T1, T2);
+impl_handler!(T1, T2, T3);
+impl_handler!(T1, T2, T3, T4);
+impl_handler!(T1, T2, T3, T4, T5);
+impl_handler!(T1, T2, T3, T4, T5, T6);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7);
+impl_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+
+/***
+ * Route handler from an async fn of up to 8 extractors, answering with what it returns:
+ *
+ *   async fn list_users(
+ *       Path(team): Path<u32>,
+ *       Query(filters): Query<Filters>,
+ *       State(db): State<Db>,
+ *   ) -> Result<Json<Vec<User>>, HttpError> {
+ *       Ok(Json(db.users(team, &filters).await?))
+ *   }
+ *   app.get("/teams/:team/users", handler(list_users));
+ *
+ * A request an extractor rejects gets its 4xx (see FromRequest) without the handler running.
+ * Handlers that need the response itself use the closure form or try_handler()
+ ***/
+pub fn handler<const SSL: bool, Args, H>(
+    handler: H,
+) -> impl Fn(HttpConnection<SSL>, HttpRequest) -> BoxedHandlerFuture + Send + Sync + 'static
+where
+    H: Handler<SSL, Args>,
+{
+    let handler = Arc::new(handler);
+    move |res, req| handler.clone().call(res, req)
+}
+
+// 5xx errors are logged, the client gets what error_handler() makes of them
+async fn send_error<const SSL: bool>(res: HttpConnection<SSL>, e: HttpError) {
+    if e.status().is_server_error() {
+        error!("[async_uws] Handler failed with {e}");
+    }
+    res.send_error(e).await
+}
@@ -0,0 +1,216 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+
+use crate::http_connection::HttpConnection;
+use crate::task;
+
+type CheckFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type Check = Arc<dyn Fn() -> CheckFuture + Send + Sync>;
+
+/***
+ * Checks behind the built-in `/healthz` and `/readyz` routes, see App::health().
+ * Both run every check, `/readyz` additionally fails once the app started shutting down
+ * (or set_ready(false) was called), so load balancers stop sending traffic before the drain.
+ ***/
+#[derive(Clone)]
+pub struct Health {
+    checks: Arc<RwLock<Vec<(String, Check)>>>,
+    check_timeout: Arc<RwLock<Duration>>,
+    shutdown_delay: Arc<RwLock<Duration>>,
+    ready: Arc<AtomicBool>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub name: String,
+    pub result: Result<(), String>,
+    pub latency: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub ready: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    // Errors can hold internals (hosts, queries), so they are only logged and not sent
+    fn to_json(&self) -> String {
+        let checks: Vec<String> = self
+            .checks
+            .iter()
+            .map(|check| {
+                if let Err(e) = check.result.as_ref() {
+                    warn!("[async_uws] Health check {} failed: {e}", check.name);
+                }
+                format!(
+                    "\"{}\":{{\"status\":\"{}\",\"latency_ms\":{:.3}}}",
+                    json_escape(&check.name),
+                    status(check.result.is_ok()),
+                    check.latency.as_secs_f64() * 1000.0,
+                )
+            })
+            .collect();
+        format!(
+            "{{\"status\":\"{}\",\"ready\":{},\"checks\":{{{}}}}}",
+            status(self.is_healthy()),
+            self.ready,
+            checks.join(","),
+        )
+    }
+}
+
+impl Health {
+    pub(crate) fn new() -> Self {
+        Health {
+            checks: Default::default(),
+            check_timeout: Arc::new(RwLock::new(Duration::from_secs(5))),
+            shutdown_delay: Default::default(),
+            ready: Arc::new(AtomicBool::new(true)),
+        }
+    }
+
+    // A check that errors or takes longer than check_timeout() fails both routes
+    pub fn add_check<F, R>(&self, name: &str, check: F) -> &Self
+    where
+        F: Fn() -> R + Send + Sync + 'static,
+        R: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let check: Check = Arc::new(move || Box::pin(check()));
+        self.checks.write().unwrap().push((name.to_string(), check));
+        self
+    }
+
+    pub fn check_timeout(&self, check_timeout: Duration) -> &Self {
+        *self.check_timeout.write().unwrap() = check_timeout;
+        self
+    }
+
+    // Time between failing `/readyz` and closing the listeners on shutdown,
+    // should cover the load balancer's probe interval. Zero by default
+    pub fn shutdown_delay(&self, shutdown_delay: Duration) -> &Self {
+        *self.shutdown_delay.write().unwrap() = shutdown_delay;
+        self
+    }
+
+    // E.g. false until caches are warm, the app sets it to false itself on shutdown
+    pub fn set_ready(&self, ready: bool) -> &Self {
+        self.ready.store(ready, Ordering::Relaxed);
+        self
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub(crate) async fn begin_shutdown(&self) {
+        self.set_ready(false);
+        let shutdown_delay = *self.shutdown_delay.read().unwrap();
+        if !shutdown_delay.is_zero() {
+            tokio::time::sleep(shutdown_delay).await;
+        }
+    }
+
+    // Runs all checks concurrently
+    pub async fn report(&self) -> HealthReport {
+        let check_timeout = *self.check_timeout.read().unwrap();
+        let checks = self.checks.read().unwrap().clone();
+
+        let names: Vec<String> = checks.iter().map(|(name, _)| name.clone()).collect();
+        let (sink, mut finished) = mpsc::unbounded_channel();
+        for (index, (name, check)) in checks.into_iter().enumerate() {
+            let sink = sink.clone();
+            task::spawn(&format!("async_uws health check {name}"), async move {
+                let started = Instant::now();
+                let result = match timeout(check_timeout, check()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!("Timed out after {check_timeout:?}")),
+                };
+                let latency = started.elapsed();
+                let _ = sink.send((
+                    index,
+                    CheckResult {
+                        name,
+                        result,
+                        latency,
+                    },
+                ));
+            });
+        }
+        drop(sink);
+
+        let mut results: Vec<Option<CheckResult>> = vec![None; names.len()];
+        while let Some((index, result)) = finished.recv().await {
+            results[index] = Some(result);
+        }
+        // A check that panicked never reports back
+        let checks = results
+            .into_iter()
+            .zip(names)
+            .map(|(result, name)| {
+                result.unwrap_or_else(|| CheckResult {
+                    name,
+                    result: Err("Check panicked".to_string()),
+                    latency: Duration::ZERO,
+                })
+            })
+            .collect();
+
+        HealthReport {
+            ready: self.is_ready(),
+            checks,
+        }
+    }
+
+    pub(crate) async fn respond<const SSL: bool>(
+        &self,
+        mut res: HttpConnection<SSL>,
+        readiness: bool,
+    ) {
+        let report = self.report().await;
+        let passed = report.is_healthy() && (!readiness || report.ready);
+        if passed {
+            res.write_status("200 OK".to_string());
+        } else {
+            res.write_status("503 Service Unavailable".to_string());
+        }
+        res.write_header("content-type".to_string(), "application/json".to_string());
+        res.write_header("cache-control".to_string(), "no-store".to_string());
+        res.end(Some(report.to_json().into_bytes()), false).await;
+    }
+}
+
+fn status(ok: bool) -> &'static str {
+    if ok {
+        "ok"
+    } else {
+        "failing"
+    }
+}
+
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -1,35 +1,231 @@
-use std::ptr::NonNull;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use log::debug;
+use std::ffi::c_int;
+use std::future::Future;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::ptr::{null_mut, NonNull};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use bytes::Bytes;
+use log::{debug, error};
 
-use tokio::sync::mpsc::{unbounded_channel, Receiver};
+use libuwebsockets_sys::{us_socket_close, us_socket_t};
+use tokio::sync::mpsc::{channel, unbounded_channel, Receiver};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{timeout, timeout_at};
 use uwebsockets_rs::http_response::HttpResponseStruct;
-use uwebsockets_rs::uws_loop::{loop_defer, UwsLoop};
+use uwebsockets_rs::uws_loop::UwsLoop;
 use uwebsockets_rs::websocket_behavior::UpgradeContext;
 
 use crate::body_reader::{BodyChunk, BodyReader};
-use crate::data_storage::SharedDataStorage;
+use crate::cache_control::CacheControl;
+use crate::cancellation::CancellationToken;
+use crate::compression::{Compression, Encoding};
+use crate::cookie::Cookie;
+#[cfg(feature = "signed-cookies")]
+use crate::cookie::CookieKey;
+use crate::data_storage::{DataStorage, SharedDataStorage};
+use crate::diagnostics::{self, loop_defer};
+use crate::error::{ErrorHandler, HttpError};
+use crate::fs;
 use crate::http_request::HttpRequest;
+#[cfg(feature = "jwt")]
+use crate::jwt::JwtClaims;
+use crate::loop_bound::LoopBound;
 use crate::loop_defer_future::LoopDeferFuture;
+use crate::multipart::{request_boundary, Multipart};
+use crate::progress::{content_length, DownloadProgress, Progress, ProgressSlot};
+use crate::response::{IntoResponse, Response, StatusCode};
+use crate::session::Session;
+use crate::sse::SseStream;
+use crate::static_files::{content_type, respond_with_file};
+use crate::validate::Validation;
 use crate::ws_behavior::{WsPerSocketUserData, WsPerSocketUserDataStorage};
 use crate::ws_message::WsMessage;
 
+pub use crate::body_reader::BodyStream;
+
+// Read ahead per chunk by send_file()
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+// Runs right before the status and headers go out, returns headers to add (e.g. a session cookie)
+pub(crate) type BeforeSend =
+    Box<dyn FnOnce() -> Pin<Box<dyn Future<Output = Vec<(String, String)>> + Send>> + Send>;
+
 pub struct HttpConnection<const SSL: bool> {
-    pub(crate) native: Option<HttpResponseStruct<SSL>>,
+    pub(crate) native: Option<LoopBound<HttpResponseStruct<SSL>>>,
     pub(crate) uws_loop: UwsLoop,
     pub(crate) body_reader: Option<BodyReader<SSL>>,
+    // Body that was already read by a wrapper (e.g. webhook verification)
+    buffered_body: Option<Vec<u8>>,
     pub is_aborted: Arc<AtomicBool>,
     data_storage: SharedDataStorage,
+    // Data of the App::scope() routers the route is in, innermost first
+    scope_data: Arc<Vec<SharedDataStorage>>,
+    // Added by middleware for this request only, see set_data()
+    request_data: DataStorage,
     per_socket_data_storage: Option<WsPerSocketUserDataStorage>,
-    upgrade_context: Option<UpgradeContext>,
+    upgrade_context: Option<LoopBound<UpgradeContext>>,
     headers: Option<Vec<(String, String)>>,
     response_status: Option<String>,
+    default_cache_control: Option<CacheControl>,
+    alpn_protocol: Option<String>,
+    remote_address: Option<String>,
+    state: ResponseStateHandle,
+    deadline: Option<Instant>,
+    first_byte_deadline: Option<Instant>,
+    // Route pattern and the response sent if this is dropped without end() / upgrade()
+    fallback: Option<(String, Arc<FallbackResponse>)>,
+    cancellation: Option<CancellationToken>,
+    upload_progress: Option<ProgressSlot>,
+    // Set once the whole request body came in, see timeout_response()
+    body_complete: Option<Arc<AtomicBool>>,
+    // Set once the body went past body_limit
+    body_overflow: Option<Arc<AtomicBool>>,
+    // Set when the handler panicked, see task::catch_panic()
+    panicked: Arc<AtomicBool>,
+    panic_response: Option<Arc<FallbackResponse>>,
+    error_handler: Option<ErrorHandler>,
+    // HEAD answered by a GET route, bodies are left out but their length is sent
+    head_request: bool,
+    download_progress: Option<DownloadProgress>,
+    // Created by the first write() or try_end()
+    writable: Option<Arc<WritableSignal<SSL>>>,
+    // LiveSettings::max_body_size of the route
+    body_limit: Option<u64>,
+    // Set by App::compression() with the encoding negotiated for the request
+    compression: Option<(Arc<Compression>, Option<Encoding>)>,
+    before_send: Vec<BeforeSend>,
+    // What the Valid extractor does on the route, see App::route_validation()
+    validation: Validation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseState {
+    NotStarted,
+    HeadersSent,
+    Streaming,
+    Finished,
+    Aborted,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseStateHandle {
+    state: Arc<AtomicU8>,
+    is_aborted: Arc<AtomicBool>,
+}
+
+impl ResponseStateHandle {
+    pub fn get(&self) -> ResponseState {
+        let state = match self.state.load(Ordering::Acquire) {
+            0 => ResponseState::NotStarted,
+            1 => ResponseState::HeadersSent,
+            2 => ResponseState::Streaming,
+            _ => ResponseState::Finished,
+        };
+        if state != ResponseState::Finished && self.is_aborted.load(Ordering::Relaxed) {
+            return ResponseState::Aborted;
+        }
+        state
+    }
+
+    fn set(&self, state: ResponseState) {
+        let state = match state {
+            ResponseState::NotStarted => 0,
+            ResponseState::HeadersSent => 1,
+            ResponseState::Streaming => 2,
+            ResponseState::Finished => 3,
+            // Derived from is_aborted
+            ResponseState::Aborted => return,
+        };
+        self.state.store(state, Ordering::Release);
+    }
+}
+
+// Status and headers collected until the first write
+struct ResponseHead {
+    status: Option<String>,
+    headers: Vec<(String, String)>,
+    default_cache_control: Option<CacheControl>,
+}
+
+impl ResponseHead {
+    fn write_to<const SSL: bool>(self, connection: &HttpResponseStruct<SSL>) {
+        if let Some(status) = self.status.as_ref() {
+            connection.write_status(status);
+        }
+
+        let mut has_cache_control = false;
+        for (key, value) in self.headers.iter() {
+            has_cache_control |= key.eq_ignore_ascii_case("cache-control");
+            connection.write_header(key, value);
+        }
+
+        if let Some(cache_control) = self.default_cache_control.filter(|_| !has_cache_control) {
+            connection.write_header("cache-control", &cache_control.header_value());
+        }
+    }
 }
 
-unsafe impl<const SSL: bool> Sync for HttpConnection<SSL> {}
+// Woken by uWS onWritable once a response under backpressure can take more data
+struct WritableSignal<const SSL: bool> {
+    notify: Arc<Notify>,
+    // Clone of the response holding the onWritable handler, released once the response ended
+    handler: Mutex<Option<LoopBound<HttpResponseStruct<SSL>>>>,
+}
+
+impl<const SSL: bool> WritableSignal<SSL> {
+    fn new() -> Self {
+        WritableSignal {
+            notify: Default::default(),
+            handler: Mutex::new(None),
+        }
+    }
+
+    // On the loop thread, registers the handler on the first backpressure only
+    fn register(&self, connection: &HttpResponseStruct<SSL>) {
+        let mut handler = self.handler.lock().unwrap();
+        if handler.is_some() {
+            return;
+        }
+        let mut native = connection.clone();
+        let notify = self.notify.clone();
+        native.on_writable(move |_| {
+            notify.notify_one();
+            true
+        });
+        *handler = Some(LoopBound::new(native));
+    }
+
+    // On the loop thread, uWS doesn't call onWritable of an ended or aborted response
+    fn release(&self) {
+        if let Some(native) = self.handler.lock().unwrap().take() {
+            native.into_inner().deinit();
+        }
+    }
+}
 
-unsafe impl<const SSL: bool> Send for HttpConnection<SSL> {}
+/***
+ * Sent when a handler drops its HttpConnection without responding (panic, cancellation, early return),
+ * so the client doesn't hang until the idle timeout.
+ ***/
+#[derive(Debug, Clone)]
+pub struct FallbackResponse {
+    pub status: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Vec<u8>>,
+}
+
+impl Default for FallbackResponse {
+    fn default() -> Self {
+        FallbackResponse {
+            status: "500 Internal Server Error".to_string(),
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+}
 
 impl<const SSL: bool> HttpConnection<SSL> {
     pub fn new(
@@ -43,52 +239,460 @@ impl<const SSL: bool> HttpConnection<SSL> {
         // Will be not None only for upgrade requests
         upgrade_context: Option<UpgradeContext>,
     ) -> Self {
+        diagnostics::response_started();
+        let upload_progress = body_reader.as_ref().map(BodyReader::progress);
+        let body_complete = body_reader.as_ref().map(BodyReader::completion);
+        let body_overflow = body_reader.as_ref().map(BodyReader::overflow);
         HttpConnection {
-            native: Some(native_response),
-            is_aborted,
+            native: Some(LoopBound::new(native_response)),
+            is_aborted: is_aborted.clone(),
             uws_loop,
             data_storage,
+            scope_data: Default::default(),
+            request_data: DataStorage::new(),
             per_socket_data_storage,
-            upgrade_context,
+            upgrade_context: upgrade_context.map(LoopBound::new),
             body_reader,
+            buffered_body: None,
             headers: None,
             response_status: None,
+            default_cache_control: None,
+            alpn_protocol: None,
+            remote_address: None,
+            state: ResponseStateHandle {
+                state: Default::default(),
+                is_aborted: is_aborted.clone(),
+            },
+            deadline: None,
+            first_byte_deadline: None,
+            fallback: None,
+            cancellation: None,
+            upload_progress,
+            body_complete,
+            body_overflow,
+            panicked: Default::default(),
+            panic_response: None,
+            error_handler: None,
+            head_request: false,
+            download_progress: None,
+            writable: None,
+            body_limit: None,
+            compression: None,
+            before_send: Vec::new(),
+            validation: Validation::default(),
         }
     }
 
-    // Will be none if there is no "content-length" header presented in request
+    // Will be none if the request has neither "content-length" nor "transfer-encoding".
+    // A body over body_limit() is answered with 413 and comes back as None, so is one still
+    // coming in at deadline() with 408. has_responded() tells them apart from an empty body
     pub async fn get_body(&mut self) -> Option<Vec<u8>> {
-        if let Some(body) = self.body_reader.take() {
-            body.collect().await
-        } else {
-            None
+        if let Some(body) = self.buffered_body.take() {
+            return Some(body).filter(|body| !body.is_empty());
+        }
+        let body = self.body_reader.take()?;
+        let cancellation = self.cancellation.clone();
+        let collect = async move {
+            match cancellation {
+                Some(cancellation) => tokio::select! {
+                    body = body.collect() => body,
+                    _ = cancellation.cancelled() => None,
+                },
+                None => body.collect().await,
+            }
+        };
+        let body = match self.deadline {
+            Some(deadline) => match timeout_at(deadline.into(), collect).await {
+                Ok(body) => body,
+                Err(_) => {
+                    self.reject_body(StatusCode::REQUEST_TIMEOUT).await;
+                    return None;
+                }
+            },
+            None => collect.await,
+        };
+        if self.body_overflowed() {
+            self.reject_body(StatusCode::PAYLOAD_TOO_LARGE).await;
+            return None;
+        }
+        body
+    }
+
+    // Max body size of the route, see App::route_max_body_size()
+    pub fn body_limit(&self) -> Option<u64> {
+        self.body_limit
+    }
+
+    pub(crate) fn set_body_limit(&mut self, limit: Option<u64>) {
+        self.body_limit = limit;
+        if let Some(reader) = self.body_reader.as_ref() {
+            reader.set_limit(limit);
         }
     }
 
-    // Will be none if there is no "content-length" header presented in request
+    fn body_overflowed(&self) -> bool {
+        self.body_overflow
+            .as_ref()
+            .is_some_and(|overflow| overflow.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn before_send(&mut self, hook: BeforeSend) {
+        self.before_send.push(hook);
+    }
+
+    async fn run_before_send(&mut self) {
+        for hook in std::mem::take(&mut self.before_send) {
+            for (key, value) in hook().await {
+                self.write_header(key, value);
+            }
+        }
+    }
+
+    pub(crate) fn set_compression(
+        &mut self,
+        compression: Arc<Compression>,
+        encoding: Option<Encoding>,
+    ) {
+        self.compression = Some((compression, encoding));
+    }
+
+    // Will be none if the request has neither "content-length" nor "transfer-encoding".
+    // Past body_limit() it ends without the fin chunk, 413 is sent when the handler returns
     pub fn get_body_stream(&mut self) -> Result<Receiver<BodyChunk>, String> {
+        if let Some(body) = self.buffered_body.take() {
+            let (sink, stream) = channel(1);
+            let _ = sink.try_send((Bytes::from(body), true));
+            return Ok(stream);
+        }
         match self.body_reader.take() {
             None => Err("Body could be read only once".to_string()),
-            Some(body) => Ok(body.take_stream()),
+            Some(body) => match self.cancellation.clone() {
+                Some(cancellation) => Ok(body.take_stream_until(cancellation)),
+                None => Ok(body.take_stream()),
+            },
         }
     }
 
-    pub fn data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
-        self.data_storage.as_ref().get_data::<T>()
+    // Same as get_body_stream(), without the fin flag
+    pub fn body_stream(&mut self) -> Result<BodyStream, String> {
+        Ok(BodyStream::new(self.get_body_stream()?))
     }
 
-    pub async fn end(self, data: Option<Vec<u8>>, close_connection: bool) {
-        let callback = move || {
-            let connection = self.native.unwrap();
-            if let Some(status) = self.response_status.as_ref() {
-                connection.write_status(status);
+    /***
+     * Collects the body up to `limit` bytes. A larger body (declared by content-length or
+     * actually sent) is answered with 413 and the connection is closed, the handler only has to
+     * return on Err. Empty if there is no body
+     ***/
+    pub async fn get_body_limited(&mut self, limit: usize) -> Result<Vec<u8>, String> {
+        let too_large = format!("Request body is larger than {limit} bytes");
+        if let Some(body) = self.buffered_body.take() {
+            if body.len() > limit {
+                self.reject_body(StatusCode::PAYLOAD_TOO_LARGE).await;
+                return Err(too_large);
             }
+            return Ok(body);
+        }
+        let Some(reader) = self.body_reader.take() else {
+            return Ok(Vec::new());
+        };
+        if reader
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            self.reject_body(StatusCode::PAYLOAD_TOO_LARGE).await;
+            return Err(too_large);
+        }
 
-            if let Some(headers) = self.headers {
-                for (key, value) in headers.iter() {
-                    connection.write_header(key, value);
+        let mut stream = match self.cancellation.clone() {
+            Some(cancellation) => BodyStream::new(reader.take_stream_until(cancellation)),
+            None => BodyStream::new(reader.take_stream()),
+        };
+        let collect = async {
+            let mut body = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                if body.len() + chunk.len() > limit {
+                    return Err(too_large.clone());
                 }
+                body.extend_from_slice(&chunk);
+            }
+            match stream.is_finished() {
+                true => Ok(body),
+                false => Err("Request body is incomplete".to_string()),
+            }
+        };
+        let body = match self.deadline {
+            Some(deadline) => match timeout_at(deadline.into(), collect).await {
+                Ok(body) => body,
+                Err(_) => {
+                    self.reject_body(StatusCode::REQUEST_TIMEOUT).await;
+                    return Err("Request body deadline passed".to_string());
+                }
+            },
+            None => collect.await,
+        };
+        if body.is_err() && self.body_overflowed() {
+            self.reject_body(StatusCode::PAYLOAD_TOO_LARGE).await;
+            return Err(format!(
+                "Request body is larger than {} bytes",
+                self.body_limit.unwrap_or_default()
+            ));
+        }
+        if body.as_ref().is_err_and(|e| *e == too_large) {
+            self.reject_body(StatusCode::PAYLOAD_TOO_LARGE).await;
+        }
+        body
+    }
+
+    /***
+     * JSON body into `T`, collected as by get_body_limited(). A body that doesn't parse is
+     * answered with 400 and the parser's message, the handler only has to return on Err
+     ***/
+    #[cfg(feature = "json")]
+    pub async fn body_json<T: serde::de::DeserializeOwned>(
+        &mut self,
+        limit: usize,
+    ) -> Result<T, String> {
+        let body = self.get_body_limited(limit).await?;
+        match serde_json::from_slice(&body) {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                let message = format!("Invalid JSON body: {e}");
+                self.reject_request(StatusCode::BAD_REQUEST, &message).await;
+                Err(message)
+            }
+        }
+    }
+
+    // Ends the response with `value` serialized, 500 if that fails
+    #[cfg(feature = "json")]
+    pub async fn json<T: serde::Serialize + ?Sized>(mut self, value: &T) {
+        match serde_json::to_vec(value) {
+            Ok(body) => {
+                self.write_header("content-type".to_string(), "application/json".to_string());
+                self.end(Some(body), false).await;
+            }
+            Err(e) => {
+                error!("[async_uws] Can't serialize JSON response: {e}");
+                self.write_status("500 Internal Server Error".to_string());
+                self.end(None, false).await;
             }
+        }
+    }
+
+    /***
+     * application/x-www-form-urlencoded body into `T`, keys as in HttpRequest::query_as().
+     * Collected up to the route's max_body_size (1 MiB if there is none) as by get_body_limited(),
+     * other content types get 415 and bodies that don't parse 400
+     ***/
+    #[cfg(feature = "serde")]
+    pub async fn body_form<T: serde::de::DeserializeOwned>(
+        &mut self,
+        req: &HttpRequest,
+    ) -> Result<T, String> {
+        let is_form = req.get_header("content-type").is_some_and(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or_default();
+            mime.trim()
+                .eq_ignore_ascii_case("application/x-www-form-urlencoded")
+        });
+        if !is_form {
+            let message = "Expected an application/x-www-form-urlencoded body".to_string();
+            self.reject_request(StatusCode::UNSUPPORTED_MEDIA_TYPE, &message)
+                .await;
+            return Err(message);
+        }
+
+        let body = self.get_body_limited(self.default_body_limit()).await?;
+        let form = String::from_utf8(body)
+            .map_err(|_| "Form body is not UTF-8".to_string())
+            .and_then(|body| crate::query_string::from_query(&body));
+        if let Err(e) = form.as_ref() {
+            self.reject_request(StatusCode::BAD_REQUEST, &format!("Invalid form body: {e}"))
+                .await;
+        }
+        form
+    }
+
+    // Error answered for the handler, which only has to return
+    #[cfg(feature = "serde")]
+    async fn reject_request(&mut self, status: StatusCode, message: &str) {
+        let response = self.error_response(HttpError::new(status, message));
+        self.write_response(response, false).await;
+    }
+
+    // Route's max_body_size, 1 MiB if there is none
+    pub(crate) fn default_body_limit(&self) -> usize {
+        self.body_limit.map_or(1024 * 1024, |limit| limit as usize)
+    }
+
+    // 413 / 408 with the connection closed, so the rest of the body isn't read
+    async fn reject_body(&mut self, status: StatusCode) {
+        let response = self.error_response(status.into());
+        self.write_response(response, true).await;
+    }
+
+    // Parts of a multipart/form-data body as they arrive, consumes the body
+    pub fn multipart(&mut self, req: &HttpRequest) -> Result<Multipart, String> {
+        let boundary = request_boundary(req)?;
+        Ok(Multipart::with_boundary(&boundary, self.get_body_stream()?))
+    }
+
+    // Called with the bytes received so far as the request body arrives, runs on the loop thread.
+    // Does nothing for requests without a body
+    pub fn on_upload_progress<F>(&mut self, hook: F)
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        if let Some(progress) = self.upload_progress.as_ref() {
+            progress.set(Arc::new(hook));
+        }
+    }
+
+    // Called after each write() and on end() with the bytes of the response body sent so far.
+    // The total comes from a "content-length" header set by the handler
+    pub fn on_download_progress<F>(&mut self, hook: F)
+    where
+        F: Fn(Progress) + Send + Sync + 'static,
+    {
+        self.download_progress = Some(DownloadProgress {
+            hook: Arc::new(hook),
+            sent: 0,
+            total: None,
+        });
+    }
+
+    // Turns the response into a Server-Sent Events stream, status and headers are set already
+    pub fn sse(self) -> SseStream<SSL> {
+        SseStream::new(self)
+    }
+
+    // Makes an already consumed body readable again by get_body / get_body_stream
+    pub fn replace_body(&mut self, body: Vec<u8>) {
+        self.body_reader = None;
+        self.buffered_body = Some(body);
+    }
+
+    pub fn data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
+        self.request_data
+            .get_data::<T>()
+            .or_else(|| self.scope_data.iter().find_map(|data| data.get_data::<T>()))
+            .or_else(|| self.data_storage.as_ref().get_data::<T>())
+    }
+
+    // Data for this request only, seen by data() of the middleware and handler after the caller.
+    // Shadows scope and app data of the same type
+    pub fn set_data<T: Send + Sync + Clone + 'static>(&mut self, data: T) {
+        self.request_data.add_data(data);
+    }
+
+    // Session of the request behind App::sessions()
+    pub fn session(&self) -> Option<&Session> {
+        self.data::<Session>()
+    }
+
+    // Claims of the bearer token behind App::jwt_auth()
+    #[cfg(feature = "jwt")]
+    pub fn claims(&self) -> Option<&JwtClaims> {
+        self.data::<JwtClaims>()
+    }
+
+    pub(crate) fn set_scope_data(&mut self, data: Arc<Vec<SharedDataStorage>>) {
+        self.scope_data = data;
+    }
+
+    // Same as end(Some(body), ..) for anything that turns into bytes: String, &'static str, Bytes
+    pub async fn end_with(mut self, body: impl Into<Bytes>, close_connection: bool) {
+        self.finish(Some(body.into()), close_connection).await
+    }
+
+    pub async fn end(mut self, data: Option<Vec<u8>>, close_connection: bool) {
+        self.finish(data.map(Bytes::from), close_connection).await
+    }
+
+    // Sends status, headers and body of `response`, see IntoResponse
+    pub async fn send(mut self, response: impl IntoResponse) {
+        self.write_response(response.into_response(), false).await
+    }
+
+    // Sends the error as the app's error_handler() maps it
+    pub async fn send_error(mut self, error: HttpError) {
+        let response = self.error_response(error);
+        self.write_response(response, false).await
+    }
+
+    pub(crate) fn set_error_handler(&mut self, error_handler: ErrorHandler) {
+        self.error_handler = Some(error_handler);
+    }
+
+    // send_error() for the app's own rejections, before the handler runs
+    pub(crate) async fn reject(mut self, status: StatusCode, close_connection: bool) {
+        let response = self.error_response(status.into());
+        self.write_response(response, close_connection).await
+    }
+
+    fn error_response(&self, error: HttpError) -> Response {
+        match self.error_handler.as_ref() {
+            Some(error_handler) => error_handler(&error),
+            None => error.into_response(),
+        }
+    }
+
+    async fn write_response(&mut self, response: Response, close_connection: bool) {
+        let (status, headers, body) = response.into_parts();
+        self.write_status(status.to_string());
+        for (key, value) in headers {
+            self.write_header(key, value);
+        }
+        self.finish(body, close_connection).await
+    }
+
+    // end() for the cases that answer for the handler and leave the connection with it
+    async fn finish(&mut self, data: Option<Bytes>, close_connection: bool) {
+        if self.state.get() == ResponseState::NotStarted {
+            self.run_before_send().await;
+        }
+        let data = match (self.compression.take(), data) {
+            (Some((compression, encoding)), Some(data))
+                if self.state.get() == ResponseState::NotStarted =>
+            {
+                let headers = self.headers.get_or_insert_with(Vec::new);
+                let status = self.response_status.as_deref();
+                Some(compression.compress(encoding, status, headers, data))
+            }
+            (_, data) => data,
+        };
+        let len = data.as_ref().map_or(0, Bytes::len);
+        let Some(end) = self.take_end(data, close_connection) else {
+            return;
+        };
+        LoopDeferFuture::new(end, self.uws_loop).await;
+
+        if let Some(mut progress) = self.download_progress.take() {
+            let sent = progress.sent + len as u64;
+            progress.total = progress.total.or(Some(sent));
+            progress.add(len);
+        }
+    }
+
+    // Callback ending the response on the loop, None if it already ended or was upgraded
+    fn take_end(
+        &mut self,
+        data: Option<Bytes>,
+        close_connection: bool,
+    ) -> Option<impl FnOnce() + Send + 'static> {
+        let native = self.native.take()?;
+        let data = match data {
+            Some(data) if self.head_request => {
+                self.report_content_length(data.len() as u64);
+                None
+            }
+            data => data,
+        };
+        let head = self.take_head();
+        let state = self.state.clone();
+        let writable = self.writable.clone();
+        Some(move || {
+            let connection = native.into_inner();
+            head.write_to(&connection);
 
             if data.is_some() {
                 let response = data.as_deref();
@@ -96,8 +700,299 @@ impl<const SSL: bool> HttpConnection<SSL> {
             } else {
                 connection.end_without_body(close_connection);
             }
+            state.set(ResponseState::Finished);
+            if let Some(writable) = writable {
+                writable.release();
+            }
+        })
+    }
+
+    // Sends status and headers now, body follows with write() and end()
+    pub async fn send_headers(&mut self) -> Result<(), String> {
+        if self.state.get() != ResponseState::NotStarted {
+            return Ok(());
+        }
+        self.run_before_send().await;
+        let head = self.take_head();
+        self.run_on_loop(ResponseState::HeadersSent, move |connection| {
+            head.write_to(connection)
+        })
+        .await
+    }
+
+    // Writes a body chunk using chunked transfer encoding, sends the headers first if needed.
+    // uWS buffers what the client can't take yet, this waits until that is drained.
+    // end() sends the terminating chunk
+    pub async fn write(&mut self, chunk: impl Into<Bytes>) -> Result<(), String> {
+        let chunk = chunk.into();
+        self.drop_content_length();
+        self.send_headers().await?;
+        if self.head_request {
+            return Ok(());
+        }
+        let len = chunk.len();
+        let writable = self.writable_signal();
+        let signal = writable.clone();
+        let (sink, stream) = oneshot::channel();
+        self.run_on_loop(ResponseState::Streaming, move |connection| {
+            let ok = connection.write(&chunk);
+            if !ok {
+                signal.register(connection);
+            }
+            let _ = sink.send(ok);
+        })
+        .await?;
+
+        if let Some(progress) = self.download_progress.as_mut() {
+            progress.add(len);
+        }
+        if let Ok(false) = stream.await {
+            self.wait_writable(&writable).await?;
+        }
+        Ok(())
+    }
+
+    /***
+     * Sends the next `chunk` of a body of `total_size` bytes, uWS adds the content-length.
+     * Only the part the client can take is written at once, the rest is sent as uWS reports the
+     * response writable again, so a slow client holds one chunk in memory and not the whole body.
+     * Returns true once `total_size` bytes are out and the response is finished.
+     * Not to be mixed with write()
+     ***/
+    pub async fn try_end(
+        &mut self,
+        chunk: impl Into<Bytes>,
+        total_size: u64,
+    ) -> Result<bool, String> {
+        let chunk = chunk.into();
+        if self.head_request {
+            self.report_content_length(total_size);
+            self.finish(None, false).await;
+            return Ok(true);
+        }
+        self.send_headers().await?;
+        if let Some(progress) = self.download_progress.as_mut() {
+            progress.total = Some(total_size);
+        }
+        let writable = self.writable_signal();
+        let mut sent = 0;
+        loop {
+            let (sink, stream) = oneshot::channel();
+            let part = chunk.clone();
+            let signal = writable.clone();
+            self.run_on_loop(ResponseState::Streaming, move |connection| {
+                let offset = connection.get_write_offset();
+                let result = connection.try_end(Some(&part[sent..]), total_size, false);
+                if result.has_responded {
+                    signal.release();
+                    let _ = sink.send((true, part.len() - sent));
+                    return;
+                }
+                if !result.ok {
+                    signal.register(connection);
+                }
+                let written = (connection.get_write_offset() - offset) as usize;
+                let _ = sink.send((false, written));
+            })
+            .await?;
+            let (has_responded, written) = stream
+                .await
+                .map_err(|_| "Response is aborted".to_string())?;
+
+            sent += written;
+            if let Some(progress) = self.download_progress.as_mut() {
+                progress.add(written);
+            }
+            if has_responded {
+                self.native = None;
+                self.state.set(ResponseState::Finished);
+                return Ok(true);
+            }
+            if sent == chunk.len() {
+                return Ok(false);
+            }
+            self.wait_writable(&writable).await?;
+        }
+    }
+
+    /***
+     * Sends the file at `path` as the body. It's read in chunks through io_uring (tokio's
+     * blocking pool without the `io-uring` feature) and written as the client takes them, like
+     * try_end(), so a large file never sits in memory. uWS owns the socket and its TLS, so there
+     * is no sendfile / splice, every chunk passes through its write buffer.
+     * content-type is guessed from the extension unless the handler wrote one. A missing file
+     * gets 404, a read error before anything was sent 500, one while streaming closes the connection
+     ***/
+    pub async fn send_file(mut self, path: impl AsRef<Path>) -> Result<(), String> {
+        let path = path.as_ref().to_path_buf();
+        let len = match fs::metadata(&path).await {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            Ok(_) => {
+                self.write_status("404 Not Found".to_string());
+                self.finish(None, false).await;
+                return Err(format!("{} is not a file", path.display()));
+            }
+            Err(e) => {
+                let status = match e.kind() {
+                    std::io::ErrorKind::NotFound => "404 Not Found",
+                    _ => "500 Internal Server Error",
+                };
+                self.write_status(status.to_string());
+                self.finish(None, false).await;
+                return Err(format!("Can't read {}: {e}", path.display()));
+            }
         };
-        LoopDeferFuture::new(callback, self.uws_loop).await;
+        if !self.has_header("content-type") {
+            self.write_header("content-type".to_string(), content_type(&path).to_string());
+        }
+
+        let sent = self.stream_file(path, 0..len).await;
+        if sent.is_err() && self.state.get() == ResponseState::NotStarted {
+            self.write_status("500 Internal Server Error".to_string());
+            self.finish(None, false).await;
+        }
+        sent
+    }
+
+    // `range` of the file as the whole body, status and headers are up to the caller
+    pub(crate) async fn stream_file(
+        &mut self,
+        path: PathBuf,
+        range: Range<u64>,
+    ) -> Result<(), String> {
+        let total = range.end - range.start;
+        if total == 0 {
+            self.finish(Some(Bytes::new()), false).await;
+            return Ok(());
+        }
+        let mut chunks = fs::read_chunks(path.clone(), range, FILE_CHUNK_SIZE);
+        while let Some(chunk) = chunks.recv().await {
+            let chunk = chunk.map_err(|e| format!("Can't read {}: {e}", path.display()))?;
+            if self.try_end(chunk, total).await? {
+                return Ok(());
+            }
+        }
+        Err(format!("{} ended early", path.display()))
+    }
+
+    /***
+     * send_file() answering a request for it: etag and last-modified are sent, a matching
+     * if-none-match / if-modified-since gets 304 and Range requests (single or multiple ranges,
+     * with If-Range) get 206 or 416, so video seeking and resumed downloads work
+     ***/
+    pub async fn serve_file(self, req: &HttpRequest, path: impl AsRef<Path>) {
+        let path = path.as_ref().to_path_buf();
+        let default_content_type = content_type(&path);
+        respond_with_file(self, req, path, default_content_type, 0).await
+    }
+
+    /***
+     * Body of unknown length (proxied data, live logs): every chunk received from `chunks` is
+     * written as it comes, the response ends with the terminating chunk once all senders are
+     * dropped. Without any chunk the body is empty and sent with `content-length: 0`. Fails if
+     * the client went away, which drops `chunks` as well
+     ***/
+    pub async fn write_chunks<T>(mut self, mut chunks: Receiver<T>) -> Result<(), String>
+    where
+        T: Into<Bytes>,
+    {
+        while let Some(chunk) = chunks.recv().await {
+            self.write(chunk).await?;
+        }
+        self.drop_content_length();
+        // Nothing was written, uWS adds the content-length for an empty body. Without one (and
+        // without chunked framing) the client would read until the connection closes
+        let body = (self.state.get() != ResponseState::Streaming).then(Vec::new);
+        self.end(body, false).await;
+        Ok(())
+    }
+
+    // content-length of the body a HEAD request leaves out, unless the handler set one
+    fn report_content_length(&mut self, len: u64) {
+        if self.state.get() == ResponseState::NotStarted && !self.has_header("content-length") {
+            self.write_header("content-length".to_string(), len.to_string());
+        }
+    }
+
+    // A chunked body can't have a content-length as well, clients would reject the response
+    fn drop_content_length(&mut self) {
+        if self.state.get() != ResponseState::NotStarted {
+            return;
+        }
+        if let Some(headers) = self.headers.as_mut() {
+            let count = headers.len();
+            headers.retain(|(key, _)| !key.eq_ignore_ascii_case("content-length"));
+            if headers.len() != count {
+                debug!("[async_uws] Dropped content-length of a chunked response");
+            }
+        }
+    }
+
+    fn writable_signal(&mut self) -> Arc<WritableSignal<SSL>> {
+        self.writable
+            .get_or_insert_with(|| Arc::new(WritableSignal::new()))
+            .clone()
+    }
+
+    // There is no onWritable after an abort, so that is polled for
+    async fn wait_writable(&self, writable: &WritableSignal<SSL>) -> Result<(), String> {
+        loop {
+            if self.is_aborted.load(Ordering::Relaxed) {
+                return Err("Response is aborted".to_string());
+            }
+            let notified = writable.notify.notified();
+            if timeout(Duration::from_millis(500), notified).await.is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn response_state(&self) -> ResponseState {
+        self.state.get()
+    }
+
+    // Stays valid after the connection is moved into a handler, for layers that check it afterwards
+    pub fn state_handle(&self) -> ResponseStateHandle {
+        self.state.clone()
+    }
+
+    fn take_head(&mut self) -> ResponseHead {
+        let headers = self.headers.take().unwrap_or_default();
+        if let Some(progress) = self.download_progress.as_mut() {
+            progress.total = content_length(&headers);
+        }
+        ResponseHead {
+            status: self.response_status.take(),
+            headers,
+            default_cache_control: self.default_cache_control.take(),
+        }
+    }
+
+    async fn run_on_loop<C>(&mut self, next_state: ResponseState, callback: C) -> Result<(), String>
+    where
+        C: FnOnce(&HttpResponseStruct<SSL>) + Send + 'static,
+    {
+        let Some(native) = self.native.clone() else {
+            return Err("Response is already finished".to_string());
+        };
+        let state = self.state.clone();
+        LoopDeferFuture::new(
+            move || {
+                // Writing to an aborted response is not allowed by uWS
+                if state.get() == ResponseState::Aborted {
+                    return;
+                }
+                callback(native.get());
+                state.set(next_state);
+            },
+            self.uws_loop,
+        )
+        .await;
+
+        match self.state.get() {
+            ResponseState::Aborted => Err("Response is aborted".to_string()),
+            _ => Ok(()),
+        }
     }
 
     pub fn write_status(&mut self, status: String) {
@@ -112,24 +1007,220 @@ impl<const SSL: bool> HttpConnection<SSL> {
         }
     }
 
+    pub(crate) fn take_header(&mut self, key: &str) -> Option<String> {
+        let headers = self.headers.as_mut()?;
+        let index = headers
+            .iter()
+            .position(|(name, _)| name.eq_ignore_ascii_case(key))?;
+        Some(headers.remove(index).1)
+    }
+
+    pub(crate) fn has_header(&self, key: &str) -> bool {
+        self.headers
+            .iter()
+            .flatten()
+            .any(|(name, _)| name.eq_ignore_ascii_case(key))
+    }
+
+    pub fn write_cache_control(&mut self, cache_control: CacheControl) {
+        self.write_header("cache-control".to_owned(), cache_control.header_value());
+    }
+
+    // One set-cookie header per call
+    pub fn set_cookie(&mut self, cookie: Cookie) {
+        self.write_header("set-cookie".to_string(), cookie.header_value());
+    }
+
+    // Sets `cookie` with its value signed by the App::cookie_key(), fails if there is none
+    #[cfg(feature = "signed-cookies")]
+    pub fn set_signed_cookie(&mut self, cookie: Cookie) -> Result<(), String> {
+        let key = self.cookie_key()?;
+        let value = key.sign(cookie.name(), cookie.value());
+        self.set_cookie(cookie.with_value(value));
+        Ok(())
+    }
+
+    // Sets `cookie` with its value encrypted by the App::cookie_key(), fails if there is none
+    #[cfg(feature = "private-cookies")]
+    pub fn set_private_cookie(&mut self, cookie: Cookie) -> Result<(), String> {
+        let key = self.cookie_key()?;
+        let value = key.encrypt(cookie.name(), cookie.value())?;
+        self.set_cookie(cookie.with_value(value));
+        Ok(())
+    }
+
+    // Value of the signed cookie `name` of `req`, None if it's missing, tampered with or there is no key
+    #[cfg(feature = "signed-cookies")]
+    pub fn signed_cookie(&self, req: &HttpRequest, name: &str) -> Option<String> {
+        req.cookies().get_signed(name, self.cookie_key().ok()?)
+    }
+
+    #[cfg(feature = "private-cookies")]
+    pub fn private_cookie(&self, req: &HttpRequest, name: &str) -> Option<String> {
+        req.cookies().get_private(name, self.cookie_key().ok()?)
+    }
+
+    #[cfg(feature = "signed-cookies")]
+    fn cookie_key(&self) -> Result<&CookieKey, String> {
+        self.data::<CookieKey>()
+            .ok_or_else(|| "No cookie key, see App::cookie_key()".to_string())
+    }
+
+    // Used when the handler doesn't write its own "cache-control" header
+    pub fn set_default_cache_control(&mut self, cache_control: CacheControl) {
+        self.default_cache_control = Some(cache_control);
+    }
+
+    // Protocol negotiated during the TLS handshake, None for plain connections or if client sent no ALPN
+    pub fn alpn_protocol(&self) -> Option<&str> {
+        self.alpn_protocol.as_deref()
+    }
+
+    pub(crate) fn set_alpn_protocol(&mut self, alpn_protocol: Option<String>) {
+        self.alpn_protocol = alpn_protocol;
+    }
+
+    // Client IP as text, taken from the PROXY header for relayed listeners
+    pub fn remote_address(&self) -> Option<&str> {
+        self.remote_address.as_deref()
+    }
+
+    pub(crate) fn set_remote_address(&mut self, remote_address: String) {
+        self.remote_address = Some(remote_address).filter(|address| !address.is_empty());
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /***
+     * Runs `future` (an upstream call, a query) until it completes, deadline() passes or the app
+     * cancels. The latter two give a 504 HttpError, so a try_handler() can just use `?`:
+     *
+     *   let user = res.until_deadline(client.get(url).send()).await??;
+     ***/
+    pub async fn until_deadline<F: Future>(&self, future: F) -> Result<F::Output, HttpError> {
+        let cancellation = self.cancellation_token();
+        let deadline = self.deadline;
+        let bounded = async {
+            match deadline {
+                Some(deadline) => timeout_at(deadline.into(), future).await.ok(),
+                None => Some(future.await),
+            }
+        };
+        let output = tokio::select! {
+            output = bounded => output,
+            _ = cancellation.cancelled() => None,
+        };
+        output.ok_or_else(|| HttpError::new(StatusCode::GATEWAY_TIMEOUT, "Request deadline passed"))
+    }
+
+    pub(crate) fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+
+    // Status and headers have to be sent before it, see App::first_byte_timeout()
+    pub fn first_byte_deadline(&self) -> Option<Instant> {
+        self.first_byte_deadline
+    }
+
+    pub(crate) fn set_first_byte_deadline(&mut self, deadline: Instant) {
+        self.first_byte_deadline = Some(deadline);
+    }
+
+    // Child of App::cancellation_token(), get_body() and body streams end early when it fires
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone().unwrap_or_default()
+    }
+
+    pub(crate) fn set_cancellation(&mut self, cancellation: CancellationToken) {
+        self.cancellation = Some(cancellation);
+    }
+
+    pub(crate) fn set_fallback(&mut self, route: String, fallback: Arc<FallbackResponse>) {
+        self.fallback = Some((route, fallback));
+    }
+
+    pub(crate) fn set_head_request(&mut self) {
+        self.head_request = true;
+    }
+
+    pub(crate) fn set_panic_response(&mut self, response: Arc<FallbackResponse>) {
+        self.panic_response = Some(response);
+    }
+
+    pub(crate) fn set_validation(&mut self, validation: Validation) {
+        self.validation = validation;
+    }
+
+    pub(crate) fn validation(&self) -> Validation {
+        self.validation
+    }
+
+    pub(crate) fn panic_flag(&self) -> Arc<AtomicBool> {
+        self.panicked.clone()
+    }
+
+    /***
+     * Sent when the handler is cancelled at a deadline. 408 while the client is still sending the
+     * body, the rest of it would be read as the next request so the connection is closed too.
+     * 504 once the body is in and the handler was too slow
+     ***/
+    fn timeout_response(&self) -> (FallbackResponse, bool) {
+        let receiving = self
+            .body_complete
+            .as_ref()
+            .is_some_and(|complete| !complete.load(Ordering::Relaxed));
+        let status = match receiving {
+            true => StatusCode::REQUEST_TIMEOUT,
+            false => StatusCode::GATEWAY_TIMEOUT,
+        };
+        (self.error_fallback(status), receiving)
+    }
+
+    // Response to `status` for Drop, just the status without an error_handler()
+    fn error_fallback(&self, status: StatusCode) -> FallbackResponse {
+        let Some(error_handler) = self.error_handler.as_ref() else {
+            return FallbackResponse {
+                status: status.to_string(),
+                ..Default::default()
+            };
+        };
+        let (status, headers, body) = error_handler(&status.into()).into_parts();
+        FallbackResponse {
+            status: status.to_string(),
+            headers,
+            body: body.map(|body| body.to_vec()),
+        }
+    }
+
     pub fn has_responded(&self) -> bool {
-        if let Some(response) = self.native.as_ref() {
-            response.has_responded()
-        } else {
-            true
+        match self.native.as_ref() {
+            Some(response) if response.is_loop_thread() => response.get().has_responded(),
+            // Off the loop thread only an abort can end the response behind our back
+            Some(_) => self.is_aborted.load(Ordering::Relaxed),
+            None => true,
         }
     }
 
+    // `user_data` is read with Websocket::connection_data(), None passes on what set_data() stored
     pub fn upgrade(
-        self,
+        mut self,
         ws_key_string: String,
         ws_protocol: Option<String>,
         ws_extensions: Option<String>,
         user_data: Option<SharedDataStorage>,
     ) {
-        let (sink, stream) = unbounded_channel::<WsMessage>();
+        let (mut sink, stream) = unbounded_channel::<WsMessage>();
+        // Upgraded during shutdown, the handler's stream ends right away
+        let cancellation = self.cancellation_token();
+        if cancellation.is_cancelled() {
+            sink = unbounded_channel().0;
+        }
 
         let ws_per_socket_data_storage = self.per_socket_data_storage.clone().unwrap();
+        let user_data =
+            user_data.unwrap_or_else(|| Arc::new(std::mem::take(&mut self.request_data)));
         let user_data = WsPerSocketUserData {
             sink,
             id: None,
@@ -137,7 +1228,25 @@ impl<const SSL: bool> HttpConnection<SSL> {
             storage: ws_per_socket_data_storage.clone(),
             is_open: Arc::new(AtomicBool::new(true)),
             shared_data_storage: self.data_storage.clone(),
-            custom_user_data: user_data.unwrap_or_default(),
+            custom_user_data: user_data,
+            payload_violations: 0,
+            message_interval: 0,
+            messages_in_interval: 0,
+            rate_window: 0,
+            window_messages: 0,
+            window_bytes: 0,
+            window_reported: false,
+            created: Instant::now(),
+            last_seen: Default::default(),
+            stats: Default::default(),
+            remote_address: None,
+            outbound_queued: Default::default(),
+            cancellation,
+            drained: Default::default(),
+            closed: Default::default(),
+            pending_pings: Default::default(),
+            native_ws: None,
+            connection_id: None,
         };
 
         let mut user_data = Box::new(user_data);
@@ -150,6 +1259,9 @@ impl<const SSL: bool> HttpConnection<SSL> {
         }
 
         let is_aborted = self.is_aborted.clone();
+        let native = self.native.take();
+        let upgrade_context = self.upgrade_context.take();
+        let state = self.state.clone();
         let callback = move || {
             let user_data_ptr = user_data_id as *mut WsPerSocketUserData;
             let mut non_null =
@@ -165,26 +1277,136 @@ impl<const SSL: bool> HttpConnection<SSL> {
                 storage.remove(&user_data_id);
                 return;
             }
-            self.native.unwrap().upgrade(
+            native.unwrap().into_inner().upgrade(
                 &ws_key_string,
                 ws_protocol,
                 ws_extensions,
-                self.upgrade_context.unwrap(),
+                upgrade_context.unwrap().into_inner(),
                 Some(user_data_ref),
             );
+            state.set(ResponseState::Finished);
         };
 
         loop_defer(self.uws_loop, callback)
     }
 
+    /***
+     * Refuses a websocket upgrade with `status`, e.g. "401 Unauthorized" with a www-authenticate
+     * header. Not async, so the upgrade hook of App::ws() can call it as well as an async one
+     ***/
+    pub fn reject_upgrade(
+        mut self,
+        status: &str,
+        headers: Vec<(String, String)>,
+        body: impl Into<Bytes>,
+    ) {
+        self.write_status(status.to_string());
+        for (key, value) in headers {
+            self.write_header(key, value);
+        }
+        if let Some(end) = self.take_end(Some(body.into()), false) {
+            loop_defer(self.uws_loop, end);
+        }
+    }
+
+    // Accepts the upgrade as the client asked, 400 if the request isn't a websocket handshake
     pub fn default_upgrade(req: HttpRequest, res: HttpConnection<SSL>) {
-        let ws_key = req
-            .get_header("sec-websocket-key")
-            .map(String::from)
-            .expect("[async_uws]: There is no sec-websocket-key in req headers");
+        let Some(ws_key) = req.get_header("sec-websocket-key").map(String::from) else {
+            res.reject_upgrade("400 Bad Request", Vec::new(), "Missing sec-websocket-key");
+            return;
+        };
         let ws_protocol = req.get_header("sec-websocket-protocol").map(String::from);
         let ws_extensions = req.get_header("sec-websocket-extensions").map(String::from);
 
         res.upgrade(ws_key, ws_protocol, ws_extensions, None);
     }
 }
+
+impl<const SSL: bool> Drop for HttpConnection<SSL> {
+    fn drop(&mut self) {
+        diagnostics::response_dropped();
+        // end() and upgrade() take the native response, so it's only left here when nobody responded
+        let Some(native) = self.native.take() else {
+            return;
+        };
+        let now = Instant::now();
+        let timed_out = self.deadline.is_some_and(|deadline| now >= deadline)
+            || self
+                .first_byte_deadline
+                .is_some_and(|deadline| now >= deadline);
+        // Dropped while unwinding from a panic in a sync callback, or after one in the handler
+        let panicked = std::thread::panicking() || self.panicked.load(Ordering::Relaxed);
+        // Cut off requests are answered even without App::fallback_response(), closing the
+        // connection when the rest of their body wasn't read
+        let (route, fallback, close_connection) = match self.fallback.take() {
+            _ if panicked => (None, self.panic_response.take().unwrap_or_default(), false),
+            _ if self.body_overflowed() => {
+                let too_large = self.error_fallback(StatusCode::PAYLOAD_TOO_LARGE);
+                (None, Arc::new(too_large), true)
+            }
+            _ if timed_out => {
+                let (timeout, close_connection) = self.timeout_response();
+                (None, Arc::new(timeout), close_connection)
+            }
+            Some((route, fallback)) => (Some(route), fallback, false),
+            None => return,
+        };
+        // Released once the response is closed or aborted below
+        let writable = self.writable.take();
+        // The client is gone, uWS won't take a response anymore
+        if self.is_aborted.load(Ordering::Relaxed) {
+            if let Some(writable) = writable {
+                loop_defer(self.uws_loop, move || writable.release());
+            }
+            return;
+        }
+
+        let is_aborted = self.is_aborted.clone();
+        let state = self.state.clone();
+        loop_defer(self.uws_loop, move || {
+            let response = native.into_inner();
+            if is_aborted.load(Ordering::Relaxed) || response.has_responded() {
+                if let Some(writable) = writable {
+                    writable.release();
+                }
+                return;
+            }
+
+            // Part of the response is already out, the client can only tell it's broken by the connection closing
+            if state.get() != ResponseState::NotStarted {
+                match route.as_deref() {
+                    Some(route) => error!("[async_uws] Handler for {route} dropped the response while streaming it, closing the connection"),
+                    None => debug!("[async_uws] Request was cut off while streaming the response, closing the connection"),
+                }
+                unsafe {
+                    us_socket_close(
+                        SSL as c_int,
+                        response.get_native() as *mut us_socket_t,
+                        0,
+                        null_mut(),
+                    );
+                }
+                if let Some(writable) = writable {
+                    writable.release();
+                }
+                return;
+            }
+
+            match route.as_deref() {
+                Some(route) => error!(
+                    "[async_uws] Handler for {route} dropped the response without ending it, sending {}",
+                    fallback.status
+                ),
+                None => debug!("[async_uws] Request was cut off, sending {}", fallback.status),
+            }
+            response.write_status(&fallback.status);
+            for (key, value) in fallback.headers.iter() {
+                response.write_header(key, value);
+            }
+            match fallback.body.as_deref() {
+                Some(body) => response.end(Some(body), close_connection),
+                None => response.end_without_body(close_connection),
+            }
+        });
+    }
+}
@@ -1,6 +1,12 @@
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
 use uwebsockets_rs::http_request::HttpRequest as SyncHttpRequest;
 
-#[derive(Debug)]
+use crate::cookie::CookieJar;
+use crate::percent_encoding::query_pairs;
+
+#[derive(Debug, Clone)]
 pub struct HttpRequest {
     pub headers: Vec<(String, String)>,
     pub full_url: String,
@@ -8,6 +14,12 @@ pub struct HttpRequest {
     pub method: String,
     pub case_sensitive_method: String,
     pub parameters: Vec<String>,
+    // (name, decoded value) of the route's `:name` and `*name` segments, see param()
+    pub(crate) route_params: Vec<(String, String)>,
+    pub(crate) deadline: Option<Instant>,
+    pub(crate) remote_address: Option<SocketAddr>,
+    // Resolved through App::trusted_proxies(), see client_ip()
+    pub(crate) client_ip: Option<IpAddr>,
 }
 
 impl HttpRequest {
@@ -17,6 +29,77 @@ impl HttpRequest {
             .find(|(key, _)| key == header_name)
             .map(|(_, value)| value.as_str())
     }
+
+    // Parsed `cookie` headers, see CookieJar::get_signed() / get_private() for the protected ones
+    pub fn cookies(&self) -> CookieJar {
+        CookieJar::from_request(self)
+    }
+
+    // Client's IP and port, see HttpConnection::remote_address() for the IP as uWS reports it
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
+    // The forwarded client if the peer is a trusted proxy, see TrustedProxies, the peer otherwise
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or_else(|| self.remote_address.map(|address| address.ip()))
+    }
+
+    // Raw query string without the `?`
+    pub fn query(&self) -> Option<&str> {
+        self.full_url.split_once('?').map(|(_, query)| query)
+    }
+
+    // Decoded key / value pairs in the order they were sent, repeated keys included
+    pub fn query_pairs(&self) -> Vec<(String, String)> {
+        self.query().map(query_pairs).unwrap_or_default()
+    }
+
+    pub fn query_param(&self, key: &str) -> Option<String> {
+        self.query_pairs()
+            .into_iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)
+    }
+
+    // Every value of a repeated key (`tag=a&tag=b`), in the order they were sent
+    pub fn query_params(&self, key: &str) -> Vec<String> {
+        self.query_pairs()
+            .into_iter()
+            .filter(|(name, _)| name == key)
+            .map(|(_, value)| value)
+            .collect()
+    }
+
+    // Query string as `T`, see query_string.rs for the supported key syntax
+    #[cfg(feature = "serde")]
+    pub fn query_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        crate::query_string::from_query(self.query().unwrap_or_default())
+    }
+
+    // `id` of a `/users/:id` route or `path` of a `/files/*path` one, percent-decoded
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.route_params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.route_params
+    }
+
+    // Route parameters as `T`, see extract::Path
+    #[cfg(feature = "serde")]
+    pub fn params_as<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        crate::query_string::from_params(&self.route_params)
+    }
+
+    // Set by App::request_deadline() / App::route_deadline(), the handler is cancelled once it passes
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
 }
 
 impl From<&mut SyncHttpRequest> for HttpRequest {
@@ -40,6 +123,10 @@ impl From<&mut SyncHttpRequest> for HttpRequest {
             method: request.get_method().into(),
             case_sensitive_method: request.get_case_sensitive_method().into(),
             parameters,
+            route_params: Vec::new(),
+            deadline: None,
+            remote_address: None,
+            client_ip: None,
         }
     }
 }
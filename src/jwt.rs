@@ -0,0 +1,654 @@
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use p256::pkcs8::DecodePublicKey;
+use rsa::pkcs1v15::Pkcs1v15Sign;
+use rsa::RsaPublicKey;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::app::BoxedHandlerFuture;
+use crate::base64::base64_decode;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::middleware::Next;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JwtError {
+    Missing,
+    Malformed,
+    // The token's alg doesn't match any key, `none` never does
+    UnsupportedAlgorithm(String),
+    InvalidSignature,
+    MissingExpiry,
+    Expired,
+    NotYetValid,
+    InvalidIssuer,
+    InvalidAudience,
+}
+
+impl Display for JwtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwtError::Missing => write!(f, "Missing bearer token"),
+            JwtError::Malformed => write!(f, "Malformed token"),
+            JwtError::UnsupportedAlgorithm(alg) => write!(f, "Unsupported algorithm {alg}"),
+            JwtError::InvalidSignature => write!(f, "Invalid signature"),
+            JwtError::MissingExpiry => write!(f, "Token has no expiry"),
+            JwtError::Expired => write!(f, "Token is expired"),
+            JwtError::NotYetValid => write!(f, "Token is not valid yet"),
+            JwtError::InvalidIssuer => write!(f, "Invalid issuer"),
+            JwtError::InvalidAudience => write!(f, "Invalid audience"),
+        }
+    }
+}
+
+impl std::error::Error for JwtError {}
+
+// Verified claims of the request's token, res.claims() in handlers behind App::jwt_auth()
+#[derive(Debug, Clone, PartialEq)]
+pub struct JwtClaims {
+    claims: Map<String, Value>,
+}
+
+impl JwtClaims {
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.claims.get(name)
+    }
+
+    pub fn subject(&self) -> Option<&str> {
+        self.claims.get("sub").and_then(Value::as_str)
+    }
+
+    // All claims as `T`, registered ones included
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, String> {
+        serde_json::from_value(Value::Object(self.claims.clone())).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Clone)]
+enum KeyKind {
+    Hs256(Vec<u8>),
+    Rs256(RsaPublicKey),
+    Es256(p256::ecdsa::VerifyingKey),
+}
+
+// Key a token is verified with, it only accepts tokens of its own algorithm
+#[derive(Clone)]
+pub struct JwtKey {
+    kind: KeyKind,
+    kid: Option<String>,
+}
+
+impl JwtKey {
+    pub fn hs256(secret: &[u8]) -> Self {
+        JwtKey {
+            kind: KeyKind::Hs256(secret.to_vec()),
+            kid: None,
+        }
+    }
+
+    // `-----BEGIN PUBLIC KEY-----` PEM of an RSA key
+    pub fn rs256_pem(pem: &str) -> Result<Self, String> {
+        let key = RsaPublicKey::from_public_key_pem(pem)
+            .map_err(|e| format!("Invalid RSA public key: {e}"))?;
+        Ok(JwtKey {
+            kind: KeyKind::Rs256(key),
+            kid: None,
+        })
+    }
+
+    // `-----BEGIN PUBLIC KEY-----` PEM of a P-256 key
+    pub fn es256_pem(pem: &str) -> Result<Self, String> {
+        let key = p256::ecdsa::VerifyingKey::from_public_key_pem(pem)
+            .map_err(|e| format!("Invalid P-256 public key: {e}"))?;
+        Ok(JwtKey {
+            kind: KeyKind::Es256(key),
+            kid: None,
+        })
+    }
+
+    // Tokens naming another kid in their header aren't checked against this key
+    pub fn kid(mut self, kid: impl Into<String>) -> Self {
+        self.kid = Some(kid.into());
+        self
+    }
+
+    fn alg(&self) -> &'static str {
+        match self.kind {
+            KeyKind::Hs256(_) => "HS256",
+            KeyKind::Rs256(_) => "RS256",
+            KeyKind::Es256(_) => "ES256",
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.kind {
+            KeyKind::Hs256(secret) => {
+                let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret) else {
+                    return false;
+                };
+                mac.update(message);
+                mac.verify_slice(signature).is_ok()
+            }
+            KeyKind::Rs256(key) => {
+                let hashed = Sha256::digest(message);
+                key.verify(Pkcs1v15Sign::new::<Sha256>(), &hashed, signature)
+                    .is_ok()
+            }
+            KeyKind::Es256(key) => {
+                use p256::ecdsa::signature::Verifier;
+                // JWS signatures are r || s, not DER
+                let Ok(signature) = p256::ecdsa::Signature::from_slice(signature) else {
+                    return false;
+                };
+                key.verify(message, &signature).is_ok()
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for JwtKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtKey")
+            .field("alg", &self.alg())
+            .field("kid", &self.kid)
+            .finish()
+    }
+}
+
+/***
+ * Bearer token authentication, see App::jwt_auth():
+ *
+ *   app.jwt_auth(
+ *       JwtAuth::new(JwtKey::rs256_pem(&public_key)?)
+ *           .issuer("https://auth.example.com")
+ *           .audience("api"),
+ *   );
+ *   app.get("/me", |res, _| async move {
+ *       let user = res.claims().and_then(|claims| claims.subject()).map(str::to_string);
+ *       res.end_with(user.unwrap_or_default(), false).await;
+ *   });
+ *
+ * The token of `authorization: Bearer <jwt>` has to be signed by one of the keys with the key's
+ * own algorithm, carry an exp in the future and match issuer() and audience() when they are set.
+ * Requests without one get 401 with a `www-authenticate: Bearer` challenge, its claims go to
+ * request data for res.claims(). Websockets are covered with upgrade_hook()
+ ***/
+#[derive(Debug, Clone)]
+pub struct JwtAuth {
+    keys: Vec<JwtKey>,
+    issuers: Vec<String>,
+    audiences: Vec<String>,
+    leeway: Duration,
+    require_exp: bool,
+    optional: bool,
+    query_param: Option<String>,
+}
+
+impl JwtAuth {
+    pub fn new(key: JwtKey) -> Self {
+        JwtAuth {
+            keys: vec![key],
+            issuers: Vec::new(),
+            audiences: Vec::new(),
+            leeway: Duration::from_secs(60),
+            require_exp: true,
+            optional: false,
+            query_param: None,
+        }
+    }
+
+    // Another accepted key, for rotation or several issuers
+    pub fn key(mut self, key: JwtKey) -> Self {
+        self.keys.push(key);
+        self
+    }
+
+    // Accepted iss, any by default. Can be called several times
+    pub fn issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuers.push(issuer.into());
+        self
+    }
+
+    // Accepted aud, the token has to name one of them. Any by default
+    pub fn audience(mut self, audience: impl Into<String>) -> Self {
+        self.audiences.push(audience.into());
+        self
+    }
+
+    // Clock skew allowed for exp and nbf, 60 seconds by default
+    pub fn leeway(mut self, leeway: Duration) -> Self {
+        self.leeway = leeway;
+        self
+    }
+
+    // Accepts tokens without exp, they never expire then
+    pub fn require_exp(mut self, require_exp: bool) -> Self {
+        self.require_exp = require_exp;
+        self
+    }
+
+    // Lets requests without a token through without claims, invalid tokens still get 401
+    pub fn optional(mut self, optional: bool) -> Self {
+        self.optional = optional;
+        self
+    }
+
+    // Also takes the token from this query parameter, browsers can't set headers on websockets
+    pub fn query_param(mut self, name: impl Into<String>) -> Self {
+        self.query_param = Some(name.into());
+        self
+    }
+
+    pub fn verify(&self, token: &str) -> Result<JwtClaims, JwtError> {
+        let (message, signature) = token.rsplit_once('.').ok_or(JwtError::Malformed)?;
+        let (header, payload) = message.split_once('.').ok_or(JwtError::Malformed)?;
+        if payload.contains('.') {
+            return Err(JwtError::Malformed);
+        }
+        let header = decode_object(header)?;
+        let alg = header
+            .get("alg")
+            .and_then(Value::as_str)
+            .ok_or(JwtError::Malformed)?;
+        let kid = header.get("kid").and_then(Value::as_str);
+        let keys: Vec<&JwtKey> = self
+            .keys
+            .iter()
+            .filter(|key| key.alg() == alg)
+            .filter(|key| kid.is_none_or(|kid| key.kid.as_deref().is_none_or(|id| id == kid)))
+            .collect();
+        if keys.is_empty() {
+            return Err(JwtError::UnsupportedAlgorithm(alg.to_string()));
+        }
+        let signature = base64_decode(signature).ok_or(JwtError::Malformed)?;
+        if !keys
+            .iter()
+            .any(|key| key.verify(message.as_bytes(), &signature))
+        {
+            return Err(JwtError::InvalidSignature);
+        }
+
+        let claims = decode_object(payload)?;
+        self.validate(&claims)?;
+        Ok(JwtClaims { claims })
+    }
+
+    fn validate(&self, claims: &Map<String, Value>) -> Result<(), JwtError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        let leeway = self.leeway.as_secs_f64();
+        match claims.get("exp").map(Value::as_f64) {
+            Some(Some(exp)) if exp + leeway <= now => return Err(JwtError::Expired),
+            Some(Some(_)) => {}
+            Some(None) => return Err(JwtError::Malformed),
+            None if self.require_exp => return Err(JwtError::MissingExpiry),
+            None => {}
+        }
+        match claims.get("nbf").map(Value::as_f64) {
+            Some(Some(nbf)) if nbf - leeway > now => return Err(JwtError::NotYetValid),
+            Some(None) => return Err(JwtError::Malformed),
+            _ => {}
+        }
+        if !self.issuers.is_empty() {
+            let issuer = claims.get("iss").and_then(Value::as_str);
+            if !issuer.is_some_and(|issuer| self.issuers.iter().any(|allowed| allowed == issuer)) {
+                return Err(JwtError::InvalidIssuer);
+            }
+        }
+        if !self.audiences.is_empty() {
+            let audiences = match claims.get("aud") {
+                Some(Value::String(audience)) => vec![audience.as_str()],
+                Some(Value::Array(audiences)) => {
+                    audiences.iter().filter_map(Value::as_str).collect()
+                }
+                _ => Vec::new(),
+            };
+            if !audiences
+                .iter()
+                .any(|audience| self.audiences.iter().any(|allowed| allowed == audience))
+            {
+                return Err(JwtError::InvalidAudience);
+            }
+        }
+        Ok(())
+    }
+
+    // Verifies the request's token and stores its claims for res.claims(), Ok(()) without a token
+    // when optional()
+    pub fn authorize<const SSL: bool>(
+        &self,
+        res: &mut HttpConnection<SSL>,
+        req: &HttpRequest,
+    ) -> Result<(), JwtError> {
+        match self.token(req) {
+            Some(token) => {
+                res.set_data(self.verify(&token)?);
+                Ok(())
+            }
+            None if self.optional => Ok(()),
+            None => Err(JwtError::Missing),
+        }
+    }
+
+    fn token(&self, req: &HttpRequest) -> Option<String> {
+        let header = req.get_header("authorization").and_then(|value| {
+            let (scheme, token) = value.trim().split_once(' ')?;
+            scheme
+                .eq_ignore_ascii_case("bearer")
+                .then(|| token.trim().to_string())
+        });
+        header.or_else(|| req.query_param(self.query_param.as_deref()?))
+    }
+
+    pub(crate) fn into_middleware<const SSL: bool>(
+        self,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync + 'static
+    {
+        let auth = Arc::new(self);
+        move |mut res, req, next| match auth.authorize(&mut res, &req) {
+            Ok(()) => next.run(res, req),
+            Err(e) => {
+                res.write_status("401 Unauthorized".to_string());
+                res.write_header("www-authenticate".to_string(), challenge(&e));
+                Box::pin(res.end_with(e.to_string(), false))
+            }
+        }
+    }
+
+    /***
+     * Upgrade hook of App::ws() that authorizes the request before `hook` runs, which finds the
+     * claims with res.claims(). Accepted with HttpConnection::default_upgrade() or upgrade()
+     * without user data, the websocket gets them too, see Websocket::connection_data():
+     *
+     *   app.ws("/ws", settings, handler, auth.upgrade_hook(HttpConnection::default_upgrade))?;
+     ***/
+    pub fn upgrade_hook<const SSL: bool, U>(
+        &self,
+        hook: U,
+    ) -> impl Fn(HttpRequest, HttpConnection<SSL>) + Send + Sync + Clone + 'static
+    where
+        U: Fn(HttpRequest, HttpConnection<SSL>) + Send + Sync + Clone + 'static,
+    {
+        let auth = Arc::new(self.clone());
+        move |req, mut res| match auth.authorize(&mut res, &req) {
+            Ok(()) => hook(req, res),
+            Err(e) => res.reject_upgrade(
+                "401 Unauthorized",
+                vec![("www-authenticate".to_string(), challenge(&e))],
+                e.to_string(),
+            ),
+        }
+    }
+}
+
+// RFC 6750 challenge, a request without a token gets no error code
+fn challenge(e: &JwtError) -> String {
+    match e {
+        JwtError::Missing => "Bearer".to_string(),
+        e => format!("Bearer error=\"invalid_token\", error_description=\"{e}\""),
+    }
+}
+
+fn decode_object(part: &str) -> Result<Map<String, Value>, JwtError> {
+    let json = base64_decode(part).ok_or(JwtError::Malformed)?;
+    match serde_json::from_slice(&json) {
+        Ok(Value::Object(object)) => Ok(object),
+        _ => Err(JwtError::Malformed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+    use serde_json::json;
+
+    use super::*;
+    use crate::base64::base64_encode;
+
+    const SECRET: &[u8] = b"secret of at least some length";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn message(header: &Value, claims: &Value) -> String {
+        format!(
+            "{}.{}",
+            base64_encode(header.to_string().as_bytes()),
+            base64_encode(claims.to_string().as_bytes())
+        )
+    }
+
+    fn hs256_with(header: &Value, claims: &Value, secret: &[u8]) -> String {
+        let message = message(header, claims);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(message.as_bytes());
+        format!("{message}.{}", base64_encode(&mac.finalize().into_bytes()))
+    }
+
+    fn hs256(claims: Value) -> String {
+        hs256_with(&json!({"alg": "HS256", "typ": "JWT"}), &claims, SECRET)
+    }
+
+    fn auth() -> JwtAuth {
+        JwtAuth::new(JwtKey::hs256(SECRET))
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        let token = hs256(json!({"sub": "ann", "exp": now() + 60, "admin": true}));
+        let claims = auth().verify(&token).unwrap();
+        assert_eq!(claims.subject(), Some("ann"));
+        assert_eq!(claims.get("admin"), Some(&Value::Bool(true)));
+    }
+
+    #[test]
+    fn rejects_alg_none() {
+        let claims = json!({"sub": "ann", "exp": now() + 60});
+        for alg in ["none", "None", "NONE", ""] {
+            let unsigned = format!("{}.", message(&json!({"alg": alg}), &claims));
+            assert_eq!(
+                auth().verify(&unsigned),
+                Err(JwtError::UnsupportedAlgorithm(alg.to_string()))
+            );
+        }
+        // Nor is a token without alg taken as unsigned
+        let unsigned = format!("{}.", message(&json!({"typ": "JWT"}), &claims));
+        assert_eq!(auth().verify(&unsigned), Err(JwtError::Malformed));
+    }
+
+    #[test]
+    fn rejects_tampered_tokens() {
+        let token = hs256(json!({"sub": "ann", "exp": now() + 60}));
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let (header, _) = message.split_once('.').unwrap();
+
+        let payload = base64_encode(
+            json!({"sub": "admin", "exp": now() + 60})
+                .to_string()
+                .as_bytes(),
+        );
+        let tampered = format!("{header}.{payload}.{signature}");
+        assert_eq!(auth().verify(&tampered), Err(JwtError::InvalidSignature));
+
+        let other_secret = hs256_with(
+            &json!({"alg": "HS256"}),
+            &json!({"sub": "ann", "exp": now() + 60}),
+            b"another secret",
+        );
+        assert_eq!(
+            auth().verify(&other_secret),
+            Err(JwtError::InvalidSignature)
+        );
+
+        let truncated = &token[..token.len() - 1];
+        assert_eq!(auth().verify(truncated), Err(JwtError::InvalidSignature));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let valid = hs256(json!({"exp": now() + 60}));
+        let not_json = format!(
+            "{}.{}.sig",
+            base64_encode(b"{\"alg\":"),
+            base64_encode(b"{}")
+        );
+        let array = format!(
+            "{}.{}.",
+            base64_encode(b"[\"HS256\"]"),
+            base64_encode(b"{}")
+        );
+        let extra_part = format!("{valid}.extra");
+        let bad_signature = format!("{valid}*");
+        for token in [
+            "",
+            "abc",
+            "a.b",
+            "a.b.c.d",
+            not_json.as_str(),
+            array.as_str(),
+            extra_part.as_str(),
+            bad_signature.as_str(),
+        ] {
+            assert_eq!(auth().verify(token), Err(JwtError::Malformed), "{token}");
+        }
+    }
+
+    #[test]
+    fn keys_only_take_their_own_algorithm() {
+        let signing = SigningKey::from_slice(&[7; 32]).unwrap();
+        let pem = signing
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap();
+        let es256 = JwtAuth::new(JwtKey::es256_pem(&pem).unwrap());
+
+        let claims = json!({"sub": "ann", "exp": now() + 60});
+        let message = message(&json!({"alg": "ES256"}), &claims);
+        let signature: p256::ecdsa::Signature = signing.sign(message.as_bytes());
+        let token = format!("{message}.{}", base64_encode(&signature.to_bytes()));
+        assert_eq!(es256.verify(&token).unwrap().subject(), Some("ann"));
+
+        // An HS256 token "signed" with the public key must not pass as ES256
+        let confused = hs256_with(&json!({"alg": "HS256"}), &claims, pem.as_bytes());
+        assert_eq!(
+            es256.verify(&confused),
+            Err(JwtError::UnsupportedAlgorithm("HS256".to_string()))
+        );
+        assert_eq!(
+            auth().verify(&token),
+            Err(JwtError::UnsupportedAlgorithm("ES256".to_string()))
+        );
+    }
+
+    #[test]
+    fn picks_keys_by_kid() {
+        let auth =
+            JwtAuth::new(JwtKey::hs256(b"old").kid("old")).key(JwtKey::hs256(SECRET).kid("new"));
+        let claims = json!({"exp": now() + 60});
+        let new = hs256_with(&json!({"alg": "HS256", "kid": "new"}), &claims, SECRET);
+        assert!(auth.verify(&new).is_ok());
+        // Without a kid every key of the algorithm is tried
+        let any = hs256_with(&json!({"alg": "HS256"}), &claims, b"old");
+        assert!(auth.verify(&any).is_ok());
+        let unknown = hs256_with(&json!({"alg": "HS256", "kid": "other"}), &claims, SECRET);
+        assert_eq!(
+            auth.verify(&unknown),
+            Err(JwtError::UnsupportedAlgorithm("HS256".to_string()))
+        );
+        let wrong = hs256_with(&json!({"alg": "HS256", "kid": "old"}), &claims, SECRET);
+        assert_eq!(auth.verify(&wrong), Err(JwtError::InvalidSignature));
+    }
+
+    #[test]
+    fn checks_exp_with_leeway() {
+        let recent = hs256(json!({"exp": now() - 30}));
+        assert!(auth().verify(&recent).is_ok());
+        assert_eq!(
+            auth().leeway(Duration::ZERO).verify(&recent),
+            Err(JwtError::Expired)
+        );
+        let expired = hs256(json!({"exp": now() - 120}));
+        assert_eq!(auth().verify(&expired), Err(JwtError::Expired));
+        let not_a_number = hs256(json!({"exp": "tomorrow"}));
+        assert_eq!(auth().verify(&not_a_number), Err(JwtError::Malformed));
+    }
+
+    #[test]
+    fn requires_exp_unless_told_otherwise() {
+        let forever = hs256(json!({"sub": "ann"}));
+        assert_eq!(auth().verify(&forever), Err(JwtError::MissingExpiry));
+        assert!(auth().require_exp(false).verify(&forever).is_ok());
+    }
+
+    #[test]
+    fn checks_nbf_with_leeway() {
+        let exp = now() + 600;
+        let soon = hs256(json!({"exp": exp, "nbf": now() + 30}));
+        assert!(auth().verify(&soon).is_ok());
+        assert_eq!(
+            auth().leeway(Duration::ZERO).verify(&soon),
+            Err(JwtError::NotYetValid)
+        );
+        let later = hs256(json!({"exp": exp, "nbf": now() + 300}));
+        assert_eq!(auth().verify(&later), Err(JwtError::NotYetValid));
+        let not_a_number = hs256(json!({"exp": exp, "nbf": null}));
+        assert_eq!(auth().verify(&not_a_number), Err(JwtError::Malformed));
+    }
+
+    #[test]
+    fn checks_the_audience() {
+        let exp = now() + 60;
+        let auth = auth().audience("api").audience("admin");
+        for aud in [json!("api"), json!(["web", "admin"])] {
+            let token = hs256(json!({"exp": exp, "aud": aud}));
+            assert!(auth.verify(&token).is_ok(), "{aud}");
+        }
+        for aud in [
+            json!("web"),
+            json!(["web"]),
+            json!([]),
+            json!(7),
+            Value::Null,
+        ] {
+            let token = hs256(json!({"exp": exp, "aud": aud}));
+            assert_eq!(auth.verify(&token), Err(JwtError::InvalidAudience), "{aud}");
+        }
+        let without = hs256(json!({"exp": exp}));
+        assert_eq!(auth.verify(&without), Err(JwtError::InvalidAudience));
+        // Any audience without audience()
+        let token = hs256(json!({"exp": exp, "aud": "web"}));
+        assert!(self::auth().verify(&token).is_ok());
+    }
+
+    #[test]
+    fn checks_the_issuer() {
+        let exp = now() + 60;
+        let auth = auth().issuer("https://auth.example.com");
+        let token = hs256(json!({"exp": exp, "iss": "https://auth.example.com"}));
+        assert!(auth.verify(&token).is_ok());
+        let other = hs256(json!({"exp": exp, "iss": "https://evil.example.com"}));
+        assert_eq!(auth.verify(&other), Err(JwtError::InvalidIssuer));
+        let without = hs256(json!({"exp": exp}));
+        assert_eq!(auth.verify(&without), Err(JwtError::InvalidIssuer));
+    }
+
+    #[test]
+    fn challenges_follow_rfc_6750() {
+        assert_eq!(challenge(&JwtError::Missing), "Bearer");
+        assert_eq!(
+            challenge(&JwtError::Expired),
+            "Bearer error=\"invalid_token\", error_description=\"Token is expired\""
+        );
+    }
+}
@@ -1,13 +1,79 @@
+pub mod accept_control;
+pub mod addr;
+pub mod any_app;
 pub mod app;
+pub mod app_config;
+pub mod app_handle;
+pub mod backplane;
+pub mod broadcast;
+pub mod cache_control;
+pub mod cancellation;
+pub mod coalesce;
+pub mod compression;
+pub mod connection_registry;
+pub mod cookie;
+pub mod cors;
 pub mod data_storage;
+pub mod diagnostics;
+pub mod directory_listing;
+pub mod dual_app;
+pub mod embedded_assets;
+pub mod error;
+#[cfg(feature = "serde")]
+pub mod extract;
+pub mod file_transfer;
+pub mod guard;
+pub mod handler;
+pub mod health;
 pub mod http_request;
 pub mod http_connection;
-mod send_ptr;
+#[cfg(feature = "jwt")]
+pub mod jwt;
+pub mod live_settings;
+pub mod middleware;
+pub mod multipart;
+pub mod presence;
+pub mod progress;
+pub mod rate_limit;
+pub mod response;
+pub mod response_cache;
+pub mod restart;
+pub mod shutdown;
+pub mod rooms;
+pub mod session;
+pub mod router;
+pub mod socket_activation;
+pub mod sse;
+pub mod static_files;
+pub mod sticky;
+pub mod tcp_options;
+pub mod tls_options;
+pub mod trusted_proxies;
+pub mod typed_websocket;
+pub mod validate;
 pub mod websocket;
+pub mod ws_actor;
 pub mod ws_behavior;
+pub mod ws_hooks;
 pub mod ws_message;
+pub mod ws_stats;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(any(feature = "signed-cookies", feature = "jwt"))]
+mod base64;
 mod body_reader;
+mod byte_range;
+mod fs;
+mod http_date;
+mod loop_bound;
 mod loop_defer_future;
+mod percent_encoding;
+#[cfg(feature = "serde")]
+mod query_string;
+mod relay;
+mod remote_address;
+mod route_pattern;
+mod task;
 
 pub mod uwebsockets_rs {
   pub use uwebsockets_rs::listen_socket::ListenSocket;
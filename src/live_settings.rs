@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use uwebsockets_rs::uws_loop::UwsLoop;
+
+use crate::diagnostics::loop_defer;
+use crate::http_request::HttpRequest;
+use crate::rate_limit::RateLimiter;
+use crate::trusted_proxies::TrustedProxies;
+
+/***
+ * Tunables that can change while the app runs, see App::update_settings(). A request reads the
+ * settings current when it arrives, open websockets pick up a new ws_idle_timeout right away.
+ ***/
+#[derive(Debug, Clone, Default)]
+pub struct LiveSettings {
+    // Counted per client IP across all routes, see App::rate_limit()
+    pub rate_limit: Option<RateLimiter>,
+    // Route pattern -> limit counted for that route alone, overrides rate_limit
+    pub route_rate_limits: HashMap<String, RateLimiter>,
+    // Requests declaring a longer content-length get 413 and the connection is closed, chunked
+    // ones once that many bytes came in (the rest isn't buffered). body_form() reads up to it too
+    pub max_body_size: Option<u64>,
+    // Route pattern -> max_body_size for that route alone
+    pub route_max_body_sizes: HashMap<String, u64>,
+    // Websockets that received nothing for this long are closed with 1001, on top of the
+    // route's native idle_timeout (which can't change once the route is added)
+    pub ws_idle_timeout: Option<Duration>,
+    // Clients outside of every range get 403 and no websocket upgrade, None lets everyone in
+    pub allow_list: Option<Vec<IpRange>>,
+    // Proxies whose forwarding headers HttpRequest::client_ip() believes, None trusts no one
+    pub trusted_proxies: Option<TrustedProxies>,
+}
+
+impl LiveSettings {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limit = Some(limiter);
+        self
+    }
+
+    pub fn route_rate_limit(mut self, pattern: &str, limiter: RateLimiter) -> Self {
+        self.route_rate_limits.insert(pattern.to_string(), limiter);
+        self
+    }
+
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.max_body_size = Some(bytes);
+        self
+    }
+
+    pub fn route_max_body_size(mut self, pattern: &str, bytes: u64) -> Self {
+        self.route_max_body_sizes.insert(pattern.to_string(), bytes);
+        self
+    }
+
+    pub fn max_body_size_for(&self, pattern: &str) -> Option<u64> {
+        self.route_max_body_sizes
+            .get(pattern)
+            .copied()
+            .or(self.max_body_size)
+    }
+
+    pub fn ws_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.ws_idle_timeout = Some(timeout);
+        self
+    }
+
+    pub fn allow(mut self, range: IpRange) -> Self {
+        self.allow_list.get_or_insert_with(Vec::new).push(range);
+        self
+    }
+
+    pub fn trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Some(proxies);
+        self
+    }
+
+    // What HttpRequest::client_ip() reports, None leaves it at the peer's address
+    pub(crate) fn client_ip(&self, req: &HttpRequest) -> Option<IpAddr> {
+        let proxies = self.trusted_proxies.as_ref()?;
+        let peer = req.remote_address()?;
+        Some(proxies.client_ip(peer.ip(), req))
+    }
+
+    // `remote_address` is HttpConnection::remote_address(), unknown clients only pass without a list
+    pub fn is_allowed(&self, remote_address: Option<&str>) -> bool {
+        let Some(allow_list) = self.allow_list.as_ref() else {
+            return true;
+        };
+        let Some(ip) = remote_address.and_then(|address| address.parse::<IpAddr>().ok()) else {
+            return false;
+        };
+        allow_list.iter().any(|range| range.contains(ip))
+    }
+}
+
+// "10.0.0.0/8", "2001:db8::/32" or a single address, IPv4 ranges also match IPv4-mapped IPv6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(range: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match range.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (range, None),
+        };
+        let network = address
+            .trim()
+            .parse::<IpAddr>()
+            .map_err(|_| format!("Invalid address in IP range {range}"))?
+            .to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length in IP range {range}"))?,
+            None => max_prefix,
+        };
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/***
+ * Current LiveSettings of an app, clones can be moved to other threads (e.g. a config watcher).
+ * Updates are applied on the uWS loop between two callbacks, so a request never sees half of one.
+ ***/
+#[derive(Clone)]
+pub struct SettingsHandle {
+    current: watch::Sender<Arc<LiveSettings>>,
+    uws_loop: UwsLoop,
+}
+
+impl SettingsHandle {
+    pub(crate) fn new(uws_loop: UwsLoop) -> Self {
+        SettingsHandle {
+            current: watch::channel(Default::default()).0,
+            uws_loop,
+        }
+    }
+
+    pub fn current(&self) -> Arc<LiveSettings> {
+        self.current.borrow().clone()
+    }
+
+    // `update` gets a copy of the current settings, whatever it leaves there replaces them
+    pub fn update<F>(&self, update: F)
+    where
+        F: FnOnce(&mut LiveSettings) + Send + 'static,
+    {
+        let current = self.current.clone();
+        loop_defer(self.uws_loop, move || {
+            let mut settings = LiveSettings::clone(&current.borrow());
+            update(&mut settings);
+            current.send_replace(Arc::new(settings));
+        });
+    }
+
+    // Right away instead of on the loop, for configuring the app before it runs
+    pub(crate) fn update_now<F>(&self, update: F)
+    where
+        F: FnOnce(&mut LiveSettings),
+    {
+        self.current
+            .send_modify(|settings| update(Arc::make_mut(settings)));
+    }
+
+    pub(crate) fn subscribe(&self) -> watch::Receiver<Arc<LiveSettings>> {
+        self.current.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(range: &str) -> IpRange {
+        range.parse().unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_v4_prefixes() {
+        let private = range("10.0.0.0/8");
+        assert!(private.contains(ip("10.0.0.0")));
+        assert!(private.contains(ip("10.255.255.255")));
+        assert!(!private.contains(ip("11.0.0.0")));
+        assert!(!private.contains(ip("9.255.255.255")));
+
+        let odd = range("192.168.1.128/25");
+        assert!(odd.contains(ip("192.168.1.200")));
+        assert!(!odd.contains(ip("192.168.1.127")));
+    }
+
+    #[test]
+    fn host_bits_of_the_network_are_ignored() {
+        let range = range("10.1.2.3/8");
+        assert!(range.contains(ip("10.200.0.1")));
+        assert!(!range.contains(ip("11.1.2.3")));
+    }
+
+    #[test]
+    fn zero_and_full_prefixes() {
+        let everyone = range("0.0.0.0/0");
+        assert!(everyone.contains(ip("1.2.3.4")));
+        assert!(everyone.contains(ip("255.255.255.255")));
+        assert!(!everyone.contains(ip("2001:db8::1")));
+        assert!(range("::/0").contains(ip("2001:db8::1")));
+
+        let single = range("203.0.113.7");
+        assert_eq!(single, range("203.0.113.7/32"));
+        assert!(single.contains(ip("203.0.113.7")));
+        assert!(!single.contains(ip("203.0.113.8")));
+        assert!(range("2001:db8::1/128").contains(ip("2001:db8::1")));
+        assert!(!range("2001:db8::1").contains(ip("2001:db8::2")));
+    }
+
+    #[test]
+    fn matches_v6_prefixes() {
+        let documentation = range("2001:db8::/32");
+        assert!(documentation.contains(ip("2001:db8:ffff::1")));
+        assert!(!documentation.contains(ip("2001:db9::1")));
+        assert!(!documentation.contains(ip("10.0.0.1")));
+
+        let odd = range("fe80::/10");
+        assert!(odd.contains(ip("febf::1")));
+        assert!(!odd.contains(ip("fec0::1")));
+    }
+
+    #[test]
+    fn v4_ranges_match_mapped_v6() {
+        assert!(range("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!range("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+        // A mapped network is parsed as the IPv4 one
+        assert_eq!(range("::ffff:10.0.0.0/8"), range("10.0.0.0/8"));
+    }
+
+    #[test]
+    fn rejects_malformed_ranges() {
+        for range in [
+            "",
+            "10.0.0",
+            "10.0.0.0/",
+            "10.0.0.0/33",
+            "10.0.0.0/-1",
+            "10.0.0.0/8/8",
+            "2001:db8::/129",
+            "example.com/8",
+            "/8",
+        ] {
+            assert!(range.parse::<IpRange>().is_err(), "{range}");
+        }
+    }
+
+    #[test]
+    fn displays_as_cidr() {
+        assert_eq!(range("10.0.0.0/8").to_string(), "10.0.0.0/8");
+        assert_eq!(range("2001:db8::1").to_string(), "2001:db8::1/128");
+    }
+
+    #[test]
+    fn allow_list_checks_the_remote_address() {
+        let open = LiveSettings::new();
+        assert!(open.is_allowed(None));
+        assert!(open.is_allowed(Some("8.8.8.8")));
+
+        let settings = LiveSettings::new()
+            .allow(range("10.0.0.0/8"))
+            .allow(range("2001:db8::/32"));
+        assert!(settings.is_allowed(Some("10.1.2.3")));
+        assert!(settings.is_allowed(Some("2001:db8::7")));
+        assert!(!settings.is_allowed(Some("8.8.8.8")));
+        // Unknown or unparsable clients are kept out once there is a list
+        assert!(!settings.is_allowed(None));
+        assert!(!settings.is_allowed(Some("not an ip")));
+    }
+}
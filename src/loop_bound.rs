@@ -0,0 +1,58 @@
+use std::cell::Cell;
+use std::thread::{self, ThreadId};
+
+thread_local! {
+    static IS_LOOP_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+// Called where the thread's uWS loop is set up, see AppStruct::new()
+pub(crate) fn mark_loop_thread() {
+    IS_LOOP_THREAD.with(|is_loop_thread| is_loop_thread.set(true));
+}
+
+/***
+ * Native uWS handle pinned to the loop thread it was created on.
+ * The holder can be moved to (and dropped on) other threads, but the handle itself is only
+ * reachable on the loop thread, so every native call has to go through `loop_defer`.
+ * uwebsockets_rs marks its handles Send + Sync, this wrapper is what makes that actually hold.
+ ***/
+#[derive(Clone)]
+pub(crate) struct LoopBound<T> {
+    value: T,
+    loop_thread: ThreadId,
+}
+
+impl<T> LoopBound<T> {
+    // Must be called on the loop thread, i.e. from a uWS callback
+    pub(crate) fn new(value: T) -> Self {
+        debug_assert!(
+            IS_LOOP_THREAD.with(Cell::get),
+            "[async_uws] Native handle bound outside of the uWS loop thread"
+        );
+        LoopBound {
+            value,
+            loop_thread: thread::current().id(),
+        }
+    }
+
+    pub(crate) fn is_loop_thread(&self) -> bool {
+        thread::current().id() == self.loop_thread
+    }
+
+    pub(crate) fn get(&self) -> &T {
+        self.assert_loop_thread();
+        &self.value
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        self.assert_loop_thread();
+        self.value
+    }
+
+    fn assert_loop_thread(&self) {
+        assert!(
+            self.is_loop_thread(),
+            "[async_uws] Native handle used outside of the uWS loop thread"
+        );
+    }
+}
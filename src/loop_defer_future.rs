@@ -3,7 +3,9 @@ use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll, Waker};
 
-use uwebsockets_rs::uws_loop::{loop_defer, UwsLoop};
+use uwebsockets_rs::uws_loop::UwsLoop;
+
+use crate::diagnostics::loop_defer;
 
 #[derive(Default)]
 struct LoopDeferFutureState {
@@ -23,15 +25,13 @@ impl LoopDeferFuture {
     let closure = move || {
       callback();
       let mut state = state_to_move.lock().unwrap();
+      state.is_completed = true;
       if let Some(waker) = state.waker.take() {
-        state.is_completed = true;
         waker.wake()
       }
     };
 
-    tokio_uring::spawn(async move {
-      loop_defer(uws_loop, closure);
-    });
+    loop_defer(uws_loop, closure);
 
     LoopDeferFuture { state }
   }
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::app::BoxedHandlerFuture;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::router::RouteHandler;
+
+pub type Middleware<const SSL: bool> =
+    Arc<dyn Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync>;
+
+/***
+ * Rest of the chain a middleware runs in, see App::middleware(). A middleware either answers
+ * the request itself or hands it on with next.run(res, req), whatever it does after run()
+ * returns happens once the handler is done:
+ *
+ *   app.middleware(|res, req, next| async move {
+ *       let started = Instant::now();
+ *       let (method, url, state) = (req.method.clone(), req.url.clone(), res.state_handle());
+ *       next.run(res, req).await;
+ *       info!("{method} {url} {:?} in {:?}", state.get(), started.elapsed());
+ *   });
+ ***/
+pub struct Next<const SSL: bool> {
+    chain: Arc<[Middleware<SSL>]>,
+    index: usize,
+    handler: RouteHandler<SSL>,
+}
+
+impl<const SSL: bool> Next<SSL> {
+    pub fn run(self, res: HttpConnection<SSL>, req: HttpRequest) -> BoxedHandlerFuture {
+        match self.chain.get(self.index).cloned() {
+            Some(middleware) => {
+                let next = Next {
+                    index: self.index + 1,
+                    ..self
+                };
+                middleware(res, req, next)
+            }
+            None => (self.handler)(res, req),
+        }
+    }
+}
+
+pub(crate) fn boxed_middleware<T, W, const SSL: bool>(middleware: T) -> Middleware<SSL>
+where
+    T: (Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> W) + 'static + Send + Sync,
+    W: Future<Output = ()> + 'static + Send,
+{
+    Arc::new(move |res, req, next| Box::pin(middleware(res, req, next)))
+}
+
+// `handler` behind `chain`, the first middleware runs first
+pub(crate) fn with_middleware<const SSL: bool>(
+    chain: Vec<Middleware<SSL>>,
+    handler: RouteHandler<SSL>,
+) -> RouteHandler<SSL> {
+    if chain.is_empty() {
+        return handler;
+    }
+    let chain: Arc<[Middleware<SSL>]> = chain.into();
+    Arc::new(move |res, req| {
+        let next = Next {
+            chain: chain.clone(),
+            index: 0,
+            handler: handler.clone(),
+        };
+        next.run(res, req)
+    })
+}
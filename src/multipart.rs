@@ -0,0 +1,560 @@
+use std::io;
+use std::path::PathBuf;
+
+use tokio::sync::mpsc::{self, Receiver};
+use tokio::sync::oneshot;
+
+use crate::body_reader::BodyChunk;
+use crate::fs;
+use crate::http_request::HttpRequest;
+use crate::task;
+
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+/***
+ * multipart/form-data body read part by part as it arrives, see HttpConnection::multipart().
+ * Part data is handed out chunk by chunk, so large files can be piped elsewhere without
+ * ever being held in memory. Parts come in the order the client sent them, a part that isn't
+ * read to the end is skipped by the next next_part() call.
+ ***/
+pub struct Multipart {
+    body: Receiver<BodyChunk>,
+    // "\r\n--boundary", the body gets a leading "\r\n" so the first boundary matches as well
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    state: State,
+    is_body_finished: bool,
+    field_limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Preamble,
+    Headers,
+    Data,
+    Finished,
+}
+
+pub struct Part<'a> {
+    multipart: &'a mut Multipart,
+    pub headers: Vec<(String, String)>,
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+impl Multipart {
+    pub fn with_boundary(boundary: &str, body: Receiver<BodyChunk>) -> Self {
+        Multipart {
+            body,
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            buffer: b"\r\n".to_vec(),
+            state: State::Preamble,
+            is_body_finished: false,
+            field_limit: 1024 * 1024,
+        }
+    }
+
+    // Max size of a part read with Part::bytes() / Part::text(), 1 MiB by default
+    pub fn field_limit(mut self, field_limit: usize) -> Self {
+        self.field_limit = field_limit;
+        self
+    }
+
+    pub async fn next_part(&mut self) -> Result<Option<Part<'_>>, String> {
+        // Skips whatever the previous part didn't read
+        while self.state == State::Data {
+            self.read_data().await?;
+        }
+        if self.state == State::Preamble {
+            self.skip_preamble().await?;
+        }
+        if self.state == State::Finished {
+            return Ok(None);
+        }
+
+        let headers = self.read_headers().await?;
+        self.state = State::Data;
+        let disposition = headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case("content-disposition"))
+            .map(|(_, value)| value.as_str())
+            .unwrap_or_default();
+        let name = disposition_param(disposition, "name");
+        let filename = disposition_param(disposition, "filename");
+        Ok(Some(Part {
+            multipart: self,
+            headers,
+            name,
+            filename,
+        }))
+    }
+
+    async fn fill(&mut self) -> Result<(), String> {
+        if self.is_body_finished {
+            return Err("Multipart body ended before the closing boundary".to_string());
+        }
+        match self.body.recv().await {
+            Some((chunk, is_fin)) => {
+                self.buffer.extend_from_slice(&chunk);
+                self.is_body_finished = is_fin;
+                Ok(())
+            }
+            None => {
+                self.is_body_finished = true;
+                Err("Multipart body ended before the closing boundary".to_string())
+            }
+        }
+    }
+
+    async fn skip_preamble(&mut self) -> Result<(), String> {
+        loop {
+            if let Some(index) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..index + self.delimiter.len());
+                return self.read_delimiter_end().await;
+            }
+            // Keep a possible start of the delimiter
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                self.buffer.drain(..self.buffer.len() - keep);
+            }
+            self.fill().await?;
+        }
+    }
+
+    // After a delimiter comes "--" for the last one or line end for another part
+    async fn read_delimiter_end(&mut self) -> Result<(), String> {
+        loop {
+            let padding = self
+                .buffer
+                .iter()
+                .take_while(|byte| **byte == b' ' || **byte == b'\t')
+                .count();
+            let rest = &self.buffer[padding..];
+            if rest.starts_with(b"--") {
+                self.state = State::Finished;
+                self.buffer.clear();
+                return Ok(());
+            }
+            if rest.starts_with(b"\r\n") {
+                self.buffer.drain(..padding + 2);
+                self.state = State::Headers;
+                return Ok(());
+            }
+            if rest.len() >= 2 {
+                return Err("Malformed multipart boundary".to_string());
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn read_headers(&mut self) -> Result<Vec<(String, String)>, String> {
+        let end = loop {
+            // A part without headers
+            if self.buffer.starts_with(b"\r\n") {
+                self.buffer.drain(..2);
+                return Ok(Vec::new());
+            }
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_HEADERS_SIZE {
+                return Err("Multipart part headers are too large".to_string());
+            }
+            self.fill().await?;
+        };
+        // Whatever size the chunks had
+        if end > MAX_HEADERS_SIZE {
+            return Err("Multipart part headers are too large".to_string());
+        }
+
+        let headers = String::from_utf8_lossy(&self.buffer[..end])
+            .split("\r\n")
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect();
+        self.buffer.drain(..end + 4);
+        Ok(headers)
+    }
+
+    // Next piece of the current part, None once the part is over
+    async fn read_data(&mut self) -> Result<Option<Vec<u8>>, String> {
+        if self.state != State::Data {
+            return Ok(None);
+        }
+        loop {
+            if let Some(index) = find(&self.buffer, &self.delimiter) {
+                let data: Vec<u8> = self.buffer.drain(..index).collect();
+                self.buffer.drain(..self.delimiter.len());
+                self.read_delimiter_end().await?;
+                return Ok(Some(data).filter(|data| !data.is_empty()));
+            }
+            // Everything but a possible start of the delimiter belongs to the part
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                let data = self.buffer.drain(..self.buffer.len() - keep).collect();
+                return Ok(Some(data));
+            }
+            self.fill().await?;
+        }
+    }
+}
+
+impl Part<'_> {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    // Set for file inputs
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == "content-type")
+            .map(|(_, value)| value.as_str())
+    }
+
+    // Next chunk of the part's data, None when the part is over. Chunks are never empty
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, String> {
+        self.multipart.read_data().await
+    }
+
+    // Whole part in memory, fails if it's larger than Multipart::field_limit()
+    pub async fn bytes(mut self) -> Result<Vec<u8>, String> {
+        let limit = self.multipart.field_limit;
+        let mut data = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            if data.len() + chunk.len() > limit {
+                return Err(format!(
+                    "Multipart part {:?} is larger than {limit} bytes",
+                    self.name.as_deref().unwrap_or_default()
+                ));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    pub async fn text(self) -> Result<String, String> {
+        String::from_utf8(self.bytes().await?).map_err(|e| e.to_string())
+    }
+
+    // Streams the part into a file at `path` (replaced if it exists) and returns its size.
+    // A file the upload failed halfway through is removed again
+    pub async fn save_to(mut self, path: impl Into<PathBuf>) -> Result<u64, String> {
+        let path = path.into();
+        let (sink, written) = write_file(path.clone());
+        let mut result = Ok(());
+        loop {
+            match self.chunk().await {
+                Ok(Some(chunk)) => {
+                    // The writer failed, its error is below
+                    if sink.send(chunk).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        drop(sink);
+
+        let written = written
+            .await
+            .unwrap_or_else(|_| Err("File writer stopped".to_string()));
+        let saved = result.and(written);
+        if saved.is_err() {
+            let _ = std::fs::remove_file(&path);
+        }
+        saved
+    }
+}
+
+// Bytes written, or why writing stopped
+type FileWritten = Result<u64, String>;
+
+// Chunks are written by their own task, see fs.rs
+fn write_file(path: PathBuf) -> (mpsc::Sender<Vec<u8>>, oneshot::Receiver<FileWritten>) {
+    let (sink, mut chunks) = mpsc::channel::<Vec<u8>>(2);
+    let (done, written) = oneshot::channel();
+    task::spawn_local("async_uws multipart file write", async move {
+        let write = async {
+            let file = fs::File::create(&path)
+                .await
+                .map_err(|e| format!("Can't create {}: {e}", path.display()))?;
+            let mut position = 0;
+            while let Some(mut chunk) = chunks.recv().await {
+                // write_at may write less than given
+                while !chunk.is_empty() {
+                    let (result, buf) = file.write_at(chunk, position).await;
+                    // Nothing written would retry the same chunk forever
+                    let result = result.and_then(|count| match count {
+                        0 => Err(io::Error::from(io::ErrorKind::WriteZero)),
+                        count => Ok(count),
+                    });
+                    let count =
+                        result.map_err(|e| format!("Can't write {}: {e}", path.display()))?;
+                    position += count as u64;
+                    chunk = buf[count..].to_vec();
+                }
+            }
+            file.close()
+                .await
+                .map_err(|e| format!("Can't close {}: {e}", path.display()))?;
+            Ok(position)
+        };
+        let _ = done.send(write.await);
+    });
+    (sink, written)
+}
+
+// Fails if the request isn't multipart or has no boundary
+pub(crate) fn request_boundary(req: &HttpRequest) -> Result<String, String> {
+    let content_type = req
+        .get_header("content-type")
+        .ok_or_else(|| "Request has no content-type".to_string())?;
+    boundary(content_type).ok_or_else(|| format!("Not a multipart content-type: {content_type}"))
+}
+
+// `multipart/form-data; boundary="abc"` -> abc
+fn boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().to_ascii_lowercase().starts_with("multipart/") {
+        return None;
+    }
+    header_param(params, "boundary").filter(|boundary| !boundary.is_empty())
+}
+
+fn disposition_param(disposition: &str, name: &str) -> Option<String> {
+    let (_, params) = disposition.split_once(';')?;
+    header_param(params, name)
+}
+
+// Finds `name=value` or `name="value"` in `a=1; b="2"`
+fn header_param(params: &str, name: &str) -> Option<String> {
+    let mut rest = params;
+    loop {
+        rest = rest.trim_start_matches([';', ' ', '\t']);
+        if rest.is_empty() {
+            return None;
+        }
+        let (key, after_key) = rest.split_once('=')?;
+        let (value, after_value) = match after_key.strip_prefix('"') {
+            Some(quoted) => unquote(quoted)?,
+            None => {
+                let end = after_key.find(';').unwrap_or(after_key.len());
+                (after_key[..end].trim().to_string(), &after_key[end..])
+            }
+        };
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+        rest = after_value;
+    }
+}
+
+// Reads a quoted string up to the closing quote, returns it and what follows
+fn unquote(quoted: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &quoted[index + 1..])),
+            '\\' => value.push(chars.next()?.1),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+// Only positions starting with the needle's first byte are compared, which is rare in file data
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let (first, rest) = needle.split_first()?;
+    let mut start = 0;
+    while let Some(offset) = haystack[start..].iter().position(|byte| byte == first) {
+        let index = start + offset;
+        let end = index + needle.len();
+        if end > haystack.len() {
+            return None;
+        }
+        if &haystack[index + 1..end] == rest {
+            return Some(index);
+        }
+        start = index + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    // Multipart over `body` arriving in chunks of `chunk_size` bytes
+    fn multipart(body: &str, chunk_size: usize) -> Multipart {
+        let chunks: Vec<&[u8]> = body.as_bytes().chunks(chunk_size).collect();
+        let (sink, stream) = mpsc::channel(chunks.len().max(1));
+        for (index, chunk) in chunks.iter().enumerate() {
+            let is_fin = index == chunks.len() - 1;
+            sink.try_send((Bytes::copy_from_slice(chunk), is_fin))
+                .unwrap();
+        }
+        Multipart::with_boundary("XyZ", stream)
+    }
+
+    async fn fields(mut multipart: Multipart) -> Result<Vec<(String, String)>, String> {
+        let mut fields = Vec::new();
+        while let Some(part) = multipart.next_part().await? {
+            let name = part.name().unwrap_or_default().to_string();
+            fields.push((name, part.text().await?));
+        }
+        Ok(fields)
+    }
+
+    const FORM: &str = "preamble to ignore\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        Hello\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a \\\"b\\\".txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line one\r\n--XyQ\r\n--Xy\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    #[tokio::test]
+    async fn reads_parts_split_anywhere() {
+        let expected = vec![
+            ("title".to_string(), "Hello".to_string()),
+            ("file".to_string(), "line one\r\n--XyQ\r\n--Xy".to_string()),
+        ];
+        for chunk_size in [1, 2, 3, 7, 13, FORM.len()] {
+            let fields = fields(multipart(FORM, chunk_size)).await.unwrap();
+            assert_eq!(fields, expected, "chunks of {chunk_size}");
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_part_headers() {
+        let mut multipart = multipart(FORM, 5);
+        let title = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(title.filename(), None);
+        assert_eq!(title.content_type(), None);
+        drop(title);
+        let file = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(file.name(), Some("file"));
+        assert_eq!(file.filename(), Some("a \"b\".txt"));
+        assert_eq!(file.content_type(), Some("text/plain"));
+    }
+
+    #[tokio::test]
+    async fn skips_unread_parts() {
+        let mut multipart = multipart(FORM, 4);
+        let mut title = multipart.next_part().await.unwrap().unwrap();
+        let chunk = title.chunk().await.unwrap().unwrap();
+        assert!(b"Hello".starts_with(&chunk));
+        let file = multipart.next_part().await.unwrap().unwrap();
+        assert_eq!(file.name(), Some("file"));
+        drop(file);
+        assert!(multipart.next_part().await.unwrap().is_none());
+        assert!(multipart.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn empty_form() {
+        assert_eq!(fields(multipart("--XyZ--\r\n", 3)).await, Ok(Vec::new()));
+    }
+
+    #[tokio::test]
+    async fn part_without_headers() {
+        let body = "--XyZ\r\n\r\nvalue\r\n--XyZ--";
+        assert_eq!(
+            fields(multipart(body, 2)).await,
+            Ok(vec![(String::new(), "value".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_bodies() {
+        for body in [
+            "",
+            "no boundary at all",
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"",
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue",
+            "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--XyZ",
+        ] {
+            assert!(fields(multipart(body, 3)).await.is_err(), "{body:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_on_malformed_boundaries() {
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nvalue\r\n--XyZ??";
+        assert!(fields(multipart(body, 3)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_on_oversized_headers() {
+        let body = format!(
+            "--XyZ\r\nX-Long: {}\r\n\r\nvalue\r\n--XyZ--",
+            "a".repeat(MAX_HEADERS_SIZE)
+        );
+        assert!(fields(multipart(&body, 1024)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn enforces_the_field_limit() {
+        let mut multipart = multipart(FORM, 8).field_limit(4);
+        let title = multipart.next_part().await.unwrap().unwrap();
+        assert!(title.bytes().await.is_err());
+    }
+
+    #[test]
+    fn parses_the_boundary() {
+        let boundary = |content_type| super::boundary(content_type);
+        assert_eq!(
+            boundary("multipart/form-data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b;c\"").as_deref(),
+            Some("a b;c")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("multipart/form-data; boundary="), None);
+        assert_eq!(boundary("multipart/form-data; boundary=\"abc"), None);
+        assert_eq!(boundary("text/plain; boundary=abc"), None);
+    }
+
+    #[test]
+    fn reads_disposition_params() {
+        let disposition = "form-data; filename*=x; name = field; filename=\"x\\\\y\"";
+        assert_eq!(
+            disposition_param(disposition, "name").as_deref(),
+            Some("field")
+        );
+        assert_eq!(
+            disposition_param(disposition, "filename").as_deref(),
+            Some("x\\y")
+        );
+        assert_eq!(disposition_param(disposition, "size"), None);
+        assert_eq!(disposition_param("form-data", "name"), None);
+    }
+
+    #[test]
+    fn finds_needles() {
+        assert_eq!(find(b"abcabd", b"abd"), Some(3));
+        assert_eq!(find(b"abcab", b"abd"), None);
+        assert_eq!(find(b"ab", b"abc"), None);
+        assert_eq!(find(b"abc", b""), None);
+        assert_eq!(find(b"", b"a"), None);
+    }
+}
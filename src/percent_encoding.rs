@@ -0,0 +1,118 @@
+use std::fmt::Write;
+
+// Decodes %XX sequences, returns None for malformed escapes or non UTF-8 output
+pub(crate) fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let hex = bytes.get(index + 1..index + 3)?;
+                // from_str_radix() would take a sign, `%+1`
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 3;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+// Encodes everything except RFC 3986 unreserved characters
+pub(crate) fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => {
+                let _ = write!(encoded, "%{byte:02X}");
+            }
+        }
+    }
+    encoded
+}
+
+// Splits `a=1&b=2` into decoded pairs, `+` means space. Malformed escapes are kept as they are
+pub(crate) fn query_pairs(query: &str) -> Vec<(String, String)> {
+    let decode = |component: &str| {
+        let component = component.replace('+', " ");
+        percent_decode(&component).unwrap_or(component)
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((key, value)) => (decode(key), decode(value)),
+            None => (decode(pair), String::new()),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%C3%A9t%c3%a9").as_deref(), Some("été"));
+        assert_eq!(percent_decode("plain").as_deref(), Some("plain"));
+    }
+
+    #[test]
+    fn rejects_malformed_escapes() {
+        assert_eq!(percent_decode("%"), None);
+        assert_eq!(percent_decode("abc%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%+1"), None);
+        // Valid escapes that don't make UTF-8
+        assert_eq!(percent_decode("%FF%FE"), None);
+    }
+
+    #[test]
+    fn encodes_all_but_unreserved() {
+        assert_eq!(percent_encode("a-b_c.d~e"), "a-b_c.d~e");
+        assert_eq!(percent_encode("a b/c?d=é"), "a%20b%2Fc%3Fd%3D%C3%A9");
+        assert_eq!(
+            percent_decode(&percent_encode("x&y=z%")).as_deref(),
+            Some("x&y=z%")
+        );
+    }
+
+    #[test]
+    fn splits_query_pairs() {
+        let pairs = query_pairs("a=1&b=two+words&&flag&c=%26%3D&d=50%");
+        let expected = [
+            ("a", "1"),
+            ("b", "two words"),
+            ("flag", ""),
+            ("c", "&="),
+            // A malformed escape is kept as it is
+            ("d", "50%"),
+        ];
+        let expected: Vec<(String, String)> = expected
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn splits_on_first_equals_only() {
+        assert_eq!(
+            query_pairs("token=a=b"),
+            vec![("token".to_string(), "a=b".to_string())]
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::error;
+use tokio::sync::{broadcast, mpsc, Notify};
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::broadcast::{BroadcastBridge, BroadcastMessage};
+use crate::cancellation::CancellationToken;
+use crate::connection_registry::ConnectionId;
+use crate::task;
+use crate::websocket::Websocket;
+
+// Free form data of a member, e.g. user name and device
+pub type PresenceMeta = BTreeMap<String, String>;
+
+// Topic of the bridge messages, Presence doesn't share a bridge's messages with Broadcast
+const PRESENCE_TOPIC: &str = "async_uws/presence";
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+const EVENTS_CAPACITY: usize = 256;
+
+// Members that joined and left `room`, the metas are grouped by key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PresenceDiff {
+    pub room: String,
+    pub joins: HashMap<String, Vec<PresenceMeta>>,
+    pub leaves: HashMap<String, Vec<PresenceMeta>>,
+}
+
+/***
+ * Who is in which room, on every worker connected to the bridge, see App::presence(). A socket
+ * is tracked under a key (usually the user id) with its meta, a user connected from two devices
+ * has two metas under the same key:
+ *
+ *   let presence = app.presence(bridge.clone());
+ *   app.data(presence);
+ *   // in the websocket handler
+ *   let meta = PresenceMeta::from([("device".to_string(), "phone".to_string())]);
+ *   presence.track(&ws, "lobby", &user_id, meta).await?;
+ *   // anywhere
+ *   let mut diffs = presence.subscribe();
+ *   while let Ok(diff) = diffs.recv().await {
+ *       rooms.broadcast(&diff.room, describe(&diff)).await?;
+ *   }
+ *
+ * Closed sockets are untracked by themselves. A worker that starts asks the others for their
+ * members, a worker that dies without closing its sockets leaves them behind until it's back.
+ ***/
+#[derive(Clone)]
+pub struct Presence {
+    bridge: Arc<dyn BroadcastBridge>,
+    origin: u64,
+    state: Arc<Mutex<PresenceState>>,
+    events: broadcast::Sender<PresenceDiff>,
+}
+
+// Members are keyed by the worker and connection they are on
+type MemberRef = (u64, u64);
+
+#[derive(Default)]
+struct PresenceState {
+    rooms: HashMap<String, HashMap<MemberRef, (String, PresenceMeta)>>,
+    // Rooms the sockets of this worker are tracked in
+    local: HashMap<ConnectionId, HashSet<String>>,
+}
+
+impl Presence {
+    pub(crate) fn start(
+        bridge: impl BroadcastBridge + 'static,
+        cancellation: CancellationToken,
+    ) -> Self {
+        let presence = Presence {
+            bridge: Arc::new(bridge),
+            origin: RandomState::new().build_hasher().finish(),
+            state: Default::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+        };
+        let receiver = presence.clone();
+        task::spawn("async_uws presence", async move {
+            tokio::select! {
+                _ = cancellation.cancelled() => {}
+                _ = receiver.receive() => {}
+            }
+        });
+        presence
+    }
+
+    // Diffs of every room, local and remote changes alike
+    pub fn subscribe(&self) -> broadcast::Receiver<PresenceDiff> {
+        self.events.subscribe()
+    }
+
+    // Tracks the socket again if it already is, with the new key and meta. Err if the socket is
+    // closed or the bridge failed, the local members are updated either way
+    pub async fn track<const SSL: bool>(
+        &self,
+        ws: &Websocket<SSL>,
+        room: &str,
+        key: &str,
+        meta: PresenceMeta,
+    ) -> Result<(), String> {
+        if !ws.is_open() {
+            return Err("WebSocket is closed!".to_string());
+        }
+        let id = ws.id();
+        let member = (self.origin, id.get());
+        let is_new_socket = {
+            let mut state = self.state.lock().unwrap();
+            let is_new_socket = !state.local.contains_key(&id);
+            state.local.entry(id).or_default().insert(room.to_string());
+            is_new_socket
+        };
+        if is_new_socket {
+            self.untrack_on_close(id, ws.closed.clone(), ws.is_open.clone());
+        }
+        self.apply(PresenceUpdate::Join {
+            room: room.to_string(),
+            member,
+            key: key.to_string(),
+            meta: meta.clone(),
+        });
+        self.send(PresenceUpdate::Join {
+            room: room.to_string(),
+            member,
+            key: key.to_string(),
+            meta,
+        })
+        .await
+    }
+
+    // Ok(false) if the socket wasn't tracked in `room`
+    pub async fn untrack<const SSL: bool>(
+        &self,
+        ws: &Websocket<SSL>,
+        room: &str,
+    ) -> Result<bool, String> {
+        self.untrack_local(ws.id(), room).await
+    }
+
+    // Members of `room` by key
+    pub fn list(&self, room: &str) -> HashMap<String, Vec<PresenceMeta>> {
+        let state = self.state.lock().unwrap();
+        let mut members: HashMap<String, Vec<PresenceMeta>> = HashMap::new();
+        for (key, meta) in state
+            .rooms
+            .get(room)
+            .into_iter()
+            .flat_map(|room| room.values())
+        {
+            members.entry(key.clone()).or_default().push(meta.clone());
+        }
+        members
+    }
+
+    async fn untrack_local(&self, id: ConnectionId, room: &str) -> Result<bool, String> {
+        let was_tracked = {
+            let mut state = self.state.lock().unwrap();
+            let was_tracked = state
+                .local
+                .get_mut(&id)
+                .is_some_and(|rooms| rooms.remove(room));
+            if state.local.get(&id).is_some_and(HashSet::is_empty) {
+                state.local.remove(&id);
+            }
+            was_tracked
+        };
+        if !was_tracked {
+            return Ok(false);
+        }
+        let leave = PresenceUpdate::Leave {
+            room: room.to_string(),
+            member: (self.origin, id.get()),
+        };
+        self.apply(leave.clone());
+        self.send(leave).await.map(|_| true)
+    }
+
+    fn untrack_on_close(&self, id: ConnectionId, closed: Arc<Notify>, is_open: Arc<AtomicBool>) {
+        let presence = self.clone();
+        task::spawn("async_uws presence cleanup", async move {
+            let closed = closed.notified();
+            tokio::pin!(closed);
+            closed.as_mut().enable();
+            if is_open.load(Ordering::Relaxed) {
+                closed.await;
+            }
+            let rooms = presence
+                .state
+                .lock()
+                .unwrap()
+                .local
+                .get(&id)
+                .cloned()
+                .unwrap_or_default();
+            for room in rooms {
+                if let Err(e) = presence.untrack_local(id, &room).await {
+                    error!("[async_uws] Presence leave of a closed socket not shared: {e}");
+                }
+            }
+        });
+    }
+
+    // Applies a join or leave to the members and emits its diff
+    fn apply(&self, update: PresenceUpdate) {
+        let mut diff = PresenceDiff::default();
+        {
+            let mut state = self.state.lock().unwrap();
+            match update {
+                PresenceUpdate::Join {
+                    room,
+                    member,
+                    key,
+                    meta,
+                } => {
+                    let members = state.rooms.entry(room.clone()).or_default();
+                    if let Some((key, meta)) = members.insert(member, (key.clone(), meta.clone())) {
+                        diff.leaves.entry(key).or_default().push(meta);
+                    }
+                    diff.joins.entry(key).or_default().push(meta);
+                    diff.room = room;
+                }
+                PresenceUpdate::Leave { room, member } => {
+                    let Some(members) = state.rooms.get_mut(&room) else {
+                        return;
+                    };
+                    let Some((key, meta)) = members.remove(&member) else {
+                        return;
+                    };
+                    if members.is_empty() {
+                        state.rooms.remove(&room);
+                    }
+                    diff.leaves.entry(key).or_default().push(meta);
+                    diff.room = room;
+                }
+                PresenceUpdate::Sync => return,
+            }
+        }
+        // No subscribers is fine
+        let _ = self.events.send(diff);
+    }
+
+    async fn send(&self, update: PresenceUpdate) -> Result<(), String> {
+        let message = BroadcastMessage {
+            origin: self.origin,
+            topic: PRESENCE_TOPIC.to_string(),
+            message: update.encode().into(),
+            opcode: Opcode::Binary,
+            compress: false,
+        };
+        self.bridge.send(&message).await
+    }
+
+    async fn receive(&self) {
+        let (sink, mut messages) = mpsc::unbounded_channel();
+        let subscribe = async {
+            while let Err(e) = self.bridge.subscribe(sink.clone()).await {
+                error!("[async_uws] Presence bridge subscription failed: {e}");
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            }
+        };
+        let deliver = async {
+            // The other workers answer with their members
+            if let Err(e) = self.send(PresenceUpdate::Sync).await {
+                error!("[async_uws] Presence sync request failed: {e}");
+            }
+            while let Some(message) = messages.recv().await {
+                if message.origin == self.origin || message.topic != PRESENCE_TOPIC {
+                    continue;
+                }
+                match PresenceUpdate::decode(&message.message) {
+                    Ok(PresenceUpdate::Sync) => self.send_local_members().await,
+                    Ok(update) => self.apply(update),
+                    Err(e) => error!("[async_uws] {e}"),
+                }
+            }
+        };
+        tokio::select! {
+            _ = subscribe => {}
+            _ = deliver => {}
+        }
+    }
+
+    async fn send_local_members(&self) {
+        let joins: Vec<PresenceUpdate> = {
+            let state = self.state.lock().unwrap();
+            state
+                .rooms
+                .iter()
+                .flat_map(|(room, members)| {
+                    members
+                        .iter()
+                        .filter(|((worker, _), _)| *worker == self.origin)
+                        .map(|(member, (key, meta))| PresenceUpdate::Join {
+                            room: room.clone(),
+                            member: *member,
+                            key: key.clone(),
+                            meta: meta.clone(),
+                        })
+                })
+                .collect()
+        };
+        for join in joins {
+            if let Err(e) = self.send(join).await {
+                error!("[async_uws] Presence sync answer failed: {e}");
+                return;
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PresenceUpdate {
+    // Asks the other workers to send their members
+    Sync,
+    Join {
+        room: String,
+        member: MemberRef,
+        key: String,
+        meta: PresenceMeta,
+    },
+    Leave {
+        room: String,
+        member: MemberRef,
+    },
+}
+
+impl PresenceUpdate {
+    // Kind, then worker and connection (8 bytes each), strings are prefixed with their length
+    // (4 bytes), a meta with its number of pairs
+    fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        match self {
+            PresenceUpdate::Sync => encoded.push(0),
+            PresenceUpdate::Join {
+                room,
+                member,
+                key,
+                meta,
+            } => {
+                encoded.push(1);
+                encode_member(&mut encoded, member);
+                encode_str(&mut encoded, room);
+                encode_str(&mut encoded, key);
+                encoded.extend_from_slice(&(meta.len() as u32).to_be_bytes());
+                for (name, value) in meta {
+                    encode_str(&mut encoded, name);
+                    encode_str(&mut encoded, value);
+                }
+            }
+            PresenceUpdate::Leave { room, member } => {
+                encoded.push(2);
+                encode_member(&mut encoded, member);
+                encode_str(&mut encoded, room);
+            }
+        }
+        encoded
+    }
+
+    fn decode(encoded: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader(encoded);
+        let update = match reader.take(1)?[0] {
+            0 => PresenceUpdate::Sync,
+            1 => {
+                let member = (reader.u64()?, reader.u64()?);
+                let room = reader.string()?;
+                let key = reader.string()?;
+                let mut meta = PresenceMeta::new();
+                for _ in 0..reader.u32()? {
+                    meta.insert(reader.string()?, reader.string()?);
+                }
+                PresenceUpdate::Join {
+                    room,
+                    member,
+                    key,
+                    meta,
+                }
+            }
+            2 => {
+                let member = (reader.u64()?, reader.u64()?);
+                let room = reader.string()?;
+                PresenceUpdate::Leave { room, member }
+            }
+            kind => return Err(format!("Invalid presence message kind {kind}")),
+        };
+        Ok(update)
+    }
+}
+
+fn encode_member(encoded: &mut Vec<u8>, (worker, connection): &MemberRef) {
+    encoded.extend_from_slice(&worker.to_be_bytes());
+    encoded.extend_from_slice(&connection.to_be_bytes());
+}
+
+fn encode_str(encoded: &mut Vec<u8>, value: &str) {
+    encoded.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    encoded.extend_from_slice(value.as_bytes());
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.0.len() < len {
+            return Err("Invalid presence message".to_string());
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| "Invalid presence message".to_string())
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex};
+
+/***
+ * Transfer progress of a request or response body, see HttpConnection::on_upload_progress()
+ * and HttpConnection::on_download_progress(). Hooks run on the uWS loop thread for uploads,
+ * so they should be cheap (e.g. push the value into a channel that a websocket forwards).
+ ***/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub transferred: u64,
+    // From content-length, None if the body is chunked
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    // 0.0 - 1.0, None if the total isn't known
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total {
+            Some(0) => Some(1.0),
+            Some(total) => Some((self.transferred as f64 / total as f64).min(1.0)),
+            None => None,
+        }
+    }
+}
+
+pub type ProgressHook = Arc<dyn Fn(Progress) + Send + Sync>;
+
+// The body reader counts from the first chunk on, the hook may be set later
+#[derive(Clone, Default)]
+pub(crate) struct ProgressSlot {
+    hook: Arc<Mutex<Option<ProgressHook>>>,
+}
+
+impl ProgressSlot {
+    pub(crate) fn set(&self, hook: ProgressHook) {
+        *self.hook.lock().unwrap() = Some(hook);
+    }
+
+    pub(crate) fn report(&self, progress: Progress) {
+        let hook = self.hook.lock().unwrap().clone();
+        if let Some(hook) = hook {
+            hook(progress);
+        }
+    }
+}
+
+// Bytes of the response written so far, see HttpConnection::on_download_progress()
+pub(crate) struct DownloadProgress {
+    pub(crate) hook: ProgressHook,
+    pub(crate) sent: u64,
+    pub(crate) total: Option<u64>,
+}
+
+impl DownloadProgress {
+    pub(crate) fn add(&mut self, len: usize) {
+        self.sent += len as u64;
+        (self.hook)(Progress {
+            transferred: self.sent,
+            total: self.total,
+        });
+    }
+}
+
+pub(crate) fn content_length(headers: &[(String, String)]) -> Option<u64> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+}
@@ -0,0 +1,733 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, MapAccess, SeqAccess, VariantAccess,
+    Visitor,
+};
+
+use crate::percent_encoding::query_pairs;
+
+/***
+ * Query string deserialization for HttpRequest::query_as(), keys follow serde_qs:
+ * `user[name]=a&user[age]=3` is a nested struct, `tag[]=a&tag[]=b`, `tag[0]=a&tag[1]=b`
+ * and `tag=a&tag=b` are all a sequence. Values are parsed on demand, so `true` / `1` / `on`
+ * work for bools and numbers are checked against the target type.
+ ***/
+pub(crate) fn from_query<T: DeserializeOwned>(query: &str) -> Result<T, String> {
+    let mut root = QueryValue::Map(Vec::new());
+    for (key, value) in query_pairs(query) {
+        root.insert(&key_path(&key), value)?;
+    }
+    T::deserialize(root).map_err(|e| e.to_string())
+}
+
+/***
+ * Route parameters for HttpRequest::params_as(): a struct or map takes them by name, a tuple
+ * or sequence in the order of the pattern, anything else needs the route to have exactly one.
+ ***/
+pub(crate) fn from_params<T: DeserializeOwned>(params: &[(String, String)]) -> Result<T, String> {
+    T::deserialize(RouteParams(params.to_vec()))
+        .map_err(|e| format!("Invalid route parameters: {}", e.0))
+}
+
+#[derive(Debug)]
+enum QueryValue {
+    Value(String),
+    Seq(Vec<QueryValue>),
+    Map(Vec<(String, QueryValue)>),
+}
+
+// `a[b][]` -> ["a", "b", ""], brackets that don't pair up are part of the name
+fn key_path(key: &str) -> Vec<String> {
+    let Some(open) = key.find('[').filter(|open| *open > 0) else {
+        return vec![key.to_string()];
+    };
+    let mut path = vec![key[..open].to_string()];
+    let mut rest = &key[open..];
+    while let Some(inner) = rest.strip_prefix('[') {
+        let Some(close) = inner.find(']') else {
+            return vec![key.to_string()];
+        };
+        path.push(inner[..close].to_string());
+        rest = &inner[close + 1..];
+    }
+    if !rest.is_empty() {
+        return vec![key.to_string()];
+    }
+    path
+}
+
+impl QueryValue {
+    fn insert(&mut self, path: &[String], value: String) -> Result<(), String> {
+        let Some((key, rest)) = path.split_first() else {
+            return Ok(());
+        };
+
+        if key.is_empty() {
+            let seq = self.as_seq()?;
+            if rest.is_empty() {
+                seq.push(QueryValue::Value(value));
+            } else {
+                let mut child = QueryValue::Map(Vec::new());
+                child.insert(rest, value)?;
+                seq.push(child);
+            }
+            return Ok(());
+        }
+
+        let QueryValue::Map(entries) = self else {
+            return Err(format!("Query key {key:?} conflicts with another value"));
+        };
+        let index = match entries.iter().position(|(name, _)| name == key) {
+            Some(index) => index,
+            None if rest.is_empty() => {
+                entries.push((key.clone(), QueryValue::Value(value)));
+                return Ok(());
+            }
+            None => {
+                entries.push((key.clone(), QueryValue::Map(Vec::new())));
+                entries.len() - 1
+            }
+        };
+
+        let entry = &mut entries[index].1;
+        if !rest.is_empty() {
+            return entry.insert(rest, value);
+        }
+        // Repeated key, `tag=a&tag=b`
+        match entry {
+            QueryValue::Value(_) => {
+                let first = std::mem::replace(entry, QueryValue::Seq(Vec::new()));
+                entry.as_seq()?.push(first);
+                entry.as_seq()?.push(QueryValue::Value(value));
+            }
+            QueryValue::Seq(seq) => seq.push(QueryValue::Value(value)),
+            QueryValue::Map(_) => {
+                return Err(format!("Query key {key:?} is both a value and a map"));
+            }
+        }
+        Ok(())
+    }
+
+    // An empty map (a freshly created child) turns into a sequence
+    fn as_seq(&mut self) -> Result<&mut Vec<QueryValue>, String> {
+        if matches!(self, QueryValue::Map(entries) if entries.is_empty()) {
+            *self = QueryValue::Seq(Vec::new());
+        }
+        match self {
+            QueryValue::Seq(seq) => Ok(seq),
+            _ => Err("Query list conflicts with a map".to_string()),
+        }
+    }
+
+    fn into_string(self) -> Result<String, QueryError> {
+        match self {
+            QueryValue::Value(value) => Ok(value),
+            _ => Err(QueryError(
+                "expected a value, found a list or map".to_string(),
+            )),
+        }
+    }
+
+    fn parse<T: FromStr>(self, expected: &str) -> Result<T, QueryError> {
+        let value = self.into_string()?;
+        value
+            .parse()
+            .map_err(|_| QueryError(format!("invalid {expected} {value:?}")))
+    }
+
+    // `a[0]=x&a[1]=y` is a map until a sequence is requested
+    fn into_seq(self) -> Result<Vec<QueryValue>, QueryError> {
+        match self {
+            QueryValue::Seq(seq) => Ok(seq),
+            QueryValue::Value(value) => Ok(vec![QueryValue::Value(value)]),
+            QueryValue::Map(entries) => {
+                let mut indexed = entries
+                    .into_iter()
+                    .map(|(key, value)| match key.parse::<usize>() {
+                        Ok(index) => Ok((index, value)),
+                        Err(_) => Err(QueryError(format!("expected a list, found key {key:?}"))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                indexed.sort_by_key(|(index, _)| *index);
+                Ok(indexed.into_iter().map(|(_, value)| value).collect())
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct QueryError(String);
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid query string: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl de::Error for QueryError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        QueryError(msg.to_string())
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident: $ty:ty),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for QueryValue {
+    type Error = QueryError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            QueryValue::Value(value) => visitor.visit_string(value),
+            QueryValue::Seq(seq) => visitor.visit_seq(QuerySeq(seq.into_iter())),
+            QueryValue::Map(entries) => visitor.visit_map(QueryMap {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        let value = self.into_string()?;
+        match value.as_str() {
+            "true" | "1" | "on" | "yes" => visitor.visit_bool(true),
+            "false" | "0" | "off" | "no" => visitor.visit_bool(false),
+            _ => Err(QueryError(format!("invalid bool {value:?}"))),
+        }
+    }
+
+    deserialize_parsed!(
+        deserialize_i8 => visit_i8: i8,
+        deserialize_i16 => visit_i16: i16,
+        deserialize_i32 => visit_i32: i32,
+        deserialize_i64 => visit_i64: i64,
+        deserialize_u8 => visit_u8: u8,
+        deserialize_u16 => visit_u16: u16,
+        deserialize_u32 => visit_u32: u32,
+        deserialize_u64 => visit_u64: u64,
+        deserialize_f32 => visit_f32: f32,
+        deserialize_f64 => visit_f64: f64,
+        deserialize_char => visit_char: char
+    );
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_string(self.into_string()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_string(self.into_string()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_byte_buf(self.into_string()?.into_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_byte_buf(self.into_string()?.into_bytes())
+    }
+
+    // `page=` is treated like a missing `page`
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            QueryValue::Value(value) if value.is_empty() => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_seq(QuerySeq(self.into_seq()?.into_iter()))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        match self {
+            QueryValue::Map(entries) => visitor.visit_map(QueryMap {
+                entries: entries.into_iter(),
+                value: None,
+            }),
+            _ => Err(QueryError("expected a map".to_string())),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_map(visitor)
+    }
+
+    // `sort=asc` for unit variants, `filter[range]=1` for variants with content
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        match self {
+            QueryValue::Value(variant) => visitor.visit_enum(QueryEnum {
+                variant,
+                value: None,
+            }),
+            QueryValue::Map(mut entries) if entries.len() == 1 => {
+                let (variant, value) = entries.remove(0);
+                visitor.visit_enum(QueryEnum {
+                    variant,
+                    value: Some(value),
+                })
+            }
+            _ => Err(QueryError("expected an enum variant".to_string())),
+        }
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+}
+
+struct QuerySeq(std::vec::IntoIter<QueryValue>);
+
+impl<'de> SeqAccess<'de> for QuerySeq {
+    type Error = QueryError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, QueryError> {
+        self.0
+            .next()
+            .map(|value| seed.deserialize(value))
+            .transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct QueryMap {
+    entries: std::vec::IntoIter<(String, QueryValue)>,
+    value: Option<QueryValue>,
+}
+
+impl<'de> MapAccess<'de> for QueryMap {
+    type Error = QueryError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, QueryError> {
+        let Some((key, value)) = self.entries.next() else {
+            return Ok(None);
+        };
+        self.value = Some(value);
+        seed.deserialize(QueryValue::Value(key)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, QueryError> {
+        let value = self
+            .value
+            .take()
+            .ok_or_else(|| QueryError("value requested before its key".to_string()))?;
+        seed.deserialize(value)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.entries.len())
+    }
+}
+
+struct QueryEnum {
+    variant: String,
+    value: Option<QueryValue>,
+}
+
+impl<'de> EnumAccess<'de> for QueryEnum {
+    type Error = QueryError;
+    type Variant = QueryVariant;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(
+        self,
+        seed: V,
+    ) -> Result<(V::Value, QueryVariant), QueryError> {
+        let variant = seed.deserialize(QueryValue::Value(self.variant))?;
+        Ok((variant, QueryVariant(self.value)))
+    }
+}
+
+struct QueryVariant(Option<QueryValue>);
+
+impl QueryVariant {
+    fn value(self) -> Result<QueryValue, QueryError> {
+        self.0
+            .ok_or_else(|| QueryError("expected a value for the enum variant".to_string()))
+    }
+}
+
+impl<'de> VariantAccess<'de> for QueryVariant {
+    type Error = QueryError;
+
+    fn unit_variant(self) -> Result<(), QueryError> {
+        match self.0 {
+            None => Ok(()),
+            Some(QueryValue::Value(value)) if value.is_empty() => Ok(()),
+            Some(_) => Err(QueryError(
+                "unexpected value for a unit variant".to_string(),
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+        self,
+        seed: T,
+    ) -> Result<T::Value, QueryError> {
+        seed.deserialize(self.value()?)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        de::Deserializer::deserialize_tuple(self.value()?, len, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        de::Deserializer::deserialize_struct(self.value()?, "", fields, visitor)
+    }
+}
+
+struct RouteParams(Vec<(String, String)>);
+
+impl RouteParams {
+    fn into_map(self) -> QueryValue {
+        let entries = self
+            .0
+            .into_iter()
+            .map(|(name, value)| (name, QueryValue::Value(value)))
+            .collect();
+        QueryValue::Map(entries)
+    }
+
+    fn into_seq(self) -> QueryValue {
+        let values = self
+            .0
+            .into_iter()
+            .map(|(_, value)| QueryValue::Value(value))
+            .collect();
+        QueryValue::Seq(values)
+    }
+
+    fn into_single(mut self) -> Result<QueryValue, QueryError> {
+        match self.0.len() {
+            1 => Ok(QueryValue::Value(self.0.remove(0).1)),
+            count => Err(QueryError(format!("expected 1 parameter, found {count}"))),
+        }
+    }
+}
+
+macro_rules! deserialize_single {
+    ($($method:ident),*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+                self.into_single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for RouteParams {
+    type Error = QueryError;
+
+    deserialize_single!(
+        deserialize_bool,
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_f32,
+        deserialize_f64,
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_option,
+        deserialize_identifier
+    );
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        self.into_map().deserialize_any(visitor)
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        self.into_seq().deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        self.into_map().deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, QueryError> {
+        self.into_single()?
+            .deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, QueryError> {
+        visitor.visit_unit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Search {
+        q: String,
+        page: Option<u32>,
+        #[serde(default)]
+        tag: Vec<String>,
+        #[serde(default)]
+        exact: bool,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct User {
+        name: String,
+        age: u8,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Nested {
+        user: User,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    enum Sort {
+        Asc,
+        Desc,
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Sorted {
+        sort: Sort,
+    }
+
+    #[test]
+    fn decodes_values() {
+        let search: Search = from_query("q=rust+lang%21&page=2&exact=on").unwrap();
+        assert_eq!(
+            search,
+            Search {
+                q: "rust lang!".to_string(),
+                page: Some(2),
+                tag: Vec::new(),
+                exact: true,
+            }
+        );
+    }
+
+    #[test]
+    fn empty_value_is_none() {
+        let search: Search = from_query("q=x&page=").unwrap();
+        assert_eq!(search.page, None);
+    }
+
+    #[test]
+    fn reads_sequences_in_every_form() {
+        for query in [
+            "q=x&tag=a&tag=b",
+            "q=x&tag[]=a&tag[]=b",
+            "q=x&tag[1]=b&tag[0]=a",
+            "q=x&tag%5B%5D=a&tag%5B%5D=b",
+        ] {
+            let search: Search = from_query(query).unwrap();
+            assert_eq!(search.tag, vec!["a", "b"], "{query}");
+        }
+    }
+
+    #[test]
+    fn reads_nested_maps() {
+        let nested: Nested = from_query("user[name]=Ann%20Lee&user[age]=31").unwrap();
+        assert_eq!(
+            nested.user,
+            User {
+                name: "Ann Lee".to_string(),
+                age: 31,
+            }
+        );
+    }
+
+    #[test]
+    fn reads_unit_variants() {
+        let sorted: Sorted = from_query("sort=desc").unwrap();
+        assert_eq!(sorted.sort, Sort::Desc);
+        assert!(from_query::<Sorted>("sort=up").is_err());
+    }
+
+    #[test]
+    fn unpaired_brackets_are_part_of_the_name() {
+        assert_eq!(key_path("a[b"), vec!["a[b"]);
+        assert_eq!(key_path("a]b"), vec!["a]b"]);
+        assert_eq!(key_path("[a]"), vec!["[a]"]);
+        assert_eq!(key_path("a[b]c"), vec!["a[b]c"]);
+        assert_eq!(key_path("a[b][]"), vec!["a", "b", ""]);
+    }
+
+    #[test]
+    fn rejects_values_of_the_wrong_type() {
+        assert!(from_query::<Search>("q=x&page=two").is_err());
+        assert!(from_query::<Search>("q=x&page=-1").is_err());
+        assert!(from_query::<Search>("q=x&exact=maybe").is_err());
+        assert!(from_query::<Nested>("user[name]=a&user[age]=300").is_err());
+        assert!(from_query::<Search>("page=1").is_err());
+    }
+
+    #[test]
+    fn rejects_conflicting_keys() {
+        assert!(from_query::<HashMap<String, String>>("a=1&a[b]=2").is_err());
+        assert!(from_query::<Search>("q=x&tag[name]=a").is_err());
+    }
+
+    #[test]
+    fn keeps_malformed_escapes() {
+        let search: Search = from_query("q=100%&tag=%zz").unwrap();
+        assert_eq!(search.q, "100%");
+        assert_eq!(search.tag, vec!["%zz"]);
+    }
+
+    #[test]
+    fn reads_route_params() {
+        let params = vec![
+            ("team".to_string(), "7".to_string()),
+            ("user".to_string(), "ann".to_string()),
+        ];
+        let (team, user): (u32, String) = from_params(&params).unwrap();
+        assert_eq!((team, user.as_str()), (7, "ann"));
+        let map: HashMap<String, String> = from_params(&params).unwrap();
+        assert_eq!(map["user"], "ann");
+        // A single value needs exactly one parameter
+        assert!(from_params::<u32>(&params).is_err());
+        assert_eq!(from_params::<u32>(&params[..1]).unwrap(), 7);
+    }
+}
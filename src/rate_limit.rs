@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::error;
+
+pub type RateLimitFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<WindowCount, String>> + Send + 'a>>;
+
+/***
+ * Where rate limit counters live. The in-memory store limits each process on its own,
+ * a shared one (RedisRateLimitStore with the "redis" feature) makes the limit hold across
+ * every instance of the app.
+ ***/
+pub trait RateLimitStore: Send + Sync {
+    // Counts a hit for `key` in its current fixed window, a new window of `window` starts
+    // with the first hit after the previous one ended
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> RateLimitFuture<'a>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowCount {
+    // Hits in the current window, this one included
+    pub count: u64,
+    pub resets_in: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub remaining: u32,
+    pub retry_after: Duration,
+}
+
+/***
+ * `limit` hits per `window` for each key, see App::rate_limit() and WsRouteSettings::rate_limiter().
+ * If the store fails the hit is allowed, an unreachable store shouldn't take the app down.
+ ***/
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    limit: u32,
+    window: Duration,
+    prefix: String,
+}
+
+impl fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimiter")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        RateLimiter {
+            store: Arc::new(MemoryRateLimitStore::default()),
+            limit,
+            window,
+            prefix: "async_uws:rl:".to_string(),
+        }
+    }
+
+    pub fn store(mut self, store: impl RateLimitStore + 'static) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    // Prepended to every key, keeps limiters sharing a store apart
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub async fn check(&self, key: &str) -> RateLimitDecision {
+        let key = format!("{}{key}", self.prefix);
+        match self.store.hit(&key, self.window).await {
+            Ok(count) => RateLimitDecision {
+                allowed: count.count <= self.limit as u64,
+                remaining: (self.limit as u64).saturating_sub(count.count) as u32,
+                retry_after: count.resets_in,
+            },
+            Err(e) => {
+                error!("[async_uws] Rate limit store error, letting the hit through: {e}");
+                RateLimitDecision {
+                    allowed: true,
+                    remaining: self.limit,
+                    retry_after: Duration::ZERO,
+                }
+            }
+        }
+    }
+}
+
+// Fixed windows per key in this process only, expired keys are dropped as the map grows
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    windows: Mutex<MemoryWindows>,
+}
+
+#[derive(Default)]
+struct MemoryWindows {
+    // Key -> (window end, hits)
+    counts: HashMap<String, (Instant, u64)>,
+    prune_at: usize,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn count(&self, key: &str, window: Duration) -> WindowCount {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.counts.len() >= windows.prune_at {
+            windows.counts.retain(|_, (end, _)| *end > now);
+            windows.prune_at = (windows.counts.len() * 2).max(1024);
+        }
+
+        let (end, count) = windows
+            .counts
+            .entry(key.to_string())
+            .or_insert((now + window, 0));
+        if *end <= now {
+            *end = now + window;
+            *count = 0;
+        }
+        *count += 1;
+        WindowCount {
+            count: *count,
+            resets_in: *end - now,
+        }
+    }
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn hit<'a>(&'a self, key: &'a str, window: Duration) -> RateLimitFuture<'a> {
+        let count = self.count(key, window);
+        Box::pin(async move { Ok(count) })
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisRateLimitStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use std::time::Duration;
+
+    use redis::aio::MultiplexedConnection;
+    use redis::{Client, Script};
+    use tokio::sync::OnceCell;
+
+    use super::{RateLimitFuture, RateLimitStore, WindowCount};
+
+    // INCR and PEXPIRE in one step, so a window always gets its expiry
+    const HIT_SCRIPT: &str = r"
+        local count = redis.call('INCR', KEYS[1])
+        if count == 1 then
+            redis.call('PEXPIRE', KEYS[1], ARGV[1])
+        end
+        return {count, redis.call('PTTL', KEYS[1])}
+    ";
+
+    // Counters shared by every instance connected to the same Redis, connects on the first hit
+    pub struct RedisRateLimitStore {
+        client: Client,
+        connection: OnceCell<MultiplexedConnection>,
+        script: Script,
+    }
+
+    impl RedisRateLimitStore {
+        // e.g. "redis://127.0.0.1:6379/0"
+        pub fn new(url: &str) -> Result<Self, String> {
+            let client = Client::open(url).map_err(|e| format!("Invalid Redis url: {e}"))?;
+            Ok(RedisRateLimitStore {
+                client,
+                connection: OnceCell::new(),
+                script: Script::new(HIT_SCRIPT),
+            })
+        }
+
+        async fn count(&self, key: &str, window: Duration) -> Result<WindowCount, String> {
+            let connection = self
+                .connection
+                .get_or_try_init(|| self.client.get_multiplexed_async_connection())
+                .await
+                .map_err(|e| format!("Can't connect to Redis: {e}"))?;
+            let mut connection = connection.clone();
+            let window_ms = window.as_millis().max(1) as u64;
+            let (count, ttl_ms): (u64, i64) = self
+                .script
+                .key(key)
+                .arg(window_ms)
+                .invoke_async(&mut connection)
+                .await
+                .map_err(|e| format!("Redis rate limit hit failed: {e}"))?;
+            Ok(WindowCount {
+                count,
+                resets_in: Duration::from_millis(ttl_ms.max(0) as u64),
+            })
+        }
+    }
+
+    impl RateLimitStore for RedisRateLimitStore {
+        fn hit<'a>(&'a self, key: &'a str, window: Duration) -> RateLimitFuture<'a> {
+            Box::pin(self.count(key, window))
+        }
+    }
+}
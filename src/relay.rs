@@ -0,0 +1,289 @@
+#[cfg(feature = "rustls")]
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fs::DirBuilder;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::os::raw::c_char;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::ptr::null_mut;
+use std::sync::Arc;
+use std::time::Duration;
+
+use libuwebsockets_sys::{us_listen_socket_t, uws_app_listen_domain, uws_app_t};
+use log::{debug, error};
+use socket2::{Domain, Socket, Type};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixStream};
+use tokio::sync::watch;
+
+#[cfg(feature = "rustls")]
+use crate::app::BoxedHandlerFuture;
+use crate::sticky::{ip_key, HashRing};
+use crate::task;
+
+#[cfg(feature = "rustls")]
+pub type RustlsStream = tokio_rustls::server::TlsStream<TcpStream>;
+#[cfg(feature = "rustls")]
+pub(crate) type AlpnStreamHandler =
+    Arc<dyn Fn(RustlsStream, SocketAddr) -> BoxedHandlerFuture + Send + Sync>;
+#[cfg(feature = "rustls")]
+pub(crate) type AlpnStreamHandlers = HashMap<Vec<u8>, AlpnStreamHandler>;
+
+const PROXY_V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/***
+ * uWS can't adopt an already bound socket, so sockets created outside of it
+ * (systemd activation, handed over by the previous process) are accepted here
+ * and relayed to a unix socket the app listens on.
+ ***/
+pub(crate) fn listen_unix<const SSL: bool>(
+    app: *mut uws_app_t,
+    path: &Path,
+) -> Result<*mut us_listen_socket_t, String> {
+    unsafe extern "C" fn on_listen(
+        listen_socket: *mut us_listen_socket_t,
+        _: *const c_char,
+        _: usize,
+        _: i32,
+        user_data: *mut c_void,
+    ) {
+        *(user_data as *mut *mut us_listen_socket_t) = listen_socket;
+    }
+
+    let domain = path.to_string_lossy().to_string();
+    let mut listen_socket: *mut us_listen_socket_t = null_mut();
+    // Listen handler is called synchronously, so a pointer to the stack is fine
+    unsafe {
+        uws_app_listen_domain(
+            SSL as i32,
+            app,
+            domain.as_ptr() as *const c_char,
+            domain.len(),
+            Some(on_listen),
+            &mut listen_socket as *mut *mut us_listen_socket_t as *mut c_void,
+        );
+    }
+
+    if listen_socket.is_null() {
+        return Err(format!("Can't listen on unix socket {domain}"));
+    }
+    Ok(listen_socket)
+}
+
+// Unique per process and listener, removed by uSockets before bind
+pub(crate) fn unix_socket_path(name: &str) -> Result<PathBuf, String> {
+    Ok(relay_socket_dir()?.join(format!("{name}.sock")))
+}
+
+/***
+ * Directory of the process' relay sockets, only accessible by the user running it (0700).
+ * The app trusts what comes in on them, including the PROXY header with the client address,
+ * so other users must neither connect to them nor put their own socket in their place.
+ * An existing directory is only used if it is ours and private.
+ ***/
+fn relay_socket_dir() -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join(format!("async_uws-{}", std::process::id()));
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => return Ok(dir),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(format!("Can't create {}: {e}", dir.display())),
+    }
+
+    let metadata = std::fs::symlink_metadata(&dir)
+        .map_err(|e| format!("Can't check {}: {e}", dir.display()))?;
+    let is_ours = metadata.is_dir()
+        && metadata.uid() == unsafe { libc::geteuid() }
+        && metadata.mode() & 0o077 == 0;
+    if !is_ours {
+        return Err(format!(
+            "{} exists and isn't a private directory of this user",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+#[derive(Clone)]
+pub(crate) enum RelayMode {
+    // Bytes are passed as is, `proxy_header` must be off for SSL apps
+    // since uWS parses the header after the TLS handshake
+    Plain {
+        proxy_header: bool,
+    },
+    // TLS is terminated here, the plain app gets decrypted bytes
+    // Connections negotiating one of the handled ALPN protocols are not relayed but passed to the handler
+    #[cfg(feature = "rustls")]
+    Rustls(tokio_rustls::TlsAcceptor, Arc<AlpnStreamHandlers>),
+}
+
+// Unix socket(s) relayed connections are piped to
+#[derive(Clone)]
+pub(crate) enum RelayTarget {
+    Single(PathBuf),
+    // One socket per worker, picked by the client IP, see App::listen_sticky()
+    Sticky(Arc<HashRing>, Arc<Vec<PathBuf>>),
+}
+
+impl RelayTarget {
+    fn path_for(&self, peer: &SocketAddr) -> &Path {
+        match self {
+            RelayTarget::Single(path) => path,
+            RelayTarget::Sticky(ring, paths) => {
+                &paths[ring.worker_for(&ip_key(&peer.ip().to_string()))]
+            }
+        }
+    }
+}
+
+// Dual stack listener with the same reuse flags uSockets sets on its own sockets
+pub(crate) fn bind_tcp(port: u16) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(Domain::IPV6, Type::STREAM, None)?;
+    socket.set_only_v6(false)?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)).into())?;
+    socket.listen(512)?;
+    Ok(socket.into())
+}
+
+/***
+ * Accepts connections on `listener` and pipes them to the unix socket `target` picks
+ * until `shutdown` flips to true.
+ * Every connection starts with a PROXY v2 header carrying the client address unless disabled by `mode`.
+ ***/
+pub(crate) async fn relay_listener(
+    listener: std::net::TcpListener,
+    target: RelayTarget,
+    mode: RelayMode,
+    mut paused: watch::Receiver<bool>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let listener = match listener
+        .set_nonblocking(true)
+        .and_then(|_| TcpListener::from_std(listener))
+    {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("[async_uws] Can't register relayed listener: {e:#?}");
+            return;
+        }
+    };
+
+    loop {
+        // While paused new clients wait in the backlog
+        let is_paused = *paused.borrow_and_update();
+        tokio::select! {
+            accepted = listener.accept(), if !is_paused => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        // Mostly out of file descriptors, retrying right away would spin
+                        error!("[async_uws] Relayed listener accept failed: {e:#?}");
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let path = target.path_for(&peer).to_path_buf();
+                let mode = mode.clone();
+                task::spawn("async_uws relay connection", async move {
+                    if let Err(e) = relay_connection(stream, peer, &path, mode).await {
+                        debug!("[async_uws] Relayed connection from {peer} closed: {e:#?}");
+                    }
+                });
+            }
+            // Err once the app and its AcceptControls are gone
+            changed = paused.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+            }
+            changed = shutdown.changed() => {
+                if changed.is_err() || *shutdown.borrow() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+async fn relay_connection(
+    inbound: TcpStream,
+    peer: SocketAddr,
+    path: &Path,
+    mode: RelayMode,
+) -> io::Result<()> {
+    let _ = inbound.set_nodelay(true);
+    let local = inbound.local_addr()?;
+    match mode {
+        RelayMode::Plain { proxy_header } => pipe(inbound, peer, local, path, proxy_header).await,
+        #[cfg(feature = "rustls")]
+        RelayMode::Rustls(acceptor, alpn_handlers) => {
+            let inbound = acceptor.accept(inbound).await?;
+            let alpn_handler = inbound
+                .get_ref()
+                .1
+                .alpn_protocol()
+                .and_then(|protocol| alpn_handlers.get(protocol))
+                .cloned();
+            if let Some(alpn_handler) = alpn_handler {
+                alpn_handler(inbound, peer).await;
+                return Ok(());
+            }
+            pipe(inbound, peer, local, path, true).await
+        }
+    }
+}
+
+async fn pipe<S>(
+    mut inbound: S,
+    peer: SocketAddr,
+    local: SocketAddr,
+    path: &Path,
+    proxy_header: bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut outbound = UnixStream::connect(path).await?;
+    if proxy_header {
+        outbound.write_all(&proxy_v2_header(peer, local)).await?;
+    }
+    tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await?;
+    Ok(())
+}
+
+pub(crate) fn proxy_v2_header(source: SocketAddr, destination: SocketAddr) -> Vec<u8> {
+    let mut header = PROXY_V2_SIGNATURE.to_vec();
+    // Version 2, PROXY command
+    header.push(0x21);
+
+    match (source.ip(), destination.ip()) {
+        (IpAddr::V4(source_ip), IpAddr::V4(destination_ip)) => {
+            // INET + STREAM
+            header.push(0x11);
+            header.extend_from_slice(&12u16.to_be_bytes());
+            header.extend_from_slice(&source_ip.octets());
+            header.extend_from_slice(&destination_ip.octets());
+        }
+        (source_ip, destination_ip) => {
+            // INET6 + STREAM
+            header.push(0x21);
+            header.extend_from_slice(&36u16.to_be_bytes());
+            header.extend_from_slice(&to_ipv6(source_ip).octets());
+            header.extend_from_slice(&to_ipv6(destination_ip).octets());
+        }
+    }
+    header.extend_from_slice(&source.port().to_be_bytes());
+    header.extend_from_slice(&destination.port().to_be_bytes());
+    header
+}
+
+fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
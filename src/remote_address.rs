@@ -0,0 +1,33 @@
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{BorrowedFd, RawFd};
+
+use libuwebsockets_sys::{us_socket_get_native_handle, us_socket_t};
+use socket2::SockRef;
+
+/***
+ * Peer of a uWS response or websocket, both are a us_socket_t underneath. uWS only reports the
+ * IP (the one from the PROXY header if the connection has one), so the port is read from the
+ * socket itself. It is 0 if the socket's peer isn't the client, e.g. a relayed connection.
+ * Must be called on the loop thread.
+ ***/
+pub(crate) fn remote_address(socket: *mut us_socket_t, ip: &str) -> Option<SocketAddr> {
+    let ip = ip.parse::<IpAddr>().ok()?.to_canonical();
+    let port = peer_address(socket)
+        .filter(|peer| peer.ip().to_canonical() == ip)
+        .map(|peer| peer.port())
+        .unwrap_or(0);
+    Some(SocketAddr::new(ip, port))
+}
+
+fn peer_address(socket: *mut us_socket_t) -> Option<SocketAddr> {
+    if socket.is_null() {
+        return None;
+    }
+    // ssl = 0 makes uSockets return the fd even for SSL sockets
+    let fd = unsafe { us_socket_get_native_handle(0, socket) as RawFd };
+    if fd < 0 {
+        return None;
+    }
+    let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+    SockRef::from(&fd).peer_addr().ok()?.as_socket()
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use uwebsockets_rs::http_response::HttpResponseStruct;
+
+use crate::http_connection::HttpConnection;
+
+type GeneratorFuture = Pin<Box<dyn Future<Output = CachedResponse> + Send>>;
+pub(crate) type Generator = Arc<dyn Fn() -> GeneratorFuture + Send + Sync>;
+
+// Status, headers and body as they go on the wire, clones share the body
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl CachedResponse {
+    pub fn new(body: impl Into<Bytes>) -> Self {
+        CachedResponse {
+            status: "200 OK".to_string(),
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn status(mut self, status: &str) -> Self {
+        self.status = status.to_string();
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub(crate) fn write_to<const SSL: bool>(&self, res: &HttpResponseStruct<SSL>) {
+        res.write_status(&self.status);
+        for (key, value) in self.headers.iter() {
+            res.write_header(key, value);
+        }
+        res.end(Some(&self.body), false);
+    }
+
+    pub(crate) async fn send<const SSL: bool>(self, mut res: HttpConnection<SSL>) {
+        res.write_status(self.status);
+        for (key, value) in self.headers {
+            res.write_header(key, value);
+        }
+        res.end_with(self.body, false).await;
+    }
+}
+
+/***
+ * Responses of App::cached_route() routes, shared by clones. Hits are written straight from the
+ * uWS callback without spawning a task, a miss (first request, expired, invalidated) runs the
+ * generator once while concurrent misses wait for its result.
+ ***/
+#[derive(Clone, Default)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+struct Entry {
+    ttl: Duration,
+    generator: Generator,
+    cached: Option<(CachedResponse, Instant)>,
+    // Bumped by invalidate(), a generator that started before doesn't store its result
+    version: u64,
+    // Held while generating, so concurrent misses don't run the generator again
+    generating: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ResponseCache {
+    // Next request for `path` runs the generator again
+    pub fn invalidate(&self, path: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(path) {
+            entry.cached = None;
+            entry.version += 1;
+        }
+    }
+
+    pub fn invalidate_all(&self) {
+        for entry in self.entries.lock().unwrap().values_mut() {
+            entry.cached = None;
+            entry.version += 1;
+        }
+    }
+
+    pub(crate) fn register(&self, path: &str, ttl: Duration, generator: Generator) {
+        let entry = Entry {
+            ttl,
+            generator,
+            cached: None,
+            version: 0,
+            generating: Default::default(),
+        };
+        self.entries.lock().unwrap().insert(path.to_string(), entry);
+    }
+
+    pub(crate) fn fresh(&self, path: &str) -> Option<CachedResponse> {
+        let entries = self.entries.lock().unwrap();
+        let (response, expires) = entries.get(path)?.cached.as_ref()?;
+        (Instant::now() < *expires).then(|| response.clone())
+    }
+
+    pub(crate) async fn get_or_generate(&self, path: &str) -> Option<CachedResponse> {
+        let generating = {
+            let entries = self.entries.lock().unwrap();
+            entries.get(path)?.generating.clone()
+        };
+        let _generating = generating.lock().await;
+        // Generated by the request this one waited for
+        if let Some(response) = self.fresh(path) {
+            return Some(response);
+        }
+
+        let (generator, version) = {
+            let entries = self.entries.lock().unwrap();
+            let entry = entries.get(path)?;
+            (entry.generator.clone(), entry.version)
+        };
+        let response = generator().await;
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(path) {
+            if entry.version == version {
+                entry.cached = Some((response.clone(), Instant::now() + entry.ttl));
+            }
+        }
+        Some(response)
+    }
+}
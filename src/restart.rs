@@ -0,0 +1,68 @@
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+
+// First fd the replacement receives, matches socket_activation
+const LISTEN_FDS_START: RawFd = 3;
+
+/***
+ * Starts a new instance of the current binary with the same arguments.
+ * `listeners` are passed using the systemd protocol, so the new process picks them up with
+ * `listen_activated()`. Ports bound with `listen()` don't need to be passed:
+ * uSockets sets SO_REUSEPORT, so the new process can bind them while the old one is still running.
+ ***/
+pub fn spawn_replacement(listeners: &[BorrowedFd<'_>]) -> io::Result<Child> {
+    let executable = std::env::current_exe()?;
+    let fds: Vec<RawFd> = listeners.iter().map(|fd| fd.as_raw_fd()).collect();
+
+    let mut command = if fds.is_empty() {
+        Command::new(executable)
+    } else {
+        // LISTEN_PID has to be the pid of the new process, the shell execs in place so `$$` is it
+        let mut command = Command::new("/bin/sh");
+        command
+            .arg("-c")
+            .arg("LISTEN_PID=$$ exec \"$0\" \"$@\"")
+            .arg(executable)
+            .env("LISTEN_FDS", fds.len().to_string())
+            .env_remove("LISTEN_FDNAMES");
+        command
+    };
+    command.args(std::env::args_os().skip(1));
+
+    if !fds.is_empty() {
+        unsafe {
+            command.pre_exec(move || move_fds(&fds));
+        }
+    }
+
+    command.spawn()
+}
+
+// Runs between fork and exec, only async-signal-safe calls here
+fn move_fds(fds: &[RawFd]) -> io::Result<()> {
+    let first_free = LISTEN_FDS_START + fds.len() as RawFd;
+    let mut moved = [0 as RawFd; 64];
+    if fds.len() > moved.len() {
+        return Err(io::Error::other("Too many listeners to pass"));
+    }
+
+    // Dup above the target range first, so sources inside it aren't overwritten
+    for (index, fd) in fds.iter().enumerate() {
+        let duplicate = unsafe { libc::fcntl(*fd, libc::F_DUPFD, first_free) };
+        if duplicate < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        moved[index] = duplicate;
+    }
+    for (index, fd) in moved[..fds.len()].iter().enumerate() {
+        // dup2 clears FD_CLOEXEC on the target
+        if unsafe { libc::dup2(*fd, LISTEN_FDS_START + index as RawFd) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        unsafe { libc::close(*fd) };
+    }
+
+    Ok(())
+}
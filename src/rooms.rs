@@ -0,0 +1,157 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::app_handle::AppHandle;
+use crate::connection_registry::ConnectionId;
+use crate::task;
+use crate::websocket::Websocket;
+use crate::ws_message::WsMessage;
+
+// Keeps room topics apart from the ones the application subscribes to itself
+const TOPIC_PREFIX: &str = "async_uws/room/";
+
+/***
+ * Named groups of websockets on top of uWS pub/sub, see App::rooms(). Every room is a topic the
+ * members are subscribed to, so broadcast() is a single publish however many members there are:
+ *
+ *   let rooms = app.rooms();
+ *   app.data(rooms);
+ *   // in the websocket handler
+ *   let rooms = ws.data::<Rooms>().unwrap().clone();
+ *   rooms.join(&ws, "lobby").await;
+ *   rooms.broadcast("lobby", "someone joined").await?;
+ *
+ * A closed socket leaves all of its rooms by itself. Clones share the rooms, to leave out the
+ * sender publish with ws.publish(&Rooms::topic("lobby"), ..) instead of broadcast().
+ ***/
+#[derive(Clone)]
+pub struct Rooms {
+    handle: AppHandle,
+    members: Arc<Mutex<Members>>,
+}
+
+#[derive(Default)]
+struct Members {
+    by_room: HashMap<String, HashSet<ConnectionId>>,
+    by_socket: HashMap<ConnectionId, HashSet<String>>,
+}
+
+impl Rooms {
+    pub fn new(handle: AppHandle) -> Self {
+        Rooms {
+            handle,
+            members: Default::default(),
+        }
+    }
+
+    // Pub/sub topic of `room`
+    pub fn topic(room: &str) -> String {
+        format!("{TOPIC_PREFIX}{room}")
+    }
+
+    // False if the socket is closed
+    pub async fn join<const SSL: bool>(&self, ws: &Websocket<SSL>, room: &str) -> bool {
+        if !ws.subscribe(&Self::topic(room)).await {
+            return false;
+        }
+        let id = ws.id();
+        let is_new_socket = {
+            let mut members = self.members.lock().unwrap();
+            members
+                .by_room
+                .entry(room.to_string())
+                .or_default()
+                .insert(id);
+            let rooms = members.by_socket.entry(id).or_default();
+            rooms.insert(room.to_string());
+            rooms.len() == 1
+        };
+        if is_new_socket {
+            self.leave_all_on_close(id, ws.closed.clone(), ws.is_open.clone());
+        }
+        true
+    }
+
+    // False if the socket wasn't in `room`
+    pub async fn leave<const SSL: bool>(&self, ws: &Websocket<SSL>, room: &str) -> bool {
+        ws.unsubscribe(&Self::topic(room)).await;
+        self.remove(ws.id(), room)
+    }
+
+    // True if any member was sent the message, Err for anything but WsMessage::Message
+    pub async fn broadcast(
+        &self,
+        room: &str,
+        message: impl Into<WsMessage>,
+    ) -> Result<bool, String> {
+        let WsMessage::Message(data, opcode) = message.into() else {
+            return Err("Only data messages can be broadcast to a room".to_string());
+        };
+        Ok(self
+            .handle
+            .publish(&Self::topic(room), data, opcode, false)
+            .await)
+    }
+
+    pub fn members(&self, room: &str) -> Vec<ConnectionId> {
+        let members = self.members.lock().unwrap();
+        members
+            .by_room
+            .get(room)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    // Rooms `id` is in
+    pub fn rooms_of(&self, id: ConnectionId) -> Vec<String> {
+        let members = self.members.lock().unwrap();
+        members
+            .by_socket
+            .get(&id)
+            .map(|rooms| rooms.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    // Rooms with at least one member
+    pub fn rooms(&self) -> Vec<String> {
+        let members = self.members.lock().unwrap();
+        members.by_room.keys().cloned().collect()
+    }
+
+    fn remove(&self, id: ConnectionId, room: &str) -> bool {
+        let mut members = self.members.lock().unwrap();
+        let Some(ids) = members.by_room.get_mut(room) else {
+            return false;
+        };
+        let removed = ids.remove(&id);
+        if ids.is_empty() {
+            members.by_room.remove(room);
+        }
+        if let Some(rooms) = members.by_socket.get_mut(&id) {
+            rooms.remove(room);
+            if rooms.is_empty() {
+                members.by_socket.remove(&id);
+            }
+        }
+        removed
+    }
+
+    // uWS drops the subscriptions of a closed socket, this drops its memberships
+    fn leave_all_on_close(&self, id: ConnectionId, closed: Arc<Notify>, is_open: Arc<AtomicBool>) {
+        let rooms = self.clone();
+        task::spawn("async_uws rooms cleanup", async move {
+            let closed = closed.notified();
+            tokio::pin!(closed);
+            closed.as_mut().enable();
+            if is_open.load(Ordering::Relaxed) {
+                closed.await;
+            }
+            for room in rooms.rooms_of(id) {
+                rooms.remove(id, &room);
+            }
+        });
+    }
+}
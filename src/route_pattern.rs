@@ -0,0 +1,337 @@
+use std::sync::{Arc, Mutex};
+
+use crate::http_request::HttpRequest;
+use crate::percent_encoding::percent_decode;
+
+/***
+ * Names of the parameters in a route pattern, `:id` segments and a trailing `*path` one. uWS
+ * matches `:id` itself but only knows a bare trailing `*`, so the wildcard's name is left out of
+ * the pattern given to uWS and its value is cut from the url instead.
+ ***/
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RoutePattern {
+    names: Vec<String>,
+    // Name of a trailing `*name` and the number of segments before it
+    wildcard: Option<(String, usize)>,
+}
+
+impl RoutePattern {
+    pub(crate) fn parse(pattern: &str) -> Self {
+        let segments: Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+        let names = segments
+            .iter()
+            .filter_map(|segment| segment.strip_prefix(':'))
+            .map(str::to_string)
+            .collect();
+        let wildcard = segments
+            .last()
+            .and_then(|segment| segment.strip_prefix('*'))
+            .filter(|name| !name.is_empty())
+            .map(|name| (name.to_string(), segments.len() - 1));
+        RoutePattern { names, wildcard }
+    }
+
+    // Decoded (name, value) pairs of a request matched by this pattern
+    pub(crate) fn params(&self, req: &HttpRequest) -> Vec<(String, String)> {
+        let decode = |value: &str| percent_decode(value).unwrap_or_else(|| value.to_string());
+        let mut params: Vec<(String, String)> = self
+            .names
+            .iter()
+            .zip(req.parameters.iter())
+            .map(|(name, value)| (name.clone(), decode(value)))
+            .collect();
+        if let Some((name, segments)) = self.wildcard.as_ref() {
+            let rest = req
+                .url
+                .match_indices('/')
+                .nth(*segments)
+                .map(|(index, _)| &req.url[index + 1..])
+                .unwrap_or_default();
+            params.push((name.clone(), decode(rest)));
+        }
+        params
+    }
+}
+
+// `/files/*path` -> `/files/*`, which is what uWS matches
+pub(crate) fn native_pattern(pattern: &str) -> String {
+    match pattern.rsplit_once('/') {
+        Some((prefix, last)) if last.len() > 1 && last.starts_with('*') => format!("{prefix}/*"),
+        _ => pattern.to_string(),
+    }
+}
+
+/***
+ * Methods registered per pattern, for the `allow` header of 405 and OPTIONS answers. `ANY` routes
+ * are kept so a catch-all of the app's own can be told apart, they never show up in allowed()
+ ***/
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteTable {
+    routes: Arc<Mutex<Vec<(&'static str, String)>>>,
+}
+
+impl RouteTable {
+    pub(crate) fn add(&self, method: &'static str, pattern: &str) {
+        let mut routes = self.routes.lock().unwrap();
+        if !routes.iter().any(|(m, p)| *m == method && p == pattern) {
+            routes.push((method, pattern.to_string()));
+        }
+    }
+
+    pub(crate) fn contains(&self, method: &str, pattern: &str) -> bool {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(m, p)| *m == method && p == pattern)
+    }
+
+    // Methods of the patterns matching `url` in the order they were added, OPTIONS last as the app
+    // answers it for every path with routes. Empty if there are none. A bare `/*` (cors() adds
+    // `OPTIONS /*`) matches every url, so it adds its method but doesn't make a path exist
+    pub(crate) fn allowed(&self, url: &str) -> Vec<&'static str> {
+        let mut allowed = Vec::new();
+        let mut exists = false;
+        for (method, pattern) in self.routes.lock().unwrap().iter() {
+            if *method == "ANY" || !matches(pattern, url) {
+                continue;
+            }
+            exists |= native_pattern(pattern) != "/*";
+            if !allowed.contains(method) {
+                allowed.push(*method);
+            }
+        }
+        if !exists {
+            return Vec::new();
+        }
+        if !allowed.contains(&"OPTIONS") {
+            allowed.push("OPTIONS");
+        }
+        allowed
+    }
+
+    // How the catch-all of App::add_fallback_route() answers a request no route took
+    pub(crate) fn unmatched(&self, method: &str, url: &str) -> Unmatched {
+        let allowed = self.allowed(url);
+        if allowed.is_empty() {
+            return Unmatched::NotFound;
+        }
+        if method.eq_ignore_ascii_case("options") {
+            return Unmatched::Options(allowed);
+        }
+        // Routes of the method turned the request down with their guards
+        if allowed
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+        {
+            return Unmatched::NotFound;
+        }
+        Unmatched::MethodNotAllowed(allowed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Unmatched {
+    // 204 with the path's methods in `allow`
+    Options(Vec<&'static str>),
+    // 405 with the same `allow`
+    MethodNotAllowed(Vec<&'static str>),
+    // App::fallback(), 404 without one
+    NotFound,
+}
+
+// Whether uWS would route `url` to `pattern`: `:name` takes one segment, a trailing `*` the rest
+pub(crate) fn matches(pattern: &str, url: &str) -> bool {
+    match_params(pattern, url).is_some()
+}
+
+// Raw values of the `:name` segments, as uWS gives them in HttpRequest::parameters
+pub(crate) fn match_params(pattern: &str, url: &str) -> Option<Vec<String>> {
+    let mut params = Vec::new();
+    let mut parts = url.trim_start_matches('/').split('/');
+    for segment in pattern.trim_start_matches('/').split('/') {
+        if segment.starts_with('*') {
+            return Some(params);
+        }
+        match parts.next() {
+            Some(part) if segment.starts_with(':') && !part.is_empty() => {
+                params.push(part.to_string())
+            }
+            Some(part) if part == segment => {}
+            _ => return None,
+        }
+    }
+    parts.next().is_none().then_some(params)
+}
+
+// Orders patterns matching the same url as uWS does: per segment static before `:name` before `*`
+pub(crate) fn specificity(pattern: &str) -> Vec<u8> {
+    pattern
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| match segment.chars().next() {
+            Some('*') => 0,
+            Some(':') => 1,
+            _ => 2,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(url: &str, parameters: &[&str]) -> HttpRequest {
+        HttpRequest {
+            headers: Vec::new(),
+            full_url: url.to_string(),
+            url: url.to_string(),
+            method: "get".to_string(),
+            case_sensitive_method: "GET".to_string(),
+            parameters: parameters.iter().map(|value| value.to_string()).collect(),
+            route_params: Vec::new(),
+            deadline: None,
+            remote_address: None,
+            client_ip: None,
+        }
+    }
+
+    fn params(pattern: &str, url: &str) -> Vec<(String, String)> {
+        let parameters = match_params(pattern, url).unwrap();
+        let parameters: Vec<&str> = parameters.iter().map(String::as_str).collect();
+        RoutePattern::parse(pattern).params(&request(url, &parameters))
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn names_params_and_wildcard() {
+        assert_eq!(
+            params("/teams/:team/users/:user", "/teams/7/users/ann"),
+            pairs(&[("team", "7"), ("user", "ann")])
+        );
+        assert_eq!(
+            params("/files/:bucket/*path", "/files/docs/2024/report.pdf"),
+            pairs(&[("bucket", "docs"), ("path", "2024/report.pdf")])
+        );
+        assert_eq!(params("/files/*", "/files/a/b"), pairs(&[]));
+    }
+
+    #[test]
+    fn decodes_param_values() {
+        assert_eq!(
+            params("/users/:name", "/users/Ann%20Lee"),
+            pairs(&[("name", "Ann Lee")])
+        );
+        assert_eq!(
+            params("/files/*path", "/files/a%2Fb/c%20d"),
+            pairs(&[("path", "a/b/c d")])
+        );
+        // Malformed escapes are kept as they are
+        assert_eq!(
+            params("/users/:name/*rest", "/users/100%/%zz"),
+            pairs(&[("name", "100%"), ("rest", "%zz")])
+        );
+    }
+
+    #[test]
+    fn wildcard_without_rest_is_empty() {
+        let pattern = RoutePattern::parse("/files/*path");
+        assert_eq!(
+            pattern.params(&request("/files", &[])),
+            pairs(&[("path", "")])
+        );
+    }
+
+    #[test]
+    fn strips_wildcard_names_for_uws() {
+        assert_eq!(native_pattern("/files/*path"), "/files/*");
+        assert_eq!(native_pattern("/files/*"), "/files/*");
+        assert_eq!(native_pattern("/users/:id"), "/users/:id");
+        assert_eq!(native_pattern("/*rest"), "/*");
+    }
+
+    #[test]
+    fn matches_like_uws() {
+        assert!(matches("/users/:id", "/users/7"));
+        assert!(!matches("/users/:id", "/users/"));
+        assert!(!matches("/users/:id", "/users/7/posts"));
+        assert!(!matches("/users/:id", "/accounts/7"));
+        assert!(matches("/files/*", "/files/a/b/c"));
+        assert!(matches("/", "/"));
+        assert!(!matches("/", "/a"));
+        assert_eq!(
+            match_params("/a/:x/b/:y", "/a/1/b/2"),
+            Some(vec!["1".to_string(), "2".to_string()])
+        );
+    }
+
+    #[test]
+    fn orders_static_before_params_before_wildcards() {
+        let mut patterns = vec!["/users/*", "/users/:id", "/users/me", "/:any/me"];
+        patterns.sort_by_key(|pattern| std::cmp::Reverse(specificity(pattern)));
+        assert_eq!(
+            patterns,
+            vec!["/users/me", "/users/:id", "/users/*", "/:any/me"]
+        );
+    }
+
+    #[test]
+    fn lists_allowed_methods() {
+        let table = RouteTable::default();
+        table.add("GET", "/users/:id");
+        table.add("POST", "/users/:id");
+        table.add("GET", "/users/:id");
+        table.add("ANY", "/*");
+        table.add("DELETE", "/users/:id");
+        assert!(table.contains("ANY", "/*"));
+        assert!(!table.contains("PUT", "/users/:id"));
+        assert_eq!(
+            table.allowed("/users/7"),
+            vec!["GET", "POST", "DELETE", "OPTIONS"]
+        );
+        assert!(table.allowed("/teams").is_empty());
+    }
+
+    #[test]
+    fn answers_unmatched_requests() {
+        let table = RouteTable::default();
+        table.add("GET", "/users/:id");
+        table.add("POST", "/users/:id");
+        assert_eq!(
+            table.unmatched("OPTIONS", "/users/7"),
+            Unmatched::Options(vec!["GET", "POST", "OPTIONS"])
+        );
+        assert_eq!(
+            table.unmatched("delete", "/users/7"),
+            Unmatched::MethodNotAllowed(vec!["GET", "POST", "OPTIONS"])
+        );
+        // A guard of the GET route turned it down
+        assert_eq!(table.unmatched("GET", "/users/7"), Unmatched::NotFound);
+        assert_eq!(table.unmatched("GET", "/teams"), Unmatched::NotFound);
+    }
+
+    #[test]
+    fn cors_catch_all_leaves_unknown_paths_404() {
+        let table = RouteTable::default();
+        table.add("GET", "/users/:id");
+        // What App::cors() registers
+        table.add("OPTIONS", "/*");
+        assert_eq!(table.unmatched("GET", "/nope"), Unmatched::NotFound);
+        assert_eq!(table.unmatched("POST", "/nope/deeper"), Unmatched::NotFound);
+        assert!(table.allowed("/nope").is_empty());
+        assert_eq!(
+            table.unmatched("POST", "/users/7"),
+            Unmatched::MethodNotAllowed(vec!["GET", "OPTIONS"])
+        );
+        // A catch-all with a name is one as well
+        table.add("PUT", "/*rest");
+        assert_eq!(table.unmatched("GET", "/nope"), Unmatched::NotFound);
+        assert_eq!(table.allowed("/users/7"), vec!["GET", "OPTIONS", "PUT"]);
+    }
+}
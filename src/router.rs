@@ -0,0 +1,185 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::app::BoxedHandlerFuture;
+use crate::cors::Cors;
+use crate::data_storage::{DataStorage, SharedDataStorage};
+use crate::guard::Guard;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+#[cfg(feature = "jwt")]
+use crate::jwt::JwtAuth;
+use crate::middleware::{boxed_middleware, with_middleware, Middleware, Next};
+
+pub(crate) type RouteHandler<const SSL: bool> =
+    Arc<dyn Fn(HttpConnection<SSL>, HttpRequest) -> BoxedHandlerFuture + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Method {
+    Get,
+    Post,
+    Patch,
+    Delete,
+    Options,
+    Put,
+    Trace,
+    Connect,
+    Any,
+}
+
+pub(crate) struct ScopedRoute<const SSL: bool> {
+    pub(crate) method: Method,
+    pub(crate) pattern: String,
+    pub(crate) handler: RouteHandler<SSL>,
+    // Data of the scopes the route is in, innermost first
+    pub(crate) data: Vec<SharedDataStorage>,
+    // Guards of the scopes the route is in, see App::route_guard()
+    pub(crate) guards: Vec<Guard>,
+}
+
+/***
+ * Routes registered under a common prefix, see App::scope():
+ *
+ *   let mut users = Router::new();
+ *   users.data(user_store).get("/:id", get_user).post("/", create_user);
+ *   app.scope("/api/v1/users", users);
+ *
+ * Data added to a router is seen by res.data() of its routes and its middleware only, and
+ * shadows app data of the same type. Routers can be nested with scope(), an inner router's data
+ * shadows the outer one's and its middleware runs after the outer one's.
+ ***/
+pub struct RouterStruct<const SSL: bool> {
+    routes: Vec<ScopedRoute<SSL>>,
+    data: DataStorage,
+    middleware: Vec<Middleware<SSL>>,
+    guards: Vec<Guard>,
+}
+
+pub type Router = RouterStruct<false>;
+pub type RouterSSL = RouterStruct<true>;
+
+macro_rules! router_route {
+    ($($method:ident => $variant:ident),*) => {
+        $(
+            pub fn $method<T, W>(&mut self, pattern: &str, handler: T) -> &mut Self
+            where
+                T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
+                W: Future<Output = ()> + 'static + Send,
+            {
+                self.add(Method::$variant, pattern, handler)
+            }
+        )*
+    };
+}
+
+impl<const SSL: bool> Default for RouterStruct<SSL> {
+    fn default() -> Self {
+        RouterStruct {
+            routes: Vec::new(),
+            data: DataStorage::new(),
+            middleware: Vec::new(),
+            guards: Vec::new(),
+        }
+    }
+}
+
+impl<const SSL: bool> RouterStruct<SSL> {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn data<T>(&mut self, data: T) -> &mut Self
+    where
+        T: Sync + Send + Clone + 'static,
+    {
+        self.data.add_data(data);
+        self
+    }
+
+    // Runs around every route of the router, after the app's middleware
+    pub fn middleware<T, W>(&mut self, middleware: T) -> &mut Self
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        self.middleware.push(boxed_middleware(middleware));
+        self
+    }
+
+    // Every route of the router only takes requests `guard` passes, see Guard
+    pub fn guard(&mut self, guard: Guard) -> &mut Self {
+        self.guards.push(guard);
+        self
+    }
+
+    // See App::cors(), preflights are answered for every path under the router's prefix
+    pub fn cors(&mut self, cors: Cors) -> &mut Self {
+        self.middleware(cors.into_middleware());
+        self.options("/*", |mut res, _| async move {
+            res.write_status("204 No Content".to_string());
+            res.end(None, false).await;
+        })
+    }
+
+    // See App::jwt_auth(), covers the routes of the router only
+    #[cfg(feature = "jwt")]
+    pub fn jwt_auth(&mut self, auth: JwtAuth) -> &mut Self {
+        self.middleware(auth.into_middleware())
+    }
+
+    router_route!(
+        get => Get,
+        post => Post,
+        patch => Patch,
+        delete => Delete,
+        options => Options,
+        put => Put,
+        trace => Trace,
+        connect => Connect,
+        any => Any
+    );
+
+    // Mounts `router` under `prefix` of this router
+    pub fn scope(&mut self, prefix: &str, router: RouterStruct<SSL>) -> &mut Self {
+        self.routes.extend(router.into_routes(prefix));
+        self
+    }
+
+    fn add<T, W>(&mut self, method: Method, pattern: &str, handler: T) -> &mut Self
+    where
+        T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync,
+        W: Future<Output = ()> + 'static + Send,
+    {
+        self.routes.push(ScopedRoute {
+            method,
+            pattern: pattern.to_string(),
+            handler: Arc::new(move |res, req| Box::pin(handler(res, req))),
+            data: Vec::new(),
+            guards: Vec::new(),
+        });
+        self
+    }
+
+    pub(crate) fn into_routes(self, prefix: &str) -> Vec<ScopedRoute<SSL>> {
+        let data: SharedDataStorage = Arc::new(self.data);
+        self.routes
+            .into_iter()
+            .map(|mut route| {
+                route.handler = with_middleware(self.middleware.clone(), route.handler);
+                route.pattern = join(prefix, &route.pattern);
+                route.data.push(data.clone());
+                route.guards.extend(self.guards.iter().cloned());
+                route
+            })
+            .collect()
+    }
+}
+
+// ("/api/", "/users") -> "/api/users", a "/" route is the prefix itself
+fn join(prefix: &str, pattern: &str) -> String {
+    let prefix = prefix.trim_end_matches('/');
+    match pattern.trim_start_matches('/') {
+        "" if !prefix.is_empty() => prefix.to_string(),
+        pattern => format!("{prefix}/{pattern}"),
+    }
+}
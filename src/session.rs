@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use log::error;
+
+use crate::app::BoxedHandlerFuture;
+#[cfg(feature = "signed-cookies")]
+use crate::cookie::CookieKey;
+use crate::cookie::{Cookie, SameSite};
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::middleware::Next;
+#[cfg(feature = "signed-cookies")]
+use crate::percent_encoding::{percent_encode, query_pairs};
+
+pub type SessionData = HashMap<String, String>;
+
+pub type SessionFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, String>> + Send + 'a>>;
+
+/***
+ * Where session data lives. The session cookie holds whatever save() returns: an id for stores
+ * keeping the data themselves (MemorySessionStore, a Redis or SQL one), the data itself for
+ * CookieSessionStore
+ ***/
+pub trait SessionStore: Send + Sync {
+    // Data behind a session cookie, None if it's unknown, expired or invalid
+    fn load<'a>(&'a self, cookie: &'a str) -> SessionFuture<'a, Option<SessionData>>;
+
+    // Keeps `data` for `ttl` and returns the cookie value, `cookie` is None for a new session
+    fn save<'a>(
+        &'a self,
+        cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> SessionFuture<'a, String>;
+
+    fn destroy<'a>(&'a self, cookie: &'a str) -> SessionFuture<'a, ()>;
+}
+
+/***
+ * Session of the request, from res.session() in handlers behind App::sessions(). Changes are
+ * saved and the cookie is sent right before the response goes out, so they have to happen before
+ * the handler ends or starts streaming the response
+ ***/
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<SessionState>>,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    data: SessionData,
+    is_new: bool,
+    changed: bool,
+    renewed: bool,
+    destroyed: bool,
+}
+
+impl Session {
+    fn new(data: Option<SessionData>) -> Self {
+        let state = SessionState {
+            is_new: data.is_none(),
+            data: data.unwrap_or_default(),
+            ..Default::default()
+        };
+        Session {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.state().data.get(key).cloned()
+    }
+
+    pub fn set(&self, key: &str, value: impl Into<String>) {
+        let mut state = self.state();
+        state.data.insert(key.to_string(), value.into());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) -> Option<String> {
+        let mut state = self.state();
+        let value = state.data.remove(key);
+        state.changed |= value.is_some();
+        value
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.changed = true;
+    }
+
+    // Value stored with set_json(), None if it's missing or doesn't parse
+    #[cfg(feature = "json")]
+    pub fn get_json<T: serde::de::DeserializeOwned>(&self, key: &str) -> Option<T> {
+        serde_json::from_str(&self.get(key)?).ok()
+    }
+
+    #[cfg(feature = "json")]
+    pub fn set_json<T: serde::Serialize + ?Sized>(
+        &self,
+        key: &str,
+        value: &T,
+    ) -> Result<(), String> {
+        let value = serde_json::to_string(value).map_err(|e| e.to_string())?;
+        self.set(key, value);
+        Ok(())
+    }
+
+    // Moves the data to a new session id, call it on login against session fixation
+    pub fn renew(&self) {
+        let mut state = self.state();
+        state.renewed = true;
+        state.changed = true;
+    }
+
+    // Drops the session from the store and removes the cookie
+    pub fn destroy(&self) {
+        let mut state = self.state();
+        state.data.clear();
+        state.destroyed = true;
+    }
+
+    // No session cookie came with the request (or it had expired)
+    pub fn is_new(&self) -> bool {
+        self.state().is_new
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.lock().unwrap()
+    }
+}
+
+/***
+ * Session middleware, see App::sessions():
+ *
+ *   app.sessions(Sessions::new(MemorySessionStore::new()).ttl(Duration::from_secs(3600)));
+ *   app.post("/login", |mut res, req| async move {
+ *       let session = res.session().unwrap();
+ *       session.renew();
+ *       session.set("user", "42");
+ *       res.end(None, false).await;
+ *   });
+ *
+ * With rolling() (on by default) every response with a session moves its expiry ttl() ahead,
+ * otherwise only responses that changed it do. Requests that never touch a session don't
+ * create one
+ ***/
+#[derive(Clone)]
+pub struct Sessions {
+    store: Arc<dyn SessionStore>,
+    cookie: Cookie,
+    ttl: Duration,
+    rolling: bool,
+}
+
+impl std::fmt::Debug for Sessions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sessions")
+            .field("cookie", &self.cookie)
+            .field("ttl", &self.ttl)
+            .field("rolling", &self.rolling)
+            .finish()
+    }
+}
+
+impl Sessions {
+    pub fn new(store: impl SessionStore + 'static) -> Self {
+        Sessions {
+            store: Arc::new(store),
+            cookie: Cookie::new("session", "")
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax),
+            ttl: Duration::from_secs(24 * 3600),
+            rolling: true,
+        }
+    }
+
+    // Name and attributes of the session cookie, `session` with Path=/, HttpOnly and
+    // SameSite=Lax by default. Max-Age is set from ttl()
+    pub fn cookie(mut self, cookie: Cookie) -> Self {
+        self.cookie = cookie;
+        self
+    }
+
+    // 24 hours by default
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn rolling(mut self, rolling: bool) -> Self {
+        self.rolling = rolling;
+        self
+    }
+
+    pub(crate) fn into_middleware<const SSL: bool>(
+        self,
+    ) -> impl Fn(HttpConnection<SSL>, HttpRequest, Next<SSL>) -> BoxedHandlerFuture + Send + Sync + 'static
+    {
+        let sessions = Arc::new(self);
+        move |res, req, next| Box::pin(sessions.clone().handle(res, req, next))
+    }
+
+    async fn handle<const SSL: bool>(
+        self: Arc<Self>,
+        mut res: HttpConnection<SSL>,
+        req: HttpRequest,
+        next: Next<SSL>,
+    ) {
+        let cookie = req.cookies().get(self.cookie.name()).map(str::to_string);
+        let data = match cookie.as_deref() {
+            Some(cookie) => self.store.load(cookie).await.unwrap_or_else(|e| {
+                error!("[async_uws] Can't load session: {e}");
+                None
+            }),
+            None => None,
+        };
+        // An expired or forged cookie is replaced
+        let cookie = cookie.filter(|_| data.is_some());
+        let session = Session::new(data);
+        res.set_data(session.clone());
+
+        let sessions = self.clone();
+        res.before_send(Box::new(move || {
+            Box::pin(async move { sessions.commit(session, cookie).await })
+        }));
+        next.run(res, req).await
+    }
+
+    // Saves the session, returns the set-cookie header to send if there is one
+    async fn commit(&self, session: Session, mut cookie: Option<String>) -> Vec<(String, String)> {
+        let (data, changed, renewed, destroyed) = {
+            let state = session.state();
+            (
+                state.data.clone(),
+                state.changed,
+                state.renewed,
+                state.destroyed,
+            )
+        };
+        if destroyed || renewed {
+            if let Some(old) = cookie.take() {
+                if let Err(e) = self.store.destroy(&old).await {
+                    error!("[async_uws] Can't destroy session: {e}");
+                }
+                if destroyed {
+                    // Same path and domain as the cookie it removes
+                    let removal = self
+                        .cookie
+                        .clone()
+                        .with_value(String::new())
+                        .max_age(Duration::ZERO)
+                        .expires(UNIX_EPOCH);
+                    return vec![("set-cookie".to_string(), removal.header_value())];
+                }
+            }
+            if destroyed {
+                return Vec::new();
+            }
+        }
+
+        let is_new = cookie.is_none();
+        if (is_new && data.is_empty()) || !(changed || self.rolling) {
+            return Vec::new();
+        }
+        match self.store.save(cookie.as_deref(), &data, self.ttl).await {
+            Ok(value) => {
+                let cookie = self.cookie.clone().with_value(value).max_age(self.ttl);
+                vec![("set-cookie".to_string(), cookie.header_value())]
+            }
+            Err(e) => {
+                error!("[async_uws] Can't save session: {e}");
+                Vec::new()
+            }
+        }
+    }
+}
+
+// Sessions of this process only, expired ones are dropped as the map grows
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: Mutex<MemorySessions>,
+}
+
+#[derive(Default)]
+struct MemorySessions {
+    // Id -> (expiry, data)
+    entries: HashMap<String, (Instant, SessionData)>,
+    prune_at: usize,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load<'a>(&'a self, cookie: &'a str) -> SessionFuture<'a, Option<SessionData>> {
+        let sessions = self.sessions.lock().unwrap();
+        let data = sessions
+            .entries
+            .get(cookie)
+            .filter(|(expiry, _)| *expiry > Instant::now())
+            .map(|(_, data)| data.clone());
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save<'a>(
+        &'a self,
+        cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> SessionFuture<'a, String> {
+        let id = match cookie {
+            Some(id) => Ok(id.to_string()),
+            None => session_id(),
+        };
+        if let Ok(id) = id.as_ref() {
+            let now = Instant::now();
+            let mut sessions = self.sessions.lock().unwrap();
+            if sessions.entries.len() >= sessions.prune_at {
+                sessions.entries.retain(|_, (expiry, _)| *expiry > now);
+                sessions.prune_at = (sessions.entries.len() * 2).max(1024);
+            }
+            sessions
+                .entries
+                .insert(id.clone(), (now + ttl, data.clone()));
+        }
+        Box::pin(async move { id })
+    }
+
+    fn destroy<'a>(&'a self, cookie: &'a str) -> SessionFuture<'a, ()> {
+        self.sessions.lock().unwrap().entries.remove(cookie);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+// 32 bytes from the kernel's CSPRNG as hex
+fn session_id() -> Result<String, String> {
+    let mut bytes = [0u8; 32];
+    let mut filled = 0;
+    while filled < bytes.len() {
+        let read = unsafe {
+            libc::getrandom(
+                bytes[filled..].as_mut_ptr() as *mut libc::c_void,
+                bytes.len() - filled,
+                0,
+            )
+        };
+        if read < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(format!("Can't generate session id: {e}"));
+        }
+        filled += read as usize;
+    }
+    Ok(bytes.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+/***
+ * Keeps the whole session in the cookie, signed with a CookieKey (or encrypted, see encrypted()),
+ * so nothing is stored on the server and every instance can read it. destroy() can't revoke a
+ * copy of the cookie the client kept, the expiry inside it still ends it. Browsers cap cookies
+ * at about 4 KiB, larger sessions fail to save
+ ***/
+#[cfg(feature = "signed-cookies")]
+#[derive(Debug, Clone)]
+pub struct CookieSessionStore {
+    key: CookieKey,
+    encrypted: bool,
+}
+
+#[cfg(feature = "signed-cookies")]
+impl CookieSessionStore {
+    pub fn new(key: CookieKey) -> Self {
+        CookieSessionStore {
+            key,
+            encrypted: false,
+        }
+    }
+
+    // The client can't read the session either
+    #[cfg(feature = "private-cookies")]
+    pub fn encrypted(key: CookieKey) -> Self {
+        CookieSessionStore {
+            key,
+            encrypted: true,
+        }
+    }
+
+    // `expiry|key=value&...` under the signature or encryption
+    fn seal(&self, data: &SessionData, ttl: Duration) -> Result<String, String> {
+        let expiry = std::time::SystemTime::now() + ttl;
+        let expiry = expiry
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        let pairs: Vec<String> = data
+            .iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect();
+        let payload = format!("{expiry}|{}", pairs.join("&"));
+        #[cfg(feature = "private-cookies")]
+        if self.encrypted {
+            return self.key.encrypt(COOKIE_SESSION_LABEL, &payload);
+        }
+        Ok(self.key.sign(COOKIE_SESSION_LABEL, &payload))
+    }
+
+    fn open(&self, cookie: &str) -> Option<SessionData> {
+        let payload = match self.encrypted {
+            #[cfg(feature = "private-cookies")]
+            true => self.key.decrypt(COOKIE_SESSION_LABEL, cookie)?,
+            _ => self.key.verify(COOKIE_SESSION_LABEL, cookie)?,
+        };
+        let (expiry, pairs) = payload.split_once('|')?;
+        let now = std::time::SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        if expiry.parse::<u64>().ok()? <= now {
+            return None;
+        }
+        Some(query_pairs(pairs).into_iter().collect())
+    }
+}
+
+// Signatures are bound to this instead of the cookie name, which the store doesn't know
+#[cfg(feature = "signed-cookies")]
+const COOKIE_SESSION_LABEL: &str = "async_uws session";
+
+// Largest cookie value browsers reliably keep, name and attributes included
+#[cfg(feature = "signed-cookies")]
+const MAX_COOKIE_SIZE: usize = 4000;
+
+#[cfg(feature = "signed-cookies")]
+impl SessionStore for CookieSessionStore {
+    fn load<'a>(&'a self, cookie: &'a str) -> SessionFuture<'a, Option<SessionData>> {
+        let data = self.open(cookie);
+        Box::pin(async move { Ok(data) })
+    }
+
+    fn save<'a>(
+        &'a self,
+        _cookie: Option<&'a str>,
+        data: &'a SessionData,
+        ttl: Duration,
+    ) -> SessionFuture<'a, String> {
+        let sealed = self.seal(data, ttl).and_then(|sealed| match sealed.len() {
+            len if len > MAX_COOKIE_SIZE => {
+                Err(format!("Session of {len} bytes doesn't fit a cookie"))
+            }
+            _ => Ok(sealed),
+        });
+        Box::pin(async move { sealed })
+    }
+
+    fn destroy<'a>(&'a self, _cookie: &'a str) -> SessionFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+}
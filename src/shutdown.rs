@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use uwebsockets_rs::uws_loop::UwsLoop;
+
+use crate::cancellation::CancellationToken;
+use crate::diagnostics::{in_flight_responses, running_ws_handlers};
+use crate::ws_behavior::{close_websockets, WsPerSocketUserDataStorage};
+
+/***
+ * Cloneable handle that shuts the app down from anywhere, see App::shutdown_handle(). It does
+ * what the shutdown stream of App::new() does, so that one can be passed as None:
+ *
+ *   shutdown() closes the app right away, after drain_on_shutdown() or ws_shutdown() if set.
+ *   shutdown_graceful(timeout) stops accepting, closes websockets with 1001 and gives requests
+ *   in flight up to `timeout` to finish before the app is closed.
+ *
+ * Either way the cancellation token only fires once the grace period is over, right before the
+ * app is closed, and run() returns after that.
+ ***/
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    requested: CancellationToken,
+    graceful: GracefulShutdown,
+    ws_storages: Vec<WsPerSocketUserDataStorage>,
+}
+
+// Timeout of a requested graceful shutdown, read once the shutdown starts
+pub(crate) type GracefulShutdown = Arc<Mutex<Option<Duration>>>;
+
+impl ShutdownHandle {
+    pub(crate) fn new(
+        requested: CancellationToken,
+        graceful: GracefulShutdown,
+        ws_storage: WsPerSocketUserDataStorage,
+    ) -> Self {
+        ShutdownHandle {
+            requested,
+            graceful,
+            ws_storages: vec![ws_storage],
+        }
+    }
+
+    pub(crate) fn join(mut self, other: ShutdownHandle) -> Self {
+        self.ws_storages.extend(other.ws_storages);
+        self
+    }
+
+    pub fn shutdown(&self) {
+        self.requested.cancel();
+    }
+
+    // Has no effect on a shutdown that already started
+    pub fn shutdown_graceful(&self, timeout: Duration) {
+        if !self.requested.is_cancelled() {
+            *self.graceful.lock().unwrap() = Some(timeout);
+        }
+        self.requested.cancel();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.requested.is_cancelled()
+    }
+
+    // No request in flight and no websocket open. Requests are counted across all apps of the process
+    pub fn is_idle(&self) -> bool {
+        is_idle(&self.ws_storages)
+    }
+
+    pub async fn wait_idle(&self) {
+        while !self.is_idle() {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+}
+
+/***
+ * How open websockets are closed on shutdown, see App::ws_shutdown(). Every socket is ended with
+ * 1001 "Server shutting down" unless close() says otherwise, so the handlers get the
+ * WsMessage::Close and can persist their state. Once the handlers returned, or at the latest
+ * after `deadline`, the cancellation token fires, the sockets still open are terminated and
+ * the handlers' streams end.
+ ***/
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WsShutdown {
+    code: i32,
+    reason: String,
+    deadline: Duration,
+}
+
+impl WsShutdown {
+    pub fn new(deadline: Duration) -> Self {
+        WsShutdown {
+            code: 1001,
+            reason: "Server shutting down".to_string(),
+            deadline,
+        }
+    }
+
+    pub fn close(mut self, code: i32, reason: &str) -> Self {
+        self.code = code;
+        self.reason = reason.to_string();
+        self
+    }
+
+    pub(crate) fn close_all<const SSL: bool>(
+        &self,
+        uws_loop: UwsLoop,
+        storage: WsPerSocketUserDataStorage,
+    ) {
+        close_websockets::<SSL>(uws_loop, storage, self.code, self.reason.clone());
+    }
+
+    pub(crate) fn deadline(&self) -> Duration {
+        self.deadline
+    }
+}
+
+// Handlers are counted across all apps of the process
+pub(crate) fn ws_handlers_done(storage: &WsPerSocketUserDataStorage) -> bool {
+    running_ws_handlers() == 0 && storage.lock().unwrap().is_empty()
+}
+
+// Polls `done` until it holds or `deadline` passes
+pub(crate) async fn wait_until(deadline: Instant, done: impl Fn() -> bool) {
+    while Instant::now() < deadline && !done() {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+pub(crate) fn is_idle(ws_storages: &[WsPerSocketUserDataStorage]) -> bool {
+    in_flight_responses() == 0
+        && ws_storages
+            .iter()
+            .all(|storage| storage.lock().unwrap().is_empty())
+}
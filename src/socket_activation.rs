@@ -0,0 +1,52 @@
+use std::net::TcpListener;
+use std::os::fd::{FromRawFd, IntoRawFd, RawFd};
+
+use socket2::{Domain, SockRef, Type};
+
+// First passed fd, see sd_listen_fds(3)
+const LISTEN_FDS_START: RawFd = 3;
+
+/***
+ * Takes the listeners passed by systemd socket activation (`LISTEN_PID` / `LISTEN_FDS`).
+ * Returns an empty list when the process wasn't socket activated.
+ * The variables are removed from the environment, so child processes won't take the same fds.
+ ***/
+pub fn listen_fds() -> Result<Vec<TcpListener>, String> {
+    let Ok(pid) = std::env::var("LISTEN_PID") else {
+        return Ok(Vec::new());
+    };
+    if pid.trim().parse::<u32>().ok() != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let count: RawFd = std::env::var("LISTEN_FDS")
+        .map_err(|_| "LISTEN_FDS is not set".to_string())?
+        .trim()
+        .parse()
+        .map_err(|_| "LISTEN_FDS is not a number".to_string())?;
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let mut listeners = Vec::with_capacity(count.max(0) as usize);
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+        let listener = unsafe { TcpListener::from_raw_fd(fd) };
+        let socket = SockRef::from(&listener);
+        let is_tcp_listener = socket
+            .domain()
+            .is_ok_and(|domain| domain == Domain::IPV4 || domain == Domain::IPV6)
+            && socket.r#type().is_ok_and(|t| t == Type::STREAM)
+            && socket.is_listener().unwrap_or_default();
+        if !is_tcp_listener {
+            // Not ours to close
+            let _ = listener.into_raw_fd();
+            return Err(format!("Passed fd {fd} is not a listening TCP socket"));
+        }
+        socket
+            .set_cloexec(true)
+            .map_err(|e| format!("Can't set FD_CLOEXEC on fd {fd}: {e}"))?;
+        listeners.push(listener);
+    }
+
+    Ok(listeners)
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{interval_at, Instant, MissedTickBehavior};
+
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub data: String,
+    // Tells the client how long to wait before reconnecting
+    pub retry: Option<Duration>,
+}
+
+impl SseEvent {
+    pub fn new(data: impl Into<String>) -> Self {
+        SseEvent {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    // Sent back by a reconnecting client as "last-event-id", see last_event_id()
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    // Line breaks in data become separate "data:" lines, which the client joins back with "\n"
+    pub fn encode(&self) -> String {
+        let mut frame = String::new();
+        if let Some(event) = self.event.as_ref() {
+            frame.push_str(&format!("event: {}\n", single_line(event)));
+        }
+        if let Some(id) = self.id.as_ref() {
+            frame.push_str(&format!("id: {}\n", single_line(id)));
+        }
+        if let Some(retry) = self.retry {
+            frame.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        for line in self.data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        frame.push('\n');
+        frame
+    }
+}
+
+/***
+ * Server-Sent Events over a streamed response, see HttpConnection::sse().
+ *
+ *   let (sink, events) = mpsc::channel(16);
+ *   tokio::spawn(produce(sink));
+ *   res.sse().forward(events).await;
+ *
+ * forward() returns Ok once every sender is dropped and Err once the client is gone, which
+ * drops `events`, so producers see it through Sender::send() failing or Sender::closed().
+ * A keep-alive comment goes out whenever nothing was sent for `keep_alive`, it keeps proxies
+ * from timing the stream out and finds clients that went away silently.
+ ***/
+pub struct SseStream<const SSL: bool> {
+    connection: HttpConnection<SSL>,
+    keep_alive: Duration,
+}
+
+impl<const SSL: bool> SseStream<SSL> {
+    pub fn new(mut connection: HttpConnection<SSL>) -> Self {
+        connection.write_status("200 OK".to_string());
+        connection.write_header("content-type".to_string(), "text/event-stream".to_string());
+        connection.write_header("cache-control".to_string(), "no-cache".to_string());
+        // Keeps nginx from buffering the stream
+        connection.write_header("x-accel-buffering".to_string(), "no".to_string());
+        SseStream {
+            connection,
+            keep_alive: Duration::from_secs(15),
+        }
+    }
+
+    // 15 seconds by default
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = keep_alive.max(Duration::from_secs(1));
+        self
+    }
+
+    // Sends the headers, forward() and send() do it as well
+    pub async fn open(&mut self) -> Result<(), String> {
+        self.connection.send_headers().await
+    }
+
+    // Fails once the client disconnected
+    pub async fn send(&mut self, event: &SseEvent) -> Result<(), String> {
+        self.connection.write(event.encode()).await
+    }
+
+    // Comment line, ignored by the client
+    pub async fn comment(&mut self, comment: &str) -> Result<(), String> {
+        let comment = format!(": {}\n\n", single_line(comment));
+        self.connection.write(comment).await
+    }
+
+    pub fn is_connected(&self) -> bool {
+        !self.connection.is_aborted.load(Ordering::Relaxed)
+    }
+
+    pub async fn forward(mut self, mut events: Receiver<SseEvent>) -> Result<(), String> {
+        self.open().await?;
+        let start = Instant::now() + self.keep_alive;
+        let mut keep_alive = interval_at(start, self.keep_alive);
+        keep_alive.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Some(event) => {
+                        self.send(&event).await?;
+                        keep_alive.reset();
+                    }
+                    None => break,
+                },
+                _ = keep_alive.tick() => self.comment("keep-alive").await?,
+            }
+        }
+        self.connection.end(None, false).await;
+        Ok(())
+    }
+
+    // Ends the stream, the client reconnects unless it was told otherwise
+    pub async fn end(self) {
+        self.connection.end(None, false).await;
+    }
+}
+
+// "last-event-id" of a reconnecting client, the id of the last event it received
+pub fn last_event_id(req: &HttpRequest) -> Option<&str> {
+    req.get_header("last-event-id")
+}
+
+// A line break would end the field early and let the rest be read as another field
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
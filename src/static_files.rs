@@ -0,0 +1,605 @@
+use std::ffi::OsString;
+use std::io;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use log::{debug, error};
+use tokio::sync::oneshot;
+
+use crate::byte_range::{
+    content_range, if_range_matches, parse_range, ranges_body, send_ranges, RangeRequest,
+};
+use crate::cache_control::CacheControl;
+use crate::directory_listing::DirectoryListing;
+use crate::fs;
+use crate::http_connection::HttpConnection;
+use crate::http_date::{format_http_date, parse_http_date};
+use crate::http_request::HttpRequest;
+use crate::percent_encoding::percent_decode;
+use crate::task;
+
+// Content-Encoding and file extension of precompressed siblings, in order of preference
+const PRECOMPRESSED_VARIANTS: [(&str, &str); 3] = [("br", "br"), ("zstd", "zst"), ("gzip", "gz")];
+
+#[derive(Debug, Clone)]
+pub struct ServeDir {
+    root: PathBuf,
+    precompressed: bool,
+    stream_threshold: u64,
+    cache_control: Option<CacheControl>,
+    directory_listing: Option<DirectoryListing>,
+    index_file: Option<String>,
+    spa_fallback: Option<PathBuf>,
+}
+
+impl ServeDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ServeDir {
+            root: root.into(),
+            precompressed: false,
+            stream_threshold: 1024 * 1024,
+            cache_control: None,
+            directory_listing: None,
+            index_file: Some("index.html".to_string()),
+            spa_fallback: None,
+        }
+    }
+
+    /***
+     * Serve `file.br` / `file.zst` / `file.gz` instead of `file` when they exist and the client
+     * accepts them, the one with the highest q-value in accept-encoding wins. content-type is the
+     * one of `file`, `vary: accept-encoding` is sent for every file of the directory
+     ***/
+    pub fn precompressed(mut self, precompressed: bool) -> Self {
+        self.precompressed = precompressed;
+        self
+    }
+
+    // Larger files are streamed as the client takes them instead of read at once, 1 MiB by default
+    pub fn stream_threshold(mut self, bytes: u64) -> Self {
+        self.stream_threshold = bytes;
+        self
+    }
+
+    pub fn cache_control(mut self, cache_control: CacheControl) -> Self {
+        self.cache_control = Some(cache_control);
+        self
+    }
+
+    // Directories without an index file are answered with 404 unless listing is enabled
+    pub fn directory_listing(mut self, directory_listing: DirectoryListing) -> Self {
+        self.directory_listing = Some(directory_listing);
+        self
+    }
+
+    /***
+     * File served for a directory, `index.html` by default, None turns it off. It wins over the
+     * listing. Directories are requested with a trailing slash, so relative links in the index
+     * resolve inside them, `/docs` is redirected to `/docs/`
+     ***/
+    pub fn index_file(mut self, index_file: Option<&str>) -> Self {
+        self.index_file = index_file.map(str::to_string);
+        self
+    }
+
+    /***
+     * For client side routed apps: `file` (relative to root, e.g. "index.html") is served for
+     * paths under the mount that don't exist. Only page loads get it, requests that accept
+     * text/html, a missing script or image is still 404. It's sent with `cache-control: no-cache`
+     * instead of cache_control(), which usually is long lived for hashed assets
+     ***/
+    pub fn spa_fallback(mut self, file: impl Into<PathBuf>) -> Self {
+        self.spa_fallback = Some(file.into());
+        self
+    }
+
+    pub async fn serve<const SSL: bool>(
+        &self,
+        mut res: HttpConnection<SSL>,
+        req: &HttpRequest,
+        mount: &str,
+    ) {
+        let Some(mut path) = self.resolve(&req.url, mount) else {
+            return not_found(res).await;
+        };
+        if fs::is_dir(&path).await {
+            let index = match self.index_file.as_ref().map(|index| path.join(index)) {
+                Some(index) if fs::is_file(&index).await => Some(index),
+                _ => None,
+            };
+            let has_content = index.is_some() || self.directory_listing.is_some();
+            if has_content && !req.url.ends_with('/') {
+                return redirect_to_directory(res, req).await;
+            }
+            match index {
+                Some(index) => path = index,
+                None if self.directory_listing.is_some() => {
+                    return self.serve_listing(res, req, &path).await;
+                }
+                None => {}
+            }
+        }
+
+        let mut cache_control = self.cache_control.clone();
+        if !fs::is_file(&path).await {
+            match self.spa_fallback_for(req).await {
+                Some(fallback) => {
+                    path = fallback;
+                    cache_control = Some(CacheControl::no_cache());
+                }
+                None => return not_found(res).await,
+            }
+        }
+
+        let (file_path, encoding) = self
+            .select_variant(&path, req.get_header("accept-encoding"))
+            .await;
+        if self.precompressed {
+            res.write_header("vary".to_string(), "accept-encoding".to_string());
+        }
+        if let Some(encoding) = encoding {
+            res.write_header("content-encoding".to_string(), encoding.to_string());
+        }
+        if let Some(cache_control) = cache_control {
+            res.write_cache_control(cache_control);
+        }
+        respond_with_file(
+            res,
+            req,
+            file_path,
+            content_type(&path),
+            self.stream_threshold,
+        )
+        .await;
+    }
+
+    async fn serve_listing<const SSL: bool>(
+        &self,
+        mut res: HttpConnection<SSL>,
+        req: &HttpRequest,
+        path: &Path,
+    ) {
+        let listing = self.directory_listing.as_ref().unwrap();
+        let entries = match listing.read_entries(path).await {
+            Ok(entries) => entries,
+            Err(e) => {
+                error!("[async_uws] Can't read directory {path:?}: {e:#?}");
+                res.write_status("500 Internal Server Error".to_string());
+                res.end(None, false).await;
+                return;
+            }
+        };
+
+        let wants_json = req
+            .get_header("accept")
+            .is_some_and(|accept| accept.contains("application/json"));
+        let (content_type, body) = if wants_json {
+            ("application/json", listing.render_json(&entries))
+        } else {
+            (
+                "text/html; charset=utf-8",
+                listing.render_html(&req.url, &entries),
+            )
+        };
+
+        res.write_header("content-type".to_string(), content_type.to_string());
+        res.write_header("vary".to_string(), "accept".to_string());
+        res.end(Some(body.into_bytes()), false).await;
+    }
+
+    async fn spa_fallback_for(&self, req: &HttpRequest) -> Option<PathBuf> {
+        let fallback = self.root.join(self.spa_fallback.as_ref()?);
+        (is_page_load(req) && fs::is_file(&fallback).await).then_some(fallback)
+    }
+
+    // Maps request url to a path inside root, rejects anything trying to escape it
+    fn resolve(&self, url: &str, mount: &str) -> Option<PathBuf> {
+        let relative = url.strip_prefix(mount)?;
+        let relative = percent_decode(relative)?;
+        let mut path = self.root.clone();
+        for component in Path::new(relative.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(segment) => path.push(segment),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+
+        Some(path)
+    }
+
+    async fn select_variant(
+        &self,
+        path: &Path,
+        accept_encoding: Option<&str>,
+    ) -> (PathBuf, Option<&'static str>) {
+        if !self.precompressed {
+            return (path.to_path_buf(), None);
+        }
+
+        // Only variants the client accepts are looked up
+        let accept_encoding = accept_encoding.unwrap_or_default();
+        let mut existing = Vec::new();
+        for (encoding, extension) in PRECOMPRESSED_VARIANTS {
+            if encoding_quality(accept_encoding, encoding) > 0.0
+                && fs::is_file(variant_path(path, extension)).await
+            {
+                existing.push(extension);
+            }
+        }
+        let variant =
+            select_precompressed(accept_encoding, |extension| existing.contains(&extension));
+        match variant {
+            Some((encoding, extension)) => (variant_path(path, extension), Some(encoding)),
+            None => (path.to_path_buf(), None),
+        }
+    }
+}
+
+fn variant_path(path: &Path, extension: &str) -> PathBuf {
+    let mut variant = OsString::from(path.as_os_str());
+    variant.push(".");
+    variant.push(extension);
+    PathBuf::from(variant)
+}
+
+/***
+ * Content-Encoding and extension of the precompressed variant to send, `has_variant` tells if
+ * the one with an extension exists. The client's preference decides, ties go to the order of
+ * PRECOMPRESSED_VARIANTS
+ ***/
+pub(crate) fn select_precompressed(
+    accept_encoding: &str,
+    has_variant: impl Fn(&str) -> bool,
+) -> Option<(&'static str, &'static str)> {
+    let mut selected: Option<(f32, &'static str, &'static str)> = None;
+    for (encoding, extension) in PRECOMPRESSED_VARIANTS {
+        let quality = encoding_quality(accept_encoding, encoding);
+        if quality <= 0.0 || selected.is_some_and(|(best, ..)| best >= quality) {
+            continue;
+        }
+        if has_variant(extension) {
+            selected = Some((quality, encoding, extension));
+        }
+    }
+    selected.map(|(_, encoding, extension)| (encoding, extension))
+}
+
+impl From<&str> for ServeDir {
+    fn from(root: &str) -> Self {
+        ServeDir::new(root)
+    }
+}
+
+impl From<String> for ServeDir {
+    fn from(root: String) -> Self {
+        ServeDir::new(root)
+    }
+}
+
+impl From<PathBuf> for ServeDir {
+    fn from(root: PathBuf) -> Self {
+        ServeDir::new(root)
+    }
+}
+
+impl From<&Path> for ServeDir {
+    fn from(root: &Path) -> Self {
+        ServeDir::new(root)
+    }
+}
+
+// Body of a file response, see respond_with_content()
+pub(crate) enum FileContent {
+    // Files larger than `stream_threshold` are streamed as the client takes them
+    Disk {
+        path: PathBuf,
+        stream_threshold: u64,
+    },
+    Memory(Bytes),
+}
+
+// respond_with_content() for the file at `path`
+pub(crate) async fn respond_with_file<const SSL: bool>(
+    res: HttpConnection<SSL>,
+    req: &HttpRequest,
+    path: PathBuf,
+    default_content_type: &str,
+    stream_threshold: u64,
+) {
+    let metadata = match fs::metadata(&path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => {
+            respond_io_error(res, &io::Error::from(ErrorKind::NotFound)).await;
+            return;
+        }
+        Err(e) => {
+            error!("[async_uws] Can't read static file {path:?}: {e:#?}");
+            respond_io_error(res, &e).await;
+            return;
+        }
+    };
+    let len = metadata.len();
+    let modified = metadata.modified().ok();
+    let content = FileContent::Disk {
+        path,
+        stream_threshold,
+    };
+    let etag = etag(len, modified);
+    respond_with_content(res, req, content, len, modified, etag, default_content_type).await;
+}
+
+/***
+ * Answers with a file, sending etag and last-modified, 304 for a matching conditional request
+ * and 206 / 416 for Range requests (If-Range falls back to the whole file when it doesn't
+ * match). A content-type written by the handler wins over `default_content_type`
+ ***/
+pub(crate) async fn respond_with_content<const SSL: bool>(
+    mut res: HttpConnection<SSL>,
+    req: &HttpRequest,
+    content: FileContent,
+    len: u64,
+    modified: Option<SystemTime>,
+    etag: String,
+    default_content_type: &str,
+) {
+    let content_type = res
+        .take_header("content-type")
+        .unwrap_or_else(|| default_content_type.to_string());
+
+    res.write_header("etag".to_string(), etag.clone());
+    if let Some(modified) = modified {
+        res.write_header("last-modified".to_string(), format_http_date(modified));
+    }
+    res.write_header("accept-ranges".to_string(), "bytes".to_string());
+    if is_not_modified(req, &etag, modified) {
+        res.write_status("304 Not Modified".to_string());
+        res.end(None, false).await;
+        return;
+    }
+
+    let has_ranges = req.method.eq_ignore_ascii_case("get")
+        && req
+            .get_header("if-range")
+            .is_none_or(|if_range| if_range_matches(if_range, &etag, modified));
+    let ranges = match req.get_header("range") {
+        Some(range) if has_ranges => parse_range(range, len),
+        _ => RangeRequest::Full,
+    };
+    let sent = match (ranges, content) {
+        (RangeRequest::Unsatisfiable, _) => {
+            res.write_status("416 Range Not Satisfiable".to_string());
+            res.write_header("content-range".to_string(), format!("bytes */{len}"));
+            res.end(None, false).await;
+            Ok(())
+        }
+        (RangeRequest::Partial(ranges), content) if ranges.len() == 1 => {
+            let range = ranges[0].clone();
+            res.write_status("206 Partial Content".to_string());
+            res.write_header("content-type".to_string(), content_type);
+            res.write_header("content-range".to_string(), content_range(&range, len));
+            match content {
+                FileContent::Disk { path, .. } => res.stream_file(path, range).await,
+                FileContent::Memory(data) => {
+                    let part = data.slice(range.start as usize..range.end as usize);
+                    res.end_with(part, false).await;
+                    Ok(())
+                }
+            }
+        }
+        (RangeRequest::Partial(ranges), FileContent::Disk { path, .. }) => {
+            send_ranges(&mut res, path, &ranges, &content_type, len).await
+        }
+        (RangeRequest::Partial(ranges), FileContent::Memory(data)) => {
+            let (multipart_type, body) = ranges_body(&req.url, &data, &ranges, &content_type);
+            res.write_status("206 Partial Content".to_string());
+            res.write_header("content-type".to_string(), multipart_type);
+            res.end(Some(body), false).await;
+            Ok(())
+        }
+        (
+            RangeRequest::Full,
+            FileContent::Disk {
+                path,
+                stream_threshold,
+            },
+        ) => {
+            res.write_header("content-type".to_string(), content_type);
+            if len > stream_threshold {
+                res.stream_file(path, 0..len).await
+            } else {
+                match read_file(path.clone()).await {
+                    Ok(body) => res.end(Some(body), false).await,
+                    Err(e) => {
+                        error!("[async_uws] Can't read static file {path:?}: {e:#?}");
+                        respond_io_error(res, &e).await;
+                    }
+                }
+                Ok(())
+            }
+        }
+        (RangeRequest::Full, FileContent::Memory(data)) => {
+            res.write_header("content-type".to_string(), content_type);
+            res.end_with(data, false).await;
+            Ok(())
+        }
+    };
+    // Dropping the response sends 500 if nothing is out yet, or closes the connection
+    if let Err(e) = sent {
+        debug!("[async_uws] Stopped streaming {}: {e}", req.url);
+    }
+}
+
+async fn respond_io_error<const SSL: bool>(mut res: HttpConnection<SSL>, e: &io::Error) {
+    let status = if e.kind() == ErrorKind::NotFound {
+        "404 Not Found"
+    } else {
+        "500 Internal Server Error"
+    };
+    res.write_status(status.to_string());
+    res.end(None, false).await;
+}
+
+// Navigations of a browser, which an SPA fallback answers
+pub(crate) fn is_page_load(req: &HttpRequest) -> bool {
+    req.get_header("accept")
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+pub(crate) async fn not_found<const SSL: bool>(mut res: HttpConnection<SSL>) {
+    res.write_status("404 Not Found".to_string());
+    res.end(None, false).await;
+}
+
+// `/docs?page=2` -> `/docs/?page=2`
+pub(crate) async fn redirect_to_directory<const SSL: bool>(
+    mut res: HttpConnection<SSL>,
+    req: &HttpRequest,
+) {
+    let location = match req.query() {
+        Some(query) => format!("{}/?{query}", req.url),
+        None => format!("{}/", req.url),
+    };
+    res.write_status("301 Moved Permanently".to_string());
+    res.write_header("location".to_string(), location);
+    res.end(None, false).await;
+}
+
+// Like nginx's, from size and mtime of the file sent, so precompressed variants get their own
+pub(crate) fn etag(len: u64, modified: Option<SystemTime>) -> String {
+    let modified = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{len:x}\"", modified.as_secs())
+}
+
+// if-none-match wins over if-modified-since, as RFC 9110 asks
+pub(crate) fn is_not_modified(req: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    if let Some(if_none_match) = req.get_header("if-none-match") {
+        return etag_matches(if_none_match, etag);
+    }
+    let since = req
+        .get_header("if-modified-since")
+        .and_then(parse_http_date);
+    match (since, modified) {
+        (Some(since), Some(modified)) => truncate_to_seconds(modified) <= since,
+        _ => false,
+    }
+}
+
+// Weak comparison of a list of entity tags, `*` matches anything
+pub(crate) fn etag_matches(list: &str, etag: &str) -> bool {
+    let etag = opaque_tag(etag);
+    list.split(',')
+        .any(|tag| tag.trim() == "*" || opaque_tag(tag) == etag)
+}
+
+fn opaque_tag(tag: &str) -> &str {
+    let tag = tag.trim();
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+// HTTP dates have no fractions of a second
+fn truncate_to_seconds(time: SystemTime) -> SystemTime {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    UNIX_EPOCH + Duration::from_secs(seconds)
+}
+
+// q-value the client gives `encoding` in accept-encoding, 0.0 if it's not acceptable
+pub(crate) fn encoding_quality(accept_encoding: &str, encoding: &str) -> f32 {
+    let mut wildcard = 0.0;
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|param| {
+                let param = param.trim();
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.trim().parse::<f32>().ok())
+            })
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(encoding) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+
+    wildcard
+}
+
+pub(crate) fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") | Some("map") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("xml") => "application/xml",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("avif") => "image/avif",
+        Some("ico") => "image/x-icon",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("otf") => "font/otf",
+        Some("mp3") => "audio/mpeg",
+        Some("ogg") => "audio/ogg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("wasm") => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+// Files are read in their own task, see fs.rs
+pub(crate) async fn read_file(path: PathBuf) -> io::Result<Vec<u8>> {
+    let (sink, stream) = oneshot::channel();
+    task::spawn_local("async_uws file read", async move {
+        let _ = sink.send(read_file_local(path).await);
+    });
+
+    stream
+        .await
+        .unwrap_or_else(|_| Err(io::Error::other("File read was cancelled")))
+}
+
+async fn read_file_local(path: PathBuf) -> io::Result<Vec<u8>> {
+    let len = fs::metadata(&path).await?.len() as usize;
+    let file = fs::File::open(&path).await?;
+    let mut content = Vec::with_capacity(len);
+    while content.len() < len {
+        let buf = Vec::with_capacity(len - content.len());
+        let (read, buf) = file.read_at(buf, content.len() as u64).await;
+        let read = read?;
+        if read == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..read]);
+    }
+    file.close().await?;
+
+    Ok(content)
+}
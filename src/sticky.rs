@@ -0,0 +1,153 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use crate::addr::{mailbox, Addr, Mailbox};
+use crate::http_request::HttpRequest;
+
+// Points per worker on the ring, enough to keep the share of keys even for a handful of workers
+const VIRTUAL_NODES: usize = 64;
+
+/***
+ * Consistent hashing of session keys onto workers. The ring only depends on the worker count,
+ * so every worker (and every process of the deployment) computes the same owner for a key, and
+ * adding a worker moves about 1/n of the keys.
+ ***/
+#[derive(Debug, Clone)]
+pub struct HashRing {
+    // (point, worker) sorted by point
+    points: Vec<(u64, usize)>,
+    workers: usize,
+}
+
+impl HashRing {
+    pub fn new(workers: usize) -> Self {
+        let mut points: Vec<(u64, usize)> = (0..workers)
+            .flat_map(|worker| {
+                (0..VIRTUAL_NODES)
+                    .map(move |node| (hash(format!("{worker}-{node}").as_bytes()), worker))
+            })
+            .collect();
+        points.sort_unstable();
+        HashRing { points, workers }
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    // Always 0 for an empty ring
+    pub fn worker_for(&self, key: &str) -> usize {
+        let point = hash(key.as_bytes());
+        let index = self.points.partition_point(|(node, _)| *node < point);
+        self.points
+            .get(index)
+            .or_else(|| self.points.first())
+            .map(|(_, worker)| *worker)
+            .unwrap_or_default()
+    }
+}
+
+// What ties a client to its worker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StickyBy {
+    Ip,
+    Cookie(String),
+}
+
+impl StickyBy {
+    // `remote_address` is HttpConnection::remote_address(), None if the request has no such cookie
+    pub fn key(&self, req: &HttpRequest, remote_address: Option<&str>) -> Option<String> {
+        match self {
+            StickyBy::Ip => remote_address.map(ip_key),
+            StickyBy::Cookie(name) => req
+                .get_header("cookie")?
+                .split(';')
+                .filter_map(|cookie| cookie.trim().split_once('='))
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.trim_matches('"').to_string()),
+        }
+    }
+}
+
+// IPv4 clients of a dual stack socket show up as ::ffff:a.b.c.d, both forms give the same key
+pub fn ip_key(address: &str) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(ip) => ip.to_canonical().to_string(),
+        Err(_) => address.to_string(),
+    }
+}
+
+/***
+ * Cross-worker messages, each worker owns one Mailbox and hands what it receives to its
+ * sessions (e.g. their Addr). Clones can be moved to every worker thread:
+ *
+ *   let (relay, mailboxes) = worker_relay::<Cmd>(workers);
+ *   relay.send_to_owner(&session_key, Cmd::Notify(..))?;
+ ***/
+pub struct WorkerRelay<M> {
+    workers: Arc<Vec<Addr<M>>>,
+    ring: Arc<HashRing>,
+}
+
+pub fn worker_relay<M>(workers: usize) -> (WorkerRelay<M>, Vec<Mailbox<M>>) {
+    let (addrs, mailboxes) = (0..workers).map(|_| mailbox()).unzip();
+    let relay = WorkerRelay {
+        workers: Arc::new(addrs),
+        ring: Arc::new(HashRing::new(workers)),
+    };
+    (relay, mailboxes)
+}
+
+impl<M> Clone for WorkerRelay<M> {
+    fn clone(&self) -> Self {
+        WorkerRelay {
+            workers: self.workers.clone(),
+            ring: self.ring.clone(),
+        }
+    }
+}
+
+impl<M> WorkerRelay<M> {
+    pub fn ring(&self) -> &HashRing {
+        &self.ring
+    }
+
+    // Worker a session key belongs to, the same one App::listen_sticky() lands its IP on
+    pub fn owner(&self, key: &str) -> usize {
+        self.ring.worker_for(key)
+    }
+
+    pub fn send_to(&self, worker: usize, message: M) -> Result<(), String> {
+        self.workers
+            .get(worker)
+            .ok_or_else(|| format!("There is no worker {worker}"))?
+            .do_send(message)
+    }
+
+    pub fn send_to_owner(&self, key: &str, message: M) -> Result<(), String> {
+        self.send_to(self.owner(key), message)
+    }
+
+    // Workers whose mailbox is gone are skipped
+    pub fn broadcast(&self, message: M)
+    where
+        M: Clone,
+    {
+        for worker in self.workers.iter() {
+            let _ = worker.do_send(message.clone());
+        }
+    }
+}
+
+// FNV-1a with a final mix, stable across processes and Rust versions unlike DefaultHasher
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51afd7ed558ccd);
+    hash ^= hash >> 33;
+    hash
+}
@@ -0,0 +1,107 @@
+use std::any::Any;
+use std::future::{poll_fn, Future};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+
+use log::error;
+
+/***
+ * Spawns a task with a name for tokio-console and runtime metrics. With the default `io-uring`
+ * feature tasks run on tokio_uring, without it on whatever tokio runtime App::run() is called
+ * from, e.g. #[tokio::main(flavor = "multi_thread")]. run() blocks its thread, so a
+ * current_thread runtime never gets to the spawned tasks.
+ * Names are only recorded with the `task-names` feature and `--cfg tokio_unstable`.
+ ***/
+pub(crate) fn spawn<F>(name: &str, future: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "task-names", feature = "io-uring"))]
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn_local(future)
+        .expect("[async_uws] Can't spawn a task");
+
+    #[cfg(all(tokio_unstable, feature = "task-names", not(feature = "io-uring")))]
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn(future)
+        .expect("[async_uws] Can't spawn a task");
+
+    #[cfg(not(all(tokio_unstable, feature = "task-names")))]
+    {
+        let _ = name;
+        #[cfg(feature = "io-uring")]
+        tokio_uring::spawn(future);
+        #[cfg(not(feature = "io-uring"))]
+        tokio::spawn(future);
+    }
+}
+
+// For tasks holding a tokio_uring file, which is !Send, see fs.rs
+#[cfg(feature = "io-uring")]
+pub(crate) fn spawn_local<F>(name: &str, future: F)
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    #[cfg(all(tokio_unstable, feature = "task-names"))]
+    tokio::task::Builder::new()
+        .name(name)
+        .spawn_local(future)
+        .expect("[async_uws] Can't spawn a task");
+
+    #[cfg(not(all(tokio_unstable, feature = "task-names")))]
+    {
+        let _ = name;
+        tokio_uring::spawn(future);
+    }
+}
+
+#[cfg(not(feature = "io-uring"))]
+pub(crate) use spawn as spawn_local;
+
+/***
+ * Runs the future `start` returns, catching panics of both. The payload is logged and `panicked`
+ * set before the future is dropped, so the response it holds goes out as App::panic_response()
+ * and the loop and its other requests carry on. Needs panic = "unwind", the default
+ ***/
+pub(crate) async fn catch_panic<F>(
+    name: String,
+    start: impl FnOnce() -> F,
+    panicked: Arc<AtomicBool>,
+) where
+    F: Future<Output = ()>,
+{
+    let future = match catch_unwind(AssertUnwindSafe(start)) {
+        Ok(future) => future,
+        Err(payload) => {
+            error!("[async_uws] {name} panicked: {}", panic_message(&payload));
+            return;
+        }
+    };
+    let mut future = pin!(future);
+    let result = poll_fn(
+        |cx| match catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+            Ok(poll) => poll.map(Ok),
+            Err(payload) => Poll::Ready(Err(payload)),
+        },
+    )
+    .await;
+    if let Err(payload) = result {
+        panicked.store(true, Ordering::Relaxed);
+        error!("[async_uws] {name} panicked: {}", panic_message(&payload));
+    }
+}
+
+pub(crate) fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("Box<dyn Any>")
+}
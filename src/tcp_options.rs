@@ -0,0 +1,108 @@
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, RawFd};
+use std::time::Duration;
+
+use libuwebsockets_sys::{us_listen_socket_t, us_socket_get_native_handle, us_socket_t};
+use socket2::{SockRef, TcpKeepalive};
+use uwebsockets_rs::listen_socket::ListenSocket;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeepaliveOptions {
+    // Idle time before the first probe
+    pub time: Duration,
+    pub interval: Duration,
+    pub retries: u32,
+}
+
+impl Default for KeepaliveOptions {
+    fn default() -> Self {
+        KeepaliveOptions {
+            time: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            retries: 6,
+        }
+    }
+}
+
+/***
+ * Socket options applied to the listen socket, accepted connections inherit them.
+ * Fields left as None keep the OS defaults.
+ ***/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: Option<bool>,
+    keepalive: Option<KeepaliveOptions>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+impl TcpOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Note: uSockets disables Nagle on accepted sockets by itself, so `false` has no effect there
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    pub fn keepalive(mut self, keepalive: KeepaliveOptions) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    pub fn apply(&self, listen_socket: ListenSocket) -> io::Result<()> {
+        self.apply_native(listen_socket.get_native())
+    }
+
+    pub(crate) fn apply_native(
+        &self,
+        listen_socket_ptr: *mut us_listen_socket_t,
+    ) -> io::Result<()> {
+        if listen_socket_ptr.is_null() {
+            return Err(io::Error::other("Listen socket is not open"));
+        }
+
+        // Listen socket starts with us_socket_t, ssl = 0 makes uSockets return the fd even for SSL apps
+        let fd = unsafe {
+            us_socket_get_native_handle(0, listen_socket_ptr as *mut us_socket_t) as RawFd
+        };
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        self.apply_to(&fd)
+    }
+
+    pub fn apply_to(&self, socket: &impl AsFd) -> io::Result<()> {
+        let socket = SockRef::from(socket);
+
+        if let Some(nodelay) = self.nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            socket.set_keepalive(true)?;
+            let params = TcpKeepalive::new()
+                .with_time(keepalive.time)
+                .with_interval(keepalive.interval)
+                .with_retries(keepalive.retries);
+            socket.set_tcp_keepalive(&params)?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        Ok(())
+    }
+}
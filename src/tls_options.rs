@@ -0,0 +1,397 @@
+use std::ffi::c_void;
+use std::os::raw::{c_int, c_long, c_uint};
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use uwebsockets_rs::us_socket_context_options::UsSocketContextOptions;
+
+// Parts of the OpenSSL API uSockets is linked against, most setters are macros over SSL_CTX_ctrl
+type SslCtx = c_void;
+type Ssl = c_void;
+
+const SSL_CTRL_SET_TLSEXT_STATUS_REQ_CB: c_int = 63;
+const SSL_CTRL_SET_TLSEXT_STATUS_REQ_CB_ARG: c_int = 64;
+const SSL_CTRL_SET_TLSEXT_STATUS_REQ_OCSP_RESP: c_int = 71;
+const SSL_CTRL_SET_SESS_CACHE_SIZE: c_int = 42;
+const SSL_CTRL_SET_SESS_CACHE_MODE: c_int = 44;
+const SSL_SESS_CACHE_OFF: c_long = 0;
+const SSL_SESS_CACHE_SERVER: c_long = 2;
+const SSL_OP_NO_TICKET: u64 = 1 << 14;
+const SSL_TLSEXT_ERR_OK: c_int = 0;
+const SSL_TLSEXT_ERR_NOACK: c_int = 3;
+const OPENSSL_NPN_NEGOTIATED: c_int = 1;
+
+extern "C" {
+    fn SSL_CTX_ctrl(ctx: *mut SslCtx, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn SSL_CTX_callback_ctrl(
+        ctx: *mut SslCtx,
+        cmd: c_int,
+        cb: Option<unsafe extern "C" fn()>,
+    ) -> c_long;
+    fn SSL_CTX_set_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_CTX_clear_options(ctx: *mut SslCtx, options: u64) -> u64;
+    fn SSL_CTX_set_timeout(ctx: *mut SslCtx, timeout: c_long) -> c_long;
+    fn SSL_CTX_set_session_id_context(ctx: *mut SslCtx, id: *const u8, len: c_uint) -> c_int;
+    fn SSL_ctrl(ssl: *mut Ssl, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    fn CRYPTO_malloc(num: usize, file: *const u8, line: c_int) -> *mut c_void;
+    fn SSL_get0_alpn_selected(ssl: *const Ssl, data: *mut *const u8, len: *mut c_uint);
+    fn SSL_CTX_set_alpn_select_cb(
+        ctx: *mut SslCtx,
+        cb: Option<AlpnSelectCallback>,
+        arg: *mut c_void,
+    );
+    fn SSL_select_next_proto(
+        out: *mut *mut u8,
+        out_len: *mut u8,
+        server: *const u8,
+        server_len: c_uint,
+        client: *const u8,
+        client_len: c_uint,
+    ) -> c_int;
+}
+
+type AlpnSelectCallback = unsafe extern "C" fn(
+    ssl: *mut Ssl,
+    out: *mut *const u8,
+    out_len: *mut u8,
+    client: *const u8,
+    client_len: c_uint,
+    arg: *mut c_void,
+) -> c_int;
+
+/***
+ * Builder for UsSocketContextOptions:
+ * `TlsConfig::new().cert("cert.pem").key("key.pem").build()?`
+ * build() checks that referenced files are readable, so a typo fails at startup rather than in listen()
+ ***/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsConfig {
+    key_file: Option<String>,
+    cert_file: Option<String>,
+    passphrase: Option<String>,
+    dh_params_file: Option<String>,
+    ca_file: Option<String>,
+    ssl_ciphers: Option<String>,
+    prefer_low_memory_usage: Option<bool>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn cert(mut self, path: impl Into<String>) -> Self {
+        self.cert_file = Some(path.into());
+        self
+    }
+
+    pub fn key(mut self, path: impl Into<String>) -> Self {
+        self.key_file = Some(path.into());
+        self
+    }
+
+    pub fn passphrase(mut self, passphrase: impl Into<String>) -> Self {
+        self.passphrase = Some(passphrase.into());
+        self
+    }
+
+    pub fn dh_params(mut self, path: impl Into<String>) -> Self {
+        self.dh_params_file = Some(path.into());
+        self
+    }
+
+    // CA used to verify client certificates
+    pub fn ca(mut self, path: impl Into<String>) -> Self {
+        self.ca_file = Some(path.into());
+        self
+    }
+
+    // OpenSSL cipher list, e.g. "ECDHE-ECDSA-AES128-GCM-SHA256:ECDHE-RSA-AES128-GCM-SHA256"
+    pub fn ciphers(mut self, ciphers: impl Into<String>) -> Self {
+        self.ssl_ciphers = Some(ciphers.into());
+        self
+    }
+
+    pub fn prefer_low_memory_usage(mut self, prefer: bool) -> Self {
+        self.prefer_low_memory_usage = Some(prefer);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match (self.cert_file.as_ref(), self.key_file.as_ref()) {
+            (Some(_), None) => return Err("TLS certificate is set without a key".to_string()),
+            (None, Some(_)) => return Err("TLS key is set without a certificate".to_string()),
+            _ => {}
+        }
+
+        let files = [
+            ("certificate", &self.cert_file),
+            ("key", &self.key_file),
+            ("DH params", &self.dh_params_file),
+            ("CA", &self.ca_file),
+        ];
+        for (name, path) in files {
+            let Some(path) = path else {
+                continue;
+            };
+            if let Err(e) = std::fs::File::open(Path::new(path)) {
+                return Err(format!("Can't read TLS {name} file {path}: {e}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    // uWS keeps the option strings for the whole process lifetime, so they're leaked
+    pub fn build(self) -> Result<UsSocketContextOptions, String> {
+        self.validate()?;
+
+        let leak = |value: Option<String>| value.map(|value| &*Box::leak(value.into_boxed_str()));
+        Ok(UsSocketContextOptions {
+            key_file_name: leak(self.key_file),
+            cert_file_name: leak(self.cert_file),
+            passphrase: leak(self.passphrase),
+            dh_params_file_name: leak(self.dh_params_file),
+            ca_file_name: leak(self.ca_file),
+            ssl_ciphers: leak(self.ssl_ciphers),
+            ssl_prefer_low_memory_usage: self.prefer_low_memory_usage,
+        })
+    }
+}
+
+// Stapled OCSP response in DER, can be replaced while the server is running
+#[derive(Debug, Clone, Default)]
+pub struct OcspStaple {
+    response: Arc<RwLock<Option<Vec<u8>>>>,
+}
+
+impl OcspStaple {
+    pub fn new(response: Vec<u8>) -> Self {
+        let staple = OcspStaple::default();
+        staple.set(response);
+        staple
+    }
+
+    pub fn set(&self, response: Vec<u8>) {
+        *self.response.write().unwrap() = Some(response).filter(|r| !r.is_empty());
+    }
+
+    pub fn clear(&self) {
+        *self.response.write().unwrap() = None;
+    }
+}
+
+/***
+ * Session resumption and OCSP stapling settings for SSL apps.
+ * Fields left as None keep the OpenSSL defaults
+ ***/
+#[derive(Debug, Clone, Default)]
+pub struct TlsSessionOptions {
+    session_cache: Option<bool>,
+    session_cache_size: Option<usize>,
+    session_timeout: Option<Duration>,
+    session_tickets: Option<bool>,
+    session_id_context: Option<Vec<u8>>,
+    ocsp_staple: Option<OcspStaple>,
+}
+
+impl TlsSessionOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Server side session ID cache
+    pub fn session_cache(mut self, enabled: bool) -> Self {
+        self.session_cache = Some(enabled);
+        self
+    }
+
+    pub fn session_cache_size(mut self, size: usize) -> Self {
+        self.session_cache_size = Some(size);
+        self
+    }
+
+    // Lifetime of both cached sessions and tickets
+    pub fn session_timeout(mut self, timeout: Duration) -> Self {
+        self.session_timeout = Some(timeout);
+        self
+    }
+
+    pub fn session_tickets(mut self, enabled: bool) -> Self {
+        self.session_tickets = Some(enabled);
+        self
+    }
+
+    // Required for ID resumption with client certificates, at most 32 bytes
+    pub fn session_id_context(mut self, context: impl Into<Vec<u8>>) -> Self {
+        self.session_id_context = Some(context.into());
+        self
+    }
+
+    pub fn ocsp_staple(mut self, staple: OcspStaple) -> Self {
+        self.ocsp_staple = Some(staple);
+        self
+    }
+
+    pub(crate) fn apply(&self, ctx: *mut SslCtx) -> Result<(), String> {
+        if ctx.is_null() {
+            return Err("App has no SSL context".to_string());
+        }
+
+        unsafe {
+            if let Some(enabled) = self.session_cache {
+                let mode = if enabled {
+                    SSL_SESS_CACHE_SERVER
+                } else {
+                    SSL_SESS_CACHE_OFF
+                };
+                SSL_CTX_ctrl(
+                    ctx,
+                    SSL_CTRL_SET_SESS_CACHE_MODE,
+                    mode,
+                    std::ptr::null_mut(),
+                );
+            }
+            if let Some(size) = self.session_cache_size {
+                SSL_CTX_ctrl(
+                    ctx,
+                    SSL_CTRL_SET_SESS_CACHE_SIZE,
+                    size as c_long,
+                    std::ptr::null_mut(),
+                );
+            }
+            if let Some(timeout) = self.session_timeout {
+                SSL_CTX_set_timeout(ctx, timeout.as_secs() as c_long);
+            }
+            match self.session_tickets {
+                Some(true) => {
+                    SSL_CTX_clear_options(ctx, SSL_OP_NO_TICKET);
+                }
+                Some(false) => {
+                    SSL_CTX_set_options(ctx, SSL_OP_NO_TICKET);
+                }
+                None => {}
+            }
+            if let Some(context) = self.session_id_context.as_ref() {
+                if context.len() > 32 {
+                    return Err("Session id context can't be longer than 32 bytes".to_string());
+                }
+                if SSL_CTX_set_session_id_context(ctx, context.as_ptr(), context.len() as c_uint)
+                    != 1
+                {
+                    return Err("Can't set session id context".to_string());
+                }
+            }
+            if let Some(staple) = self.ocsp_staple.as_ref() {
+                // Lives as long as the SSL context, which is never freed before exit
+                let arg = Arc::into_raw(staple.response.clone()) as *mut c_void;
+                SSL_CTX_ctrl(ctx, SSL_CTRL_SET_TLSEXT_STATUS_REQ_CB_ARG, 0, arg);
+                let cb: unsafe extern "C" fn(*mut Ssl, *mut c_void) -> c_int = on_ocsp_status;
+                SSL_CTX_callback_ctrl(
+                    ctx,
+                    SSL_CTRL_SET_TLSEXT_STATUS_REQ_CB,
+                    Some(std::mem::transmute::<
+                        unsafe extern "C" fn(*mut Ssl, *mut c_void) -> c_int,
+                        unsafe extern "C" fn(),
+                    >(cb)),
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn on_ocsp_status(ssl: *mut Ssl, arg: *mut c_void) -> c_int {
+    let response = &*(arg as *const RwLock<Option<Vec<u8>>>);
+    let response = response.read().unwrap();
+    let Some(response) = response.as_ref() else {
+        return SSL_TLSEXT_ERR_NOACK;
+    };
+
+    // OpenSSL takes ownership and frees it with OPENSSL_free
+    let buffer = CRYPTO_malloc(response.len(), c"async_uws".as_ptr() as *const u8, 0);
+    if buffer.is_null() {
+        return SSL_TLSEXT_ERR_NOACK;
+    }
+    std::ptr::copy_nonoverlapping(response.as_ptr(), buffer as *mut u8, response.len());
+    SSL_ctrl(
+        ssl,
+        SSL_CTRL_SET_TLSEXT_STATUS_REQ_OCSP_RESP,
+        response.len() as c_long,
+        buffer,
+    );
+
+    SSL_TLSEXT_ERR_OK
+}
+
+// Protocols the uWS TLS listener can serve, it has no HTTP/2 or other protocols behind it
+const SERVED_ALPN_PROTOCOLS: [&str; 1] = ["http/1.1"];
+
+// Protocols in order of server preference, only ones in SERVED_ALPN_PROTOCOLS
+pub(crate) fn set_alpn_protocols(ctx: *mut SslCtx, protocols: &[&str]) -> Result<(), String> {
+    if ctx.is_null() {
+        return Err("App has no SSL context".to_string());
+    }
+
+    // Wire format: every protocol prefixed with its length
+    let mut wire = Vec::new();
+    for protocol in protocols {
+        // A client picking h2 would send frames uWS can't parse
+        if !SERVED_ALPN_PROTOCOLS.contains(protocol) {
+            return Err(format!(
+                "ALPN protocol {protocol:?} can't be served by uWS, only http/1.1 can. \
+                 Other protocols need listen_rustls() with an alpn_stream_handler()"
+            ));
+        }
+        wire.push(protocol.len() as u8);
+        wire.extend_from_slice(protocol.as_bytes());
+    }
+
+    // Lives as long as the SSL context, which is never freed before exit
+    let arg = Box::into_raw(Box::new(wire)) as *mut c_void;
+    unsafe { SSL_CTX_set_alpn_select_cb(ctx, Some(on_alpn_select), arg) };
+    Ok(())
+}
+
+unsafe extern "C" fn on_alpn_select(
+    _ssl: *mut Ssl,
+    out: *mut *const u8,
+    out_len: *mut u8,
+    client: *const u8,
+    client_len: c_uint,
+    arg: *mut c_void,
+) -> c_int {
+    let server = &*(arg as *const Vec<u8>);
+    let status = SSL_select_next_proto(
+        out as *mut *mut u8,
+        out_len,
+        server.as_ptr(),
+        server.len() as c_uint,
+        client,
+        client_len,
+    );
+
+    if status == OPENSSL_NPN_NEGOTIATED {
+        SSL_TLSEXT_ERR_OK
+    } else {
+        SSL_TLSEXT_ERR_NOACK
+    }
+}
+
+pub(crate) fn negotiated_alpn(ssl: *mut Ssl) -> Option<String> {
+    if ssl.is_null() {
+        return None;
+    }
+
+    let mut data: *const u8 = std::ptr::null();
+    let mut len: c_uint = 0;
+    unsafe { SSL_get0_alpn_selected(ssl, &mut data, &mut len) };
+    if data.is_null() || len == 0 {
+        return None;
+    }
+
+    let protocol = unsafe { std::slice::from_raw_parts(data, len as usize) };
+    Some(String::from_utf8_lossy(protocol).to_string())
+}
@@ -0,0 +1,89 @@
+use std::net::IpAddr;
+
+use crate::http_request::HttpRequest;
+use crate::live_settings::IpRange;
+
+/***
+ * Peers whose forwarding headers are believed, see App::trusted_proxies() and
+ * HttpRequest::client_ip(). `Forwarded` is read if the request has one, `X-Forwarded-For`
+ * otherwise. The chain is walked from the nearest hop back and the first address outside of
+ * the trusted ranges is the client, so a client can't pass itself off as someone else by
+ * sending the header itself.
+ ***/
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies {
+    ranges: Vec<IpRange>,
+}
+
+impl TrustedProxies {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn trust(mut self, range: IpRange) -> Self {
+        self.ranges.push(range);
+        self
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.ranges.iter().any(|range| range.contains(ip))
+    }
+
+    // `peer` itself if it isn't trusted or the request has no usable forwarding header
+    pub fn client_ip(&self, peer: IpAddr, req: &HttpRequest) -> IpAddr {
+        let mut client = peer.to_canonical();
+        if !self.is_trusted(client) {
+            return client;
+        }
+        // An entry that isn't an address (`unknown`, an obfuscated name) ends the walk
+        for hop in forwarded_chain(req).iter().rev() {
+            let Some(hop) = hop else {
+                break;
+            };
+            client = *hop;
+            if !self.is_trusted(client) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+// Addresses of every hop, the client first
+fn forwarded_chain(req: &HttpRequest) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = list_header(req, "forwarded")
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.split_once('=')?;
+                key.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| parse_node(value))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    list_header(req, "x-forwarded-for")
+        .map(parse_node)
+        .collect()
+}
+
+// Elements of every `name` header, in order
+fn list_header<'a>(req: &'a HttpRequest, name: &'a str) -> impl Iterator<Item = &'a str> {
+    req.headers
+        .iter()
+        .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+        .flat_map(|(_, value)| value.split(','))
+}
+
+// `192.0.2.60`, `192.0.2.60:4711`, `"[2001:db8::17]:4711"` or a bare IPv6 address
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    let address = match node.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']')?.0,
+        None if node.matches(':').count() == 1 => node.split_once(':')?.0,
+        None => node,
+    };
+    address.parse::<IpAddr>().ok().map(|ip| ip.to_canonical())
+}
@@ -0,0 +1,170 @@
+use std::marker::PhantomData;
+
+use bytes::Bytes;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::websocket::{SendStatus, Websocket};
+use crate::ws_message::WsMessage;
+
+/***
+ * Turns application messages into websocket frames and back, see TypedWebsocket. JsonCodec
+ * (with the "json" feature), MessagePackCodec ("msgpack"), CborCodec ("cbor") and ProtobufCodec
+ * ("protobuf") come with the crate, other formats implement this trait.
+ ***/
+pub trait Codec<T> {
+    // Opcode of the frames encode() produces
+    fn opcode(&self) -> Opcode;
+    fn encode(&self, value: &T) -> Result<Bytes, String>;
+    fn decode(&self, data: &[u8]) -> Result<T, String>;
+}
+
+/***
+ * Websocket that receives `In` and sends `Out`, both going through the codec `C`:
+ *
+ *   let mut ws = TypedWebsocket::<_, ChatIn, ChatOut, _>::new(ws, JsonCodec);
+ *   while let Some(message) = ws.recv().await {
+ *       let answer = match message {
+ *           Ok(ChatIn::Say(text)) => ChatOut::Said(text),
+ *           Err(e) => ChatOut::Error(e),
+ *       };
+ *       ws.send(&answer).await?;
+ *   }
+ *
+ * Pings, pongs and drain events are skipped, the socket itself is still there with websocket()
+ ***/
+pub struct TypedWebsocket<const SSL: bool, In, Out, C> {
+    websocket: Websocket<SSL>,
+    codec: C,
+    messages: PhantomData<fn(Out) -> In>,
+}
+
+impl<const SSL: bool, In, Out, C> TypedWebsocket<SSL, In, Out, C>
+where
+    C: Codec<In> + Codec<Out>,
+{
+    pub fn new(websocket: Websocket<SSL>, codec: C) -> Self {
+        TypedWebsocket {
+            websocket,
+            codec,
+            messages: PhantomData,
+        }
+    }
+
+    // None once the socket is closed, Err for a message that doesn't decode or was dropped
+    pub async fn recv(&mut self) -> Option<Result<In, String>> {
+        while let Some(message) = self.websocket.stream.recv().await {
+            match message {
+                WsMessage::Message(data, _) => {
+                    return Some(Codec::<In>::decode(&self.codec, &data));
+                }
+                WsMessage::Violation(violation) => {
+                    return Some(Err(format!("Message dropped: {violation:?}")));
+                }
+                WsMessage::Close(_, _) => return None,
+                WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Drain(_) => {}
+            }
+        }
+        None
+    }
+
+    pub async fn send(&mut self, message: &Out) -> Result<SendStatus, String> {
+        let data = Codec::<Out>::encode(&self.codec, message)?;
+        let opcode = Codec::<Out>::opcode(&self.codec);
+        self.websocket.send(WsMessage::Message(data, opcode)).await
+    }
+
+    pub fn websocket(&mut self) -> &mut Websocket<SSL> {
+        &mut self.websocket
+    }
+
+    pub fn into_inner(self) -> Websocket<SSL> {
+        self.websocket
+    }
+}
+
+// Text frames
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for JsonCodec {
+    fn opcode(&self) -> Opcode {
+        Opcode::Text
+    }
+
+    fn encode(&self, value: &T) -> Result<Bytes, String> {
+        serde_json::to_vec(value)
+            .map(Bytes::from)
+            .map_err(|e| format!("Can't encode JSON message: {e}"))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, String> {
+        serde_json::from_slice(data).map_err(|e| format!("Invalid JSON message: {e}"))
+    }
+}
+
+// Binary frames, structs are encoded as maps so fields can be added without breaking clients
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+#[cfg(feature = "msgpack")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for MessagePackCodec {
+    fn opcode(&self) -> Opcode {
+        Opcode::Binary
+    }
+
+    fn encode(&self, value: &T) -> Result<Bytes, String> {
+        rmp_serde::to_vec_named(value)
+            .map(Bytes::from)
+            .map_err(|e| format!("Can't encode MessagePack message: {e}"))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, String> {
+        rmp_serde::from_slice(data).map_err(|e| format!("Invalid MessagePack message: {e}"))
+    }
+}
+
+// Binary frames
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl<T: serde::Serialize + serde::de::DeserializeOwned> Codec<T> for CborCodec {
+    fn opcode(&self) -> Opcode {
+        Opcode::Binary
+    }
+
+    fn encode(&self, value: &T) -> Result<Bytes, String> {
+        let mut data = Vec::new();
+        ciborium::into_writer(value, &mut data)
+            .map_err(|e| format!("Can't encode CBOR message: {e}"))?;
+        Ok(Bytes::from(data))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, String> {
+        ciborium::from_reader(data).map_err(|e| format!("Invalid CBOR message: {e}"))
+    }
+}
+
+// Binary frames, for prost generated messages
+#[cfg(feature = "protobuf")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProtobufCodec;
+
+#[cfg(feature = "protobuf")]
+impl<T: prost::Message + Default> Codec<T> for ProtobufCodec {
+    fn opcode(&self) -> Opcode {
+        Opcode::Binary
+    }
+
+    fn encode(&self, value: &T) -> Result<Bytes, String> {
+        Ok(Bytes::from(value.encode_to_vec()))
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<T, String> {
+        T::decode(data).map_err(|e| format!("Invalid Protobuf message: {e}"))
+    }
+}
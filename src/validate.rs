@@ -0,0 +1,291 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::ops::Deref;
+
+use crate::error::HttpError;
+#[cfg(feature = "serde")]
+use crate::extract::{Path, Query};
+use crate::handler::{ExtractFuture, FromRequest};
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+#[cfg(feature = "json")]
+use crate::response::Json;
+use crate::response::StatusCode;
+
+/***
+ * Rules a value has to pass once it is extracted, see Valid:
+ *
+ *   impl Validate for NewUser {
+ *       fn validate(&self) -> Result<(), FieldErrors> {
+ *           let mut errors = FieldErrors::new();
+ *           if self.name.is_empty() {
+ *               errors.add("name", "must not be empty");
+ *           }
+ *           if self.age < 18 {
+ *               errors.add("age", "must be at least 18");
+ *           }
+ *           errors.into_result()
+ *       }
+ *   }
+ ***/
+pub trait Validate {
+    fn validate(&self) -> Result<(), FieldErrors>;
+}
+
+// Messages per field, in the order they were added. A field can have several
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FieldErrors {
+    errors: Vec<(String, String)>,
+}
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn add(&mut self, field: &str, message: &str) -> &mut Self {
+        self.errors.push((field.to_string(), message.to_string()));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.errors
+            .iter()
+            .map(|(field, message)| (field.as_str(), message.as_str()))
+    }
+
+    // Ok if nothing was added
+    pub fn into_result(self) -> Result<(), FieldErrors> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl Display for FieldErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for (index, (field, message)) in self.iter().enumerate() {
+            if index > 0 {
+                f.write_str("\n")?;
+            }
+            write!(f, "{field}: {message}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FieldErrors {}
+
+/***
+ * Types deriving validator's Validate, with the `validator` feature. Fields of nested structs and
+ * lists are dotted, `address.city` or `items[1].name`, a rule without a message gives its code
+ ***/
+#[cfg(feature = "validator")]
+impl<T: validator::Validate> Validate for T {
+    fn validate(&self) -> Result<(), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        if let Err(e) = validator::Validate::validate(self) {
+            add_validation_errors(&mut errors, "", &e);
+        }
+        errors.into_result()
+    }
+}
+
+// Sorted by field, validator keeps them in a HashMap
+#[cfg(feature = "validator")]
+fn add_validation_errors(
+    errors: &mut FieldErrors,
+    prefix: &str,
+    validation_errors: &validator::ValidationErrors,
+) {
+    let mut fields: Vec<_> = validation_errors.errors().iter().collect();
+    fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (field, kind) in fields {
+        let field = format!("{prefix}{field}");
+        match kind {
+            validator::ValidationErrorsKind::Field(field_errors) => {
+                for error in field_errors {
+                    errors.add(&field, error.message.as_deref().unwrap_or(&error.code));
+                }
+            }
+            validator::ValidationErrorsKind::Struct(nested) => {
+                add_validation_errors(errors, &format!("{field}."), nested);
+            }
+            validator::ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    add_validation_errors(errors, &format!("{field}[{index}]."), nested);
+                }
+            }
+        }
+    }
+}
+
+/***
+ * Extractor that runs Validate on what `E` extracted, a value failing it gets 422 without the
+ * handler running:
+ *
+ *   async fn create_user(Valid(Json(user)): Valid<Json<NewUser>>) -> StatusCode { .. }
+ *   app.post("/users", handler(create_user));
+ *
+ * Works with Json, Query and Path of a `T: Validate`. The HttpError keeps the FieldErrors as its
+ * source, problem_json() lists them under `errors`, one array of messages per field. Whether it
+ * runs and the status it answers with are set per route with App::route_validation()
+ ***/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Valid<E>(pub E);
+
+impl<E> Valid<E> {
+    pub fn into_inner(self) -> E {
+        self.0
+    }
+}
+
+impl<E> Deref for Valid<E> {
+    type Target = E;
+
+    fn deref(&self) -> &E {
+        &self.0
+    }
+}
+
+impl<E: FromRequest + Validate> FromRequest for Valid<E> {
+    fn from_request<'a, const SSL: bool>(
+        res: &'a mut HttpConnection<SSL>,
+        req: &'a HttpRequest,
+    ) -> ExtractFuture<'a, Self> {
+        let validation = res.validation();
+        Box::pin(async move {
+            let value = E::from_request(res, req).await?;
+            if !validation.enabled {
+                return Ok(Valid(value));
+            }
+            match value.validate() {
+                Ok(()) => Ok(Valid(value)),
+                Err(errors) => {
+                    let error = HttpError::new(validation.status, errors.to_string());
+                    Err(error.with_source(errors))
+                }
+            }
+        })
+    }
+}
+
+/***
+ * What Valid does on a route, see App::route_validation(). Runs Validate and answers 422 by
+ * default:
+ *
+ *   app.route_validation("/import", Validation::new().status(StatusCode::BAD_REQUEST));
+ *   app.route_validation("/drafts", Validation::skip());
+ ***/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Validation {
+    enabled: bool,
+    status: StatusCode,
+}
+
+impl Validation {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Hands values to the handler without running Validate
+    pub fn skip() -> Self {
+        Validation {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    // Status of values failing Validate
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation {
+            enabled: true,
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Validate> Validate for Path<T> {
+    fn validate(&self) -> Result<(), FieldErrors> {
+        self.0.validate()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<T: Validate> Validate for Query<T> {
+    fn validate(&self) -> Result<(), FieldErrors> {
+        self.0.validate()
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T: Validate> Validate for Json<T> {
+    fn validate(&self) -> Result<(), FieldErrors> {
+        self.0.validate()
+    }
+}
+
+#[cfg(all(test, feature = "validator"))]
+mod tests {
+    use std::borrow::Cow;
+
+    use super::*;
+
+    struct Signup {
+        name: String,
+        age: u8,
+    }
+
+    impl validator::Validate for Signup {
+        fn validate(&self) -> Result<(), validator::ValidationErrors> {
+            let mut errors = validator::ValidationErrors::new();
+            if self.name.is_empty() {
+                errors.add("name", validator::ValidationError::new("length"));
+            }
+            if self.age < 18 {
+                let mut error = validator::ValidationError::new("range");
+                error.message = Some(Cow::from("must be at least 18"));
+                errors.add("age", error);
+            }
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        }
+    }
+
+    #[test]
+    fn maps_validator_errors() {
+        let signup = Signup {
+            name: String::new(),
+            age: 16,
+        };
+        let errors = signup.validate().unwrap_err();
+        let errors: Vec<(&str, &str)> = errors.iter().collect();
+        assert_eq!(
+            errors,
+            vec![("age", "must be at least 18"), ("name", "length")]
+        );
+
+        let signup = Signup {
+            name: "ada".to_string(),
+            age: 36,
+        };
+        assert_eq!(signup.validate(), Ok(()));
+    }
+}
@@ -0,0 +1,184 @@
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use log::debug;
+use sha2::Sha256;
+
+use crate::app::BoxedHandlerFuture;
+use crate::error::HttpError;
+use crate::http_connection::HttpConnection;
+use crate::http_request::HttpRequest;
+use crate::response::StatusCode;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookStyle {
+    // `x-hub-signature-256: sha256=<hex>` over the raw body
+    GitHub,
+    // `stripe-signature: t=<ts>,v1=<hex>` over `<ts>.<body>`
+    Stripe,
+    // `x-slack-signature: v0=<hex>` over `v0:<x-slack-request-timestamp>:<body>`
+    Slack,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookVerifier {
+    secret: Vec<u8>,
+    style: WebhookStyle,
+    // Max allowed age of the signed timestamp, not used by GitHub style
+    tolerance: Duration,
+}
+
+impl WebhookVerifier {
+    pub fn new(style: WebhookStyle, secret: impl Into<Vec<u8>>) -> Self {
+        WebhookVerifier {
+            secret: secret.into(),
+            style,
+            tolerance: Duration::from_secs(300),
+        }
+    }
+
+    pub fn github(secret: impl Into<Vec<u8>>) -> Self {
+        Self::new(WebhookStyle::GitHub, secret)
+    }
+
+    pub fn stripe(secret: impl Into<Vec<u8>>) -> Self {
+        Self::new(WebhookStyle::Stripe, secret)
+    }
+
+    pub fn slack(secret: impl Into<Vec<u8>>) -> Self {
+        Self::new(WebhookStyle::Slack, secret)
+    }
+
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn verify(&self, req: &HttpRequest, body: &[u8]) -> Result<(), String> {
+        match self.style {
+            WebhookStyle::GitHub => {
+                let signature = req
+                    .get_header("x-hub-signature-256")
+                    .ok_or("Missing x-hub-signature-256 header")?;
+                let signature = signature
+                    .strip_prefix("sha256=")
+                    .ok_or("Unsupported signature algorithm")?;
+                self.verify_hex(&[body], signature)
+            }
+            WebhookStyle::Stripe => {
+                let header = req
+                    .get_header("stripe-signature")
+                    .ok_or("Missing stripe-signature header")?;
+                let mut timestamp = None;
+                let mut signatures = Vec::new();
+                for item in header.split(',') {
+                    match item.trim().split_once('=') {
+                        Some(("t", value)) => timestamp = Some(value),
+                        Some(("v1", value)) => signatures.push(value),
+                        _ => {}
+                    }
+                }
+                let timestamp = timestamp.ok_or("Missing timestamp in stripe-signature")?;
+                self.check_timestamp(timestamp)?;
+                let payload: [&[u8]; 3] = [timestamp.as_bytes(), b".", body];
+                signatures
+                    .into_iter()
+                    .find(|signature| self.verify_hex(&payload, signature).is_ok())
+                    .map(|_| ())
+                    .ok_or_else(|| "Signature mismatch".to_string())
+            }
+            WebhookStyle::Slack => {
+                let timestamp = req
+                    .get_header("x-slack-request-timestamp")
+                    .ok_or("Missing x-slack-request-timestamp header")?;
+                let signature = req
+                    .get_header("x-slack-signature")
+                    .ok_or("Missing x-slack-signature header")?;
+                let signature = signature
+                    .strip_prefix("v0=")
+                    .ok_or("Unsupported signature version")?;
+                self.check_timestamp(timestamp)?;
+                let payload: [&[u8]; 4] = [b"v0:", timestamp.as_bytes(), b":", body];
+                self.verify_hex(&payload, signature)
+            }
+        }
+    }
+
+    fn verify_hex(&self, payload: &[&[u8]], signature: &str) -> Result<(), String> {
+        let signature = decode_hex(signature).ok_or("Signature is not valid hex")?;
+        let mut mac = HmacSha256::new_from_slice(&self.secret)
+            .map_err(|_| "Invalid webhook secret".to_string())?;
+        for part in payload {
+            mac.update(part);
+        }
+        mac.verify_slice(&signature)
+            .map_err(|_| "Signature mismatch".to_string())
+    }
+
+    fn check_timestamp(&self, timestamp: &str) -> Result<(), String> {
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| "Timestamp is not a number".to_string())?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.abs_diff(timestamp) > self.tolerance.as_secs() {
+            return Err("Timestamp is outside of the tolerance window".to_string());
+        }
+        Ok(())
+    }
+}
+
+/***
+ * Reads the raw body and verifies its signature before calling `handler`.
+ * The verified payload is still available to the handler through `res.get_body()`.
+ * The body is read up to the route's max_body_size (1 MiB if there is none), larger ones get
+ * 413. Requests with a bad signature are rejected with 401, the reason is only logged.
+ ***/
+pub fn verify_webhook<T, W, const SSL: bool>(
+    verifier: WebhookVerifier,
+    handler: T,
+) -> impl (Fn(HttpConnection<SSL>, HttpRequest) -> BoxedHandlerFuture) + 'static + Send + Sync
+where
+    T: (Fn(HttpConnection<SSL>, HttpRequest) -> W) + 'static + Send + Sync + Clone,
+    W: Future<Output = ()> + 'static + Send,
+{
+    move |mut res: HttpConnection<SSL>, req: HttpRequest| {
+        let verifier = verifier.clone();
+        let handler = handler.clone();
+        Box::pin(async move {
+            let limit = res.default_body_limit();
+            let body = match res.get_body_limited(limit).await {
+                Ok(body) => body,
+                // A body over the limit has been answered with 413 already
+                Err(e) => return res.send_error(HttpError::bad_request(e)).await,
+            };
+            if let Err(e) = verifier.verify(&req, &body) {
+                debug!(
+                    "[async_uws] Webhook verification failed for {}: {e}",
+                    req.url
+                );
+                let error = HttpError::new(StatusCode::UNAUTHORIZED, "Invalid signature");
+                return res.send_error(error).await;
+            }
+
+            res.replace_body(body);
+            handler(res, req).await;
+        })
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
@@ -1,29 +1,65 @@
-use std::future::Future;
+use std::collections::VecDeque;
+use std::ffi::{c_int, c_void};
+use std::future::{poll_fn, Future};
+use std::net::SocketAddr;
 use std::pin::Pin;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
 
+use bytes::Bytes;
+use libuwebsockets_sys::uws_ws_cork;
 use log::error;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use uwebsockets_rs::uws_loop::{loop_defer, UwsLoop};
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, WeakUnboundedSender};
+use tokio::sync::{oneshot, Notify};
+use uwebsockets_rs::uws_loop::UwsLoop;
 use uwebsockets_rs::websocket::{Opcode, SendStatus as NativeSendStatus, WebSocketStruct};
 
+use crate::backplane::BackplaneLink;
+use crate::cancellation::CancellationToken;
+use crate::connection_registry::ConnectionId;
 use crate::data_storage::SharedDataStorage;
-use crate::ws_message::WsMessage;
+use crate::diagnostics::loop_defer;
+use crate::loop_bound::LoopBound;
+use crate::task;
+use crate::ws_behavior::{LastSeen, OverflowPolicy, PendingPings};
+use crate::ws_message::{WsMessage, WsViolation, ERR_OUTBOUND_OVERFLOW};
+use crate::ws_stats::{count_sent, WsCounters, WsStats};
+
+// Payload of the next ping_rtt(), unique across sockets so pongs can't be mixed up
+static NEXT_PING: AtomicU64 = AtomicU64::new(1);
 
 pub struct Websocket<const SSL: bool> {
     pub stream: UnboundedReceiver<WsMessage>,
-    native: WebSocketStruct<SSL>,
+    id: ConnectionId,
+    native: LoopBound<WebSocketStruct<SSL>>,
     uws_loop: UwsLoop,
-    is_open: Arc<AtomicBool>,
+    pub(crate) is_open: Arc<AtomicBool>,
     global_data_storage: SharedDataStorage,
     per_connection_data_storage: SharedDataStorage,
+    // Depth of the split() sink, shared with the per socket data for Diagnostics
+    pub(crate) outbound_queued: Arc<AtomicUsize>,
+    pub(crate) cancellation: CancellationToken,
+    pub(crate) drained: Arc<Notify>,
+    pub(crate) closed: Arc<Notify>,
+    pub(crate) pending_pings: PendingPings,
+    pub(crate) remote_address: Option<SocketAddr>,
+    // WsRouteSettings::max_backpressure of the route, 0 is no limit
+    pub(crate) max_backpressure: u32,
+    // The app's, publishes are handed to it
+    pub(crate) backplane: Arc<OnceLock<BackplaneLink>>,
+    // WsRouteSettings::outbound_queue of the route, the split() sink is unbounded without it
+    pub(crate) outbound_limit: Option<(usize, OverflowPolicy)>,
+    // Incoming side of the socket, overflows of the split() sink are reported into it
+    pub(crate) violations: Option<WeakUnboundedSender<WsMessage>>,
+    // WsRouteSettings::batch_sends of the route
+    pub(crate) batch_sends: bool,
+    pub(crate) last_seen: Arc<LastSeen>,
+    pub(crate) stats: Arc<WsCounters>,
 }
 
-unsafe impl<const SSL: bool> Send for Websocket<SSL> {}
-unsafe impl<const SSL: bool> Sync for Websocket<SSL> {}
-
 impl<const SSL: bool> Websocket<SSL> {
     pub fn new(
         native: WebSocketStruct<SSL>,
@@ -35,39 +71,67 @@ impl<const SSL: bool> Websocket<SSL> {
     ) -> Self {
         Websocket {
             stream: from_native_stream,
-            native,
+            id: ConnectionId::next(),
+            native: LoopBound::new(native),
             uws_loop,
             is_open,
             global_data_storage,
             per_connection_data_storage,
+            outbound_queued: Default::default(),
+            cancellation: Default::default(),
+            drained: Default::default(),
+            closed: Default::default(),
+            pending_pings: Default::default(),
+            remote_address: None,
+            max_backpressure: 0,
+            backplane: Default::default(),
+            outbound_limit: None,
+            violations: None,
+            batch_sends: false,
+            last_seen: Default::default(),
+            stats: Default::default(),
         }
     }
 
     /***
      * Returns sink & stream. Sink accepts (WsMessage, bool, bool) where fist bool is 'compress' param and second 'fin' (Like in 'send_with_option' method)
+     * With the "futures" feature they are a futures Sink and Stream as well, see WsSink.
+     * The sink queues up to WsRouteSettings::outbound_queue messages, see OverflowPolicy. With
+     * WsRouteSettings::batch_sends whatever is queued goes out corked in one loop callback
      ***/
-    pub fn split(
-        self,
-    ) -> (
-        UnboundedSender<(WsMessage, bool, bool)>,
-        UnboundedReceiver<WsMessage>,
-    ) {
-        let (to_client_sink, mut to_client_stream) = unbounded_channel::<(WsMessage, bool, bool)>();
+    pub fn split(mut self) -> (WsSink, WsStream) {
+        let outbound = Arc::new(Outbound {
+            state: Default::default(),
+            limit: self.outbound_limit,
+            queued: self.outbound_queued.clone(),
+            violations: self.violations.take(),
+        });
+        let stream = std::mem::replace(&mut self.stream, unbounded_channel().1);
 
         let uws_loop = self.uws_loop;
-        tokio_uring::spawn(async move {
-            while let Some((message, compress, fin)) = to_client_stream.recv().await {
+        let queue = outbound.clone();
+        task::spawn("async_uws ws writer", async move {
+            while let Some(next) = queue.next(self.batch_sends).await {
+                let Outgoing::Messages(mut messages) = next else {
+                    self.end(1008, Some(ERR_OUTBOUND_OVERFLOW)).await;
+                    break;
+                };
                 let websocket = self.native.clone();
 
-                let status = send_to_socket(
-                    message,
-                    compress,
-                    fin,
-                    websocket,
-                    uws_loop,
-                    self.is_open.clone(),
-                )
-                .await;
+                let status = if messages.len() == 1 {
+                    let (message, compress, fin) = messages.remove(0);
+                    send_to_socket(
+                        message,
+                        compress,
+                        fin,
+                        websocket,
+                        uws_loop,
+                        self.is_open.clone(),
+                    )
+                    .await
+                } else {
+                    send_batch(messages, websocket, uws_loop, self.is_open.clone()).await
+                };
 
                 if let Err(e) = status {
                     error!("[async_uws] Error sending message to client: {e:#?}");
@@ -82,9 +146,10 @@ impl<const SSL: bool> Websocket<SSL> {
                     break;
                 }
             }
+            queue.close();
         });
 
-        (to_client_sink, self.stream)
+        (WsSink { outbound }, WsStream { receiver: stream })
     }
 
     pub fn data<T: Send + Sync + Clone + 'static>(&self) -> Option<&T> {
@@ -95,10 +160,177 @@ impl<const SSL: bool> Websocket<SSL> {
         self.per_connection_data_storage.as_ref().get_data::<T>()
     }
 
+    // Child of App::cancellation_token(), `stream` ends (recv() returns None) when it fires
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancellation.clone()
+    }
+
     pub fn is_open(&self) -> bool {
         self.is_open.load(Ordering::SeqCst)
     }
 
+    // Key of the socket in the app's ConnectionRegistry
+    pub fn id(&self) -> ConnectionId {
+        self.id
+    }
+
+    /***
+     * Closing from our side, either way the handler's stream gets the Close message once uWS
+     * closed the socket, and both return false if it already was:
+     *
+     *   end() sends a close frame with `code` and `reason` after whatever is still buffered,
+     *   the connection is closed once that is written.
+     *   terminate() drops the connection right away without a close frame, the Close message
+     *   carries 1006.
+     ***/
+    pub async fn end(&self, code: i32, reason: Option<&str>) -> bool {
+        let reason = reason.map(String::from);
+        self.with_native(move |native| {
+            native.end(code, reason.as_deref());
+            true
+        })
+        .await
+    }
+
+    pub async fn terminate(&self) -> bool {
+        self.with_native(|native| {
+            native.close();
+            true
+        })
+        .await
+    }
+
+    // Resolves once the socket is closed, by either side
+    pub async fn closed(&self) {
+        let closed = self.closed.notified();
+        tokio::pin!(closed);
+        closed.as_mut().enable();
+        if self.is_open() {
+            closed.await;
+        }
+    }
+
+    // Last message, ping or pong of the client, the socket opening until there is one
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen.get()
+    }
+
+    // Messages and bytes so far, see WsStats
+    pub fn stats(&self) -> WsStats {
+        self.stats.get()
+    }
+
+    // Client's IP and port, see remote_address::remote_address()
+    pub fn remote_address(&self) -> Option<SocketAddr> {
+        self.remote_address
+    }
+
+    /***
+     * Runs `f` on the loop with the socket corked, whatever it sends is written in one syscall
+     * once it returns instead of one deferred callback and write per message:
+     *
+     *   ws.cork(move |ws| {
+     *       for tick in ticks {
+     *           let _ = ws.send(tick);
+     *       }
+     *   })
+     *   .await;
+     *
+     * None if the socket is closed. `f` must not panic, it runs inside a uWS callback
+     ***/
+    pub async fn cork<F, R>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&CorkedWebsocket<SSL>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.with_native(move |native| Some(cork_native(native, f)))
+            .await
+    }
+
+    // Bytes uWS still holds for this socket because the client reads slower than we send
+    pub async fn buffered_amount(&self) -> u32 {
+        self.with_native(|native| native.get_buffered_amount())
+            .await
+    }
+
+    /***
+     * Native uWS pub/sub: publish() delivers to every socket of the app subscribed to the topic
+     * except this one, App::publish() to all of them. Subscriptions end with the socket.
+     * All of these return false once the socket is closed. With App::backplane() publishes reach
+     * the subscribers on the other nodes as well.
+     ***/
+    pub async fn subscribe(&self, topic: &str) -> bool {
+        let topic = topic.to_string();
+        self.with_native(move |native| native.subscribe(&topic))
+            .await
+    }
+
+    pub async fn unsubscribe(&self, topic: &str) -> bool {
+        let topic = topic.to_string();
+        self.with_native(move |native| native.unsubscribe(&topic))
+            .await
+    }
+
+    pub async fn is_subscribed(&self, topic: &str) -> bool {
+        let topic = topic.to_string();
+        self.with_native(move |native| native.is_subscribed(&topic))
+            .await
+    }
+
+    pub async fn publish(
+        &self,
+        topic: &str,
+        message: impl Into<Bytes>,
+        opcode: Opcode,
+        compress: bool,
+    ) -> bool {
+        let message = message.into();
+        let backplane = self.backplane.clone();
+        let topic = topic.to_string();
+        self.with_native(move |native| {
+            let published = native.publish_with_options(&topic, &message, opcode.clone(), compress);
+            if let Some(backplane) = backplane.get() {
+                backplane.published(&topic, message, opcode, compress);
+            }
+            published
+        })
+        .await
+    }
+
+    // Runs `f` on the loop while the socket is open, the result is the default once it's closed
+    async fn with_native<R, F>(&self, f: F) -> R
+    where
+        R: Default + Send + 'static,
+        F: FnOnce(&WebSocketStruct<SSL>) -> R + Send + 'static,
+    {
+        let (sink, stream) = oneshot::channel();
+        let native = self.native.clone();
+        let is_open = self.is_open.clone();
+        loop_defer(self.uws_loop, move || {
+            let result = if is_open.load(Ordering::Relaxed) {
+                f(native.get())
+            } else {
+                R::default()
+            };
+            let _ = sink.send(result);
+        });
+        stream.await.unwrap_or_default()
+    }
+
+    // Waits until at most `max_buffered` bytes are left in the uWS buffer, or the socket closes
+    pub async fn flush(&self, max_buffered: u32) {
+        loop {
+            let drained = self.drained.notified();
+            tokio::pin!(drained);
+            drained.as_mut().enable();
+            if !self.is_open() || self.buffered_amount().await <= max_buffered {
+                return;
+            }
+            // There is no drain event after a close, so the wait is bounded
+            let _ = tokio::time::timeout(Duration::from_millis(500), drained).await;
+        }
+    }
+
     pub async fn send(&mut self, message: WsMessage) -> Result<SendStatus, String> {
         send_to_socket(
             message,
@@ -111,6 +343,54 @@ impl<const SSL: bool> Websocket<SSL> {
         .await
     }
 
+    // send() that first waits while more than the route's max_backpressure is buffered, so a
+    // fast producer doesn't have its messages dropped or the socket closed
+    pub async fn send_with_backpressure(
+        &mut self,
+        message: WsMessage,
+    ) -> Result<SendStatus, String> {
+        if self.max_backpressure > 0 {
+            self.flush(self.max_backpressure).await;
+        }
+        self.send(message).await
+    }
+
+    // Control frames of our own, e.g. liveness checks next to send_pings_automatically
+    pub async fn ping(&mut self, payload: impl Into<Bytes>) -> Result<SendStatus, String> {
+        self.send(WsMessage::Ping(Some(payload.into()))).await
+    }
+
+    pub async fn pong(&mut self, payload: impl Into<Bytes>) -> Result<SendStatus, String> {
+        self.send(WsMessage::Pong(Some(payload.into()))).await
+    }
+
+    // Pings and waits for the pong echoing it, the pong still reaches `stream` as usual
+    pub async fn ping_rtt(&mut self, timeout: Duration) -> Result<Duration, String> {
+        let payload = NEXT_PING
+            .fetch_add(1, Ordering::Relaxed)
+            .to_be_bytes()
+            .to_vec();
+        let (sink, pong) = oneshot::channel();
+        self.pending_pings
+            .lock()
+            .unwrap()
+            .insert(payload.clone(), sink);
+        let sent = Instant::now();
+        let rtt = match self.ping(payload.clone()).await {
+            Ok(SendStatus::Success | SendStatus::Backpressure) => {
+                match tokio::time::timeout(timeout, pong).await {
+                    Ok(Ok(received)) => Ok(received - sent),
+                    Ok(Err(_)) => Err("WebSocket is closed!".to_string()),
+                    Err(_) => Err(format!("No pong within {timeout:?}")),
+                }
+            }
+            Ok(status) => Err(format!("Ping wasn't sent, send status: {status:?}")),
+            Err(e) => Err(e),
+        };
+        self.pending_pings.lock().unwrap().remove(&payload);
+        rtt
+    }
+
     pub async fn send_with_options(
         &mut self,
         message: WsMessage,
@@ -138,6 +418,294 @@ struct WebsocketSendFutureState {
     waker: Option<Waker>,
     send_status: Option<SendStatus>,
 }
+/***
+ * Sending half of Websocket::split(), a writer task sends the messages in order and stops at the
+ * first one that isn't sent with SendStatus::Success. With the "futures" feature it is a
+ * Sink of WsMessage, sent uncompressed as a final frame, and of (WsMessage, compress, fin).
+ * Sending only queues the message, so flushing and closing the Sink return right away. Clones
+ * share the queue, the writer stops once all of them are dropped and the queue is empty
+ ***/
+pub struct WsSink {
+    outbound: Arc<Outbound>,
+}
+
+type OutboundMessage = (WsMessage, bool, bool);
+
+impl WsSink {
+    // (message, compress, fin), waits for room with OverflowPolicy::Block and fails once the
+    // writer task stopped
+    pub async fn send(&self, message: OutboundMessage) -> Result<(), SendError<OutboundMessage>> {
+        let mut message = Some(message);
+        poll_fn(|cx| {
+            let pending = message.take().expect("polled after completion");
+            match self.outbound.push(pending, Some(cx.waker())) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(pending)) => Poll::Ready(Err(SendError(pending))),
+                Err(TrySendError::Full(pending)) => {
+                    message = Some(pending);
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    // Like send() without waiting, Full only comes with OverflowPolicy::Block
+    pub fn try_send(&self, message: OutboundMessage) -> Result<(), TrySendError<OutboundMessage>> {
+        self.outbound.push(message, None)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.outbound.state.lock().unwrap().closed
+    }
+
+    // Messages waiting for the writer task
+    pub fn len(&self) -> usize {
+        self.outbound.state.lock().unwrap().messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Clone for WsSink {
+    fn clone(&self) -> Self {
+        self.outbound.state.lock().unwrap().senders += 1;
+        WsSink {
+            outbound: self.outbound.clone(),
+        }
+    }
+}
+
+impl Drop for WsSink {
+    fn drop(&mut self) {
+        let mut state = self.outbound.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            if let Some(writer) = state.writer.take() {
+                writer.wake();
+            }
+        }
+    }
+}
+
+enum Outgoing {
+    Messages(Vec<OutboundMessage>),
+    // OverflowPolicy::Close, the writer closes the socket
+    Close,
+}
+
+// Queue between the WsSink clones and the writer task of Websocket::split()
+struct Outbound {
+    state: Mutex<OutboundState>,
+    limit: Option<(usize, OverflowPolicy)>,
+    // Websocket::outbound_queued, for Diagnostics
+    queued: Arc<AtomicUsize>,
+    violations: Option<WeakUnboundedSender<WsMessage>>,
+}
+
+struct OutboundState {
+    messages: VecDeque<OutboundMessage>,
+    senders: usize,
+    // The writer task stopped
+    closed: bool,
+    // Set by OverflowPolicy::Close, the writer closes the socket
+    close_requested: bool,
+    // Reported once until the queue has room again
+    overflowing: bool,
+    writer: Option<Waker>,
+    // Senders waiting for room, OverflowPolicy::Block only
+    blocked: Vec<Waker>,
+}
+
+impl Default for OutboundState {
+    fn default() -> Self {
+        OutboundState {
+            messages: VecDeque::new(),
+            senders: 1,
+            closed: false,
+            close_requested: false,
+            overflowing: false,
+            writer: None,
+            blocked: Vec::new(),
+        }
+    }
+}
+
+impl Outbound {
+    // Full leaves `waker` to be woken once there is room
+    fn push(
+        &self,
+        message: OutboundMessage,
+        waker: Option<&Waker>,
+    ) -> Result<(), TrySendError<OutboundMessage>> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(TrySendError::Closed(message));
+        }
+        if let Some((capacity, policy)) = self.limit {
+            if state.messages.len() >= capacity {
+                if !state.overflowing {
+                    state.overflowing = true;
+                    self.report_overflow(capacity, policy);
+                }
+                match policy {
+                    OverflowPolicy::Block => {
+                        if let Some(waker) = waker {
+                            state.blocked.push(waker.clone());
+                        }
+                        return Err(TrySendError::Full(message));
+                    }
+                    OverflowPolicy::DropOldest => {
+                        state.messages.pop_front();
+                    }
+                    OverflowPolicy::DropNewest => return Ok(()),
+                    OverflowPolicy::Close => {
+                        state.close_requested = true;
+                        if let Some(writer) = state.writer.take() {
+                            writer.wake();
+                        }
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        state.messages.push_back(message);
+        self.queued.store(state.messages.len(), Ordering::Relaxed);
+        if let Some(writer) = state.writer.take() {
+            writer.wake();
+        }
+        Ok(())
+    }
+
+    fn report_overflow(&self, capacity: usize, policy: OverflowPolicy) {
+        let violation = WsViolation::OutboundOverflow { capacity, policy };
+        if let Some(violations) = self.violations.as_ref().and_then(|weak| weak.upgrade()) {
+            let _ = violations.send(WsMessage::Violation(violation));
+        }
+    }
+
+    // The next message, or all of the queued ones with `batch`. None once every sink is gone
+    // and the queue is empty
+    async fn next(&self, batch: bool) -> Option<Outgoing> {
+        poll_fn(|cx| {
+            let mut state = self.state.lock().unwrap();
+            if state.close_requested {
+                return Poll::Ready(Some(Outgoing::Close));
+            }
+            if !state.messages.is_empty() {
+                let count = if batch { state.messages.len() } else { 1 };
+                let messages: Vec<_> = state.messages.drain(..count).collect();
+                self.queued.store(state.messages.len(), Ordering::Relaxed);
+                state.overflowing = false;
+                for sender in state.blocked.drain(..) {
+                    sender.wake();
+                }
+                return Poll::Ready(Some(Outgoing::Messages(messages)));
+            }
+            if state.senders == 0 {
+                return Poll::Ready(None);
+            }
+            state.writer = Some(cx.waker().clone());
+            Poll::Pending
+        })
+        .await
+    }
+
+    // The writer stopped, waiting senders get Closed
+    fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+        self.queued.store(0, Ordering::Relaxed);
+        for sender in state.blocked.drain(..) {
+            sender.wake();
+        }
+    }
+}
+
+// Receiving half of Websocket::split(), a futures Stream with the "futures" feature
+pub struct WsStream {
+    receiver: UnboundedReceiver<WsMessage>,
+}
+
+impl WsStream {
+    // None once the socket is closed and every message was received
+    pub async fn recv(&mut self) -> Option<WsMessage> {
+        self.receiver.recv().await
+    }
+
+    pub fn into_inner(self) -> UnboundedReceiver<WsMessage> {
+        self.receiver
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_core::Stream for WsStream {
+    type Item = WsMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WsMessage>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_sink::Sink<(WsMessage, bool, bool)> for WsSink {
+    type Error = String;
+
+    // Waits for room with OverflowPolicy::Block
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        let mut state = self.outbound.state.lock().unwrap();
+        if state.closed {
+            return Poll::Ready(Err("WebSocket is closed!".to_string()));
+        }
+        if let Some((capacity, OverflowPolicy::Block)) = self.outbound.limit {
+            if state.messages.len() >= capacity {
+                state.blocked.push(cx.waker().clone());
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: (WsMessage, bool, bool)) -> Result<(), String> {
+        self.try_send(item).map_err(|e| match e {
+            TrySendError::Full(_) => "WebSocket sink is full".to_string(),
+            TrySendError::Closed(_) => "WebSocket is closed!".to_string(),
+        })
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures_sink::Sink<WsMessage> for WsSink {
+    type Error = String;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        futures_sink::Sink::<(WsMessage, bool, bool)>::poll_ready(self, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: WsMessage) -> Result<(), String> {
+        futures_sink::Sink::<(WsMessage, bool, bool)>::start_send(self, (item, false, true))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), String>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 pub struct WebsocketSendFuture {
     state: Arc<Mutex<WebsocketSendFutureState>>,
 }
@@ -155,9 +723,7 @@ impl WebsocketSendFuture {
             }
         };
 
-        tokio_uring::spawn(async move {
-            loop_defer(uws_loop, closure);
-        });
+        loop_defer(uws_loop, closure);
 
         WebsocketSendFuture { state }
     }
@@ -194,11 +760,111 @@ impl From<NativeSendStatus> for SendStatus {
     }
 }
 
-async fn send_to_socket<const SSL: bool>(
+// Sends of Websocket::cork(), only valid within its closure
+pub struct CorkedWebsocket<'a, const SSL: bool> {
+    native: &'a WebSocketStruct<SSL>,
+}
+
+impl<const SSL: bool> CorkedWebsocket<'_, SSL> {
+    pub fn send(&self, message: impl Into<WsMessage>) -> Result<SendStatus, String> {
+        self.send_with_options(message, false, true)
+    }
+
+    pub fn send_with_options(
+        &self,
+        message: impl Into<WsMessage>,
+        compress: bool,
+        fin: bool,
+    ) -> Result<SendStatus, String> {
+        let status = match message.into() {
+            WsMessage::Message(data, opcode) => {
+                let status = self.native.send_with_options(&data, opcode, compress, fin);
+                count_sent(self.native, data.len(), &status);
+                status
+            }
+            WsMessage::Ping(data) => {
+                self.native
+                    .send_with_options(&data.unwrap_or_default(), Opcode::Ping, false, true)
+            }
+            WsMessage::Pong(data) => {
+                self.native
+                    .send_with_options(&data.unwrap_or_default(), Opcode::Pong, false, true)
+            }
+            WsMessage::Close(code, reason) => {
+                self.native.end(code, reason.as_deref());
+                return Ok(SendStatus::Success);
+            }
+            WsMessage::Violation(_) => {
+                return Err("Violation is only received, it can't be sent".to_string());
+            }
+            WsMessage::Drain(_) => {
+                return Err("Drain is only received, it can't be sent".to_string());
+            }
+        };
+        Ok(status.into())
+    }
+}
+
+// Must be called on the loop thread with an open socket. uwebsockets_rs' own cork() can't take
+// a closure that borrows
+fn cork_native<const SSL: bool, R>(
+    native: &WebSocketStruct<SSL>,
+    f: impl FnOnce(&CorkedWebsocket<SSL>) -> R,
+) -> R {
+    let corked = CorkedWebsocket { native };
+    let mut f = Some(f);
+    let mut result = None;
+    let mut run = || result = f.take().map(|f| f(&corked));
+    let mut run: &mut dyn FnMut() = &mut run;
+    unsafe {
+        uws_ws_cork(
+            SSL as c_int,
+            native.get_native_ws(),
+            Some(on_cork),
+            &mut run as *mut &mut dyn FnMut() as *mut c_void,
+        );
+    }
+    result.expect("[async_uws] uWS didn't run the cork handler")
+}
+
+unsafe extern "C" fn on_cork(user_data: *mut c_void) {
+    let run = &mut *(user_data as *mut &mut dyn FnMut());
+    run();
+}
+
+// The messages of one loop callback, corked. Stops at the first one that isn't a Success
+async fn send_batch<const SSL: bool>(
+    messages: Vec<OutboundMessage>,
+    websocket: LoopBound<WebSocketStruct<SSL>>,
+    uws_loop: UwsLoop,
+    is_open: Arc<AtomicBool>,
+) -> Result<SendStatus, String> {
+    let (sink, stream) = oneshot::channel();
+    loop_defer(uws_loop, move || {
+        if !is_open.load(Ordering::Relaxed) {
+            let _ = sink.send(Ok(SendStatus::WsDisconnected));
+            return;
+        }
+        let status = cork_native(websocket.get(), |corked| {
+            let mut status = Ok(SendStatus::Success);
+            for (message, compress, fin) in messages {
+                status = corked.send_with_options(message, compress, fin);
+                if status != Ok(SendStatus::Success) {
+                    break;
+                }
+            }
+            status
+        });
+        let _ = sink.send(status);
+    });
+    stream.await.unwrap_or(Ok(SendStatus::WsDisconnected))
+}
+
+pub(crate) async fn send_to_socket<const SSL: bool>(
     message: WsMessage,
     compress: bool,
     fin: bool,
-    websocket: WebSocketStruct<SSL>,
+    websocket: LoopBound<WebSocketStruct<SSL>>,
     uws_loop: UwsLoop,
     is_open: Arc<AtomicBool>,
 ) -> Result<SendStatus, String> {
@@ -208,9 +874,10 @@ async fn send_to_socket<const SSL: bool>(
                 if !is_open.load(Ordering::Relaxed) {
                     return SendStatus::WsDisconnected;
                 }
-                websocket
-                    .send_with_options(&msg, opcode, compress, fin)
-                    .into()
+                let native = websocket.get();
+                let status = native.send_with_options(&msg, opcode, compress, fin);
+                count_sent(native, msg.len(), &status);
+                status.into()
             };
             WebsocketSendFuture::new(Box::new(callback), uws_loop).await
         }
@@ -220,6 +887,7 @@ async fn send_to_socket<const SSL: bool>(
                     return SendStatus::WsDisconnected;
                 }
                 websocket
+                    .get()
                     .send_with_options(&msg.unwrap_or_default(), Opcode::Ping, false, true)
                     .into()
             };
@@ -231,6 +899,7 @@ async fn send_to_socket<const SSL: bool>(
                     return SendStatus::WsDisconnected;
                 }
                 websocket
+                    .get()
                     .send_with_options(&msg.unwrap_or_default(), Opcode::Pong, false, true)
                     .into()
             };
@@ -241,11 +910,17 @@ async fn send_to_socket<const SSL: bool>(
                 if !is_open.load(Ordering::Relaxed) {
                     return SendStatus::WsDisconnected;
                 }
-                websocket.end(code, reason.as_deref());
+                websocket.get().end(code, reason.as_deref());
                 SendStatus::Success
             };
             WebsocketSendFuture::new(Box::new(callback), uws_loop).await
         }
+        WsMessage::Violation(_) => {
+            return Err("Violation is only received, it can't be sent".to_string());
+        }
+        WsMessage::Drain(_) => {
+            return Err("Drain is only received, it can't be sent".to_string());
+        }
     };
     Ok(send_status)
 }
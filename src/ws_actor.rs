@@ -0,0 +1,86 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use bytes::Bytes;
+use uwebsockets_rs::websocket::Opcode;
+
+use crate::websocket::Websocket;
+use crate::ws_message::WsMessage;
+
+pub type ActorFuture<'a> = Pin<Box<dyn Future<Output = ()> + Send + 'a>>;
+
+/***
+ * Callback style websocket handler, see App::ws_actor(). Every connection gets its own actor
+ * from Default, the callbacks of one connection run one at a time and share the state in `self`:
+ *
+ *   #[derive(Default)]
+ *   struct Counter {
+ *       received: usize,
+ *   }
+ *
+ *   impl WsActor for Counter {
+ *       fn on_message<'a, const SSL: bool>(
+ *           &'a mut self,
+ *           ws: &'a mut Websocket<SSL>,
+ *           message: Bytes,
+ *           _opcode: Opcode,
+ *       ) -> ActorFuture<'a> {
+ *           Box::pin(async move {
+ *               self.received += message.len();
+ *               let _ = ws.send(format!("{} bytes so far", self.received).into()).await;
+ *           })
+ *       }
+ *   }
+ *
+ *   app.ws_actor::<Counter>("/count", WsRouteSettings::default())?;
+ *
+ * Pings, pongs and violations aren't passed on, uWS answers pings by itself.
+ ***/
+pub trait WsActor: Default + Send + 'static {
+    fn on_open<'a, const SSL: bool>(&'a mut self, ws: &'a mut Websocket<SSL>) -> ActorFuture<'a> {
+        let _ = ws;
+        Box::pin(async {})
+    }
+
+    fn on_message<'a, const SSL: bool>(
+        &'a mut self,
+        ws: &'a mut Websocket<SSL>,
+        message: Bytes,
+        opcode: Opcode,
+    ) -> ActorFuture<'a>;
+
+    // Called once, with 1001 if the app shuts down before the client closes
+    fn on_close(&mut self, code: i32, reason: Option<String>) -> ActorFuture<'_> {
+        let _ = (code, reason);
+        Box::pin(async {})
+    }
+
+    // uWS sent out buffered data and `buffered` bytes are still waiting, see
+    // Websocket::buffered_amount()
+    fn on_drain<'a, const SSL: bool>(
+        &'a mut self,
+        ws: &'a mut Websocket<SSL>,
+        buffered: u32,
+    ) -> ActorFuture<'a> {
+        let _ = (ws, buffered);
+        Box::pin(async {})
+    }
+}
+
+pub(crate) async fn run_actor<A: WsActor, const SSL: bool>(mut ws: Websocket<SSL>) {
+    let mut actor = A::default();
+    actor.on_open(&mut ws).await;
+    while let Some(message) = ws.stream.recv().await {
+        match message {
+            WsMessage::Message(data, opcode) => actor.on_message(&mut ws, data, opcode).await,
+            WsMessage::Drain(buffered) => actor.on_drain(&mut ws, buffered).await,
+            WsMessage::Close(code, reason) => {
+                actor.on_close(code, reason).await;
+                return;
+            }
+            WsMessage::Ping(_) | WsMessage::Pong(_) | WsMessage::Violation(_) => {}
+        }
+    }
+    // The stream only ends without a Close on shutdown
+    actor.on_close(1001, None).await;
+}
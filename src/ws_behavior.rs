@@ -1,9 +1,18 @@
 use std::collections::HashMap;
-use std::future::Future;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::future::{pending, Future};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
-use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use bytes::Bytes;
+use libuwebsockets_sys::{
+    us_create_timer, us_socket_t, us_timer_close, us_timer_ext, us_timer_set, us_timer_t,
+    uws_res_get_native_handle, uws_websocket_t,
+};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, watch, Notify};
+use tokio::time::sleep_until;
 use uwebsockets_rs::http_request::HttpRequest as SyncHttpRequest;
 use uwebsockets_rs::http_response::HttpResponseStruct;
 use uwebsockets_rs::uws_loop::UwsLoop;
@@ -12,11 +21,26 @@ use uwebsockets_rs::websocket_behavior::{
     CompressOptions, UpgradeContext, WebSocketBehavior as NativeWebSocketBehavior,
 };
 
+use crate::backplane::BackplaneLink;
+use crate::cancellation::CancellationToken;
+use crate::connection_registry::{ConnectionId, ConnectionRegistry};
 use crate::data_storage::SharedDataStorage;
+use crate::diagnostics::{loop_defer, RunningWsHandler};
 use crate::http_request::HttpRequest;
 use crate::http_connection::HttpConnection;
+use crate::live_settings::{LiveSettings, SettingsHandle};
+use crate::loop_bound::LoopBound;
+use crate::rate_limit::RateLimiter;
+use crate::remote_address;
+use crate::task;
+use crate::tls_options::negotiated_alpn;
 use crate::websocket::Websocket;
-use crate::ws_message::WsMessage;
+use crate::ws_hooks::{WsHookContext, WsHooks};
+use crate::ws_message::{
+    WsMessage, WsViolation, ERR_HEARTBEAT_TIMEOUT, ERR_INVALID_TEXT, ERR_MESSAGE_RATE,
+    ERR_TOO_BIG_MESSAGE, ERR_TOO_BIG_MESSAGE_INFLATION,
+};
+use crate::ws_stats::WsCounters;
 
 pub type SharedWsPerSocketUserData = Box<WsPerSocketUserData>;
 pub type WsPerSocketUserDataStorage = Arc<Mutex<HashMap<usize, SharedWsPerSocketUserData>>>;
@@ -30,8 +54,172 @@ pub struct WsPerSocketUserData {
     pub(crate) is_open: Arc<AtomicBool>,
     pub(crate) shared_data_storage: SharedDataStorage,
     pub(crate) custom_user_data: SharedDataStorage,
+    pub(crate) payload_violations: u32,
+    // Interval (as counted by IntervalClock) the messages below were received in
+    pub(crate) message_interval: u64,
+    pub(crate) messages_in_interval: u32,
+    // Second (as counted by IntervalClock) of WsRouteSettings::message_rate and what it let through
+    pub(crate) rate_window: u64,
+    pub(crate) window_messages: u32,
+    pub(crate) window_bytes: u64,
+    pub(crate) window_reported: bool,
+    pub(crate) created: Instant,
+    pub(crate) last_seen: Arc<LastSeen>,
+    pub(crate) stats: Arc<WsCounters>,
+    pub(crate) outbound_queued: Arc<AtomicUsize>,
+    pub(crate) cancellation: CancellationToken,
+    // Woken by the uWS drain event, see Websocket::flush()
+    pub(crate) drained: Arc<Notify>,
+    // Woken once uWS closed the socket, see Websocket::closed()
+    pub(crate) closed: Arc<Notify>,
+    // Payload -> waiter of a Websocket::ping_rtt(), answered by the matching pong
+    pub(crate) pending_pings: PendingPings,
+    // uws_websocket_t once the socket is open, only used on the loop thread
+    pub(crate) native_ws: Option<usize>,
+    // Set once open, the socket is in the app's ConnectionRegistry if it has one
+    pub(crate) connection_id: Option<ConnectionId>,
+    pub(crate) remote_address: Option<SocketAddr>,
+}
+
+impl WsPerSocketUserData {
+    // None until the socket is open
+    fn hook_context(&self) -> Option<WsHookContext> {
+        Some(WsHookContext {
+            id: self.connection_id?,
+            remote_address: self.remote_address,
+            stats: self.stats.get(),
+        })
+    }
+}
+
+pub(crate) type PendingPings = Arc<Mutex<HashMap<Vec<u8>, oneshot::Sender<Instant>>>>;
+
+// When the client was last heard of, every message, ping and pong sets it on the loop
+#[derive(Debug)]
+pub(crate) struct LastSeen {
+    opened: Instant,
+    // Milliseconds since `opened`
+    elapsed: AtomicU64,
+}
+
+impl Default for LastSeen {
+    fn default() -> Self {
+        LastSeen {
+            opened: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LastSeen {
+    fn touch(&self) {
+        let elapsed = self.opened.elapsed().as_millis() as u64;
+        self.elapsed.store(elapsed, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> Instant {
+        self.opened + Duration::from_millis(self.elapsed.load(Ordering::Relaxed))
+    }
+}
+
+// Pings of WsRouteSettings::heartbeat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub interval: Duration,
+    // Intervals in a row without a word from the client before it is closed with 1001
+    pub missed: u32,
+}
+
+// What happens to a message longer than max_payload_length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadLimitPolicy {
+    // uWS closes the connection right away, the handler receives Close(1009, ..)
+    #[default]
+    Close,
+    // Messages up to `hard_limit` are dropped and reported as WsMessage::Violation,
+    // the one after `warnings` of them closes the connection with 1009
+    Warn {
+        warnings: u32,
+        hard_limit: u32,
+    },
+}
+
+// What happens to the messages of a connection beyond WsRouteSettings::message_rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageRateAction {
+    // Dropped, the first one of every second is reported as WsMessage::Violation
+    #[default]
+    Drop,
+    // Held back until the next second, the messages after them queue up in the meantime
+    Delay,
+    // The connection is closed with 1008
+    Close,
 }
 
+// What the split() sink does with a message once WsRouteSettings::outbound_queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    // WsSink::send() waits for room, try_send() fails with Full
+    #[default]
+    Block,
+    // The oldest queued message is dropped to make room
+    DropOldest,
+    // The new message is dropped
+    DropNewest,
+    // The connection is closed with 1008, whatever is queued is dropped
+    Close,
+}
+
+// Per connection limits counted over one second windows, see WsRouteSettings::message_rate()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MessageRate {
+    pub messages_per_second: Option<u32>,
+    pub bytes_per_second: Option<u64>,
+    pub action: MessageRateAction,
+}
+
+impl MessageRate {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn messages(mut self, per_second: u32) -> Self {
+        self.messages_per_second = Some(per_second);
+        self
+    }
+
+    pub fn bytes(mut self, per_second: u64) -> Self {
+        self.bytes_per_second = Some(per_second);
+        self
+    }
+
+    pub fn action(mut self, action: MessageRateAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    // The limit `messages` and `bytes` received in one second are over, if any
+    fn exceeded(&self, messages: u32, bytes: u64) -> Option<WsViolation> {
+        let interval = Duration::from_secs(1);
+        if let Some(limit) = self.messages_per_second.filter(|limit| messages > *limit) {
+            return Some(WsViolation::RateLimited { limit, interval });
+        }
+        if let Some(limit) = self.bytes_per_second.filter(|limit| bytes > *limit) {
+            return Some(WsViolation::ByteRateLimited { limit, interval });
+        }
+        None
+    }
+}
+
+/***
+ * Settings of a ws route, built from the defaults rather than as a struct literal:
+ *
+ *   WsRouteSettings::default().max_payload(1 << 20).max_backpressure(4 << 20).idle_timeout(60)
+ *
+ * The defaults are uWS' own, a field set to None is off (0 / false) rather than uWS' default.
+ * App::ws() checks the settings with validate() and returns the error, so a combination uWS
+ * would reject or that can't work fails when the route is added instead of once clients connect.
+ ***/
 #[derive(Debug, Clone)]
 pub struct WsRouteSettings {
     pub compression: Option<u32>,
@@ -42,27 +230,266 @@ pub struct WsRouteSettings {
     pub reset_idle_timeout_on_send: Option<bool>,
     pub send_pings_automatically: Option<bool>,
     pub max_lifetime: Option<u16>,
+    pub payload_limit_policy: Option<PayloadLimitPolicy>,
+    // Messages above the limit are dropped and reported once per interval as WsMessage::Violation
+    pub max_messages_per_interval: Option<u32>,
+    pub message_interval: Option<Duration>,
+    // Like max_messages_per_interval but counted per client IP in the limiter's store
+    pub rate_limiter: Option<RateLimiter>,
+    pub message_rate: Option<MessageRate>,
+    // Messages the split() sink holds before `outbound_overflow` applies, unbounded if None
+    pub outbound_queue_capacity: Option<usize>,
+    pub outbound_overflow: Option<OverflowPolicy>,
+    // The split() sink sends everything queued corked in one loop callback
+    pub batch_sends: Option<bool>,
+    pub heartbeat: Option<Heartbeat>,
+    // Handlers receive WsMessage::Drain whenever uWS drains the socket's backpressure
+    pub drain_events: Option<bool>,
+    pub hooks: WsHooks,
 }
 
+// Mirrors uWS defaults, compression is off since it costs memory per connection
 impl Default for WsRouteSettings {
     fn default() -> Self {
-        let compressor: u32 = CompressOptions::SharedCompressor.into();
-        let decompressor: u32 = CompressOptions::SharedDecompressor.into();
         WsRouteSettings {
-            compression: Some(compressor | decompressor),
-            max_payload_length: Some(1024),
-            idle_timeout: Some(800),
-            max_backpressure: Some(10),
+            compression: Some(CompressOptions::Disabled.into()),
+            max_payload_length: Some(16 * 1024),
+            idle_timeout: Some(120),
+            max_backpressure: Some(64 * 1024),
             close_on_backpressure_limit: Some(false),
-            reset_idle_timeout_on_send: Some(true),
+            reset_idle_timeout_on_send: Some(false),
             send_pings_automatically: Some(true),
-            max_lifetime: Some(111),
+            max_lifetime: Some(0),
+            payload_limit_policy: Some(PayloadLimitPolicy::Close),
+            max_messages_per_interval: None,
+            message_interval: Some(Duration::from_secs(1)),
+            rate_limiter: None,
+            message_rate: None,
+            outbound_queue_capacity: None,
+            outbound_overflow: Some(OverflowPolicy::Block),
+            batch_sends: Some(false),
+            heartbeat: None,
+            drain_events: Some(false),
+            hooks: Default::default(),
+        }
+    }
+}
+
+impl WsRouteSettings {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    // Bitwise or of CompressOptions values
+    pub fn compression(mut self, compression: u32) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub fn max_payload(mut self, bytes: u32) -> Self {
+        self.max_payload_length = Some(bytes);
+        self
+    }
+
+    // Seconds, 0 disables it, otherwise 8..=960. uWS rounds it to its 4 second timer
+    pub fn idle_timeout(mut self, seconds: u16) -> Self {
+        self.idle_timeout = Some(seconds);
+        self
+    }
+
+    // 0 disables it, otherwise at least max_payload_length
+    pub fn max_backpressure(mut self, bytes: u32) -> Self {
+        self.max_backpressure = Some(bytes);
+        self
+    }
+
+    pub fn close_on_backpressure_limit(mut self, close: bool) -> Self {
+        self.close_on_backpressure_limit = Some(close);
+        self
+    }
+
+    pub fn reset_idle_timeout_on_send(mut self, reset: bool) -> Self {
+        self.reset_idle_timeout_on_send = Some(reset);
+        self
+    }
+
+    pub fn send_pings_automatically(mut self, send: bool) -> Self {
+        self.send_pings_automatically = Some(send);
+        self
+    }
+
+    // Minutes, 0 disables it, at most 240
+    pub fn max_lifetime(mut self, minutes: u16) -> Self {
+        self.max_lifetime = Some(minutes);
+        self
+    }
+
+    pub fn payload_limit_policy(mut self, policy: PayloadLimitPolicy) -> Self {
+        self.payload_limit_policy = Some(policy);
+        self
+    }
+
+    pub fn max_messages_per_interval(mut self, messages: u32, interval: Duration) -> Self {
+        self.max_messages_per_interval = Some(messages);
+        self.message_interval = Some(interval);
+        self
+    }
+
+    // Messages pass through a task that asks the limiter before they reach the handler,
+    // so a store shared between instances keeps a client within the limit on all of them
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    // Messages and bytes a single connection may send per second, see MessageRateAction
+    pub fn message_rate(mut self, rate: MessageRate) -> Self {
+        self.message_rate = Some(rate);
+        self
+    }
+
+    // Bounds the queue of Websocket::split()'s sink, a slow client otherwise lets it grow for
+    // as long as the handler keeps sending
+    pub fn outbound_queue(mut self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.outbound_queue_capacity = Some(capacity);
+        self.outbound_overflow = Some(overflow);
+        self
+    }
+
+    /***
+     * Pings every connection each `interval` and closes the ones that didn't send anything, not
+     * even a pong, for `missed` intervals in a row with 1001 (CloseReason::HeartbeatTimeout).
+     * Unlike send_pings_automatically a dead client is found without waiting for idle_timeout,
+     * see also Websocket::last_seen()
+     ***/
+    pub fn heartbeat(mut self, interval: Duration, missed: u32) -> Self {
+        self.heartbeat = Some(Heartbeat { interval, missed });
+        self
+    }
+
+    // For many small messages from the split() sink, e.g. ticks, see Websocket::cork()
+    pub fn batch_sends(mut self, enabled: bool) -> Self {
+        self.batch_sends = Some(enabled);
+        self
+    }
+
+    // For streaming without filling the backpressure buffer: send until buffered_amount() nears
+    // max_backpressure, then wait for WsMessage::Drain (or use Websocket::flush())
+    pub fn drain_events(mut self, enabled: bool) -> Self {
+        self.drain_events = Some(enabled);
+        self
+    }
+
+    // Called on the loop thread as every socket opens, the future it returns is spawned
+    pub fn on_open<H, W>(mut self, hook: H) -> Self
+    where
+        H: Fn(WsHookContext) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_open(hook);
+        self
+    }
+
+    // Like on_open() with the close code and reason the handler gets, see WsHooks
+    pub fn on_close<H, W>(mut self, hook: H) -> Self
+    where
+        H: Fn(WsHookContext, i32, Option<String>) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_close(hook);
+        self
+    }
+
+    // Like on_open() with the bytes still buffered, fires whether or not drain_events is set
+    pub fn on_drain<H, W>(mut self, hook: H) -> Self
+    where
+        H: Fn(WsHookContext, u32) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.set_drain(hook);
+        self
+    }
+
+    // Limit uWS enforces by itself, above max_payload_length when a grace policy is set
+    fn native_max_payload_length(&self) -> u32 {
+        match self.payload_limit_policy.unwrap_or_default() {
+            PayloadLimitPolicy::Close => self.max_payload_length.unwrap_or_default(),
+            PayloadLimitPolicy::Warn { hard_limit, .. } => hard_limit,
+        }
+    }
+
+    // uWS terminates the process on some of these, so they are checked before the route is registered
+    pub fn validate(&self) -> Result<(), String> {
+        let idle_timeout = self.idle_timeout.unwrap_or_default();
+        if (1..8).contains(&idle_timeout) {
+            return Err("idle_timeout must be either 0 or at least 8 seconds".to_string());
+        }
+        if idle_timeout > 960 {
+            return Err("idle_timeout must not be greater than 960 seconds".to_string());
+        }
+        if self.max_lifetime.unwrap_or_default() > 240 {
+            return Err("max_lifetime must not be greater than 240 minutes".to_string());
         }
+        if self.max_payload_length == Some(0) {
+            return Err("max_payload_length of 0 rejects every message".to_string());
+        }
+        if let Some(PayloadLimitPolicy::Warn { hard_limit, .. }) = self.payload_limit_policy {
+            if hard_limit <= self.max_payload_length.unwrap_or_default() {
+                return Err(
+                    "PayloadLimitPolicy::Warn hard_limit must exceed max_payload_length"
+                        .to_string(),
+                );
+            }
+        }
+        if self.max_messages_per_interval == Some(0) {
+            return Err("max_messages_per_interval of 0 rejects every message".to_string());
+        }
+        if self.max_messages_per_interval.is_some()
+            && self.message_interval.unwrap_or_default() < Duration::from_millis(1)
+        {
+            return Err("message_interval must be at least 1 ms".to_string());
+        }
+        if let Some(rate) = self.message_rate {
+            if rate.messages_per_second.is_none() && rate.bytes_per_second.is_none() {
+                return Err("message_rate needs a message or a byte limit".to_string());
+            }
+            if rate.messages_per_second == Some(0) || rate.bytes_per_second == Some(0) {
+                return Err("message_rate of 0 rejects every message".to_string());
+            }
+        }
+        if let Some(heartbeat) = self.heartbeat {
+            if heartbeat.interval < Duration::from_millis(1) {
+                return Err("heartbeat interval must be at least 1 ms".to_string());
+            }
+            if heartbeat.missed == 0 {
+                return Err("heartbeat must allow at least 1 missed interval".to_string());
+            }
+        }
+        if self.outbound_queue_capacity == Some(0) {
+            return Err("outbound_queue_capacity must be at least 1".to_string());
+        }
+        // Sending back a message of the largest size would already be dropped
+        let max_backpressure = self.max_backpressure.unwrap_or_default();
+        if max_backpressure != 0 && max_backpressure < self.max_payload_length.unwrap_or_default() {
+            return Err("max_backpressure must be 0 or at least max_payload_length".to_string());
+        }
+        if self.close_on_backpressure_limit.unwrap_or_default() && max_backpressure == 0 {
+            return Err(
+                "close_on_backpressure_limit requires a non zero max_backpressure".to_string(),
+            );
+        }
+        if self.send_pings_automatically.unwrap_or_default() && idle_timeout == 0 {
+            return Err("send_pings_automatically has no effect without idle_timeout".to_string());
+        }
+
+        Ok(())
     }
 }
 
 pub struct WebsocketBehavior<const SSL: bool> {
     pub native_ws_behaviour: NativeWebSocketBehavior<SSL>,
+    // Timers of the route's rate limits, closed by the app once it is closed
+    pub(crate) timers: Vec<IntervalTimer>,
 }
 
 impl<const SSL: bool> WebsocketBehavior<SSL> {
@@ -79,9 +506,81 @@ impl<const SSL: bool> WebsocketBehavior<SSL> {
         U: Fn(HttpRequest, HttpConnection<SSL>) + 'static + Send + Sync + Clone,
         R: Future<Output = ()> + 'static + Send,
     {
+        Self::new_named(
+            "async_uws ws".to_string(),
+            settings,
+            uws_loop,
+            ws_per_socket_data_storage,
+            handler,
+            upgrade_hook,
+            global_data_storage,
+            None,
+            Default::default(),
+            Default::default(),
+        )
+    }
+
+    // Every connection's handler runs in a task named `task_name`, `live_settings` adds the
+    // allow list check and ws_idle_timeout of App::update_settings(). Sockets are added to
+    // `connections` once the app has a registry, publishes and subscriptions go to `backplane`
+    // once the app has one
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new_named<H, R, U>(
+        task_name: String,
+        settings: WsRouteSettings,
+        uws_loop: UwsLoop,
+        ws_per_socket_data_storage: WsPerSocketUserDataStorage,
+        handler: H,
+        upgrade_hook: U,
+        global_data_storage: SharedDataStorage,
+        live_settings: Option<SettingsHandle>,
+        connections: Arc<OnceLock<ConnectionRegistry>>,
+        backplane: Arc<OnceLock<BackplaneLink>>,
+    ) -> Self
+    where
+        H: (Fn(Websocket<SSL>) -> R) + 'static + Send + Sync + Clone,
+        U: Fn(HttpRequest, HttpConnection<SSL>) + 'static + Send + Sync + Clone,
+        R: Future<Output = ()> + 'static + Send,
+    {
+        let upgrade_settings = live_settings.clone();
+        let closed_connections = connections.clone();
+        let open_hooks = settings.hooks.clone();
+        let close_hooks = settings.hooks.clone();
+        let drain_hooks = settings.hooks.clone();
+        let subscription_backplane = backplane.clone();
+        let mut timers = Vec::new();
+        let message_limits = MessageLimits {
+            payload_limit: settings.max_payload_length.unwrap_or_default(),
+            payload_limit_policy: settings.payload_limit_policy.unwrap_or_default(),
+            rate_limit: settings.max_messages_per_interval.map(|messages| {
+                let interval = settings.message_interval.unwrap_or_default();
+                let (clock, timer) = IntervalClock::start(uws_loop, interval);
+                timers.push(timer);
+                (messages, interval, clock)
+            }),
+            message_rate: settings
+                .message_rate
+                .filter(|rate| rate.action != MessageRateAction::Delay)
+                .map(|rate| {
+                    let (clock, timer) = IntervalClock::start(uws_loop, Duration::from_secs(1));
+                    timers.push(timer);
+                    (rate, clock)
+                }),
+        };
+        let paced_rate = settings
+            .message_rate
+            .filter(|rate| rate.action == MessageRateAction::Delay);
+        let rate_limiter = settings.rate_limiter.clone();
+        let drain_events = settings.drain_events.unwrap_or_default();
+        let max_backpressure = settings.max_backpressure.unwrap_or_default();
+        let batch_sends = settings.batch_sends.unwrap_or_default();
+        let heartbeat = settings.heartbeat;
+        let outbound_limit = settings
+            .outbound_queue_capacity
+            .map(|capacity| (capacity, settings.outbound_overflow.unwrap_or_default()));
         let native_ws_behaviour = NativeWebSocketBehavior {
             compression: settings.compression.unwrap_or_default(),
-            max_payload_length: settings.max_payload_length.unwrap_or_default(),
+            max_payload_length: settings.native_max_payload_length(),
             idle_timeout: settings.idle_timeout.unwrap_or_default(),
             max_backpressure: settings.max_backpressure.unwrap_or_default(),
             close_on_backpressure_limit: settings.close_on_backpressure_limit.unwrap_or_default(),
@@ -90,14 +589,33 @@ impl<const SSL: bool> WebsocketBehavior<SSL> {
             max_lifetime: settings.max_lifetime.unwrap_or_default(),
             upgrade: Some(Box::new(
                 move |mut res: HttpResponseStruct<SSL>, mut req: SyncHttpRequest, ctx: UpgradeContext| {
+                    let remote_address = res.get_remote_address_as_text().to_string();
+                    if let Some(settings) = upgrade_settings.as_ref() {
+                        if !settings.current().is_allowed(Some(&remote_address)) {
+                            res.write_status("403 Forbidden");
+                            res.end(None, false);
+                            return;
+                        }
+                    }
+
                     let is_aborted = Arc::new(AtomicBool::new(false));
                     let is_aborted_to_move = is_aborted.clone();
                     res.on_aborted(move || {
                         is_aborted_to_move.store(true, Ordering::Relaxed);
                     });
 
-                    let req = HttpRequest::from(&mut req);
-                    let res = HttpConnection::<SSL>::new(
+                    let mut req = HttpRequest::from(&mut req);
+                    let socket = res.get_native() as *mut us_socket_t;
+                    req.remote_address = remote_address::remote_address(socket, &remote_address);
+                    if let Some(settings) = upgrade_settings.as_ref() {
+                        req.client_ip = settings.current().client_ip(&req);
+                    }
+                    let alpn_protocol = if SSL {
+                        negotiated_alpn(unsafe { uws_res_get_native_handle(1, res.get_native()) })
+                    } else {
+                        None
+                    };
+                    let mut res = HttpConnection::<SSL>::new(
                         res,
                         uws_loop,
                         is_aborted.clone(),
@@ -106,6 +624,8 @@ impl<const SSL: bool> WebsocketBehavior<SSL> {
                         Some(ws_per_socket_data_storage.clone()),
                         Some(ctx),
                     );
+                    res.set_alpn_protocol(alpn_protocol);
+                    res.set_remote_address(remote_address);
                     upgrade_hook(req, res);
                 },
             )),
@@ -115,70 +635,514 @@ impl<const SSL: bool> WebsocketBehavior<SSL> {
                     .get_user_data::<WsPerSocketUserData>()
                     .expect("[async_uws]: There is no receiver / sender pair in ws user data");
 
-                let stream = user_data.stream.take().unwrap();
+                let mut stream = user_data.stream.take().unwrap();
+                user_data.native_ws = Some(ws_connection.get_native_ws() as usize);
+                let address = remote_address::remote_address(
+                    ws_connection.get_native_ws() as *mut us_socket_t,
+                    ws_connection.get_remote_address_as_text(),
+                );
+                if let Some(limiter) = rate_limiter.clone() {
+                    let client = ws_connection.get_remote_address_as_text().to_string();
+                    stream = rate_limited(stream, limiter, client);
+                }
+                if let Some(rate) = paced_rate {
+                    stream = paced(stream, rate);
+                }
+                if let Some(settings) = live_settings.as_ref() {
+                    let native = LoopBound::new(ws_connection.clone());
+                    let is_open = user_data.is_open.clone();
+                    stream = idle_limited(stream, native, uws_loop, is_open, settings.subscribe());
+                }
                 let is_open = user_data.is_open.clone();
                 let data_storage = user_data.shared_data_storage.clone();
                 let per_connection_data_storage = user_data.custom_user_data.clone();
-                tokio_uring::spawn(async move {
-                    let ws = Websocket::new(
-                        ws_connection,
-                        uws_loop,
-                        stream,
-                        is_open,
-                        data_storage,
-                        per_connection_data_storage,
+                let outbound_queued = user_data.outbound_queued.clone();
+                let mut ws = Websocket::new(
+                    ws_connection.clone(),
+                    uws_loop,
+                    stream,
+                    is_open.clone(),
+                    data_storage,
+                    per_connection_data_storage,
+                );
+                ws.outbound_queued = outbound_queued;
+                ws.cancellation = user_data.cancellation.clone();
+                ws.drained = user_data.drained.clone();
+                ws.closed = user_data.closed.clone();
+                ws.pending_pings = user_data.pending_pings.clone();
+                ws.remote_address = address;
+                ws.max_backpressure = max_backpressure;
+                ws.backplane = backplane.clone();
+                ws.outbound_limit = outbound_limit;
+                ws.batch_sends = batch_sends;
+                ws.last_seen = user_data.last_seen.clone();
+                ws.stats = user_data.stats.clone();
+                if let Some(heartbeat) = heartbeat {
+                    task::spawn(
+                        "async_uws ws heartbeat",
+                        keep_alive(
+                            heartbeat,
+                            LoopBound::new(ws_connection.clone()),
+                            uws_loop,
+                            ws.is_open.clone(),
+                            ws.last_seen.clone(),
+                        ),
                     );
-                    handler(ws).await;
+                }
+                ws.violations = Some(user_data.sink.downgrade());
+                user_data.connection_id = Some(ws.id());
+                user_data.remote_address = address;
+                open_hooks.opened(|| user_data.hook_context());
+                if let Some(registry) = connections.get() {
+                    let stats = ws.stats.clone();
+                    registry.register(ws.id(), ws_connection, uws_loop, is_open, address, stats);
+                }
+                let running = RunningWsHandler::new();
+                let handler = handler(ws);
+                task::spawn(&task_name, async move {
+                    let _running = running;
+                    handler.await
                 });
             })),
-            message: Some(Box::new(message)),
+            message: Some(Box::new(move |native_ws, message, opcode| {
+                on_message(native_ws, message, opcode, &message_limits)
+            })),
             ping: Some(Box::new(ping)),
             pong: Some(Box::new(pong)),
-            close: Some(Box::new(close)),
-            drain: Some(Box::new(drain)),
-            subscription: Some(Box::new(subscription)),
+            close: Some(Box::new(move |native_ws, code, reason| {
+                close(native_ws, code, reason, &closed_connections, &close_hooks)
+            })),
+            drain: Some(Box::new(move |native_ws| {
+                drain(native_ws, drain_events, &drain_hooks)
+            })),
+            subscription: Some(Box::new(move |_native_ws, topic, new_count, old_count| {
+                if let Some(backplane) = subscription_backplane.get() {
+                    backplane.subscription(topic, new_count, old_count);
+                }
+            })),
         };
 
         WebsocketBehavior {
             native_ws_behaviour,
+            timers,
+        }
+    }
+}
+
+// Drops messages over the limit, the first dropped one in a window is reported as a violation
+fn rate_limited(
+    mut stream: UnboundedReceiver<WsMessage>,
+    limiter: RateLimiter,
+    client: String,
+) -> UnboundedReceiver<WsMessage> {
+    let (sink, limited_stream) = unbounded_channel();
+    task::spawn("async_uws ws rate limit", async move {
+        let mut reported_until = Instant::now();
+        while let Some(message) = stream.recv().await {
+            if message.is_msg() {
+                let decision = limiter.check(&client).await;
+                if !decision.allowed {
+                    let now = Instant::now();
+                    if now < reported_until {
+                        continue;
+                    }
+                    reported_until = now + decision.retry_after;
+                    let violation = WsViolation::RateLimited {
+                        limit: limiter.limit(),
+                        interval: limiter.window(),
+                    };
+                    if sink.send(WsMessage::Violation(violation)).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+            }
+            if sink.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    limited_stream
+}
+
+// Holds back the messages beyond MessageRateAction::Delay limits until the second they fit in
+fn paced(
+    mut stream: UnboundedReceiver<WsMessage>,
+    rate: MessageRate,
+) -> UnboundedReceiver<WsMessage> {
+    let (sink, paced_stream) = unbounded_channel();
+    task::spawn("async_uws ws message rate", async move {
+        let mut window_start = Instant::now();
+        let (mut messages, mut bytes) = (0u32, 0u64);
+        while let Some(message) = stream.recv().await {
+            if let WsMessage::Message(data, _) = &message {
+                if window_start.elapsed() >= Duration::from_secs(1) {
+                    window_start = Instant::now();
+                    (messages, bytes) = (0, 0);
+                }
+                let length = data.len() as u64;
+                // A message above the byte limit on its own still gets a second to itself
+                if messages > 0 && rate.exceeded(messages + 1, bytes + length).is_some() {
+                    window_start += Duration::from_secs(1);
+                    sleep_until(window_start.into()).await;
+                    (messages, bytes) = (0, 0);
+                }
+                messages += 1;
+                bytes += length;
+            }
+            if sink.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    paced_stream
+}
+
+// Pings every interval until the socket is closed, by this or otherwise
+async fn keep_alive<const SSL: bool>(
+    heartbeat: Heartbeat,
+    native: LoopBound<WebSocketStruct<SSL>>,
+    uws_loop: UwsLoop,
+    is_open: Arc<AtomicBool>,
+    last_seen: Arc<LastSeen>,
+) {
+    let mut ticks = tokio::time::interval(heartbeat.interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    // The first tick is right away
+    ticks.tick().await;
+    let mut missed = 0;
+    loop {
+        ticks.tick().await;
+        if !is_open.load(Ordering::Relaxed) {
+            return;
+        }
+        if last_seen.get().elapsed() >= heartbeat.interval {
+            missed += 1;
+        } else {
+            missed = 0;
+        }
+        let is_dead = missed >= heartbeat.missed;
+        let native = native.clone();
+        let is_open = is_open.clone();
+        loop_defer(uws_loop, move || {
+            if !is_open.load(Ordering::Relaxed) {
+                return;
+            }
+            if is_dead {
+                native.get().end(1001, Some(ERR_HEARTBEAT_TIMEOUT));
+            } else {
+                native
+                    .get()
+                    .send_with_options(&[], Opcode::Ping, false, true);
+            }
+        });
+        if is_dead {
+            return;
         }
     }
 }
 
-fn message<const SSL: bool>(native_ws: WebSocketStruct<SSL>, message: &[u8], opcode: Opcode) {
+// Closes the socket once nothing arrived for LiveSettings::ws_idle_timeout, which is re-read on update
+fn idle_limited<const SSL: bool>(
+    mut stream: UnboundedReceiver<WsMessage>,
+    native: LoopBound<WebSocketStruct<SSL>>,
+    uws_loop: UwsLoop,
+    is_open: Arc<AtomicBool>,
+    mut settings: watch::Receiver<Arc<LiveSettings>>,
+) -> UnboundedReceiver<WsMessage> {
+    let (sink, idle_stream) = unbounded_channel();
+    task::spawn("async_uws ws idle timeout", async move {
+        let mut last_message = Instant::now();
+        let mut is_closing = false;
+        let mut settings_closed = false;
+        loop {
+            let idle_timeout = settings.borrow().ws_idle_timeout;
+            let idle = async {
+                match idle_timeout {
+                    Some(idle_timeout) => sleep_until((last_message + idle_timeout).into()).await,
+                    None => pending().await,
+                }
+            };
+            tokio::select! {
+                message = stream.recv() => {
+                    let Some(message) = message else {
+                        break;
+                    };
+                    // Drain is about what we send, not what the client does
+                    if !message.is_drain() {
+                        last_message = Instant::now();
+                    }
+                    if sink.send(message).is_err() {
+                        break;
+                    }
+                }
+                changed = settings.changed(), if !settings_closed => {
+                    settings_closed = changed.is_err();
+                }
+                // The stream ends with the Close message once uWS closed the socket
+                _ = idle, if !is_closing => {
+                    is_closing = true;
+                    let native = native.clone();
+                    let is_open = is_open.clone();
+                    loop_defer(uws_loop, move || {
+                        if is_open.load(Ordering::Relaxed) {
+                            native.get().end(1001, Some("Idle timeout"));
+                        }
+                    });
+                }
+            }
+        }
+    });
+    idle_stream
+}
+
+// Checked in the message callback, so rejected messages never reach tokio
+struct MessageLimits {
+    payload_limit: u32,
+    payload_limit_policy: PayloadLimitPolicy,
+    rate_limit: Option<(u32, Duration, IntervalClock)>,
+    // Without MessageRateAction::Delay, that one is paced outside of the loop
+    message_rate: Option<(MessageRate, IntervalClock)>,
+}
+
+/***
+ * Counts elapsed intervals with a repeating uWS timer, one per route.
+ * The message callback then only compares the current count with the one stored per socket.
+ ***/
+struct IntervalClock {
+    current: Arc<AtomicU64>,
+}
+
+impl IntervalClock {
+    // The timer runs until IntervalTimer::close()
+    fn start(uws_loop: UwsLoop, interval: Duration) -> (Self, IntervalTimer) {
+        let current = Arc::new(AtomicU64::new(0));
+        let interval_ms = interval.as_millis().clamp(1, i32::MAX as u128) as i32;
+        let timer = unsafe {
+            // Fallthrough, so the timer doesn't keep the loop alive after the app is closed
+            let timer = us_create_timer(
+                uws_loop.get_native(),
+                1,
+                std::mem::size_of::<*const AtomicU64>() as u32,
+            );
+            let ext = us_timer_ext(timer) as *mut *const AtomicU64;
+            // Released by IntervalTimer::close()
+            ext.write(Arc::into_raw(current.clone()));
+            us_timer_set(timer, Some(on_interval_elapsed), interval_ms, interval_ms);
+            timer
+        };
+        (IntervalClock { current }, IntervalTimer(timer as usize))
+    }
+
+    fn current(&self) -> u64 {
+        self.current.load(Ordering::Relaxed)
+    }
+}
+
+// us_timer_t of an IntervalClock, stored as usize and only used on the loop thread
+pub(crate) struct IntervalTimer(usize);
+
+impl IntervalTimer {
+    // On the loop thread, the clock stops counting
+    pub(crate) fn close(self) {
+        let timer = self.0 as *mut us_timer_t;
+        unsafe {
+            let current = *(us_timer_ext(timer) as *mut *const AtomicU64);
+            drop(Arc::from_raw(current));
+            us_timer_close(timer);
+        }
+    }
+}
+
+unsafe extern "C" fn on_interval_elapsed(timer: *mut us_timer_t) {
+    let current = *(us_timer_ext(timer) as *mut *const AtomicU64);
+    (*current).fetch_add(1, Ordering::Relaxed);
+}
+
+fn on_message<const SSL: bool>(
+    native_ws: WebSocketStruct<SSL>,
+    message: &[u8],
+    opcode: Opcode,
+    limits: &MessageLimits,
+) {
     let user_data = native_ws
         .get_user_data::<WsPerSocketUserData>()
         .expect("[async_uws]: There is no receiver / sender pair in ws user data");
+    user_data.last_seen.touch();
+    user_data.stats.received(message.len());
+
+    if let Some((max_messages, interval, clock)) = limits.rate_limit.as_ref() {
+        let current = clock.current();
+        if user_data.message_interval != current {
+            user_data.message_interval = current;
+            user_data.messages_in_interval = 0;
+        }
+        user_data.messages_in_interval = user_data.messages_in_interval.saturating_add(1);
+        if user_data.messages_in_interval > *max_messages {
+            // Report only the first dropped message, a flood shouldn't turn into a flood of events
+            if user_data.messages_in_interval == max_messages + 1 {
+                let violation = WsViolation::RateLimited {
+                    limit: *max_messages,
+                    interval: *interval,
+                };
+                user_data
+                    .sink
+                    .send(WsMessage::Violation(violation))
+                    .unwrap_or_default();
+            }
+            return;
+        }
+    }
+
+    if let Some((rate, clock)) = limits.message_rate.as_ref() {
+        let current = clock.current();
+        if user_data.rate_window != current {
+            user_data.rate_window = current;
+            user_data.window_messages = 0;
+            user_data.window_bytes = 0;
+            user_data.window_reported = false;
+        }
+        let messages = user_data.window_messages.saturating_add(1);
+        let bytes = user_data.window_bytes.saturating_add(message.len() as u64);
+        if let Some(violation) = rate.exceeded(messages, bytes) {
+            if rate.action == MessageRateAction::Close {
+                native_ws.end(1008, Some(ERR_MESSAGE_RATE));
+                return;
+            }
+            if !user_data.window_reported {
+                user_data.window_reported = true;
+                user_data
+                    .sink
+                    .send(WsMessage::Violation(violation))
+                    .unwrap_or_default();
+            }
+            return;
+        }
+        // Dropped messages don't count, a smaller one may still fit
+        user_data.window_messages = messages;
+        user_data.window_bytes = bytes;
+    }
+
+    // With PayloadLimitPolicy::Close uWS never lets a longer message through
+    let payload_limit = limits.payload_limit;
+    if let PayloadLimitPolicy::Warn { warnings, .. } = limits.payload_limit_policy {
+        if message.len() > payload_limit as usize {
+            if user_data.payload_violations >= warnings {
+                native_ws.end(1009, Some(ERR_TOO_BIG_MESSAGE));
+                return;
+            }
+            user_data.payload_violations += 1;
+            let violation = WsViolation::PayloadTooLarge {
+                length: message.len(),
+                limit: payload_limit,
+            };
+            user_data
+                .sink
+                .send(WsMessage::Violation(violation))
+                .unwrap_or_default();
+            return;
+        }
+    }
 
     user_data
         .sink
-        .send(WsMessage::Message(Vec::from(message), opcode))
+        .send(WsMessage::Message(Bytes::copy_from_slice(message), opcode))
         .unwrap_or_default();
 }
 
-fn close<const SSL: bool>(native_ws: WebSocketStruct<SSL>, code: i32, reason: Option<&str>) {
+fn close<const SSL: bool>(
+    native_ws: WebSocketStruct<SSL>,
+    code: i32,
+    reason: Option<&str>,
+    connections: &OnceLock<ConnectionRegistry>,
+    hooks: &WsHooks,
+) {
     let user_data = native_ws
         .get_user_data::<WsPerSocketUserData>()
         .expect("[async_uws]: There is no receiver / sender pair in ws user data");
+    if let (Some(registry), Some(id)) = (connections.get(), user_data.connection_id) {
+        registry.unregister(id);
+    }
 
+    // uWS reports sockets it closed itself as 1006, give those their proper close code
+    let code = match reason {
+        Some(ERR_TOO_BIG_MESSAGE | ERR_TOO_BIG_MESSAGE_INFLATION) if code == 1006 => 1009,
+        Some(ERR_INVALID_TEXT) if code == 1006 => 1007,
+        _ => code,
+    };
+    user_data.stats.closed(code);
+    hooks.closed(|| user_data.hook_context(), code, reason);
     user_data
         .sink
         .send(WsMessage::Close(code, reason.map(String::from)))
         .unwrap_or_default();
     user_data.is_open.store(false, Ordering::Relaxed);
+    user_data.closed.notify_waiters();
+    // Dropping the waiters fails their ping_rtt()
+    user_data.pending_pings.lock().unwrap().clear();
 
     let mut storage = user_data.storage.lock().unwrap();
     storage.remove(&user_data.id.unwrap());
 }
 
+// Ends every open socket with `code`, the handlers get the close like any other
+pub(crate) fn close_websockets<const SSL: bool>(
+    uws_loop: UwsLoop,
+    storage: WsPerSocketUserDataStorage,
+    code: i32,
+    reason: String,
+) {
+    loop_defer(uws_loop, move || {
+        // end() runs the close callback right away, which takes the lock again
+        let sockets: Vec<usize> = storage
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|user_data| user_data.native_ws)
+            .collect();
+        for socket in sockets {
+            let native_ws = WebSocketStruct::<SSL>::new(socket as *mut uws_websocket_t);
+            native_ws.end(code, Some(&reason));
+        }
+    });
+}
+
+// Closes every socket that is still open without a close handshake
+pub(crate) fn terminate_websockets<const SSL: bool>(
+    uws_loop: UwsLoop,
+    storage: WsPerSocketUserDataStorage,
+) {
+    loop_defer(uws_loop, move || {
+        let sockets: Vec<usize> = storage
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|user_data| user_data.native_ws)
+            .collect();
+        for socket in sockets {
+            WebSocketStruct::<SSL>::new(socket as *mut uws_websocket_t).close();
+        }
+    });
+}
+
+// Replaces every sink, so handlers' `stream.recv()` returns None once the queued messages are read
+pub(crate) fn end_handler_streams(uws_loop: UwsLoop, storage: WsPerSocketUserDataStorage) {
+    loop_defer(uws_loop, move || {
+        for user_data in storage.lock().unwrap().values_mut() {
+            user_data.sink = unbounded_channel().0;
+        }
+    });
+}
+
 fn ping<const SSL: bool>(native_ws: WebSocketStruct<SSL>, message: Option<&[u8]>) {
     let user_data = native_ws
         .get_user_data::<WsPerSocketUserData>()
         .expect("[async_uws]: There is no receiver / sender pair in ws user data");
+    user_data.last_seen.touch();
 
     user_data
         .sink
-        .send(WsMessage::Ping(message.map(Vec::from)))
+        .send(WsMessage::Ping(message.map(Bytes::copy_from_slice)))
         .unwrap_or_default();
 }
 
@@ -186,22 +1150,27 @@ fn pong<const SSL: bool>(native_ws: WebSocketStruct<SSL>, message: Option<&[u8]>
     let user_data = native_ws
         .get_user_data::<WsPerSocketUserData>()
         .expect("[async_uws]: There is no receiver / sender pair in ws user data");
+    user_data.last_seen.touch();
+    let waiter =
+        message.and_then(|payload| user_data.pending_pings.lock().unwrap().remove(payload));
+    if let Some(waiter) = waiter {
+        let _ = waiter.send(Instant::now());
+    }
 
     user_data
         .sink
-        .send(WsMessage::Pong(message.map(Vec::from)))
+        .send(WsMessage::Pong(message.map(Bytes::copy_from_slice)))
         .unwrap_or_default();
 }
 
-fn drain<const SSL: bool>(_native_ws: WebSocketStruct<SSL>) {
-    todo!("Handle drain event")
-}
-
-fn subscription<const SSL: bool>(
-    _native_ws: WebSocketStruct<SSL>,
-    _topic: &str,
-    _param1: i32,
-    _param2: i32,
-) {
-    todo!("handle incoming subscription")
+fn drain<const SSL: bool>(native_ws: WebSocketStruct<SSL>, drain_events: bool, hooks: &WsHooks) {
+    if let Some(user_data) = native_ws.get_user_data::<WsPerSocketUserData>() {
+        user_data.drained.notify_waiters();
+        let buffered = native_ws.get_buffered_amount();
+        user_data.stats.buffered(buffered);
+        hooks.drained(|| user_data.hook_context(), buffered);
+        if drain_events {
+            let _ = user_data.sink.send(WsMessage::Drain(buffered));
+        }
+    }
 }
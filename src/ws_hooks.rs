@@ -0,0 +1,113 @@
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::app::BoxedHandlerFuture;
+use crate::connection_registry::ConnectionId;
+use crate::task;
+use crate::ws_stats::WsStats;
+
+// The connection a hook fires for
+#[derive(Debug, Clone)]
+pub struct WsHookContext {
+    pub id: ConnectionId,
+    pub remote_address: Option<SocketAddr>,
+    pub stats: WsStats,
+}
+
+type OpenHook = Arc<dyn Fn(WsHookContext) -> BoxedHandlerFuture + Send + Sync>;
+type CloseHook =
+    Arc<dyn Fn(WsHookContext, i32, Option<String>) -> BoxedHandlerFuture + Send + Sync>;
+type DrainHook = Arc<dyn Fn(WsHookContext, u32) -> BoxedHandlerFuture + Send + Sync>;
+
+/***
+ * Callbacks of WsRouteSettings::on_open(), on_close() and on_drain(), they fire for every
+ * connection of the route next to its handler. A hook is called on the loop thread right in the
+ * uWS event, so it should only do quick synchronous work there, the future it returns runs in
+ * a task of its own:
+ *
+ *   WsRouteSettings::default()
+ *       .on_open(move |_| {
+ *           open_sockets.fetch_add(1, Ordering::Relaxed);
+ *           async {}
+ *       })
+ *       .on_close(move |connection, code, _| audit.log(connection.id, code))
+ ***/
+#[derive(Clone, Default)]
+pub struct WsHooks {
+    pub(crate) open: Option<OpenHook>,
+    pub(crate) close: Option<CloseHook>,
+    pub(crate) drain: Option<DrainHook>,
+}
+
+impl fmt::Debug for WsHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WsHooks")
+            .field("open", &self.open.is_some())
+            .field("close", &self.close.is_some())
+            .field("drain", &self.drain.is_some())
+            .finish()
+    }
+}
+
+impl WsHooks {
+    pub(crate) fn set_open<H, W>(&mut self, hook: H)
+    where
+        H: Fn(WsHookContext) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.open = Some(Arc::new(move |context| Box::pin(hook(context))));
+    }
+
+    pub(crate) fn set_close<H, W>(&mut self, hook: H)
+    where
+        H: Fn(WsHookContext, i32, Option<String>) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.close = Some(Arc::new(move |context, code, reason| {
+            Box::pin(hook(context, code, reason))
+        }));
+    }
+
+    pub(crate) fn set_drain<H, W>(&mut self, hook: H)
+    where
+        H: Fn(WsHookContext, u32) -> W + Send + Sync + 'static,
+        W: Future<Output = ()> + Send + 'static,
+    {
+        self.drain = Some(Arc::new(move |context, buffered| {
+            Box::pin(hook(context, buffered))
+        }));
+    }
+
+    // All of these are called on the loop thread, `context` is None until the socket is open
+    pub(crate) fn opened(&self, context: impl FnOnce() -> Option<WsHookContext>) {
+        if let (Some(hook), Some(context)) = (self.open.as_ref(), context()) {
+            task::spawn("async_uws ws open hook", hook(context));
+        }
+    }
+
+    pub(crate) fn closed(
+        &self,
+        context: impl FnOnce() -> Option<WsHookContext>,
+        code: i32,
+        reason: Option<&str>,
+    ) {
+        let Some(hook) = self.close.as_ref() else {
+            return;
+        };
+        if let Some(context) = context() {
+            let future = hook(context, code, reason.map(String::from));
+            task::spawn("async_uws ws close hook", future);
+        }
+    }
+
+    pub(crate) fn drained(&self, context: impl FnOnce() -> Option<WsHookContext>, buffered: u32) {
+        let Some(hook) = self.drain.as_ref() else {
+            return;
+        };
+        if let Some(context) = context() {
+            task::spawn("async_uws ws drain hook", hook(context, buffered));
+        }
+    }
+}
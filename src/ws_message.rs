@@ -1,11 +1,74 @@
+use std::time::Duration;
+
+use bytes::Bytes;
 use uwebsockets_rs::websocket::Opcode;
 
+use crate::ws_behavior::OverflowPolicy;
+
+// Reasons uWS passes along with 1006 when it closes the socket itself
+pub(crate) const ERR_TOO_BIG_MESSAGE: &str = "Received too big message";
+pub(crate) const ERR_TOO_BIG_MESSAGE_INFLATION: &str =
+    "Received too big message, or other inflation error";
+pub(crate) const ERR_INVALID_TEXT: &str = "Received invalid UTF-8";
+pub(crate) const ERR_WEBSOCKET_TIMEOUT: &str = "WebSocket timed out from inactivity";
+// Reason of the 1008 close of MessageRateAction::Close
+pub(crate) const ERR_MESSAGE_RATE: &str = "Message rate exceeded";
+// Reason of the 1001 close of WsRouteSettings::heartbeat
+pub(crate) const ERR_HEARTBEAT_TIMEOUT: &str = "Heartbeat timeout";
+// Reason of the 1008 close of OverflowPolicy::Close
+pub(crate) const ERR_OUTBOUND_OVERFLOW: &str = "Outgoing queue overflow";
+
+// Payloads are Bytes, cloning a message to send it to many sockets doesn't copy it
 #[derive(Clone, Debug)]
 pub enum WsMessage {
-    Message(Vec<u8>, Opcode),
-    Ping(Option<Vec<u8>>),
-    Pong(Option<Vec<u8>>),
+    Message(Bytes, Opcode),
+    Ping(Option<Bytes>),
+    Pong(Option<Bytes>),
     Close(i32, Option<String>),
+    // Only received, reports a message the route settings didn't let through
+    Violation(WsViolation),
+    // Only received with WsRouteSettings::drain_events, uWS sent out buffered data and this
+    // much is still buffered, see Websocket::buffered_amount()
+    Drain(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WsViolation {
+    // Message was dropped, see PayloadLimitPolicy::Warn
+    PayloadTooLarge {
+        length: usize,
+        limit: u32,
+    },
+    // Messages are dropped until the interval ends, see WsRouteSettings::max_messages_per_interval
+    RateLimited {
+        limit: u32,
+        interval: Duration,
+    },
+    // Like RateLimited, for the bytes per second of WsRouteSettings::message_rate
+    ByteRateLimited {
+        limit: u64,
+        interval: Duration,
+    },
+    // The split() sink was full, reported once until it has room again, see
+    // WsRouteSettings::outbound_queue
+    OutboundOverflow {
+        capacity: usize,
+        policy: OverflowPolicy,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CloseReason {
+    Normal,
+    GoingAway,
+    InvalidPayload,
+    MessageTooBig,
+    IdleTimeout,
+    // The client missed the heartbeats of WsRouteSettings::heartbeat
+    HeartbeatTimeout,
+    // Connection dropped without a close frame
+    Abnormal,
+    Other(i32),
 }
 
 impl WsMessage {
@@ -15,6 +78,8 @@ impl WsMessage {
             WsMessage::Ping(_) => false,
             WsMessage::Pong(_) => false,
             WsMessage::Close(_, _) => false,
+            WsMessage::Violation(_) => false,
+            WsMessage::Drain(_) => false,
         }
     }
     pub fn is_ping(&self) -> bool {
@@ -23,6 +88,8 @@ impl WsMessage {
             WsMessage::Ping(_) => true,
             WsMessage::Pong(_) => false,
             WsMessage::Close(_, _) => false,
+            WsMessage::Violation(_) => false,
+            WsMessage::Drain(_) => false,
         }
     }
     pub fn is_pong(&self) -> bool {
@@ -31,6 +98,8 @@ impl WsMessage {
             WsMessage::Ping(_) => false,
             WsMessage::Pong(_) => true,
             WsMessage::Close(_, _) => false,
+            WsMessage::Violation(_) => false,
+            WsMessage::Drain(_) => false,
         }
     }
     pub fn is_close(&self) -> bool {
@@ -39,29 +108,62 @@ impl WsMessage {
             WsMessage::Ping(_) => false,
             WsMessage::Pong(_) => false,
             WsMessage::Close(_, _) => true,
+            WsMessage::Violation(_) => false,
+            WsMessage::Drain(_) => false,
         }
     }
+    pub fn is_violation(&self) -> bool {
+        matches!(self, WsMessage::Violation(_))
+    }
+    pub fn is_drain(&self) -> bool {
+        matches!(self, WsMessage::Drain(_))
+    }
+
+    pub fn close_reason(&self) -> Option<CloseReason> {
+        let WsMessage::Close(code, reason) = self else {
+            return None;
+        };
+        let reason = match code {
+            1000 => CloseReason::Normal,
+            1001 if reason.as_deref() == Some(ERR_HEARTBEAT_TIMEOUT) => {
+                CloseReason::HeartbeatTimeout
+            }
+            1001 => CloseReason::GoingAway,
+            1007 => CloseReason::InvalidPayload,
+            1009 => CloseReason::MessageTooBig,
+            1006 if reason.as_deref() == Some(ERR_WEBSOCKET_TIMEOUT) => CloseReason::IdleTimeout,
+            1006 => CloseReason::Abnormal,
+            code => CloseReason::Other(*code),
+        };
+        Some(reason)
+    }
 }
 
 impl From<String> for WsMessage {
     fn from(value: String) -> Self {
-        WsMessage::Message(Vec::from(value.as_bytes()), Opcode::Text)
+        WsMessage::Message(Bytes::from(value), Opcode::Text)
     }
 }
 impl From<&str> for WsMessage {
     fn from(value: &str) -> Self {
-        WsMessage::Message(Vec::from(value.as_bytes()), Opcode::Text)
+        WsMessage::Message(Bytes::copy_from_slice(value.as_bytes()), Opcode::Text)
     }
 }
 
 impl From<Vec<u8>> for WsMessage {
     fn from(value: Vec<u8>) -> Self {
-        WsMessage::Message(value, Opcode::Binary)
+        WsMessage::Message(Bytes::from(value), Opcode::Binary)
     }
 }
 
 impl From<&[u8]> for WsMessage {
     fn from(value: &[u8]) -> Self {
-        WsMessage::Message(value.into(), Opcode::Binary)
+        WsMessage::Message(Bytes::copy_from_slice(value), Opcode::Binary)
+    }
+}
+
+impl From<Bytes> for WsMessage {
+    fn from(value: Bytes) -> Self {
+        WsMessage::Message(value, Opcode::Binary)
     }
 }
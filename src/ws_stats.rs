@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use uwebsockets_rs::websocket::{SendStatus as NativeSendStatus, WebSocketStruct};
+
+use crate::ws_behavior::WsPerSocketUserData;
+
+// Snapshot of the counters of one websocket, see Websocket::stats() and ConnectionRegistry::stats()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WsStats {
+    // Data messages only, pings and pongs aren't counted
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    // Messages uWS took, dropped ones aren't counted
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    // As of the last send or drain event, Websocket::buffered_amount() asks uWS
+    pub buffered_amount: u32,
+    // Time of the upgrade request
+    pub connected_at: SystemTime,
+    // None while the socket is open
+    pub close_code: Option<i32>,
+}
+
+impl WsStats {
+    pub fn connected_for(&self) -> Duration {
+        self.connected_at.elapsed().unwrap_or_default()
+    }
+}
+
+// Written by the uWS callbacks and sends on the loop, read from anywhere
+#[derive(Debug)]
+pub(crate) struct WsCounters {
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    buffered_amount: AtomicU32,
+    connected_at: SystemTime,
+    // 0 while open
+    close_code: AtomicI32,
+}
+
+impl Default for WsCounters {
+    fn default() -> Self {
+        WsCounters {
+            messages_received: Default::default(),
+            bytes_received: Default::default(),
+            messages_sent: Default::default(),
+            bytes_sent: Default::default(),
+            buffered_amount: Default::default(),
+            connected_at: SystemTime::now(),
+            close_code: Default::default(),
+        }
+    }
+}
+
+impl WsCounters {
+    pub(crate) fn received(&self, length: usize) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(length as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn buffered(&self, buffered_amount: u32) {
+        self.buffered_amount
+            .store(buffered_amount, Ordering::Relaxed);
+    }
+
+    pub(crate) fn closed(&self, code: i32) {
+        self.close_code.store(code, Ordering::Relaxed);
+    }
+
+    pub(crate) fn get(&self) -> WsStats {
+        let close_code = self.close_code.load(Ordering::Relaxed);
+        WsStats {
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            buffered_amount: self.buffered_amount.load(Ordering::Relaxed),
+            connected_at: self.connected_at,
+            close_code: (close_code != 0).then_some(close_code),
+        }
+    }
+}
+
+// Must be called on the loop thread right after a data message was handed to uWS
+pub(crate) fn count_sent<const SSL: bool>(
+    native: &WebSocketStruct<SSL>,
+    length: usize,
+    status: &NativeSendStatus,
+) {
+    let Some(user_data) = native.get_user_data::<WsPerSocketUserData>() else {
+        return;
+    };
+    let counters = &user_data.stats;
+    if !matches!(status, NativeSendStatus::Dropped) {
+        counters.messages_sent.fetch_add(1, Ordering::Relaxed);
+        counters
+            .bytes_sent
+            .fetch_add(length as u64, Ordering::Relaxed);
+    }
+    counters.buffered(native.get_buffered_amount());
+}